@@ -0,0 +1,18 @@
+-- Per-device secrets. Only ciphertext is stored: each value is sealed to the
+-- device's X25519 public key (reported by smithd on startup) before it is
+-- written, so neither this table nor command_queue ever holds plaintext.
+--
+-- When a device reports a different key (reflashed, keys dir wiped) its
+-- existing rows can no longer be opened; home.rs drops them and records it in
+-- the ledger so an operator knows to re-enter them.
+ALTER TABLE device ADD COLUMN secrets_public_key TEXT;
+
+CREATE TABLE device_secret (
+    device_id INTEGER NOT NULL REFERENCES device(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    delivery TEXT NOT NULL DEFAULT 'file' CHECK (delivery IN ('file', 'env')),
+    ciphertext TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (device_id, name)
+);
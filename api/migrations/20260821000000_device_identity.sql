@@ -0,0 +1,17 @@
+-- Device identity keys. smithd generates an Ed25519 key on first boot and
+-- registers with its public half plus a signature over a single-use challenge.
+-- The first key a serial registers with is pinned here; afterwards registration
+-- and key-based session refresh only succeed with that same key, so knowing a
+-- device's serial and MAC is no longer enough to claim its token.
+--
+-- NULL for devices whose daemon predates identity keys. They pin a key on their
+-- first session refresh after upgrading (POST /smith/identity, authenticated
+-- with the existing token).
+ALTER TABLE device ADD COLUMN identity_public_key TEXT;
+
+-- Challenges are consumed on use and expire after a minute; expired rows are
+-- swept whenever a new challenge is issued.
+CREATE TABLE device_challenge (
+    challenge TEXT PRIMARY KEY,
+    expires_at TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,30 @@
+-- Download bandwidth policies, assigned by label: every device carrying the
+-- label with the given value gets the policy. A device matching several gets
+-- the most recently updated one.
+--
+-- smithd reports which policy it is applying along with its usage, and
+-- home.rs queues the right one again whenever the two differ, so devices pick
+-- up label changes without anything else having to notice them.
+CREATE TABLE bandwidth_policy (
+    id SERIAL PRIMARY KEY,
+    label_id INTEGER NOT NULL REFERENCES label(id) ON DELETE CASCADE,
+    value VARCHAR(255) NOT NULL,
+    policy JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (label_id, value)
+);
+
+-- Bytes downloaded per calendar month (device local time), as last reported.
+CREATE TABLE device_bandwidth_usage (
+    device_id INTEGER NOT NULL REFERENCES device(id) ON DELETE CASCADE,
+    month VARCHAR(7) NOT NULL,
+    ethernet_bytes BIGINT NOT NULL DEFAULT 0,
+    wifi_bytes BIGINT NOT NULL DEFAULT 0,
+    lte_bytes BIGINT NOT NULL DEFAULT 0,
+    other_bytes BIGINT NOT NULL DEFAULT 0,
+    link TEXT NOT NULL,
+    paused TEXT,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (device_id, month)
+);
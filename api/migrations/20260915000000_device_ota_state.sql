@@ -0,0 +1,13 @@
+-- The last OTA state each device reported. smithd persists it across the
+-- reboot that applies the update and reports it again once it has checked
+-- which L4T version came up, so this ends in `verified` or `failed`.
+CREATE TABLE device_ota_state (
+    device_id INTEGER PRIMARY KEY REFERENCES device(id) ON DELETE CASCADE,
+    phase TEXT NOT NULL,
+    target_version TEXT,
+    current_version TEXT,
+    progress_percent SMALLINT NOT NULL DEFAULT 0,
+    error TEXT,
+    state JSONB NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,7 @@
+-- Compose-like container services a release runs. The spec names its images by
+-- the release's container image packages, whose `file` is `reference@digest`.
+CREATE TABLE release_container_spec (
+    release_id INTEGER PRIMARY KEY REFERENCES release(id) ON DELETE CASCADE,
+    spec JSONB NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL
+);
@@ -0,0 +1,7 @@
+-- Scripts a release runs around installing its packages: pre_install,
+-- post_install and verify, each with a timeout.
+CREATE TABLE release_hooks (
+    release_id INTEGER PRIMARY KEY REFERENCES release(id) ON DELETE CASCADE,
+    hooks JSONB NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL
+);
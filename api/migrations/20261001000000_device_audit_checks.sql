@@ -0,0 +1,15 @@
+-- One row per device and compliance check, replaced on every audit report.
+CREATE TABLE device_audit_check (
+    device_id INTEGER NOT NULL REFERENCES device(id) ON DELETE CASCADE,
+    check_id TEXT NOT NULL,
+    title TEXT NOT NULL,
+    severity TEXT NOT NULL,
+    -- NULL when the device could not run the check.
+    passed BOOLEAN,
+    evidence TEXT NOT NULL,
+    remediation TEXT NOT NULL,
+    checked_at TIMESTAMPTZ DEFAULT NOW() NOT NULL,
+    PRIMARY KEY (device_id, check_id)
+);
+
+CREATE INDEX device_audit_check_check_id_idx ON device_audit_check (check_id);
@@ -0,0 +1,11 @@
+-- Monitored files that differ from a device's integrity baseline, replaced on
+-- every integrity report.
+CREATE TABLE device_integrity_drift (
+    device_id INTEGER NOT NULL REFERENCES device(id) ON DELETE CASCADE,
+    path TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    old JSONB,
+    new JSONB,
+    detected_at TIMESTAMPTZ DEFAULT NOW() NOT NULL,
+    PRIMARY KEY (device_id, path)
+);
@@ -0,0 +1,32 @@
+-- The modem each device last reported through ModemManager, replaced on every
+-- report.
+CREATE TABLE device_modem_status (
+    device_id INTEGER PRIMARY KEY REFERENCES device(id) ON DELETE CASCADE,
+    manufacturer TEXT,
+    model TEXT,
+    imei TEXT,
+    iccid TEXT,
+    operator TEXT,
+    state TEXT NOT NULL,
+    registration TEXT NOT NULL,
+    access_technology TEXT,
+    apn TEXT,
+    data_connected BOOLEAN NOT NULL,
+    rx_bytes BIGINT NOT NULL,
+    tx_bytes BIGINT NOT NULL,
+    watchdog_power_cycles INTEGER NOT NULL DEFAULT 0,
+    updated_at TIMESTAMPTZ DEFAULT NOW() NOT NULL
+);
+
+-- Signal readings, kept for a week.
+CREATE TABLE device_modem_signal (
+    device_id INTEGER NOT NULL REFERENCES device(id) ON DELETE CASCADE,
+    sampled_at TIMESTAMPTZ NOT NULL,
+    quality SMALLINT NOT NULL,
+    access_technology TEXT,
+    rssi DOUBLE PRECISION,
+    rsrp DOUBLE PRECISION,
+    rsrq DOUBLE PRECISION,
+    snr DOUBLE PRECISION,
+    PRIMARY KEY (device_id, sampled_at)
+);
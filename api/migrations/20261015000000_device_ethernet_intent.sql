@@ -0,0 +1,19 @@
+-- Wired profiles of a device's network intent. They carry per-device
+-- addressing, so unlike Wi-Fi they don't reference the shared network catalog.
+CREATE TABLE device_ethernet_intent (
+    id           SERIAL PRIMARY KEY,
+    device_id    INTEGER NOT NULL REFERENCES device(id) ON DELETE CASCADE,
+    profile_name TEXT NOT NULL,
+    interface    TEXT,
+    vlan_id      INTEGER CHECK (vlan_id BETWEEN 1 AND 4094),
+    ipv4         JSONB NOT NULL,
+    ipv6         JSONB NOT NULL,
+    dns          TEXT[] NOT NULL DEFAULT '{}',
+    priority     INTEGER NOT NULL,
+    managed_by   TEXT NOT NULL,
+    created_at   TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at   TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CONSTRAINT device_ethernet_intent_device_profile_unique UNIQUE (device_id, profile_name)
+);
+
+CREATE INDEX idx_device_ethernet_intent_device_id ON device_ethernet_intent(device_id);
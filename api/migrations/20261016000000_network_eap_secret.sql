@@ -0,0 +1,11 @@
+-- What an enterprise network authenticates with. Kept out of `credentials`,
+-- which content-addresses the catalog, and sealed to each device on apply.
+CREATE TABLE network_eap_secret (
+    network_id           INTEGER PRIMARY KEY REFERENCES network(id) ON DELETE CASCADE,
+    password             TEXT,
+    ca_cert              TEXT,
+    client_cert          TEXT,
+    private_key          TEXT,
+    private_key_password TEXT,
+    updated_at           TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,17 @@
+-- The upstream connectivity each device last reported: full, portal, limited
+-- or none, and since when.
+CREATE TABLE device_connectivity (
+    device_id INTEGER PRIMARY KEY REFERENCES device(id) ON DELETE CASCADE,
+    state TEXT NOT NULL,
+    since TIMESTAMPTZ NOT NULL,
+    updated_at TIMESTAMPTZ DEFAULT NOW() NOT NULL
+);
+
+-- Every change of state, kept for 30 days. A device re-sends the transitions
+-- of a post whose response it lost, hence the key.
+CREATE TABLE device_connectivity_transition (
+    device_id INTEGER NOT NULL REFERENCES device(id) ON DELETE CASCADE,
+    changed_at TIMESTAMPTZ NOT NULL,
+    state TEXT NOT NULL,
+    PRIMARY KEY (device_id, changed_at, state)
+);
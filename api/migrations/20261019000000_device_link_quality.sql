@@ -0,0 +1,22 @@
+-- Latency and loss each device measured to the API host and its default
+-- gateway, one row per interval, kept for a week.
+CREATE TABLE device_link_quality (
+    device_id INTEGER NOT NULL REFERENCES device(id) ON DELETE CASCADE,
+    started_at TIMESTAMPTZ NOT NULL,
+    ended_at TIMESTAMPTZ NOT NULL,
+    bssid TEXT,
+    signal SMALLINT,
+    api_sent INTEGER,
+    api_received INTEGER,
+    api_rtt_p50_ms DOUBLE PRECISION,
+    api_rtt_p90_ms DOUBLE PRECISION,
+    api_rtt_p99_ms DOUBLE PRECISION,
+    api_jitter_ms DOUBLE PRECISION,
+    gateway_sent INTEGER,
+    gateway_received INTEGER,
+    gateway_rtt_p50_ms DOUBLE PRECISION,
+    gateway_rtt_p90_ms DOUBLE PRECISION,
+    gateway_rtt_p99_ms DOUBLE PRECISION,
+    gateway_jitter_ms DOUBLE PRECISION,
+    PRIMARY KEY (device_id, started_at)
+);
@@ -0,0 +1,32 @@
+-- WireGuard concentrators devices can be peered with. Each hands out tunnel
+-- addresses from its pool; the first host of the pool is the concentrator's.
+CREATE TABLE vpn (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    endpoint TEXT NOT NULL,
+    public_key TEXT NOT NULL,
+    address_pool CIDR NOT NULL,
+    allowed_ips TEXT[] NOT NULL,
+    dns TEXT[] NOT NULL DEFAULT '{}',
+    persistent_keepalive INTEGER,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- The public half of the device's WireGuard key, reported by smithd on
+-- startup. The private half never leaves the device.
+ALTER TABLE device ADD COLUMN wireguard_public_key TEXT;
+
+-- A device's VPN intent: the concentrator it peers with, the address it was
+-- given and the tunnel as the device last reported it. Revoking the device
+-- removes the row, and with it the peer the concentrator syncs.
+CREATE TABLE device_vpn_peer (
+    device_id INTEGER PRIMARY KEY REFERENCES device(id) ON DELETE CASCADE,
+    vpn_id INTEGER NOT NULL REFERENCES vpn(id) ON DELETE CASCADE,
+    address INET NOT NULL,
+    latest_handshake_at TIMESTAMPTZ,
+    rx_bytes BIGINT,
+    tx_bytes BIGINT,
+    status_updated_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (vpn_id, address)
+);
@@ -0,0 +1,23 @@
+-- The hardware each device last reported. The lists are kept whole as JSON;
+-- what devices are filtered by is also kept in columns of its own.
+CREATE TABLE device_hardware (
+    device_id INTEGER PRIMARY KEY REFERENCES device(id) ON DELETE CASCADE,
+    cpu_model TEXT,
+    cpu_cores INTEGER NOT NULL,
+    memory_bytes BIGINT NOT NULL,
+    -- e.g. {emmc,usb}
+    storage_kinds TEXT[] NOT NULL,
+    -- vendor:product, e.g. {046d:0825}
+    usb_ids TEXT[] NOT NULL,
+    pci_ids TEXT[] NOT NULL,
+    -- Connectors with a display attached, e.g. {HDMI-A-1}
+    connected_displays TEXT[] NOT NULL,
+    block_devices JSONB NOT NULL,
+    usb_devices JSONB NOT NULL,
+    pci_devices JSONB NOT NULL,
+    displays JSONB NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX device_hardware_usb_ids_idx ON device_hardware USING GIN (usb_ids);
+CREATE INDEX device_hardware_pci_ids_idx ON device_hardware USING GIN (pci_ids);
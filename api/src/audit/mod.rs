@@ -0,0 +1,248 @@
+use crate::serialized::serialized_name;
+use serde::Serialize;
+use smith::utils::schema::{AuditCheckResult, FileMetadata, IntegrityChange, IntegrityChangeKind};
+use sqlx::types::Json as SqlxJson;
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::{Postgres, Transaction};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+pub mod route;
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DeviceAuditCheck {
+    pub check_id: String,
+    pub title: String,
+    /// One of `info`, `low`, `medium`, `high` and `critical`.
+    pub severity: String,
+    /// `None` if the device could not run the check.
+    pub passed: Option<bool>,
+    pub evidence: String,
+    pub remediation: String,
+    pub checked_at: DateTime<Utc>,
+}
+
+/// How the devices with one value of a label fare in their audits.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LabelCompliance {
+    pub value: String,
+    /// Devices with this value that reported audit checks.
+    pub devices: i64,
+    /// Of those, the devices that failed none of their checks.
+    pub compliant_devices: i64,
+    /// Checks the devices could run, and how many of them passed.
+    pub checks: i64,
+    pub passed_checks: i64,
+    /// `passed_checks` out of `checks`, `None` if no check could be run.
+    pub compliance_percent: Option<f64>,
+}
+
+/// A monitored file that differs from the device's integrity baseline.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct IntegrityDrift {
+    pub path: String,
+    /// One of `added`, `removed`, `changed` and `baseline_reset`, the last
+    /// for the device's baseline itself, taken again after it was lost.
+    pub kind: String,
+    /// The file in the baseline: its sha256, uid, gid and mode.
+    #[schema(value_type = Option<Object>)]
+    pub old: Option<SqlxJson<FileMetadata>>,
+    /// The file on the device, in the same shape.
+    #[schema(value_type = Option<Object>)]
+    pub new: Option<SqlxJson<FileMetadata>>,
+    /// When the device first reported the file as it is now.
+    pub detected_at: DateTime<Utc>,
+}
+
+/// Drifted files named in a ledger entry before the rest are only counted.
+const LEDGER_PATHS: usize = 5;
+
+fn drift_summary(changes: &[&IntegrityChange]) -> String {
+    let mut named: Vec<String> = changes
+        .iter()
+        .take(LEDGER_PATHS)
+        .map(|change| format!("{} {}", change.path, serialized_name(change.kind)))
+        .collect();
+    if changes.len() > LEDGER_PATHS {
+        named.push(format!("{} more", changes.len() - LEDGER_PATHS));
+    }
+    format!(
+        "Monitored files drifted from the baseline: {}.",
+        named.join(", ")
+    )
+}
+
+fn percent(passed: i64, total: i64) -> Option<f64> {
+    (total > 0).then(|| passed as f64 * 100.0 / total as f64)
+}
+
+/// Replaces the device's audit checks with the ones it just reported.
+pub async fn save_checks(
+    device_id: i32,
+    checks: &[AuditCheckResult],
+    tx: &mut Transaction<'_, Postgres>,
+) -> anyhow::Result<()> {
+    let ids: Vec<String> = checks.iter().map(|check| check.id.clone()).collect();
+    // Checks the profile now skips no longer apply.
+    sqlx::query!(
+        "DELETE FROM device_audit_check WHERE device_id = $1 AND NOT (check_id = ANY($2))",
+        device_id,
+        &ids
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    for check in checks {
+        sqlx::query!(
+            r#"
+            INSERT INTO device_audit_check
+                (device_id, check_id, title, severity, passed, evidence, remediation, checked_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, NOW())
+            ON CONFLICT (device_id, check_id) DO UPDATE SET
+                title = EXCLUDED.title,
+                severity = EXCLUDED.severity,
+                passed = EXCLUDED.passed,
+                evidence = EXCLUDED.evidence,
+                remediation = EXCLUDED.remediation,
+                checked_at = EXCLUDED.checked_at
+            "#,
+            device_id,
+            check.id,
+            check.title,
+            serialized_name(check.severity),
+            check.passed,
+            check.evidence,
+            check.remediation,
+        )
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Replaces the device's drift with what it just reported, and notes in the
+/// ledger any file that drifted since the last report.
+pub async fn save_integrity(
+    device_id: i32,
+    changes: &[IntegrityChange],
+    tx: &mut Transaction<'_, Postgres>,
+) -> anyhow::Result<()> {
+    let known: HashMap<String, Option<FileMetadata>> = sqlx::query!(
+        r#"SELECT path, new as "new: SqlxJson<FileMetadata>" FROM device_integrity_drift WHERE device_id = $1"#,
+        device_id
+    )
+    .fetch_all(&mut **tx)
+    .await?
+    .into_iter()
+    .map(|row| (row.path, row.new.map(|new| new.0)))
+    .collect();
+    let (resets, fresh): (Vec<&IntegrityChange>, Vec<&IntegrityChange>) = changes
+        .iter()
+        .filter(|change| known.get(&change.path) != Some(&change.new))
+        .partition(|change| change.kind == IntegrityChangeKind::BaselineReset);
+
+    let paths: Vec<String> = changes.iter().map(|change| change.path.clone()).collect();
+    sqlx::query!(
+        "DELETE FROM device_integrity_drift WHERE device_id = $1 AND NOT (path = ANY($2))",
+        device_id,
+        &paths
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    for change in changes {
+        sqlx::query!(
+            r#"
+            INSERT INTO device_integrity_drift (device_id, path, kind, old, new, detected_at)
+            VALUES ($1, $2, $3, $4, $5, NOW())
+            ON CONFLICT (device_id, path) DO UPDATE SET
+                kind = EXCLUDED.kind,
+                old = EXCLUDED.old,
+                new = EXCLUDED.new,
+                detected_at = CASE
+                    WHEN device_integrity_drift.new IS DISTINCT FROM EXCLUDED.new
+                    THEN EXCLUDED.detected_at
+                    ELSE device_integrity_drift.detected_at
+                END
+            "#,
+            device_id,
+            change.path,
+            serialized_name(change.kind),
+            change.old.as_ref().map(SqlxJson) as _,
+            change.new.as_ref().map(SqlxJson) as _,
+        )
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    for reset in resets {
+        sqlx::query!(
+            r#"INSERT INTO ledger (device_id, "class", "text") VALUES ($1, $2, $3)"#,
+            device_id,
+            "integrity",
+            format!(
+                "Integrity baseline {} was lost and taken again; changes made before then went unseen.",
+                reset.path
+            )
+        )
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    if !fresh.is_empty() {
+        sqlx::query!(
+            r#"INSERT INTO ledger (device_id, "class", "text") VALUES ($1, $2, $3)"#,
+            device_id,
+            "integrity",
+            drift_summary(&fresh)
+        )
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smith::utils::schema::AuditSeverity;
+
+    #[test]
+    fn severity_is_stored_as_serialized() {
+        let check = AuditCheckResult {
+            id: "firewall_active".into(),
+            title: "Firewall active".into(),
+            severity: AuditSeverity::Critical,
+            passed: Some(false),
+            evidence: String::new(),
+            remediation: String::new(),
+        };
+        assert_eq!(serialized_name(check.severity), "critical");
+    }
+
+    #[test]
+    fn ledger_names_the_first_drifted_files() {
+        let changes: Vec<IntegrityChange> = (0..7)
+            .map(|i| IntegrityChange {
+                path: format!("/etc/sudoers.d/{i}"),
+                kind: IntegrityChangeKind::Added,
+                old: None,
+                new: None,
+            })
+            .collect();
+        let summary = drift_summary(&changes.iter().collect::<Vec<_>>());
+        assert!(
+            summary
+                .starts_with("Monitored files drifted from the baseline: /etc/sudoers.d/0 added, ")
+        );
+        assert!(summary.ends_with("/etc/sudoers.d/4 added, 2 more."));
+    }
+
+    #[test]
+    fn compliance_without_checks_is_unknown() {
+        assert_eq!(percent(3, 4), Some(75.0));
+        assert_eq!(percent(0, 0), None);
+    }
+}
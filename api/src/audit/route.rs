@@ -0,0 +1,291 @@
+use crate::State;
+use crate::audit::{DeviceAuditCheck, IntegrityDrift, LabelCompliance, percent};
+use crate::home::add_commands;
+use crate::middlewares::authorization;
+use crate::user::CurrentUser;
+use axum::extract::{Path, Query};
+use axum::http::StatusCode;
+use axum::{Extension, Json};
+use serde::Deserialize;
+use smith::utils::schema::{FileMetadata, IntegrityChange, SafeCommandRequest, SafeCommandTx};
+use sqlx::types::Json as SqlxJson;
+use tracing::error;
+use utoipa::IntoParams;
+
+const TAG: &str = "audit";
+
+#[utoipa::path(
+    get,
+    path = "/devices/{device_id}/audit/checks",
+    params(
+        ("device_id" = i32, Path),
+    ),
+    responses(
+        (status = StatusCode::OK, description = "The compliance checks the device last reported", body = Vec<DeviceAuditCheck>),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to read devices"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve audit checks"),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn get_audit_checks_for_device(
+    Path(device_id): Path<i32>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Result<Json<Vec<DeviceAuditCheck>>, StatusCode> {
+    if !authorization::check(current_user, "devices", "read") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let checks = sqlx::query_as!(
+        DeviceAuditCheck,
+        r#"
+        SELECT check_id, title, severity, passed, evidence, remediation, checked_at
+        FROM device_audit_check
+        WHERE device_id = $1
+        ORDER BY passed NULLS FIRST, check_id
+        "#,
+        device_id
+    )
+    .fetch_all(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("Failed to get audit checks for device {device_id}: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(checks))
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ComplianceQuery {
+    /// The label to group devices by, e.g. `department`.
+    pub label: String,
+    /// Only count this check, e.g. `firewall_active`.
+    pub check: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/audit/compliance",
+    params(ComplianceQuery),
+    responses(
+        (status = StatusCode::OK, description = "Audit compliance per value of the label", body = Vec<LabelCompliance>),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to read devices"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to compute compliance"),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn get_compliance_by_label(
+    Query(query): Query<ComplianceQuery>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Result<Json<Vec<LabelCompliance>>, StatusCode> {
+    if !authorization::check(current_user, "devices", "read") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let rows = sqlx::query!(
+        r#"
+        WITH per_device AS (
+            SELECT
+                dl.value,
+                dac.device_id,
+                COUNT(*) FILTER (WHERE dac.passed IS NOT NULL) AS checks,
+                COUNT(*) FILTER (WHERE dac.passed) AS passed_checks,
+                BOOL_AND(dac.passed IS NOT FALSE) AS compliant
+            FROM device_label dl
+            JOIN label l ON l.id = dl.label_id
+            JOIN device_audit_check dac ON dac.device_id = dl.device_id
+            WHERE l.name = $1 AND ($2::TEXT IS NULL OR dac.check_id = $2)
+            GROUP BY dl.value, dac.device_id
+        )
+        SELECT
+            value,
+            COUNT(*) AS "devices!",
+            COUNT(*) FILTER (WHERE compliant) AS "compliant_devices!",
+            COALESCE(SUM(checks), 0)::BIGINT AS "checks!",
+            COALESCE(SUM(passed_checks), 0)::BIGINT AS "passed_checks!"
+        FROM per_device
+        GROUP BY value
+        ORDER BY value
+        "#,
+        query.label,
+        query.check,
+    )
+    .fetch_all(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("Failed to compute compliance by {}: {err}", query.label);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(
+        rows.into_iter()
+            .map(|row| LabelCompliance {
+                compliance_percent: percent(row.passed_checks, row.checks),
+                value: row.value,
+                devices: row.devices,
+                compliant_devices: row.compliant_devices,
+                checks: row.checks,
+                passed_checks: row.passed_checks,
+            })
+            .collect(),
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/devices/{device_id}/integrity",
+    params(
+        ("device_id" = i32, Path),
+    ),
+    responses(
+        (status = StatusCode::OK, description = "Monitored files that differ from the device's baseline", body = Vec<IntegrityDrift>),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to read devices"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve integrity drift"),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn get_integrity_drift_for_device(
+    Path(device_id): Path<i32>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Result<Json<Vec<IntegrityDrift>>, StatusCode> {
+    if !authorization::check(current_user, "devices", "read") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let drift = sqlx::query_as!(
+        IntegrityDrift,
+        r#"
+        SELECT path, kind,
+            old as "old: SqlxJson<FileMetadata>",
+            new as "new: SqlxJson<FileMetadata>",
+            detected_at
+        FROM device_integrity_drift
+        WHERE device_id = $1
+        ORDER BY path
+        "#,
+        device_id
+    )
+    .fetch_all(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("Failed to get integrity drift for device {device_id}: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(drift))
+}
+
+#[utoipa::path(
+    post,
+    path = "/devices/{device_id}/integrity/accept",
+    params(
+        ("device_id" = i32, Path),
+    ),
+    responses(
+        (status = StatusCode::ACCEPTED, description = "The device was told to take the drifted files into its baseline"),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to accept drift"),
+        (status = StatusCode::NOT_FOUND, description = "Device not found"),
+        (status = StatusCode::CONFLICT, description = "The device reported no drift"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to queue the command"),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn accept_integrity_baseline(
+    Path(device_id): Path<i32>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Result<StatusCode, StatusCode> {
+    // Accepting drift hides tampering from later audits.
+    if !authorization::check(current_user.clone(), "commands", "freeform") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let serial_number =
+        sqlx::query_scalar!("SELECT serial_number FROM device WHERE id = $1", device_id)
+            .fetch_optional(&state.pg_pool)
+            .await
+            .map_err(|err| {
+                error!("Failed to get device {device_id}: {err}");
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?
+            .ok_or(StatusCode::NOT_FOUND)?;
+
+    let changes = sqlx::query!(
+        r#"
+        SELECT path, kind,
+            old as "old: SqlxJson<FileMetadata>",
+            new as "new: SqlxJson<FileMetadata>"
+        FROM device_integrity_drift
+        WHERE device_id = $1
+        ORDER BY path
+        "#,
+        device_id
+    )
+    .fetch_all(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("Failed to get integrity drift for device {device_id}: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?
+    .into_iter()
+    .filter_map(|row| {
+        Some(IntegrityChange {
+            kind: serde_json::from_value(serde_json::Value::String(row.kind)).ok()?,
+            path: row.path,
+            old: row.old.map(|old| old.0),
+            new: row.new.map(|new| new.0),
+        })
+    })
+    .collect::<Vec<_>>();
+    if changes.is_empty() {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let count = changes.len();
+    add_commands(
+        &serial_number,
+        vec![SafeCommandRequest {
+            id: 0,
+            command: SafeCommandTx::AcceptIntegrityBaseline { changes },
+            continue_on_error: false,
+        }],
+        &state.pg_pool,
+        Some(current_user.user_id),
+    )
+    .await
+    .map_err(|err| {
+        error!("Failed to queue integrity baseline for device {device_id}: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    sqlx::query!(
+        r#"INSERT INTO ledger (device_id, "class", "text") VALUES ($1, $2, $3)"#,
+        device_id,
+        "integrity",
+        format!("{count} drifted file(s) accepted as the new baseline.")
+    )
+    .execute(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("Failed to insert ledger entry for device {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(StatusCode::ACCEPTED)
+}
@@ -0,0 +1,74 @@
+use smith::utils::schema::Challenge;
+use sqlx::{PgExecutor, PgPool};
+use std::time::Duration;
+use tracing::error;
+use uuid::Uuid;
+
+/// How long a device has to sign and return a challenge.
+const CHALLENGE_TTL_SECS: i32 = 60;
+
+/// Challenges that may be outstanding at once. Issuing is unauthenticated, so
+/// this bounds the table; a fleet refreshing its sessions stays far below it.
+const MAX_OUTSTANDING: i64 = 10_000;
+
+/// Interval between passes deleting expired challenges.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Issues a single-use challenge for a device to sign with its identity key.
+/// Returns `None` while too many challenges are outstanding.
+pub async fn issue(pool: &PgPool) -> sqlx::Result<Option<Challenge>> {
+    let challenge = Uuid::new_v4().simple().to_string();
+
+    let issued = sqlx::query!(
+        r#"
+        INSERT INTO device_challenge (challenge, expires_at)
+        SELECT $1, NOW() + make_interval(secs => $2)
+        WHERE (SELECT COUNT(*) FROM device_challenge WHERE expires_at > NOW()) < $3
+        "#,
+        challenge,
+        CHALLENGE_TTL_SECS as f64,
+        MAX_OUTSTANDING
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    if issued == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some(Challenge {
+        challenge,
+        expires_in: CHALLENGE_TTL_SECS as u64,
+    }))
+}
+
+/// Consumes `challenge`. Returns false if it was never issued, already used or
+/// expired, so each signature can be presented exactly once.
+pub async fn consume<'e>(executor: impl PgExecutor<'e>, challenge: &str) -> sqlx::Result<bool> {
+    let consumed = sqlx::query!(
+        "DELETE FROM device_challenge WHERE challenge = $1 AND expires_at > NOW()",
+        challenge
+    )
+    .execute(executor)
+    .await?
+    .rows_affected();
+
+    Ok(consumed > 0)
+}
+
+/// Deletes expired challenges, which are never consumed. Idempotent, so it is
+/// safe to run on every replica.
+pub fn spawn_sweeper(pool: PgPool) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            ticker.tick().await;
+            if let Err(err) = sqlx::query!("DELETE FROM device_challenge WHERE expires_at <= NOW()")
+                .execute(&pool)
+                .await
+            {
+                error!("Failed to sweep expired device challenges: {err}");
+            }
+        }
+    });
+}
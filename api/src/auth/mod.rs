@@ -1,3 +1,4 @@
+pub mod challenge;
 pub mod device_jwt;
 pub mod jwks;
 pub mod route;
@@ -1,11 +1,14 @@
 use crate::State;
+use crate::auth::challenge;
 use crate::handlers::AuthedDevice;
 use axum::http::StatusCode;
 use axum::http::header::CACHE_CONTROL;
 use axum::response::{IntoResponse, Response};
 use axum::{Extension, Json};
 use serde::{Deserialize, Serialize};
-use tracing::error;
+use smith::identity::{self, Purpose};
+use smith::utils::schema::{Challenge, IdentityProof, SessionProof};
+use tracing::{error, warn};
 
 const AUTH_TAG: &str = "auth";
 
@@ -100,6 +103,212 @@ pub async fn session(
     }))
 }
 
+#[utoipa::path(
+    post,
+    path = "/smith/challenge",
+    responses(
+        (status = StatusCode::OK, description = "Single-use challenge for the device identity key to sign"),
+        (status = StatusCode::TOO_MANY_REQUESTS, description = "Too many challenges are outstanding"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to issue challenge"),
+    ),
+    tag = AUTH_TAG
+)]
+pub async fn challenge(Extension(state): Extension<State>) -> Result<Json<Challenge>, StatusCode> {
+    let challenge = challenge::issue(&state.pg_pool)
+        .await
+        .map_err(|err| {
+            error!("Failed to issue device challenge: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or_else(|| {
+            warn!("Refusing a device challenge, too many are outstanding");
+            StatusCode::TOO_MANY_REQUESTS
+        })?;
+
+    Ok(Json(challenge))
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/session",
+    responses(
+        (status = StatusCode::OK, description = "Mint a short-lived device JWT by signing a challenge with the identity key", body = DeviceSessionResponse),
+        (status = StatusCode::UNAUTHORIZED, description = "Challenge or signature invalid, or device revoked"),
+        (status = StatusCode::CONFLICT, description = "Device has no identity key pinned yet"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to mint JWT"),
+    ),
+    tag = AUTH_TAG
+)]
+pub async fn session_with_proof(
+    Extension(state): Extension<State>,
+    Json(proof): Json<SessionProof>,
+) -> Result<Json<DeviceSessionResponse>, StatusCode> {
+    let consumed = challenge::consume(&state.pg_pool, &proof.challenge)
+        .await
+        .map_err(|err| {
+            error!("Failed to consume device challenge: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if !consumed {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    // A revoked device has its token cleared, which must end key-based
+    // sessions as well as opaque-token ones.
+    let device = sqlx::query!(
+        r#"
+        SELECT id, serial_number, identity_public_key, approved
+        FROM device
+        WHERE serial_number = $1 AND token IS NOT NULL
+        "#,
+        proof.serial_number
+    )
+    .fetch_optional(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("Failed to get device {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?
+    .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    if !device.approved {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let public_key = device.identity_public_key.ok_or(StatusCode::CONFLICT)?;
+
+    if let Err(err) = identity::verify(
+        &public_key,
+        Purpose::Session,
+        &device.serial_number,
+        &proof.challenge,
+        &proof.signature,
+    ) {
+        warn!(
+            "Session proof for device {} rejected: {err}",
+            device.serial_number
+        );
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let token = state
+        .device_jwt_signer
+        .mint(device.id, &device.serial_number)
+        .map_err(|err| {
+            error!(
+                "Failed to mint device JWT for device {}: {err:?}",
+                device.id
+            );
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(DeviceSessionResponse {
+        token,
+        expires_in: state.device_jwt_signer.ttl_seconds(),
+        token_type: "Bearer",
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/smith/identity",
+    responses(
+        (status = StatusCode::NO_CONTENT, description = "Identity key pinned, or already pinned"),
+        (status = StatusCode::UNAUTHORIZED, description = "Challenge or signature invalid"),
+        (status = StatusCode::CONFLICT, description = "A different identity key is already pinned"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to pin identity key"),
+    ),
+    security(
+        ("device_token" = [])
+    ),
+    tag = AUTH_TAG
+)]
+/// Lets a device registered before identity keys existed pin one, authenticated
+/// by the token it already holds. Registration pins the key for everyone else.
+pub async fn enroll_identity(
+    Extension(state): Extension<State>,
+    device: AuthedDevice,
+    Json(proof): Json<IdentityProof>,
+) -> Result<StatusCode, StatusCode> {
+    let mut tx = state.pg_pool.begin().await.map_err(|err| {
+        error!("Failed to start transaction {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let consumed = challenge::consume(&mut *tx, &proof.challenge)
+        .await
+        .map_err(|err| {
+            error!("Failed to consume device challenge: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if !consumed {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    if let Err(err) = identity::verify(
+        &proof.public_key,
+        Purpose::Register,
+        &device.serial_number,
+        &proof.challenge,
+        &proof.signature,
+    ) {
+        warn!(
+            "Identity enrolment for device {} rejected: {err}",
+            device.serial_number
+        );
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let pinned = sqlx::query_scalar!(
+        "SELECT identity_public_key FROM device WHERE id = $1 FOR UPDATE",
+        device.id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|err| {
+        error!("Failed to get device {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    match pinned {
+        Some(pinned) if pinned == proof.public_key => return Ok(StatusCode::NO_CONTENT),
+        Some(_) => return Err(StatusCode::CONFLICT),
+        None => {}
+    }
+
+    sqlx::query!(
+        "UPDATE device SET identity_public_key = $2 WHERE id = $1",
+        device.id,
+        proof.public_key
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|err| {
+        error!("Failed to pin identity key for device {}: {err}", device.id);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    sqlx::query!(
+        r#"INSERT INTO ledger (device_id, "class", "text") VALUES ($1, $2, $3)"#,
+        device.id,
+        "identity",
+        format!("Identity key {} pinned", proof.public_key)
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|err| {
+        error!("Failed to insert ledger entry for device {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    tx.commit().await.map_err(|err| {
+        error!("Failed to commit transaction {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
 /// `/.well-known/jwks.json` — public Ed25519 verification key for device JWTs.
 /// Consumed by the TS api (and any other verifier) for offline signature checks.
 pub async fn jwks_well_known(Extension(state): Extension<State>) -> Response {
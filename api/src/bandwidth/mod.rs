@@ -0,0 +1,190 @@
+use crate::home::add_commands;
+use serde::{Deserialize, Serialize};
+use smith::utils::schema::{BandwidthPolicy, SafeCommandRequest, SafeCommandTx};
+use sqlx::PgPool;
+use sqlx::types::Json as SqlxJson;
+use sqlx::types::chrono::{DateTime, Utc};
+use utoipa::ToSchema;
+
+pub mod route;
+
+/// Queued command id for `UpdateBandwidthPolicy`, matching the id smithd
+/// reports `BandwidthUsage` under.
+const UPDATE_BANDWIDTH_POLICY_CMD_ID: i32 = -8;
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BandwidthPolicyEntry {
+    pub id: i32,
+    pub label: String,
+    pub value: String,
+    #[schema(value_type = Object)]
+    pub policy: SqlxJson<BandwidthPolicy>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SetBandwidthPolicy {
+    /// Applies to every device carrying this label with this value.
+    pub label: String,
+    pub value: String,
+    #[schema(value_type = Object)]
+    pub policy: BandwidthPolicy,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DeviceBandwidthUsage {
+    /// `YYYY-MM`, device local time.
+    pub month: String,
+    pub ethernet_bytes: i64,
+    pub wifi_bytes: i64,
+    pub lte_bytes: i64,
+    pub other_bytes: i64,
+    pub link: String,
+    pub paused: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Rejects policies the daemon could not apply sensibly.
+pub fn validate(policy: &BandwidthPolicy) -> Result<(), String> {
+    let rates = std::iter::once(&policy.rates).chain(policy.schedules.iter().map(|s| &s.rates));
+    for rate in rates.flat_map(|r| [r.ethernet, r.wifi, r.lte]).flatten() {
+        if !rate.is_finite() || rate < 0.0 {
+            return Err(format!("{rate} is not a valid rate in MB/s"));
+        }
+    }
+
+    for schedule in &policy.schedules {
+        if schedule.start_hour > 23 || schedule.end_hour > 23 {
+            return Err(format!(
+                "schedule {}-{} is outside 0-23",
+                schedule.start_hour, schedule.end_hour
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// The policy a device should be applying, from its labels.
+pub async fn policy_for_device(
+    device_id: i32,
+    pool: &PgPool,
+) -> anyhow::Result<Option<BandwidthPolicy>> {
+    let policy = sqlx::query_scalar!(
+        r#"
+        SELECT bp.policy as "policy!: SqlxJson<BandwidthPolicy>"
+        FROM bandwidth_policy bp
+        JOIN device_label dl ON dl.label_id = bp.label_id AND dl.value = bp.value
+        WHERE dl.device_id = $1
+        ORDER BY bp.updated_at DESC, bp.id DESC
+        LIMIT 1
+        "#,
+        device_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(policy.map(|policy| policy.0))
+}
+
+/// Whether the device has a policy update it hasn't answered yet, so a
+/// device that is offline gets one command rather than one per report.
+pub async fn policy_update_pending(device_id: i32, pool: &PgPool) -> anyhow::Result<bool> {
+    let pending = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM command_queue cq
+            WHERE cq.device_id = $1
+              AND cq.canceled = false
+              AND cq.cmd::jsonb ? 'UpdateBandwidthPolicy'
+              AND NOT EXISTS (SELECT 1 FROM command_response cr WHERE cr.command_id = cq.id)
+        ) as "pending!"
+        "#,
+        device_id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(pending)
+}
+
+pub async fn queue_update_bandwidth_policy(
+    serial_number: &str,
+    policy: Option<BandwidthPolicy>,
+    pool: &PgPool,
+) -> anyhow::Result<()> {
+    add_commands(
+        serial_number,
+        vec![SafeCommandRequest {
+            id: UPDATE_BANDWIDTH_POLICY_CMD_ID,
+            command: SafeCommandTx::UpdateBandwidthPolicy { policy },
+            continue_on_error: false,
+        }],
+        pool,
+        None,
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Queues the now effective policy to every device carrying `label_id` with
+/// `value`. Returns how many devices were sent one.
+pub async fn queue_for_label(label_id: i32, value: &str, pool: &PgPool) -> anyhow::Result<usize> {
+    let devices = sqlx::query!(
+        r#"
+        SELECT d.id, d.serial_number
+        FROM device d
+        JOIN device_label dl ON dl.device_id = d.id
+        WHERE dl.label_id = $1 AND dl.value = $2 AND d.archived = false
+        "#,
+        label_id,
+        value
+    )
+    .fetch_all(pool)
+    .await?;
+
+    for device in &devices {
+        let policy = policy_for_device(device.id, pool).await?;
+        queue_update_bandwidth_policy(&device.serial_number, policy, pool).await?;
+    }
+
+    Ok(devices.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smith::utils::schema::{BandwidthSchedule, LinkRates};
+
+    #[test]
+    fn rejects_negative_rates_and_bad_hours() {
+        let mut policy = BandwidthPolicy {
+            rates: LinkRates {
+                lte: Some(0.5),
+                ..Default::default()
+            },
+            schedules: vec![BandwidthSchedule {
+                start_hour: 22,
+                end_hour: 6,
+                rates: LinkRates {
+                    wifi: Some(0.0),
+                    ..Default::default()
+                },
+            }],
+            lte_monthly_budget_bytes: Some(2_000_000_000),
+        };
+        assert!(validate(&policy).is_ok());
+
+        policy.schedules[0].end_hour = 24;
+        assert!(validate(&policy).is_err());
+
+        policy.schedules[0].end_hour = 6;
+        policy.schedules[0].rates.ethernet = Some(-1.0);
+        assert!(validate(&policy).is_err());
+
+        policy.schedules.clear();
+        policy.rates.wifi = Some(f64::NAN);
+        assert!(validate(&policy).is_err());
+    }
+}
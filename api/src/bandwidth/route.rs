@@ -0,0 +1,228 @@
+use crate::State;
+use crate::bandwidth::{
+    BandwidthPolicyEntry, DeviceBandwidthUsage, SetBandwidthPolicy, queue_for_label, validate,
+};
+use crate::middlewares::authorization;
+use crate::user::CurrentUser;
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::{Extension, Json};
+use smith::utils::schema::BandwidthPolicy;
+use sqlx::types::Json as SqlxJson;
+use tracing::{error, info, warn};
+
+const TAG: &str = "bandwidth";
+
+#[utoipa::path(
+    get,
+    path = "/bandwidth/policies",
+    responses(
+        (status = StatusCode::OK, description = "Bandwidth policies and the labels they apply to", body = Vec<BandwidthPolicyEntry>),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to read devices"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve bandwidth policies"),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn get_bandwidth_policies(
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Result<Json<Vec<BandwidthPolicyEntry>>, StatusCode> {
+    if !authorization::check(current_user, "devices", "read") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let policies = sqlx::query_as!(
+        BandwidthPolicyEntry,
+        r#"
+        SELECT bp.id, l.name as label, bp.value,
+            bp.policy as "policy: SqlxJson<BandwidthPolicy>", bp.updated_at
+        FROM bandwidth_policy bp
+        JOIN label l ON l.id = bp.label_id
+        ORDER BY l.name, bp.value
+        "#
+    )
+    .fetch_all(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("Failed to get bandwidth policies: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(policies))
+}
+
+#[utoipa::path(
+    put,
+    path = "/bandwidth/policies",
+    request_body = SetBandwidthPolicy,
+    responses(
+        (status = StatusCode::OK, description = "Policy saved and queued for the devices carrying the label", body = BandwidthPolicyEntry),
+        (status = StatusCode::BAD_REQUEST, description = "Invalid rate or schedule hours"),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to manage downloads"),
+        (status = StatusCode::NOT_FOUND, description = "Label not found"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to save bandwidth policy"),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn set_bandwidth_policy(
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(body): Json<SetBandwidthPolicy>,
+) -> Result<Json<BandwidthPolicyEntry>, StatusCode> {
+    if !authorization::check(current_user, "commands", "ota") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    if let Err(err) = validate(&body.policy) {
+        warn!(
+            "Rejected bandwidth policy for {}={}: {err}",
+            body.label, body.value
+        );
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let label_id = sqlx::query_scalar!("SELECT id FROM label WHERE name = $1", body.label)
+        .fetch_optional(&state.pg_pool)
+        .await
+        .map_err(|err| {
+            error!("Failed to fetch label {}: {err}", body.label);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let entry = sqlx::query_as!(
+        BandwidthPolicyEntry,
+        r#"
+        INSERT INTO bandwidth_policy (label_id, value, policy)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (label_id, value) DO UPDATE SET
+            policy = EXCLUDED.policy,
+            updated_at = NOW()
+        RETURNING id, $4::text as "label!", value,
+            policy as "policy: SqlxJson<BandwidthPolicy>", updated_at
+        "#,
+        label_id,
+        body.value,
+        SqlxJson(&body.policy) as _,
+        body.label
+    )
+    .fetch_one(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("Failed to save bandwidth policy: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let queued = queue_for_label(label_id, &body.value, &state.pg_pool)
+        .await
+        .map_err(|err| {
+            error!("Failed to queue bandwidth policy: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    info!(
+        "Bandwidth policy for {}={} queued to {queued} device(s)",
+        body.label, body.value
+    );
+
+    Ok(Json(entry))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/bandwidth/policies/{policy_id}",
+    params(
+        ("policy_id" = i32, Path),
+    ),
+    responses(
+        (status = StatusCode::NO_CONTENT, description = "Policy removed and the devices it applied to updated"),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to manage downloads"),
+        (status = StatusCode::NOT_FOUND, description = "Policy not found"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to delete bandwidth policy"),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn delete_bandwidth_policy(
+    Path(policy_id): Path<i32>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Result<StatusCode, StatusCode> {
+    if !authorization::check(current_user, "commands", "ota") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let deleted = sqlx::query!(
+        "DELETE FROM bandwidth_policy WHERE id = $1 RETURNING label_id, value",
+        policy_id
+    )
+    .fetch_optional(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("Failed to delete bandwidth policy {policy_id}: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    // Devices fall back to another matching policy, or to none at all.
+    queue_for_label(deleted.label_id, &deleted.value, &state.pg_pool)
+        .await
+        .map_err(|err| {
+            error!("Failed to queue bandwidth policy: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/devices/{device_id}/bandwidth",
+    params(
+        ("device_id" = i32, Path),
+    ),
+    responses(
+        (status = StatusCode::OK, description = "Data downloaded by the device per month, newest first", body = Vec<DeviceBandwidthUsage>),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to read devices"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve bandwidth usage"),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn get_bandwidth_usage_for_device(
+    Path(device_id): Path<i32>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Result<Json<Vec<DeviceBandwidthUsage>>, StatusCode> {
+    if !authorization::check(current_user, "devices", "read") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let usage = sqlx::query_as!(
+        DeviceBandwidthUsage,
+        r#"
+        SELECT month, ethernet_bytes, wifi_bytes, lte_bytes, other_bytes, link, paused, updated_at
+        FROM device_bandwidth_usage
+        WHERE device_id = $1
+        ORDER BY month DESC
+        "#,
+        device_id
+    )
+    .fetch_all(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("Failed to get bandwidth usage for device {device_id}: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(usage))
+}
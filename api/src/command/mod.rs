@@ -17,6 +17,10 @@ pub fn redact_cmd_data(mut cmd: serde_json::Value) -> serde_json::Value {
                 && let Some(obj) = creds.as_object_mut()
             {
                 obj.remove("psk");
+                if let Some(eap) = obj.get_mut("eap").and_then(|v| v.as_object_mut()) {
+                    eap.remove("password");
+                    eap.remove("private_key_password");
+                }
             }
         }
     }
@@ -119,11 +119,20 @@ pub async fn available_commands() -> Result<Json<Vec<SafeCommandTx>>, StatusCode
             tools: "ota_tools.tbz2".to_string(),
             payload: "ota_payload_package.tar.gz".to_string(),
             rate: 1.0,
+            version: Some("35.4.1".to_string()),
+            sha256: None,
         },
         SafeCommandTx::CheckOTAStatus,
         SafeCommandTx::StartOTA,
+        SafeCommandTx::ListDownloads { ids: Vec::new() },
+        SafeCommandTx::UpgradePlan { release_id: None },
         SafeCommandTx::TestNetwork,
         SafeCommandTx::RunAudit,
+        SafeCommandTx::SetModemApn {
+            apn: "internet".to_string(),
+        },
+        SafeCommandTx::SetModemEnabled { enabled: true },
+        SafeCommandTx::PowerCycleModem,
     ]))
 }
 
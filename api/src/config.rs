@@ -1,5 +1,6 @@
 use anyhow::Context;
 use axum::http::HeaderMap;
+use smith::utils::schema::DownloadMirror;
 use std::env;
 use std::time::Duration;
 use tracing::{info, warn};
@@ -98,6 +99,12 @@ pub struct Config {
     pub device_jwt_issuer: String,
     /// Lifetime of issued device JWTs.
     pub device_jwt_ttl_seconds: u64,
+    /// Extra places devices may download packages from, tried before the api.
+    /// JSON, e.g. `[{"name":"site-cache","url":"http://10.0.0.2:8080"}]`.
+    pub download_mirrors: Vec<DownloadMirror>,
+    /// Whether daemons that predate identity keys may still register. Turn it
+    /// off once every device has pinned a key.
+    pub allow_keyless_registration: bool,
 }
 
 impl Config {
@@ -134,6 +141,15 @@ impl Config {
                 .ok()
                 .and_then(|s| s.parse().ok())
                 .unwrap_or(3600),
+            download_mirrors: match env::var("DOWNLOAD_MIRRORS") {
+                Ok(mirrors) => serde_json::from_str(&mirrors)
+                    .context("DOWNLOAD_MIRRORS must be a JSON list of mirrors.")?,
+                Err(_) => Vec::new(),
+            },
+            allow_keyless_registration: env::var("ALLOW_KEYLESS_REGISTRATION")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(true),
         })
     }
 }
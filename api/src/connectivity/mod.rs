@@ -0,0 +1,155 @@
+use crate::serialized::serialized_name;
+use serde::Serialize;
+use smith::utils::schema::{ConnectivityReport, LinkQualityInterval};
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use utoipa::ToSchema;
+
+pub mod route;
+
+/// The upstream connectivity a device last reported.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DeviceConnectivity {
+    /// One of `full`, `portal`, `limited` and `none`.
+    pub state: String,
+    /// When the device entered `state`, by its own clock.
+    pub since: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A change of a device's upstream connectivity.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ConnectivityTransition {
+    pub changed_at: DateTime<Utc>,
+    pub state: String,
+}
+
+fn unix_time(secs: u64) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp(i64::try_from(secs).ok()?, 0)
+}
+
+/// Stores the device's current connectivity and the transitions it reported.
+/// Transitions the device re-sends after losing a response are skipped.
+pub async fn save_report(
+    device_id: i32,
+    report: &ConnectivityReport,
+    pool: &PgPool,
+) -> anyhow::Result<()> {
+    let mut tx = pool.begin().await?;
+
+    for transition in &report.transitions {
+        let Some(changed_at) = unix_time(transition.at) else {
+            continue;
+        };
+        sqlx::query!(
+            r#"
+            INSERT INTO device_connectivity_transition (device_id, changed_at, state)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            "#,
+            device_id,
+            changed_at,
+            serialized_name(transition.state),
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    sqlx::query!(
+        "DELETE FROM device_connectivity_transition WHERE device_id = $1 AND changed_at < NOW() - INTERVAL '30 days'",
+        device_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    if let Some(since) = unix_time(report.since) {
+        sqlx::query!(
+            r#"
+            INSERT INTO device_connectivity (device_id, state, since, updated_at)
+            VALUES ($1, $2, $3, NOW())
+            ON CONFLICT (device_id) DO UPDATE SET
+                state = EXCLUDED.state,
+                since = EXCLUDED.since,
+                updated_at = EXCLUDED.updated_at
+            "#,
+            device_id,
+            serialized_name(report.state),
+            since,
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Stores the intervals of latency probing a device reported.
+pub async fn save_link_quality(
+    device_id: i32,
+    intervals: &[LinkQualityInterval],
+    tx: &mut Transaction<'_, Postgres>,
+) -> anyhow::Result<()> {
+    for interval in intervals {
+        let (Some(started_at), Some(ended_at)) =
+            (unix_time(interval.start), unix_time(interval.end))
+        else {
+            continue;
+        };
+        let api = interval.api.clone().unwrap_or_default();
+        let gateway = interval.gateway.clone().unwrap_or_default();
+        sqlx::query!(
+            r#"
+            INSERT INTO device_link_quality
+                (device_id, started_at, ended_at, bssid, signal,
+                 api_sent, api_received, api_rtt_p50_ms, api_rtt_p90_ms, api_rtt_p99_ms, api_jitter_ms,
+                 gateway_sent, gateway_received, gateway_rtt_p50_ms, gateway_rtt_p90_ms,
+                 gateway_rtt_p99_ms, gateway_jitter_ms)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17)
+            ON CONFLICT (device_id, started_at) DO NOTHING
+            "#,
+            device_id,
+            started_at,
+            ended_at,
+            interval.bssid,
+            interval.signal.map(i16::from),
+            interval.api.as_ref().map(|api| api.sent as i32),
+            interval.api.as_ref().map(|api| api.received as i32),
+            api.rtt_p50_ms,
+            api.rtt_p90_ms,
+            api.rtt_p99_ms,
+            api.jitter_ms,
+            interval.gateway.as_ref().map(|gateway| gateway.sent as i32),
+            interval.gateway.as_ref().map(|gateway| gateway.received as i32),
+            gateway.rtt_p50_ms,
+            gateway.rtt_p90_ms,
+            gateway.rtt_p99_ms,
+            gateway.jitter_ms,
+        )
+        .execute(&mut **tx)
+        .await?;
+    }
+    sqlx::query!(
+        "DELETE FROM device_link_quality WHERE device_id = $1 AND started_at < NOW() - INTERVAL '7 days'",
+        device_id
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smith::utils::schema::ConnectivityState;
+
+    #[test]
+    fn states_are_stored_as_serialized() {
+        assert_eq!(serialized_name(ConnectivityState::Portal), "portal");
+        assert_eq!(serialized_name(ConnectivityState::None), "none");
+        assert_eq!(
+            unix_time(1_760_000_000).unwrap().to_rfc3339(),
+            "2025-10-09T08:53:20+00:00"
+        );
+        assert!(unix_time(u64::MAX).is_none());
+    }
+}
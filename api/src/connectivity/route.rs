@@ -0,0 +1,161 @@
+use crate::State;
+use crate::connectivity::{ConnectivityTransition, DeviceConnectivity};
+use crate::middlewares::authorization;
+use crate::user::CurrentUser;
+use axum::extract::Query;
+use axum::{Extension, Json, extract::Path};
+use axum::{http::StatusCode, response::Result};
+use models::device::LinkQuality;
+use serde::Deserialize;
+use tracing::error;
+use utoipa::IntoParams;
+
+const TAG: &str = "devices";
+
+#[utoipa::path(
+    get,
+    path = "/devices/{device_id}/connectivity",
+    params(
+        ("device_id" = i32, Path),
+    ),
+    responses(
+        (status = StatusCode::OK, description = "The upstream connectivity the device last reported", body = DeviceConnectivity),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to read devices"),
+        (status = StatusCode::NOT_FOUND, description = "The device has not reported its connectivity"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve the connectivity"),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn get_connectivity_for_device(
+    Path(device_id): Path<i32>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Result<Json<DeviceConnectivity>, StatusCode> {
+    if !authorization::check(current_user, "devices", "read") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let connectivity = sqlx::query_as!(
+        DeviceConnectivity,
+        "SELECT state, since, updated_at FROM device_connectivity WHERE device_id = $1",
+        device_id
+    )
+    .fetch_optional(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("Failed to get connectivity for device {device_id}: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    connectivity.map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct HistoryQuery {
+    /// How far back to go, 7 days by default. Transitions are kept for 30
+    /// days, latency intervals for 7.
+    pub hours: Option<i32>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/devices/{device_id}/connectivity/history",
+    params(
+        ("device_id" = i32, Path),
+        HistoryQuery,
+    ),
+    responses(
+        (status = StatusCode::OK, description = "The device's connectivity transitions, oldest first", body = Vec<ConnectivityTransition>),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to read devices"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve the connectivity history"),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn get_connectivity_history_for_device(
+    Path(device_id): Path<i32>,
+    Query(query): Query<HistoryQuery>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Result<Json<Vec<ConnectivityTransition>>, StatusCode> {
+    if !authorization::check(current_user, "devices", "read") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let history = sqlx::query_as!(
+        ConnectivityTransition,
+        r#"
+        SELECT changed_at, state
+        FROM device_connectivity_transition
+        WHERE device_id = $1 AND changed_at >= NOW() - make_interval(hours => $2)
+        ORDER BY changed_at
+        "#,
+        device_id,
+        query.hours.unwrap_or(24 * 7)
+    )
+    .fetch_all(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("Failed to get connectivity history for device {device_id}: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(history))
+}
+
+#[utoipa::path(
+    get,
+    path = "/devices/{device_id}/link-quality",
+    params(
+        ("device_id" = i32, Path),
+        HistoryQuery,
+    ),
+    responses(
+        (status = StatusCode::OK, description = "The device's latency probing intervals, oldest first", body = Vec<LinkQuality>),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to read devices"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve the link quality"),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn get_link_quality_for_device(
+    Path(device_id): Path<i32>,
+    Query(query): Query<HistoryQuery>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Result<Json<Vec<LinkQuality>>, StatusCode> {
+    if !authorization::check(current_user, "devices", "read") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let intervals = sqlx::query_as!(
+        LinkQuality,
+        r#"
+        SELECT started_at, ended_at, bssid, signal,
+               api_sent, api_received, api_rtt_p50_ms, api_rtt_p90_ms, api_rtt_p99_ms,
+               api_jitter_ms, gateway_sent, gateway_received, gateway_rtt_p50_ms,
+               gateway_rtt_p90_ms, gateway_rtt_p99_ms, gateway_jitter_ms
+        FROM device_link_quality
+        WHERE device_id = $1 AND started_at >= NOW() - make_interval(hours => $2)
+        ORDER BY started_at
+        "#,
+        device_id,
+        query.hours.unwrap_or(24 * 7)
+    )
+    .fetch_all(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("Failed to get link quality for device {device_id}: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(intervals))
+}
@@ -3,7 +3,8 @@ use crate::slack::send_slack_notification;
 use models::release::Release;
 use serde::{Deserialize, Serialize, Serializer};
 use serde_json::{Value, json};
-use smith::utils::schema::{DeviceRegistration, DeviceRegistrationResponse};
+use smith::identity::{self, Purpose};
+use smith::utils::schema::{DeviceRegistration, DeviceRegistrationResponse, IpConfig};
 use sqlx::PgPool;
 use sqlx::types::chrono::{DateTime, Utc};
 use sqlx::types::{Json as SqlxJson, chrono, ipnetwork};
@@ -239,6 +240,49 @@ pub struct PatchIntentRequest {
     pub managed_by: Option<String>,
 }
 
+/// A wired profile of the device's intent: plain Ethernet, or a VLAN on top
+/// of `interface` when `vlan_id` is set.
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct DeviceEthernetIntent {
+    pub id: i32,
+    pub device_id: i32,
+    pub profile_name: String,
+    pub interface: Option<String>,
+    pub vlan_id: Option<i32>,
+    /// `method` (`auto`, `manual` or `disabled`), CIDR `addresses` and `gateway`.
+    #[schema(value_type = Object)]
+    pub ipv4: SqlxJson<IpConfig>,
+    #[schema(value_type = Object)]
+    pub ipv6: SqlxJson<IpConfig>,
+    pub dns: Vec<String>,
+    pub priority: i32,
+    pub managed_by: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, utoipa::ToSchema)]
+pub struct EthernetIntentRequest {
+    pub profile_name: String,
+    pub interface: Option<String>,
+    pub vlan_id: Option<u16>,
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    pub ipv4: IpConfig,
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    pub ipv6: IpConfig,
+    #[serde(default)]
+    pub dns: Vec<String>,
+}
+
+/// Replaces the wired profiles of the intent, highest priority first.
+#[derive(Debug, Deserialize, utoipa::ToSchema)]
+pub struct PutEthernetIntentRequest {
+    pub profiles: Vec<EthernetIntentRequest>,
+    pub managed_by: String,
+}
+
 #[derive(Debug, Serialize, utoipa::ToSchema)]
 pub struct ApplyIntentResponse {
     pub bundle_uuid: Uuid,
@@ -476,6 +520,74 @@ pub async fn register_device(
         }
     }
 
+    let pinned_key = sqlx::query_scalar!(
+        "SELECT identity_public_key FROM device WHERE id = $1",
+        result.id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    match (pinned_key, payload.identity) {
+        (pinned, Some(proof)) => {
+            if !crate::auth::challenge::consume(&mut *tx, &proof.challenge).await? {
+                warn!("Registration for {serial_sanitized} used an unknown or expired challenge");
+                return Err(RegistrationError::InvalidIdentity);
+            }
+            if let Err(err) = identity::verify(
+                &proof.public_key,
+                Purpose::Register,
+                serial_sanitized,
+                &proof.challenge,
+                &proof.signature,
+            ) {
+                warn!("Registration for {serial_sanitized} failed identity check: {err}");
+                return Err(RegistrationError::InvalidIdentity);
+            }
+
+            match pinned {
+                Some(pinned) if pinned == proof.public_key => {}
+                Some(_) => {
+                    warn!("Registration for {serial_sanitized} presented a different identity key");
+                    return Err(RegistrationError::InvalidIdentity);
+                }
+                None => {
+                    // Trust on first use: the first key a serial registers with
+                    // is the one an operator approves.
+                    sqlx::query!(
+                        "UPDATE device SET identity_public_key = $2 WHERE id = $1",
+                        result.id,
+                        proof.public_key
+                    )
+                    .execute(&mut *tx)
+                    .await?;
+
+                    sqlx::query!(
+                        "INSERT INTO ledger (device_id, class, text) VALUES ($1, $2, $3);",
+                        result.id,
+                        "identity",
+                        format!("Identity key {} pinned", proof.public_key)
+                    )
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|err| {
+                        error!("Failed to log identity key to ledger {err}");
+                        RegistrationError::FailedToLogInLedger
+                    })?;
+                }
+            }
+        }
+        (Some(_), None) => {
+            warn!("Registration for {serial_sanitized} without the identity key it pinned");
+            return Err(RegistrationError::InvalidIdentity);
+        }
+        // Daemons that predate identity keys, until the fleet has pinned one.
+        (None, None) if config.allow_keyless_registration => {}
+        (None, None) => {
+            warn!("Registration for {serial_sanitized} without an identity key");
+            return Err(RegistrationError::InvalidIdentity);
+        }
+    }
+
     if result.approved == Some(true) {
         match result.token {
             Some(_) => {
@@ -933,4 +1045,7 @@ pub enum RegistrationError {
     NotApprovedDevice,
     #[error("Failed to log in ledger")]
     FailedToLogInLedger,
+    /// Missing, unverifiable or different from the key pinned for this serial.
+    #[error("Device identity could not be verified")]
+    InvalidIdentity,
 }
@@ -2,14 +2,16 @@ use crate::State;
 use crate::device::debug_ap;
 use crate::device::{
     ApplyIntentResponse, ApproveDeviceBody, ConfiguredNetwork, CreateIntentRequest,
-    DebugApCredentials, DeviceHealth, DeviceLedgerItem, DeviceLedgerItemPaginated,
-    DeviceNetworkIntent, DeviceRelease, DeviceUptime, LabelWithValues, NewVariable, Note,
-    PatchIntentRequest, RawDevice, SMITHD_SERVICE_NAME, ServiceOutage, UpdateDeviceRelease,
+    DebugApCredentials, DeviceEthernetIntent, DeviceHealth, DeviceLedgerItem,
+    DeviceLedgerItemPaginated, DeviceNetworkIntent, DeviceRelease, DeviceUptime,
+    EthernetIntentRequest, LabelWithValues, NewVariable, Note, PatchIntentRequest,
+    PutEthernetIntentRequest, RawDevice, SMITHD_SERVICE_NAME, ServiceOutage, UpdateDeviceRelease,
     UpdateDevicesRelease, Variable, WifiScanResult,
 };
 use crate::event::PublicEvent;
 use crate::handlers::AuthedDevice;
 use crate::middlewares::authorization;
+use crate::network::EapSecret;
 use crate::release::get_release_by_id;
 use crate::slack::send_slack_notification;
 use crate::user::CurrentUser;
@@ -21,12 +23,13 @@ use axum_extra::extract::Query;
 use chrono::Duration;
 use models::device::{
     CommandsPaginated, Device, DeviceCommandResponse, DeviceFilter, DeviceNetwork,
-    DeviceSortDirection, DeviceSortField,
+    DeviceSortDirection, DeviceSortField, LinkQuality,
 };
 use models::modem::Modem;
 use models::release::Release;
 use serde::Deserialize;
 use serde_json::json;
+use smith::secrets;
 use smith::utils::schema;
 use smith::utils::schema::SafeCommandRequest;
 use sqlx::types::Json as SqlxJson;
@@ -149,6 +152,9 @@ pub async fn get_devices(
     let sort_labels_desc = matches!(filter.sort, Some(DeviceSortField::Labels))
         && matches!(filter.order, Some(DeviceSortDirection::Desc));
     let search_terms = parse_search_terms(filter.search.as_deref().unwrap_or(""));
+    // sysfs prints the ids in lowercase hex.
+    let usb_ids: Vec<String> = filter.usb.iter().map(|id| id.to_lowercase()).collect();
+    let pci_ids: Vec<String> = filter.pci.iter().map(|id| id.to_lowercase()).collect();
 
     #[allow(deprecated)]
     let devices = sqlx::query!(
@@ -213,6 +219,8 @@ pub async fn get_devices(
             dn.upload_speed_mbps as "network_upload_speed_mbps?",
             dn.source as "network_source?",
             dn.updated_at as "network_updated_at?",
+            (SELECT to_jsonb(lq) FROM device_link_quality lq WHERE lq.device_id = d.id
+             ORDER BY lq.started_at DESC LIMIT 1) as "link_quality?: SqlxJson<LinkQuality>",
             d.intent_version,
             d.observed_intent_version,
             d.network_conditions,
@@ -265,6 +273,19 @@ pub async fn get_devices(
                      AND rs.watchdog_sec IS NOT NULL
                      AND dss.active_state != 'active'
                )))
+          AND (($19::text IS NULL AND $20::bigint IS NULL AND $21::text IS NULL
+                AND CARDINALITY($22::text[]) = 0 AND CARDINALITY($23::text[]) = 0
+                AND $24::boolean IS NULL)
+               OR EXISTS (
+                   SELECT 1 FROM device_hardware h
+                   WHERE h.device_id = d.id
+                     AND ($19 IS NULL OR POSITION(LOWER($19) IN LOWER(COALESCE(h.cpu_model, ''))) > 0)
+                     AND ($20 IS NULL OR h.memory_bytes >= $20 * 1024 * 1024)
+                     AND ($21 IS NULL OR $21 = ANY(h.storage_kinds))
+                     AND h.usb_ids @> $22
+                     AND h.pci_ids @> $23
+                     AND ($24 IS NULL OR (CARDINALITY(h.connected_displays) > 0) = $24)
+               ))
         GROUP BY d.id, ip.id, m.id, r.id, rd.id, tr.id, trd.id, dn.device_id
         ORDER BY
             CASE WHEN $15 THEN d.serial_number END ASC NULLS LAST,
@@ -308,6 +329,12 @@ pub async fn get_devices(
         sort_serial_desc,
         sort_labels_asc,
         sort_labels_desc,
+        filter.cpu_model,
+        filter.min_memory_mb,
+        filter.storage,
+        usb_ids.as_slice(),
+        pci_ids.as_slice(),
+        filter.display_connected,
     )
     .fetch_all(&state.pg_pool)
     .await
@@ -402,13 +429,14 @@ pub async fn get_devices(
                 None
             };
 
-            let network = if row.network_score.is_some() {
+            let network = if row.network_score.is_some() || row.link_quality.is_some() {
                 Some(DeviceNetwork {
                     network_score: row.network_score,
                     download_speed_mbps: row.network_download_speed_mbps,
                     upload_speed_mbps: row.network_upload_speed_mbps,
                     source: row.network_source,
                     updated_at: row.network_updated_at,
+                    link_quality: row.link_quality.map(|link_quality| link_quality.0),
                 })
             } else {
                 None
@@ -1888,6 +1916,8 @@ pub async fn get_device_info(
         dn.upload_speed_mbps as "network_upload_speed_mbps?",
         dn.source as "network_source?",
         dn.updated_at as "network_updated_at?",
+        (SELECT to_jsonb(lq) FROM device_link_quality lq WHERE lq.device_id = d.id
+         ORDER BY lq.started_at DESC LIMIT 1) as "link_quality?: SqlxJson<LinkQuality>",
         d.intent_version,
         d.observed_intent_version,
         d.network_conditions,
@@ -2002,13 +2032,14 @@ pub async fn get_device_info(
         None
     };
 
-    let network = if device_row.network_score.is_some() {
+    let network = if device_row.network_score.is_some() || device_row.link_quality.is_some() {
         Some(DeviceNetwork {
             network_score: device_row.network_score,
             download_speed_mbps: device_row.network_download_speed_mbps,
             upload_speed_mbps: device_row.network_upload_speed_mbps,
             source: device_row.network_source,
             updated_at: device_row.network_updated_at,
+            link_quality: device_row.link_quality.map(|link_quality| link_quality.0),
         })
     } else {
         None
@@ -2080,6 +2111,8 @@ pub async fn delete_device(
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
 
+    remove_vpn_peer(device_id, &mut tx).await?;
+
     tx.commit().await.map_err(|err| {
         error!("Failed to commit transaction {err}");
         StatusCode::INTERNAL_SERVER_ERROR
@@ -2088,6 +2121,41 @@ pub async fn delete_device(
     Ok(StatusCode::NO_CONTENT)
 }
 
+/// Takes the device off its VPN, so the concentrator drops the peer on its
+/// next sync. The device itself is no longer trusted to remove the tunnel.
+async fn remove_vpn_peer(
+    device_id: i32,
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> Result<(), StatusCode> {
+    let removed = sqlx::query!(
+        "DELETE FROM device_vpn_peer WHERE device_id = $1",
+        device_id
+    )
+    .execute(&mut **tx)
+    .await
+    .map_err(|err| {
+        error!("Failed to remove VPN peer {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?
+    .rows_affected();
+
+    if removed > 0 {
+        sqlx::query!(
+            r#"INSERT INTO ledger (device_id, "class", "text") VALUES ($1, $2, $3)"#,
+            device_id,
+            "vpn",
+            "VPN peer removed."
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(|err| {
+            error!("Failed to insert ledger entry for device {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    }
+    Ok(())
+}
+
 #[derive(serde::Deserialize, utoipa::ToSchema)]
 pub struct UpdateDeviceRequest {
     pub labels: Option<HashMap<String, String>>,
@@ -2418,6 +2486,8 @@ pub async fn revoke_device(
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
 
+    remove_vpn_peer(device_id, &mut tx).await?;
+
     tx.commit().await.map_err(|err| {
         error!("Failed to commit transaction {err}");
         StatusCode::INTERNAL_SERVER_ERROR
@@ -3181,6 +3251,11 @@ pub async fn create_device_intent(
             WHERE dni.device_id = $1
               AND existing_n.name = new_n.name
               AND dni.network_id != $2
+        ) OR EXISTS (
+            SELECT 1
+            FROM device_ethernet_intent dei
+            JOIN network new_n ON new_n.id = $2
+            WHERE dei.device_id = $1 AND dei.profile_name = new_n.name
         ) as "exists!"
         "#,
         resolved_id,
@@ -3459,11 +3534,245 @@ pub async fn delete_device_intent(
     Ok(StatusCode::NO_CONTENT)
 }
 
+async fn fetch_ethernet_intent(
+    device_id: i32,
+    executor: impl sqlx::PgExecutor<'_>,
+) -> Result<Vec<DeviceEthernetIntent>, sqlx::Error> {
+    sqlx::query_as!(
+        DeviceEthernetIntent,
+        r#"
+        SELECT
+            id,
+            device_id,
+            profile_name,
+            interface,
+            vlan_id,
+            ipv4 as "ipv4: SqlxJson<schema::IpConfig>",
+            ipv6 as "ipv6: SqlxJson<schema::IpConfig>",
+            dns,
+            priority,
+            managed_by,
+            created_at,
+            updated_at
+        FROM device_ethernet_intent
+        WHERE device_id = $1
+        ORDER BY priority ASC
+        "#,
+        device_id
+    )
+    .fetch_all(executor)
+    .await
+}
+
+/// The wire shape of a wired intent row, validated the way smithd will.
+fn intent_ethernet(
+    profile: &EthernetIntentRequest,
+    priority: i32,
+) -> Result<schema::IntentEthernet, String> {
+    let intent = schema::IntentEthernet {
+        profile_name: profile.profile_name.clone(),
+        interface: profile.interface.clone(),
+        vlan_id: profile.vlan_id,
+        ipv4: profile.ipv4.clone(),
+        ipv6: profile.ipv6.clone(),
+        dns: profile.dns.clone(),
+        priority,
+    };
+    intent.validate()?;
+    Ok(intent)
+}
+
+#[utoipa::path(
+    get,
+    path = "/devices/{device_id}/intent/ethernet",
+    params(
+        ("device_id" = String, Path),
+    ),
+    responses(
+        (status = StatusCode::OK, description = "Wired profiles of the device's intent", body = Vec<DeviceEthernetIntent>),
+        (status = StatusCode::NOT_FOUND, description = "Device not found"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve device intent"),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = DEVICES_TAG
+)]
+pub async fn get_device_ethernet_intent(
+    Path(device_id): Path<String>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Result<Json<Vec<DeviceEthernetIntent>>, StatusCode> {
+    if !authorization::check(current_user, "devices", "read") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let resolved_id = resolve_device_id(&device_id, &state.pg_pool).await?;
+
+    let intents = fetch_ethernet_intent(resolved_id, &state.pg_pool)
+        .await
+        .map_err(|err| {
+            error!("Failed to get ethernet intent for device {resolved_id}: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(intents))
+}
+
+#[utoipa::path(
+    put,
+    path = "/devices/{device_id}/intent/ethernet",
+    params(
+        ("device_id" = String, Path),
+    ),
+    request_body = PutEthernetIntentRequest,
+    responses(
+        (status = StatusCode::OK, description = "Wired profiles replaced", body = Vec<DeviceEthernetIntent>),
+        (status = StatusCode::BAD_REQUEST, description = "Invalid or duplicate profile"),
+        (status = StatusCode::CONFLICT, description = "Profile name already used by a Wi-Fi intent"),
+        (status = StatusCode::NOT_FOUND, description = "Device not found"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to update intent"),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = DEVICES_TAG
+)]
+pub async fn put_device_ethernet_intent(
+    Path(device_id): Path<String>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(payload): Json<PutEthernetIntentRequest>,
+) -> Result<Json<Vec<DeviceEthernetIntent>>, StatusCode> {
+    if !authorization::check(current_user, "devices", "write") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let mut names = std::collections::HashSet::new();
+    for (index, profile) in payload.profiles.iter().enumerate() {
+        if let Err(message) = intent_ethernet(profile, index as i32 + 1) {
+            warn!(
+                profile = profile.profile_name,
+                "Rejected ethernet intent: {message}"
+            );
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        if !names.insert(profile.profile_name.as_str()) {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    }
+
+    let resolved_id = resolve_device_id(&device_id, &state.pg_pool).await?;
+
+    let mut tx = state.pg_pool.begin().await.map_err(|err| {
+        error!("Failed to start transaction: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    sqlx::query!(
+        "SELECT 1 as x FROM device WHERE id = $1 FOR UPDATE",
+        resolved_id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|err| {
+        error!("Failed to lock device row {resolved_id}: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    // smithd keys profiles by name, so a wired profile can't share one with a
+    // Wi-Fi profile of the same intent.
+    let names: Vec<String> = names.into_iter().map(String::from).collect();
+    let profile_name_conflict = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1
+            FROM device_network_intent dni
+            JOIN network n ON n.id = dni.network_id
+            WHERE dni.device_id = $1 AND n.name = ANY($2)
+        ) as "exists!"
+        "#,
+        resolved_id,
+        &names
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|err| {
+        error!("Failed to check profile name uniqueness: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    if profile_name_conflict {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    sqlx::query!(
+        "DELETE FROM device_ethernet_intent WHERE device_id = $1",
+        resolved_id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|err| {
+        error!("Failed to clear ethernet intent for device {resolved_id}: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    for (index, profile) in payload.profiles.iter().enumerate() {
+        sqlx::query!(
+            r#"
+            INSERT INTO device_ethernet_intent
+                (device_id, profile_name, interface, vlan_id, ipv4, ipv6, dns, priority, managed_by)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            "#,
+            resolved_id,
+            profile.profile_name,
+            profile.interface,
+            profile.vlan_id.map(i32::from),
+            SqlxJson(&profile.ipv4) as _,
+            SqlxJson(&profile.ipv6) as _,
+            &profile.dns,
+            index as i32 + 1,
+            payload.managed_by,
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|err| {
+            error!("Failed to insert ethernet intent for device {resolved_id}: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    }
+
+    sqlx::query!(
+        "UPDATE device SET intent_version = intent_version + 1 WHERE id = $1",
+        resolved_id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|err| {
+        error!("Failed to increment intent_version: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let intents = fetch_ethernet_intent(resolved_id, &mut *tx)
+        .await
+        .map_err(|err| {
+            error!("Failed to fetch ethernet intent for device {resolved_id}: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    tx.commit().await.map_err(|err| {
+        error!("Failed to commit transaction: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(intents))
+}
+
 /// Map a stored `security_type` (and its psk, if any) to the wire credential
 /// object smithd's applier consumes. Capability-bounded: only ever emits a
 /// `key_mgmt` smithd can apply (`none`/`wpa-psk`). Returns `None` when smithd
-/// can't apply the type yet (`sae`, `owe`, `wpa-eap`, unknown) or a
-/// psk-requiring type has no psk. `sae`/`owe` used to degrade to
+/// can't apply the type yet (`sae`, `owe`, unknown) or a psk-requiring type
+/// has no psk. `wpa-eap` goes through `wire_eap_credentials` instead. `sae`/`owe` used to degrade to
 /// `wpa-psk`/`none`, but that silently applies weaker security than the
 /// network is actually configured for — refused instead.
 fn wire_credentials(security_type: &str, psk: Option<&str>) -> Option<serde_json::Value> {
@@ -3477,6 +3786,53 @@ fn wire_credentials(security_type: &str, psk: Option<&str>) -> Option<serde_json
     }
 }
 
+/// The wire credentials of a `wpa-eap` network: its EAP settings from the
+/// catalog row, its secrets from `network_eap_secret`, and its certificates
+/// sealed to the device, which must have reported its public key. Returns why
+/// the network can't be applied otherwise.
+fn wire_eap_credentials(
+    credentials: &serde_json::Value,
+    identity: Option<&serde_json::Value>,
+    secret: EapSecret,
+    public_key: Option<&str>,
+) -> Result<serde_json::Value, &'static str> {
+    let text = |key: &str| {
+        credentials
+            .get(key)
+            .and_then(|v| v.as_str())
+            .map(String::from)
+    };
+    let seal = |pem: Option<String>| -> Result<Option<String>, &'static str> {
+        let Some(pem) = pem else {
+            return Ok(None);
+        };
+        let public_key = public_key.ok_or("device has reported no key to seal certificates to")?;
+        secrets::seal(public_key, pem.as_bytes())
+            .map(Some)
+            .map_err(|_| "failed to seal a certificate to the device key")
+    };
+
+    let eap = schema::EapCredentials {
+        method: text("eap").ok_or("network has no EAP method")?,
+        identity: identity
+            .and_then(|identity| identity.get("username"))
+            .and_then(|username| username.as_str())
+            .ok_or("network has no EAP identity")?
+            .to_string(),
+        anonymous_identity: text("anonymous_identity"),
+        phase2_auth: text("phase2_auth"),
+        password: secret.password,
+        ca_cert: seal(secret.ca_cert)?,
+        client_cert: seal(secret.client_cert)?,
+        private_key: seal(secret.private_key)?,
+        private_key_password: secret.private_key_password,
+        pmf_required: matches!(text("pmf").as_deref(), Some("required" | "3")),
+    };
+    eap.validate()
+        .map_err(|_| "network has incomplete EAP settings")?;
+    Ok(serde_json::json!({ "key_mgmt": "wpa-eap", "eap": eap }))
+}
+
 #[utoipa::path(
     post,
     path = "/devices/{device_id}/intent/apply",
@@ -3486,6 +3842,7 @@ fn wire_credentials(security_type: &str, psk: Option<&str>) -> Option<serde_json
     responses(
         (status = StatusCode::ACCEPTED, description = "ApplyNetworks command queued", body = ApplyIntentResponse),
         (status = StatusCode::BAD_REQUEST, description = "No intent (version 0) or network with NULL SSID"),
+        (status = StatusCode::UNPROCESSABLE_ENTITY, description = "No Wi-Fi network of the intent is applyable by smithd"),
         (status = StatusCode::NOT_FOUND, description = "Device not found"),
         (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to queue command"),
     ),
@@ -3511,8 +3868,8 @@ pub async fn apply_device_intent(
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
 
-    let intent_version: i32 = sqlx::query_scalar!(
-        "SELECT intent_version FROM device WHERE id = $1 FOR UPDATE",
+    let device = sqlx::query!(
+        "SELECT intent_version, secrets_public_key FROM device WHERE id = $1 FOR UPDATE",
         resolved_id
     )
     .fetch_one(&mut *tx)
@@ -3521,6 +3878,7 @@ pub async fn apply_device_intent(
         error!("Failed to read intent_version for device {resolved_id}: {err}");
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
+    let intent_version = device.intent_version;
 
     if intent_version == 0 {
         return Err(StatusCode::BAD_REQUEST);
@@ -3539,9 +3897,17 @@ pub async fn apply_device_intent(
             n.name,
             n.security_type as "security_type!",
             n.is_network_hidden,
-            n.credentials ->> 'psk' AS credentials_psk
+            n.credentials ->> 'psk' AS credentials_psk,
+            n.credentials,
+            n.identity,
+            s.password AS "eap_password?",
+            s.ca_cert AS "eap_ca_cert?",
+            s.client_cert AS "eap_client_cert?",
+            s.private_key AS "eap_private_key?",
+            s.private_key_password AS "eap_private_key_password?"
         FROM device_network_intent dni
         JOIN network n ON n.id = dni.network_id
+        LEFT JOIN network_eap_secret s ON s.network_id = n.id
         WHERE dni.device_id = $1 AND n.network_type = 'wifi'
         ORDER BY dni.priority ASC
         "#,
@@ -3554,7 +3920,14 @@ pub async fn apply_device_intent(
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
 
-    if networks.is_empty() || networks.iter().any(|n| n.ssid.is_none()) {
+    let ethernet = fetch_ethernet_intent(resolved_id, &mut *tx)
+        .await
+        .map_err(|err| {
+            error!("Failed to fetch ethernet intent for device {resolved_id}: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    if (networks.is_empty() && ethernet.is_empty()) || networks.iter().any(|n| n.ssid.is_none()) {
         return Err(StatusCode::BAD_REQUEST);
     }
 
@@ -3580,15 +3953,52 @@ pub async fn apply_device_intent(
     //       { "profile_name": "<catalog name>", "ssid": "<ssid>", "priority": <i32>,
     //         "hidden": <bool>, "security_type": "open",
     //         "credentials": { "key_mgmt": "none" } }  // open network
+    //       { "profile_name": "<catalog name>", "ssid": "<ssid>", "priority": <i32>,
+    //         "hidden": <bool>, "security_type": "wpa-eap",
+    //         "credentials": { "key_mgmt": "wpa-eap", "eap": {
+    //           "method": "peap", "identity": "<username>", "password": "<password>",
+    //           "phase2_auth": "mschapv2", "ca_cert": "<sealed PEM>", ... } } }
+    //     ],
+    //     "ethernet": [
+    //       { "profile_name": "<name>", "interface": "eth0", "vlan_id": 20,
+    //         "ipv4": { "method": "manual", "addresses": ["10.0.20.5/24"],
+    //                   "gateway": "10.0.20.1" },
+    //         "ipv6": { "method": "auto", "addresses": [], "gateway": null },
+    //         "dns": ["10.0.0.53"], "priority": <i32> }
     //     ]
     //   }
     // }
     // hidden/security_type sit alongside credentials, not inside it: they're
     // for smithd's existing-profile matching, not the applied shape itself.
+    let has_wifi = !networks.is_empty();
     let applyable_networks: Vec<serde_json::Value> = networks
-        .iter()
+        .into_iter()
         .filter_map(|n| {
-            let credentials = wire_credentials(&n.security_type, n.credentials_psk.as_deref());
+            let credentials = if n.security_type == "wpa-eap" {
+                let secret = EapSecret {
+                    password: n.eap_password,
+                    ca_cert: n.eap_ca_cert,
+                    client_cert: n.eap_client_cert,
+                    private_key: n.eap_private_key,
+                    private_key_password: n.eap_private_key_password,
+                };
+                wire_eap_credentials(
+                    &n.credentials,
+                    n.identity.as_ref(),
+                    secret,
+                    device.secrets_public_key.as_deref(),
+                )
+                .inspect_err(|reason| {
+                    warn!(
+                        device_id = resolved_id,
+                        network = n.name,
+                        "skipping enterprise network in ApplyNetworks: {reason}"
+                    );
+                })
+                .ok()
+            } else {
+                wire_credentials(&n.security_type, n.credentials_psk.as_deref())
+            };
             let Some(credentials) = credentials else {
                 warn!(
                     device_id = resolved_id,
@@ -3608,7 +4018,9 @@ pub async fn apply_device_intent(
         })
         .collect();
 
-    if applyable_networks.is_empty() {
+    // A wired-only intent has no Wi-Fi to send; a Wi-Fi intent that filters
+    // down to nothing would make smithd delete every Wi-Fi profile it applied.
+    if has_wifi && applyable_networks.is_empty() {
         error!(
             device_id = resolved_id,
             "no applyable networks remain after security_type filtering; refusing to send empty ApplyNetworks"
@@ -3616,10 +4028,24 @@ pub async fn apply_device_intent(
         return Err(StatusCode::UNPROCESSABLE_ENTITY);
     }
 
+    let ethernet: Vec<schema::IntentEthernet> = ethernet
+        .into_iter()
+        .map(|row| schema::IntentEthernet {
+            profile_name: row.profile_name,
+            interface: row.interface,
+            vlan_id: row.vlan_id.and_then(|id| u16::try_from(id).ok()),
+            ipv4: row.ipv4.0,
+            ipv6: row.ipv6.0,
+            dns: row.dns,
+            priority: row.priority,
+        })
+        .collect();
+
     let cmd = serde_json::json!({
         "ApplyNetworks": {
             "version": intent_version,
-            "networks": applyable_networks
+            "networks": applyable_networks,
+            "ethernet": ethernet
         }
     });
 
@@ -3654,8 +4080,10 @@ pub async fn apply_device_intent(
 
 #[cfg(test)]
 mod tests {
-    use super::{parse_search_terms, wire_credentials};
+    use super::{parse_search_terms, wire_credentials, wire_eap_credentials};
+    use crate::network::EapSecret;
     use serde_json::json;
+    use smith::secrets;
 
     // Mirrors the legacy inline heuristic in apply_device_intent exactly.
     // Used to verify the dual-read path produces byte-identical output for
@@ -3689,12 +4117,60 @@ mod tests {
         assert_eq!(wire_credentials("wpa-psk", None), None);
     }
 
+    #[test]
+    fn eap_credentials_combine_catalog_and_secret() {
+        let credentials = json!({ "eap": "peap", "phase2_auth": "mschapv2", "pmf": "required" });
+        let identity = json!({ "username": "nurse" });
+        let secret = || EapSecret {
+            password: Some("secret".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            wire_eap_credentials(&credentials, Some(&identity), secret(), None),
+            Ok(json!({ "key_mgmt": "wpa-eap", "eap": {
+                "method": "peap", "identity": "nurse", "anonymous_identity": null,
+                "phase2_auth": "mschapv2", "password": "secret", "ca_cert": null,
+                "client_cert": null, "private_key": null, "private_key_password": null,
+                "pmf_required": true,
+            }}))
+        );
+        assert!(wire_eap_credentials(&credentials, None, secret(), None).is_err());
+        assert!(
+            wire_eap_credentials(&credentials, Some(&identity), EapSecret::default(), None)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn eap_certificates_are_sealed_to_the_device() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = secrets::load_or_create_key(dir.path()).unwrap();
+        let public_key = secrets::public_key_base64(&key);
+        let credentials = json!({ "eap": "tls" });
+        let identity = json!({ "username": "device-17" });
+        let secret = || EapSecret {
+            client_cert: Some("CLIENT PEM".to_string()),
+            private_key: Some("KEY PEM".to_string()),
+            ..Default::default()
+        };
+
+        // Nothing to seal to yet.
+        assert!(wire_eap_credentials(&credentials, Some(&identity), secret(), None).is_err());
+
+        let wire = wire_eap_credentials(&credentials, Some(&identity), secret(), Some(&public_key))
+            .unwrap();
+        let sealed = wire["eap"]["client_cert"].as_str().unwrap();
+        assert_ne!(sealed, "CLIENT PEM");
+        assert_eq!(secrets::open(&key, sealed).unwrap(), b"CLIENT PEM");
+    }
+
     #[test]
     fn unapplyable_types_are_skipped() {
         // WPA3 and Enhanced Open have no correct nmcli-applicable degradation
         assert_eq!(wire_credentials("sae", Some("secret")), None);
         assert_eq!(wire_credentials("owe", None), None);
-        // enterprise not applyable by smithd yet
+        // enterprise needs its EAP settings, see wire_eap_credentials
         assert_eq!(wire_credentials("wpa-eap", Some("secret")), None);
         // unknown / unmodelled types
         assert_eq!(wire_credentials("wep", None), None);
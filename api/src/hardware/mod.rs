@@ -0,0 +1,158 @@
+use crate::serialized::serialized_name;
+use serde::Serialize;
+use serde_json::{Value, json};
+use smith::utils::schema::HardwareInventory;
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::{Postgres, Transaction};
+use utoipa::ToSchema;
+
+pub mod route;
+
+/// The hardware a device last reported.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DeviceHardware {
+    pub cpu_model: Option<String>,
+    pub cpu_cores: i32,
+    /// Usable memory, somewhat less than what is installed.
+    pub memory_bytes: i64,
+    /// Each with `name`, `kind`, `model`, `size_bytes`, `rotational` and
+    /// `removable`.
+    #[schema(value_type = Vec<Object>)]
+    pub block_devices: Value,
+    /// Each with `vendor_id`, `product_id`, `manufacturer` and `product`.
+    #[schema(value_type = Vec<Object>)]
+    pub usb_devices: Value,
+    /// Each with `address`, `vendor_id`, `device_id` and `class`.
+    #[schema(value_type = Vec<Object>)]
+    pub pci_devices: Value,
+    /// Each with `name`, `connected` and `mode`.
+    #[schema(value_type = Vec<Object>)]
+    pub displays: Value,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// `vendor:product`, as the device filters take them.
+fn usb_ids(inventory: &HardwareInventory) -> Vec<String> {
+    let mut ids: Vec<String> = inventory
+        .usb_devices
+        .iter()
+        .map(|usb| format!("{}:{}", usb.vendor_id, usb.product_id).to_lowercase())
+        .collect();
+    ids.sort();
+    ids.dedup();
+    ids
+}
+
+fn pci_ids(inventory: &HardwareInventory) -> Vec<String> {
+    let mut ids: Vec<String> = inventory
+        .pci_devices
+        .iter()
+        .map(|pci| format!("{}:{}", pci.vendor_id, pci.device_id).to_lowercase())
+        .collect();
+    ids.sort();
+    ids.dedup();
+    ids
+}
+
+fn storage_kinds(inventory: &HardwareInventory) -> Vec<String> {
+    let mut kinds: Vec<String> = inventory
+        .block_devices
+        .iter()
+        .map(|block| serialized_name(block.kind))
+        .collect();
+    kinds.sort();
+    kinds.dedup();
+    kinds
+}
+
+pub async fn save_inventory(
+    device_id: i32,
+    inventory: &HardwareInventory,
+    tx: &mut Transaction<'_, Postgres>,
+) -> anyhow::Result<()> {
+    let connected_displays: Vec<String> = inventory
+        .displays
+        .iter()
+        .filter(|display| display.connected)
+        .map(|display| display.name.clone())
+        .collect();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO device_hardware
+            (device_id, cpu_model, cpu_cores, memory_bytes, storage_kinds, usb_ids, pci_ids,
+             connected_displays, block_devices, usb_devices, pci_devices, displays, updated_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, NOW())
+        ON CONFLICT (device_id) DO UPDATE SET
+            cpu_model = EXCLUDED.cpu_model,
+            cpu_cores = EXCLUDED.cpu_cores,
+            memory_bytes = EXCLUDED.memory_bytes,
+            storage_kinds = EXCLUDED.storage_kinds,
+            usb_ids = EXCLUDED.usb_ids,
+            pci_ids = EXCLUDED.pci_ids,
+            connected_displays = EXCLUDED.connected_displays,
+            block_devices = EXCLUDED.block_devices,
+            usb_devices = EXCLUDED.usb_devices,
+            pci_devices = EXCLUDED.pci_devices,
+            displays = EXCLUDED.displays,
+            updated_at = EXCLUDED.updated_at
+        "#,
+        device_id,
+        inventory.cpu_model,
+        inventory.cpu_cores as i32,
+        inventory.memory_bytes as i64,
+        &storage_kinds(inventory),
+        &usb_ids(inventory),
+        &pci_ids(inventory),
+        &connected_displays,
+        json!(inventory.block_devices),
+        json!(inventory.usb_devices),
+        json!(inventory.pci_devices),
+        json!(inventory.displays),
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smith::utils::schema::{BlockDevice, BlockDeviceKind, PciDevice, UsbDevice};
+
+    #[test]
+    fn derives_the_filter_columns() {
+        let usb = |vendor_id: &str, product_id: &str| UsbDevice {
+            vendor_id: vendor_id.into(),
+            product_id: product_id.into(),
+            ..Default::default()
+        };
+        let block = |kind| BlockDevice {
+            kind,
+            ..Default::default()
+        };
+        let inventory = HardwareInventory {
+            block_devices: vec![
+                block(BlockDeviceKind::Usb),
+                block(BlockDeviceKind::Emmc),
+                block(BlockDeviceKind::Usb),
+            ],
+            usb_devices: vec![
+                usb("046D", "0825"),
+                usb("0bda", "8153"),
+                usb("046d", "0825"),
+            ],
+            pci_devices: vec![PciDevice {
+                address: "0000:01:00.0".into(),
+                vendor_id: "10ec".into(),
+                device_id: "8168".into(),
+                class: "020000".into(),
+            }],
+            ..Default::default()
+        };
+        assert_eq!(storage_kinds(&inventory), ["emmc", "usb"]);
+        assert_eq!(usb_ids(&inventory), ["046d:0825", "0bda:8153"]);
+        assert_eq!(pci_ids(&inventory), ["10ec:8168"]);
+    }
+}
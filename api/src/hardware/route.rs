@@ -0,0 +1,55 @@
+use crate::State;
+use crate::hardware::DeviceHardware;
+use crate::middlewares::authorization;
+use crate::user::CurrentUser;
+use axum::{Extension, Json, extract::Path};
+use axum::{http::StatusCode, response::Result};
+use tracing::error;
+
+const TAG: &str = "devices";
+
+#[utoipa::path(
+    get,
+    path = "/devices/{device_id}/hardware",
+    params(
+        ("device_id" = i32, Path),
+    ),
+    responses(
+        (status = StatusCode::OK, description = "The hardware the device last reported", body = DeviceHardware),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to read devices"),
+        (status = StatusCode::NOT_FOUND, description = "The device has not reported its hardware"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve the hardware"),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn get_hardware_for_device(
+    Path(device_id): Path<i32>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Result<Json<DeviceHardware>, StatusCode> {
+    if !authorization::check(current_user, "devices", "read") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let hardware = sqlx::query_as!(
+        DeviceHardware,
+        r#"
+        SELECT cpu_model, cpu_cores, memory_bytes, block_devices, usb_devices, pci_devices,
+               displays, updated_at
+        FROM device_hardware
+        WHERE device_id = $1
+        "#,
+        device_id
+    )
+    .fetch_optional(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("Failed to get hardware for device {device_id}: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    hardware.map(Json).ok_or(StatusCode::NOT_FOUND)
+}
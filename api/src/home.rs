@@ -1,5 +1,14 @@
+use crate::audit;
+use crate::bandwidth;
+use crate::connectivity;
 use crate::device::{SMITHD_SERVICE_NAME, Variable};
+use crate::hardware;
+use crate::modem;
 use crate::network::route::content_credentials;
+use crate::ota;
+use crate::secret;
+use crate::serialized::serialized_name;
+use crate::vpn;
 use anyhow::Result;
 use serde_json::Value;
 use serde_json::json;
@@ -8,7 +17,7 @@ use smith::utils::schema::SafeCommandTx::{UpdateNetwork, UpdateVariables};
 use smith::utils::schema::{
     HomePost, NetworkType, SafeCommandRequest, SafeCommandRx, ServiceStatus,
 };
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, Transaction};
 use tracing::debug;
 use tracing::error;
 
@@ -491,6 +500,7 @@ pub async fn save_responses(
             SafeCommandRx::AuditReport {
                 disk_encrypted,
                 password_access_disabled,
+                ref checks,
             } => {
                 sqlx::query!(
                     r#"
@@ -507,6 +517,144 @@ pub async fn save_responses(
                 )
                 .execute(pool)
                 .await?;
+                // Daemons from before audit profiles report no checks.
+                if !checks.is_empty() {
+                    audit::save_checks(device_id, checks, &mut tx).await?;
+                }
+            }
+            SafeCommandRx::IntegrityReport { ref changes, .. } => {
+                audit::save_integrity(device_id, changes, &mut tx).await?;
+            }
+            SafeCommandRx::ModemStatus { ref modem } => {
+                modem::save_status(device_id, modem.as_ref(), &mut tx).await?;
+            }
+            SafeCommandRx::HardwareInventory { ref inventory } => {
+                hardware::save_inventory(device_id, inventory, &mut tx).await?;
+            }
+            SafeCommandRx::LinkQuality { ref intervals } => {
+                connectivity::save_link_quality(device_id, intervals, &mut tx).await?;
+            }
+            SafeCommandRx::GetSecrets { ref public_key } => {
+                let previous = sqlx::query_scalar!(
+                    "SELECT secrets_public_key FROM device WHERE id = $1",
+                    device_id
+                )
+                .fetch_one(&mut *tx)
+                .await?;
+
+                // Secrets sealed to the old key can't be opened anymore. Drop
+                // them rather than ship ciphertext the device will reject, and
+                // say so in the ledger so they get set again.
+                if previous.as_deref().is_some_and(|key| key != public_key) {
+                    let dropped =
+                        sqlx::query!("DELETE FROM device_secret WHERE device_id = $1", device_id)
+                            .execute(&mut *tx)
+                            .await?
+                            .rows_affected();
+
+                    if dropped > 0 {
+                        sqlx::query!(
+                            r#"INSERT INTO ledger (device_id, "class", "text") VALUES ($1, $2, $3)"#,
+                            device_id,
+                            "secret",
+                            format!(
+                                "Device secrets key changed; {dropped} secret(s) dropped and must be set again."
+                            )
+                        )
+                        .execute(&mut *tx)
+                        .await?;
+                    }
+                }
+
+                sqlx::query!(
+                    "UPDATE device SET secrets_public_key = $2 WHERE id = $1",
+                    device_id,
+                    public_key
+                )
+                .execute(&mut *tx)
+                .await?;
+
+                let has_secrets = sqlx::query_scalar!(
+                    r#"SELECT EXISTS(SELECT 1 FROM device_secret WHERE device_id = $1) AS "exists!""#,
+                    device_id
+                )
+                .fetch_one(&mut *tx)
+                .await?;
+
+                // The daemon reports its key on every start, so this also
+                // re-delivers the set after a reflash that kept the keys dir.
+                if has_secrets {
+                    secret::queue_update_secrets(device_id, device_serial_number, &mut tx).await?;
+                }
+            }
+            SafeCommandRx::WireGuardKey { ref public_key } => {
+                let previous = sqlx::query_scalar!(
+                    "SELECT wireguard_public_key FROM device WHERE id = $1",
+                    device_id
+                )
+                .fetch_one(&mut *tx)
+                .await?;
+
+                // The daemon reports its key on every start; re-applying the
+                // tunnel each time would only bounce it. A new key, or the first
+                // one after the intent was set, is what the device is waiting on.
+                if previous.as_deref() != Some(public_key.as_str()) {
+                    sqlx::query!(
+                        "UPDATE device SET wireguard_public_key = $2 WHERE id = $1",
+                        device_id,
+                        public_key
+                    )
+                    .execute(&mut *tx)
+                    .await?;
+
+                    if let Some(config) = vpn::config_for_device(device_id, &mut tx).await? {
+                        vpn::queue_apply_vpn(device_serial_number, Some(config), &mut tx).await?;
+                    }
+                }
+            }
+            SafeCommandRx::BandwidthUsage { ref usage } => {
+                sqlx::query!(
+                    r#"
+        INSERT INTO device_bandwidth_usage
+            (device_id, month, ethernet_bytes, wifi_bytes, lte_bytes, other_bytes, link, paused, updated_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, NOW())
+        ON CONFLICT (device_id, month) DO UPDATE SET
+            ethernet_bytes = EXCLUDED.ethernet_bytes,
+            wifi_bytes = EXCLUDED.wifi_bytes,
+            lte_bytes = EXCLUDED.lte_bytes,
+            other_bytes = EXCLUDED.other_bytes,
+            link = EXCLUDED.link,
+            paused = EXCLUDED.paused,
+            updated_at = NOW()
+        "#,
+                    device_id,
+                    usage.month,
+                    usage.ethernet_bytes as i64,
+                    usage.wifi_bytes as i64,
+                    usage.lte_bytes as i64,
+                    usage.other_bytes as i64,
+                    serialized_name(usage.link),
+                    usage.paused
+                )
+                .execute(&mut *tx)
+                .await?;
+
+                // Labels may have changed since the device last got a policy,
+                // or it may have come back from a reflash without one.
+                let policy = bandwidth::policy_for_device(device_id, pool).await?;
+                if policy != usage.policy
+                    && !bandwidth::policy_update_pending(device_id, pool).await?
+                {
+                    bandwidth::queue_update_bandwidth_policy(device_serial_number, policy, pool)
+                        .await?;
+                }
+            }
+            SafeCommandRx::OtaState { ref state }
+            | SafeCommandRx::CheckOTAStatus {
+                ota: Some(ref state),
+                ..
+            } => {
+                ota::save_state(device_id, state, &mut tx).await?;
             }
             SafeCommandRx::ApplyNetworksResult {
                 applied_version,
@@ -717,15 +865,27 @@ pub async fn add_commands(
 ) -> Result<Vec<i32>> {
     debug!("Adding commands to device {}", serial_number);
     debug!("Commands: {:?}", commands);
-    let mut command_ids = Vec::new();
-
     let mut tx = pool.begin().await?;
+    let command_ids = add_commands_in_tx(serial_number, commands, &mut tx, user_id).await?;
+    tx.commit().await?;
+    Ok(command_ids)
+}
+
+/// Queues `commands` within `tx`, so they only go out if what they follow
+/// from is committed with them.
+pub async fn add_commands_in_tx(
+    serial_number: &str,
+    commands: Vec<SafeCommandRequest>,
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: Option<i32>,
+) -> Result<Vec<i32>> {
+    let mut command_ids = Vec::new();
 
     let bundle_id = sqlx::query!(
         r#"INSERT INTO command_bundles (user_id) VALUES ($1) RETURNING uuid"#,
         user_id
     )
-    .fetch_one(&mut *tx)
+    .fetch_one(&mut **tx)
     .await?;
 
     for command in commands {
@@ -744,13 +904,12 @@ pub async fn add_commands(
             command.continue_on_error,
             bundle_id.uuid
         )
-        .fetch_one(&mut *tx)
+        .fetch_one(&mut **tx)
         .await?;
 
         command_ids.push(command_id);
     }
 
-    tx.commit().await?;
     Ok(command_ids)
 }
 
@@ -30,9 +30,12 @@ use utoipa::{Modify, OpenApi};
 use utoipa_axum::{router::OpenApiRouter, routes};
 use utoipa_swagger_ui::SwaggerUi;
 
+mod audit;
 mod auth;
+mod bandwidth;
 mod command;
 mod config;
+mod connectivity;
 mod dashboard;
 mod deployment;
 mod device;
@@ -41,6 +44,7 @@ mod error;
 mod event;
 mod files;
 mod handlers;
+mod hardware;
 mod health;
 mod home;
 mod ip_address;
@@ -50,16 +54,20 @@ mod metric;
 mod middlewares;
 mod modem;
 pub mod network;
+mod ota;
 mod package;
 mod relay;
 mod release;
 mod rollout;
+mod secret;
 mod sentry;
+mod serialized;
 pub mod slack;
 mod smith;
 mod storage;
 mod telemetry;
 mod user;
+mod vpn;
 
 #[derive(Clone, Debug)]
 pub struct State {
@@ -231,6 +239,9 @@ async fn start_main_server(
     // go down to a day, so the real cleanup has to run here.
     files::spawn_sweeper(state.pg_pool.clone(), &config.assets_bucket_name);
 
+    // Challenges are issued unauthenticated and most expire unanswered.
+    auth::challenge::spawn_sweeper(state.pg_pool.clone());
+
     let recorder_handle = metric::setup_metrics_recorder();
 
     let mut api_doc = ApiDoc::openapi();
@@ -260,6 +271,7 @@ async fn start_main_server(
             network::route::get_network_by_id,
             network::route::delete_network_by_id
         ))
+        .routes(routes!(network::route::set_network_eap))
         .routes(routes!(network::route::start_extended_network_test))
         .routes(routes!(network::route::get_extended_test_status))
         .routes(routes!(network::route::list_extended_test_sessions))
@@ -285,8 +297,24 @@ async fn start_main_server(
             package::route::release_package
         ))
         .routes(routes!(package::route::get_package_latest))
+        .routes(routes!(package::route::register_container_image))
         .routes(routes!(modem::route::get_modem_list))
         .routes(routes!(modem::route::get_modem_by_id))
+        .routes(routes!(modem::route::get_modem_status_for_device))
+        .routes(routes!(modem::route::get_modem_signal_for_device))
+        .routes(routes!(connectivity::route::get_connectivity_for_device))
+        .routes(routes!(
+            connectivity::route::get_connectivity_history_for_device
+        ))
+        .routes(routes!(connectivity::route::get_link_quality_for_device))
+        .routes(routes!(hardware::route::get_hardware_for_device))
+        .routes(routes!(vpn::route::get_vpns, vpn::route::create_vpn))
+        .routes(routes!(vpn::route::get_vpn_peers))
+        .routes(routes!(
+            vpn::route::get_vpn_for_device,
+            vpn::route::set_vpn_for_device,
+            vpn::route::delete_vpn_for_device
+        ))
         .routes(routes!(
             distribution::route::get_distributions,
             distribution::route::create_distribution
@@ -326,6 +354,14 @@ async fn start_main_server(
             release::route::create_release_service
         ))
         .routes(routes!(release::route::delete_release_service))
+        .routes(routes!(
+            release::route::get_release_containers,
+            release::route::set_release_containers
+        ))
+        .routes(routes!(
+            release::route::get_release_hooks,
+            release::route::set_release_hooks
+        ))
         .routes(routes!(release::route::promote_release))
         .routes(routes!(
             device::route::get_network_for_device,
@@ -338,6 +374,10 @@ async fn start_main_server(
         ))
         .routes(routes!(device::route::get_services_for_device))
         .routes(routes!(device::route::get_audit_for_device))
+        .routes(routes!(audit::route::get_audit_checks_for_device))
+        .routes(routes!(audit::route::get_compliance_by_label))
+        .routes(routes!(audit::route::get_integrity_drift_for_device))
+        .routes(routes!(audit::route::accept_integrity_baseline))
         .routes(routes!(device::route::get_configured_networks_for_device))
         .routes(routes!(device::route::get_wifi_scan_for_device))
         .routes(routes!(
@@ -348,6 +388,10 @@ async fn start_main_server(
             device::route::update_device_intent,
             device::route::delete_device_intent,
         ))
+        .routes(routes!(
+            device::route::get_device_ethernet_intent,
+            device::route::put_device_ethernet_intent,
+        ))
         .routes(routes!(device::route::apply_device_intent))
         .routes(routes!(rollout::route::api_rollout,))
         .routes(routes!(rollout::route::get_distribution_rollouts))
@@ -388,6 +432,19 @@ async fn start_main_server(
         .routes(routes!(device::route::delete_token))
         .routes(routes!(device::route::update_devices_target_release))
         .routes(routes!(device::route::get_variables))
+        .routes(routes!(secret::route::get_secrets_for_device))
+        .routes(routes!(
+            secret::route::set_secret_for_device,
+            secret::route::delete_secret_for_device
+        ))
+        .routes(routes!(secret::route::rotate_secret))
+        .routes(routes!(
+            bandwidth::route::get_bandwidth_policies,
+            bandwidth::route::set_bandwidth_policy
+        ))
+        .routes(routes!(bandwidth::route::delete_bandwidth_policy))
+        .routes(routes!(bandwidth::route::get_bandwidth_usage_for_device))
+        .routes(routes!(ota::route::get_ota_state_for_device))
         .routes(routes!(
             command::route::get_bundle_commands,
             command::route::issue_commands_to_devices
@@ -418,10 +475,17 @@ async fn start_main_server(
         .routes(routes!(smith::route::download_file))
         .routes(routes!(smith::route::fetch_package))
         .routes(routes!(smith::route::list_release_packages))
+        .routes(routes!(smith::route::list_release_containers))
+        .routes(routes!(smith::route::list_release_hooks))
         .routes(routes!(smith::route::test_file))
         .routes(routes!(smith::route::test_upload))
         .routes(routes!(files::route::upload_file))
-        .routes(routes!(auth::route::session))
+        .routes(routes!(
+            auth::route::session,
+            auth::route::session_with_proof
+        ))
+        .routes(routes!(auth::route::challenge))
+        .routes(routes!(auth::route::enroll_identity))
         .split_for_parts();
 
     let smith_router = smith_router
@@ -30,10 +30,24 @@ pub fn required_permission(command: &SafeCommandTx) -> Permission {
         FreeForm { .. } => "freeform",
         OpenTunnel { .. } | CloseTunnel => "tunnel",
         DownloadOTA { .. } | CheckOTAStatus | StartOTA => "ota",
+        // Cancelling can stall an OTA or an upgrade mid-transfer, so it is
+        // gated like starting one.
+        CancelDownload { .. } => "ota",
+        // Caps or pauses every download on the device, OTAs included.
+        UpdateBandwidthPolicy { .. } => "ota",
         // Root-equivalent read of the whole device filesystem. Kept separate
         // from `freeform` so it can be granted or revoked on its own, but it is
         // deliberately not part of `basic`.
         OpenFileSession { .. } | CloseFileSession { .. } => "files",
+        UpdateSecrets { .. } => "secrets",
+        // Accepting drift hides tampering from later audits.
+        AcceptIntegrityBaseline { .. } => "freeform",
+        // A wrong APN or a disabled modem strands a device that is only
+        // reachable over LTE.
+        SetModemApn { .. } | SetModemEnabled { .. } | PowerCycleModem => "modem",
+        // Routes the device's traffic through a tunnel of the caller's
+        // choosing. Issued by the VPN intent, not by hand.
+        ApplyVpn { .. } => "freeform",
         Ping
         | Upgrade
         | Restart
@@ -47,7 +61,9 @@ pub fn required_permission(command: &SafeCommandTx) -> Permission {
         | GetLogs { .. }
         | ReportNMProfiles
         | WifiScan
-        | ApplyNetworks { .. } => "basic",
+        | ApplyNetworks { .. }
+        | ListDownloads { .. }
+        | UpgradePlan { .. } => "basic",
         // Only ever produced by a daemon deserializing a command it doesn't
         // recognize; the api never issues it. Gated as `freeform` so that if one
         // is ever submitted it needs the most privileged action, and rejected
@@ -1,9 +1,208 @@
+use crate::serialized::serialized_name;
 use models::modem::Modem;
-use sqlx::PgPool;
+use serde::Serialize;
+use smith::utils::schema::{ModemSignalSample, ModemStatus};
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
 use tracing::error;
+use utoipa::ToSchema;
 
 pub mod route;
 
+/// The modem a device last reported through ModemManager.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DeviceModemStatus {
+    pub manufacturer: Option<String>,
+    pub model: Option<String>,
+    pub imei: Option<String>,
+    pub iccid: Option<String>,
+    pub operator: Option<String>,
+    /// ModemManager's state, e.g. `registered` or `connected`.
+    pub state: String,
+    /// One of `idle`, `home`, `searching`, `denied`, `unknown`, `roaming` and
+    /// `emergency_only`.
+    pub registration: String,
+    pub access_technology: Option<String>,
+    pub apn: Option<String>,
+    pub data_connected: bool,
+    /// Bytes through the data bearer since it connected.
+    pub rx_bytes: i64,
+    pub tx_bytes: i64,
+    /// Times the device power-cycled the modem since smithd started.
+    pub watchdog_power_cycles: i32,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// One reading of a device's modem signal.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ModemSignal {
+    pub sampled_at: DateTime<Utc>,
+    /// 0-100.
+    pub quality: i16,
+    pub access_technology: Option<String>,
+    /// dBm.
+    pub rssi: Option<f64>,
+    /// dBm.
+    pub rsrp: Option<f64>,
+    /// dB.
+    pub rsrq: Option<f64>,
+    /// dB.
+    pub snr: Option<f64>,
+}
+
+fn sampled_at(sample: &ModemSignalSample) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp(i64::try_from(sample.at).ok()?, 0)
+}
+
+/// Replaces the device's modem with the one it just reported, or forgets it
+/// when the device no longer has one. The modem is also linked to the device
+/// by IMEI, as the telemetry route does.
+pub async fn save_status(
+    device_id: i32,
+    status: Option<&ModemStatus>,
+    tx: &mut Transaction<'_, Postgres>,
+) -> anyhow::Result<()> {
+    let Some(status) = status else {
+        sqlx::query!(
+            "DELETE FROM device_modem_status WHERE device_id = $1",
+            device_id
+        )
+        .execute(&mut **tx)
+        .await?;
+        sqlx::query!("UPDATE device SET modem_id = NULL WHERE id = $1", device_id)
+            .execute(&mut **tx)
+            .await?;
+        return Ok(());
+    };
+
+    let previous_cycles = sqlx::query_scalar!(
+        "SELECT watchdog_power_cycles FROM device_modem_status WHERE device_id = $1",
+        device_id
+    )
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO device_modem_status
+            (device_id, manufacturer, model, imei, iccid, operator, state, registration,
+             access_technology, apn, data_connected, rx_bytes, tx_bytes, watchdog_power_cycles,
+             updated_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, NOW())
+        ON CONFLICT (device_id) DO UPDATE SET
+            manufacturer = EXCLUDED.manufacturer,
+            model = EXCLUDED.model,
+            imei = EXCLUDED.imei,
+            iccid = EXCLUDED.iccid,
+            operator = EXCLUDED.operator,
+            state = EXCLUDED.state,
+            registration = EXCLUDED.registration,
+            access_technology = EXCLUDED.access_technology,
+            apn = EXCLUDED.apn,
+            data_connected = EXCLUDED.data_connected,
+            rx_bytes = EXCLUDED.rx_bytes,
+            tx_bytes = EXCLUDED.tx_bytes,
+            watchdog_power_cycles = EXCLUDED.watchdog_power_cycles,
+            updated_at = EXCLUDED.updated_at
+        "#,
+        device_id,
+        status.manufacturer,
+        status.model,
+        status.imei,
+        status.iccid,
+        status.operator,
+        serialized_name(status.state),
+        serialized_name(status.registration),
+        status.access_technology,
+        status.apn,
+        status.data_connected,
+        status.rx_bytes as i64,
+        status.tx_bytes as i64,
+        status.watchdog_power_cycles as i32,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    for sample in &status.signal {
+        let Some(sampled_at) = sampled_at(sample) else {
+            continue;
+        };
+        sqlx::query!(
+            r#"
+            INSERT INTO device_modem_signal
+                (device_id, sampled_at, quality, access_technology, rssi, rsrp, rsrq, snr)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            ON CONFLICT (device_id, sampled_at) DO NOTHING
+            "#,
+            device_id,
+            sampled_at,
+            i16::from(sample.quality),
+            sample.access_technology,
+            sample.rssi,
+            sample.rsrp,
+            sample.rsrq,
+            sample.snr,
+        )
+        .execute(&mut **tx)
+        .await?;
+    }
+    sqlx::query!(
+        "DELETE FROM device_modem_signal WHERE device_id = $1 AND sampled_at < NOW() - INTERVAL '7 days'",
+        device_id
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    // The count starts over when smithd restarts.
+    let cycled = match previous_cycles {
+        Some(previous) => (status.watchdog_power_cycles as i32 - previous).max(0),
+        None => status.watchdog_power_cycles as i32,
+    };
+    if cycled > 0 {
+        sqlx::query!(
+            r#"INSERT INTO ledger (device_id, "class", "text") VALUES ($1, $2, $3)"#,
+            device_id,
+            "modem",
+            format!(
+                "Power-cycled the modem {cycled} time(s): it was registered on the network without data."
+            )
+        )
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    if let Some(imei) = &status.imei {
+        let modem_id = sqlx::query_scalar!(
+            r#"
+            INSERT INTO modem (imei, network_provider, updated_at)
+            VALUES ($1, $2, NOW())
+            ON CONFLICT (imei) DO UPDATE SET network_provider = $2, updated_at = NOW()
+            RETURNING id
+            "#,
+            imei,
+            status.operator.clone().unwrap_or_default(),
+        )
+        .fetch_one(&mut **tx)
+        .await?;
+        sqlx::query!(
+            "UPDATE device SET modem_id = NULL WHERE modem_id = $1 AND id <> $2",
+            modem_id,
+            device_id
+        )
+        .execute(&mut **tx)
+        .await?;
+        sqlx::query!(
+            "UPDATE device SET modem_id = $1 WHERE id = $2",
+            modem_id,
+            device_id
+        )
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    Ok(())
+}
+
 pub async fn save_modem(
     serial_number: String,
     imei: String,
@@ -73,3 +272,42 @@ pub async fn clear_modem(serial_number: String, pool: &PgPool) -> anyhow::Result
     })?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smith::utils::schema::{ModemRegistration, ModemState};
+
+    #[test]
+    fn enums_are_stored_as_serialized() {
+        assert_eq!(serialized_name(ModemState::Connected), "connected");
+        assert_eq!(
+            serialized_name(ModemRegistration::EmergencyOnly),
+            "emergency_only"
+        );
+    }
+
+    #[test]
+    fn samples_are_dated_by_unix_seconds() {
+        let sample = ModemSignalSample {
+            at: 1_760_000_000,
+            quality: 80,
+            access_technology: Some("lte".into()),
+            rssi: Some(-65.0),
+            rsrp: None,
+            rsrq: None,
+            snr: None,
+        };
+        assert_eq!(
+            sampled_at(&sample).unwrap().to_rfc3339(),
+            "2025-10-09T08:53:20+00:00"
+        );
+        assert!(
+            sampled_at(&ModemSignalSample {
+                at: u64::MAX,
+                ..sample
+            })
+            .is_none()
+        );
+    }
+}
@@ -1,8 +1,13 @@
 use crate::State;
-use crate::modem::Modem;
+use crate::middlewares::authorization;
+use crate::modem::{DeviceModemStatus, Modem, ModemSignal};
+use crate::user::CurrentUser;
+use axum::extract::Query;
 use axum::{Extension, Json, extract::Path};
 use axum::{http::StatusCode, response::Result};
+use serde::Deserialize;
 use tracing::error;
+use utoipa::IntoParams;
 
 const TAG: &str = "modems";
 
@@ -61,3 +66,105 @@ pub async fn get_modem_by_id(
 
     Ok(Json(modem))
 }
+
+#[utoipa::path(
+    get,
+    path = "/devices/{device_id}/modem",
+    params(
+        ("device_id" = i32, Path),
+    ),
+    responses(
+        (status = StatusCode::OK, description = "The modem the device last reported", body = DeviceModemStatus),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to read devices"),
+        (status = StatusCode::NOT_FOUND, description = "The device has not reported a modem"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve the modem"),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn get_modem_status_for_device(
+    Path(device_id): Path<i32>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Result<Json<DeviceModemStatus>, StatusCode> {
+    if !authorization::check(current_user, "devices", "read") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let status = sqlx::query_as!(
+        DeviceModemStatus,
+        r#"
+        SELECT manufacturer, model, imei, iccid, operator, state, registration,
+               access_technology, apn, data_connected, rx_bytes, tx_bytes,
+               watchdog_power_cycles, updated_at
+        FROM device_modem_status
+        WHERE device_id = $1
+        "#,
+        device_id
+    )
+    .fetch_optional(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("Failed to get modem status for device {device_id}: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    status.map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SignalQuery {
+    /// How far back to go, 24 hours by default. Readings are kept for a week.
+    pub hours: Option<i32>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/devices/{device_id}/modem/signal",
+    params(
+        ("device_id" = i32, Path),
+        SignalQuery,
+    ),
+    responses(
+        (status = StatusCode::OK, description = "The device's modem signal readings, oldest first", body = Vec<ModemSignal>),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to read devices"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve the signal history"),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn get_modem_signal_for_device(
+    Path(device_id): Path<i32>,
+    Query(query): Query<SignalQuery>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Result<Json<Vec<ModemSignal>>, StatusCode> {
+    if !authorization::check(current_user, "devices", "read") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let signal = sqlx::query_as!(
+        ModemSignal,
+        r#"
+        SELECT sampled_at, quality, access_technology, rssi, rsrp, rsrq, snr
+        FROM device_modem_signal
+        WHERE device_id = $1 AND sampled_at >= NOW() - make_interval(hours => $2)
+        ORDER BY sampled_at
+        "#,
+        device_id,
+        query.hours.unwrap_or(24)
+    )
+    .fetch_all(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("Failed to get modem signal for device {device_id}: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(signal))
+}
@@ -1,2 +1,37 @@
+use serde::Deserialize;
+use utoipa::ToSchema;
+
 pub mod evaluation;
 pub mod route;
+
+/// The secret half of an enterprise network, from `network_eap_secret`.
+#[derive(Debug, Default)]
+pub struct EapSecret {
+    pub password: Option<String>,
+    pub ca_cert: Option<String>,
+    pub client_cert: Option<String>,
+    pub private_key: Option<String>,
+    pub private_key_password: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SetNetworkEap {
+    /// `peap`, `ttls` or `tls`.
+    pub method: String,
+    pub identity: String,
+    pub anonymous_identity: Option<String>,
+    /// Inner authentication of PEAP and TTLS, e.g. `mschapv2`.
+    pub phase2_auth: Option<String>,
+    /// Required by PEAP and TTLS.
+    pub password: Option<String>,
+    /// PEM. Sealed to each device when the intent is applied.
+    pub ca_cert: Option<String>,
+    /// PEM, required by TLS.
+    pub client_cert: Option<String>,
+    /// PEM, required by TLS.
+    pub private_key: Option<String>,
+    pub private_key_password: Option<String>,
+    /// Require management frame protection, i.e. WPA3-Enterprise only.
+    #[serde(default)]
+    pub pmf_required: bool,
+}
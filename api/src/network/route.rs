@@ -1,4 +1,5 @@
 use crate::State;
+use crate::middlewares::authorization;
 use crate::user::CurrentUser;
 use axum::http::StatusCode;
 use axum::response::Result;
@@ -9,12 +10,13 @@ use axum::{
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
-use smith::utils::schema::NetworkType;
+use smith::utils::schema::{EapCredentials, NetworkType};
 use smith::utils::schema::{Network, NetworkInfo, NewNetwork, SpeedSample};
 use tracing::{error, info, warn};
 use utoipa::ToSchema;
 use uuid::Uuid;
 
+use super::SetNetworkEap;
 use super::evaluation::{Evaluation, evaluate};
 
 const NETWORKS_TAG: &str = "networks";
@@ -170,6 +172,137 @@ pub async fn delete_network_by_id(
     Ok(StatusCode::NO_CONTENT)
 }
 
+#[utoipa::path(
+    put,
+    path = "/networks/{network_id}/eap",
+    params(
+        ("network_id" = i32, Path),
+    ),
+    request_body = SetNetworkEap,
+    responses(
+        (status = StatusCode::NO_CONTENT, description = "EAP settings stored, applied with the next intent"),
+        (status = StatusCode::BAD_REQUEST, description = "Incomplete settings, or not a wpa-eap network"),
+        (status = StatusCode::NOT_FOUND, description = "Network not found"),
+        (status = StatusCode::CONFLICT, description = "Another network already has this SSID and identity"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to store EAP settings"),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = NETWORKS_TAG
+)]
+pub async fn set_network_eap(
+    Path(network_id): Path<i32>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(body): Json<SetNetworkEap>,
+) -> Result<StatusCode, StatusCode> {
+    if !authorization::check(current_user, "devices", "write") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    // Checked with the same rules smithd applies, with the PEM in place of
+    // what will be sealed.
+    let eap = EapCredentials {
+        method: body.method.clone(),
+        identity: body.identity.clone(),
+        password: body.password.clone(),
+        client_cert: body.client_cert.clone(),
+        private_key: body.private_key.clone(),
+        ..Default::default()
+    };
+    if let Err(message) = eap.validate() {
+        warn!(network_id, "Rejected EAP settings: {message}");
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let pems = [&body.ca_cert, &body.client_cert, &body.private_key];
+    if pems
+        .into_iter()
+        .flatten()
+        .any(|pem| !pem.contains("-----BEGIN "))
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let mut tx = state.pg_pool.begin().await.map_err(internal_error(
+        "Failed to begin set_network_eap transaction",
+    ))?;
+
+    let security_type = sqlx::query_scalar!(
+        "SELECT security_type FROM network WHERE id = $1 FOR UPDATE",
+        network_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(internal_error("Failed to lock network"))?
+    .ok_or(StatusCode::NOT_FOUND)?;
+    if security_type.as_deref() != Some("wpa-eap") {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    // Same keys ReportNMProfiles patches in from the device, so a profile
+    // applied from here reports back as the row it came from.
+    let mut patch = json!({
+        "eap": body.method,
+        "pmf": if body.pmf_required { "required" } else { "default" },
+    });
+    if let Some(phase2_auth) = &body.phase2_auth {
+        patch["phase2_auth"] = json!(phase2_auth);
+    }
+    if let Some(anonymous_identity) = &body.anonymous_identity {
+        patch["anonymous_identity"] = json!(anonymous_identity);
+    }
+    sqlx::query!(
+        r#"UPDATE network
+           SET credentials = (credentials - ARRAY['phase2_auth', 'anonymous_identity']) || $2::jsonb,
+               identity = $3
+           WHERE id = $1"#,
+        network_id,
+        patch,
+        json!({ "username": body.identity }),
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|err| {
+        if err.to_string().contains("network_ident_uq") {
+            StatusCode::CONFLICT
+        } else {
+            error!("Failed to update EAP settings of network {network_id}: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    })?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO network_eap_secret
+            (network_id, password, ca_cert, client_cert, private_key, private_key_password)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (network_id) DO UPDATE SET
+            password = EXCLUDED.password,
+            ca_cert = EXCLUDED.ca_cert,
+            client_cert = EXCLUDED.client_cert,
+            private_key = EXCLUDED.private_key,
+            private_key_password = EXCLUDED.private_key_password,
+            updated_at = now()
+        "#,
+        network_id,
+        body.password,
+        body.ca_cert,
+        body.client_cert,
+        body.private_key,
+        body.private_key_password,
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(internal_error("Failed to store EAP secret"))?;
+
+    tx.commit().await.map_err(internal_error(
+        "Failed to commit set_network_eap transaction",
+    ))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
 /// The one construction of the content-addressing `credentials` envelope.
 ///
 /// Both the lock key and the identity match project `->>'psk'`, so a row must be
@@ -0,0 +1,77 @@
+use serde::Serialize;
+use smith::utils::schema::OtaState;
+use sqlx::types::Json as SqlxJson;
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::{Postgres, Transaction};
+use utoipa::ToSchema;
+
+pub mod route;
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DeviceOtaState {
+    pub phase: String,
+    pub target_version: Option<String>,
+    pub current_version: Option<String>,
+    pub progress_percent: i16,
+    pub error: Option<String>,
+    /// Everything the device reported, including download byte counts.
+    #[schema(value_type = Object)]
+    pub state: SqlxJson<OtaState>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Lowercase, as the phase is serialized.
+fn phase_name(state: &OtaState) -> String {
+    serde_json::to_value(state.phase)
+        .ok()
+        .and_then(|phase| phase.as_str().map(str::to_owned))
+        .unwrap_or_default()
+}
+
+pub async fn save_state(
+    device_id: i32,
+    state: &OtaState,
+    tx: &mut Transaction<'_, Postgres>,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO device_ota_state
+            (device_id, phase, target_version, current_version, progress_percent, error, state, updated_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, NOW())
+        ON CONFLICT (device_id) DO UPDATE SET
+            phase = EXCLUDED.phase,
+            target_version = EXCLUDED.target_version,
+            current_version = EXCLUDED.current_version,
+            progress_percent = EXCLUDED.progress_percent,
+            error = EXCLUDED.error,
+            state = EXCLUDED.state,
+            updated_at = NOW()
+        "#,
+        device_id,
+        phase_name(state),
+        state.target_version,
+        state.current_version,
+        state.progress_percent as i16,
+        state.error,
+        SqlxJson(state) as _
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smith::utils::schema::OtaPhase;
+
+    #[test]
+    fn phase_is_stored_as_serialized() {
+        let state = OtaState {
+            phase: OtaPhase::Rebooting,
+            ..Default::default()
+        };
+        assert_eq!(phase_name(&state), "rebooting");
+    }
+}
@@ -0,0 +1,59 @@
+use crate::State;
+use crate::middlewares::authorization;
+use crate::ota::DeviceOtaState;
+use crate::user::CurrentUser;
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::{Extension, Json};
+use smith::utils::schema::OtaState;
+use sqlx::types::Json as SqlxJson;
+use tracing::error;
+
+const TAG: &str = "ota";
+
+#[utoipa::path(
+    get,
+    path = "/devices/{device_id}/ota",
+    params(
+        ("device_id" = i32, Path),
+    ),
+    responses(
+        (status = StatusCode::OK, description = "The last OTA state the device reported", body = DeviceOtaState),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to read devices"),
+        (status = StatusCode::NOT_FOUND, description = "The device has not reported an OTA"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve OTA state"),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn get_ota_state_for_device(
+    Path(device_id): Path<i32>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Result<Json<DeviceOtaState>, StatusCode> {
+    if !authorization::check(current_user, "devices", "read") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let ota = sqlx::query_as!(
+        DeviceOtaState,
+        r#"
+        SELECT phase, target_version, current_version, progress_percent, error,
+            state as "state: SqlxJson<OtaState>", updated_at
+        FROM device_ota_state
+        WHERE device_id = $1
+        "#,
+        device_id
+    )
+    .fetch_optional(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("Failed to get OTA state for device {device_id}: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(Json(ota))
+}
@@ -7,6 +7,7 @@ use crate::config::Config;
 use crate::storage::Storage;
 use serde::Serialize;
 use service::{extract_service_name, is_service_file_path, parse_service_file};
+use smith::utils::schema::is_container_image;
 use sqlx::PgPool;
 use sqlx::types::chrono;
 use std::io::{Cursor, Read};
@@ -114,6 +115,33 @@ impl Package {
         }
     }
 
+    /// Registers a container image. Nothing is uploaded: devices pull the
+    /// image from its registry, pinned to `digest`.
+    pub async fn new_container_image(
+        name: &str,
+        version: &str,
+        architecture: &str,
+        reference: &str,
+        digest: &str,
+        pool: &PgPool,
+    ) -> anyhow::Result<Package> {
+        let file = format!("{reference}@{digest}");
+        Ok(sqlx::query_as!(
+            Package,
+            "
+          INSERT INTO package (name, version, architecture, file)
+          VALUES ($1, $2, $3, $4)
+          RETURNING *
+          ",
+            name,
+            version,
+            architecture,
+            file
+        )
+        .fetch_one(pool)
+        .await?)
+    }
+
     pub async fn delete(
         package_id: &i32,
         config: &'static Config,
@@ -127,7 +155,9 @@ impl Package {
         )
         .fetch_one(pool)
         .await?;
-        Storage::delete_from_s3(&config.packages_bucket_name, &package.file).await?;
+        if !is_container_image(&package.file) {
+            Storage::delete_from_s3(&config.packages_bucket_name, &package.file).await?;
+        }
         Ok(package)
     }
 }
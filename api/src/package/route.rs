@@ -135,6 +135,83 @@ pub async fn release_package(
     Ok(StatusCode::OK)
 }
 
+#[derive(Debug, Deserialize, utoipa::ToSchema)]
+pub struct RegisterContainerImage {
+    pub name: String,
+    pub version: String,
+    /// `all` for multi-arch images.
+    #[serde(default = "RegisterContainerImage::default_architecture")]
+    pub architecture: String,
+    /// The image without tag or digest, e.g. `ghcr.io/teton/vision`.
+    pub reference: String,
+    /// `sha256:<64 hex digits>`, the manifest or index devices pull.
+    pub digest: String,
+}
+
+impl RegisterContainerImage {
+    fn default_architecture() -> String {
+        "all".to_string()
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() || self.version.trim().is_empty() {
+            return Err("name and version are required".to_string());
+        }
+        if self.reference.is_empty() || self.reference.contains(['@', ' ']) {
+            return Err(format!("{:?} is not an image reference", self.reference));
+        }
+        let valid_digest = self.digest.strip_prefix("sha256:").is_some_and(|hex| {
+            hex.len() == 64
+                && hex
+                    .chars()
+                    .all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase())
+        });
+        if !valid_digest {
+            return Err(format!("{:?} is not a sha256 digest", self.digest));
+        }
+        Ok(())
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/packages/containers",
+    request_body = RegisterContainerImage,
+    responses(
+        (status = 201, description = "Container image registered as a package", body = Package),
+        (status = 400, description = "Invalid reference or digest"),
+        (status = 500, description = "Failure", body = String),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = PACKAGES_TAG
+)]
+pub async fn register_container_image(
+    Extension(state): Extension<State>,
+    Json(image): Json<RegisterContainerImage>,
+) -> axum::response::Result<(StatusCode, Json<Package>), StatusCode> {
+    if let Err(err) = image.validate() {
+        error!("Rejected container image {}: {err}", image.name);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let package = Package::new_container_image(
+        &image.name,
+        &image.version,
+        &image.architecture,
+        &image.reference,
+        &image.digest,
+        &state.pg_pool,
+    )
+    .await
+    .map_err(|err| {
+        error!("error: Failed to save container image: {:?}", err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok((StatusCode::CREATED, Json(package)))
+}
+
 /// Parses AppImage filename in format "Name_Version_Arch.AppImage"
 /// e.g. "Teton Craft Table_0.1.0_amd64.AppImage" -> ("Teton Craft Table", "0.1.0", "amd64")
 fn parse_appimage_filename(filename: &str) -> Option<(String, String, String)> {
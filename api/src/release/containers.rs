@@ -0,0 +1,144 @@
+use smith::utils::schema::{CONTAINER_SERVICE_PREFIX, ContainerSpec, is_container_image};
+use sqlx::types::Json as SqlxJson;
+use sqlx::{PgPool, Postgres, Transaction};
+
+/// Service checks for a container without a healthcheck of its own run this often.
+const DEFAULT_WATCHDOG_SEC: i32 = 30;
+
+/// The spec as saved, with images named by package.
+pub async fn get_spec(release_id: i32, pool: &PgPool) -> sqlx::Result<ContainerSpec> {
+    let spec = sqlx::query_scalar!(
+        r#"SELECT spec as "spec: SqlxJson<ContainerSpec>" FROM release_container_spec WHERE release_id = $1"#,
+        release_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(spec.map(|spec| spec.0).unwrap_or_default())
+}
+
+/// The release's container image packages, as `(name, reference@digest)`.
+pub async fn get_images(release_id: i32, pool: &PgPool) -> sqlx::Result<Vec<(String, String)>> {
+    let packages = sqlx::query!(
+        "SELECT package.name, package.file
+         FROM release_packages
+         JOIN package ON package.id = release_packages.package_id
+         WHERE release_packages.release_id = $1",
+        release_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(packages
+        .into_iter()
+        .filter(|p| is_container_image(&p.file))
+        .map(|p| (p.name, p.file))
+        .collect())
+}
+
+/// Points every service at the pinned reference of the image package it names.
+pub fn resolve(
+    spec: &ContainerSpec,
+    images: &[(String, String)],
+) -> Result<ContainerSpec, Vec<String>> {
+    let mut resolved = spec.clone();
+    let mut errors = Vec::new();
+    for (name, service) in resolved.services.iter_mut() {
+        match images.iter().find(|(package, _)| *package == service.image) {
+            Some((_, reference)) => service.image = reference.clone(),
+            None => errors.push(format!(
+                "services.{name}.image: the release has no container image {:?}",
+                service.image
+            )),
+        }
+    }
+    if errors.is_empty() {
+        Ok(resolved)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Saves the spec and registers a service check for each of its containers.
+pub async fn save_spec(
+    release_id: i32,
+    spec: &ContainerSpec,
+    tx: &mut Transaction<'_, Postgres>,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        "INSERT INTO release_container_spec (release_id, spec)
+         VALUES ($1, $2)
+         ON CONFLICT (release_id) DO UPDATE SET spec = EXCLUDED.spec, updated_at = NOW()",
+        release_id,
+        SqlxJson(spec) as _
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    // Checks of containers the spec keeps stay, and with them their statuses.
+    let names: Vec<String> = spec
+        .services
+        .keys()
+        .map(|name| format!("{CONTAINER_SERVICE_PREFIX}{name}"))
+        .collect();
+    sqlx::query!(
+        "DELETE FROM release_services
+         WHERE release_id = $1 AND package_id IS NULL AND starts_with(service_name, $2)
+           AND NOT (service_name = ANY($3))",
+        release_id,
+        CONTAINER_SERVICE_PREFIX,
+        &names
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    for (name, service) in &spec.services {
+        let watchdog_sec = service
+            .healthcheck
+            .as_ref()
+            .map_or(DEFAULT_WATCHDOG_SEC, |check| check.interval_sec as i32);
+        sqlx::query!(
+            "INSERT INTO release_services (release_id, package_id, service_name, watchdog_sec)
+             VALUES ($1, NULL, $2, $3)
+             ON CONFLICT (release_id, service_name) DO UPDATE SET watchdog_sec = EXCLUDED.watchdog_sec",
+            release_id,
+            format!("{CONTAINER_SERVICE_PREFIX}{name}"),
+            watchdog_sec
+        )
+        .execute(&mut **tx)
+        .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smith::utils::schema::ContainerService;
+
+    #[test]
+    fn images_resolve_to_the_release_packages() {
+        let mut spec = ContainerSpec::default();
+        spec.services.insert(
+            "vision".into(),
+            ContainerService {
+                image: "vision".into(),
+                ..Default::default()
+            },
+        );
+        let images = vec![(
+            "vision".to_string(),
+            "ghcr.io/teton/vision@sha256:abc".to_string(),
+        )];
+
+        let resolved = resolve(&spec, &images).unwrap();
+        assert_eq!(
+            resolved.services["vision"].image,
+            "ghcr.io/teton/vision@sha256:abc"
+        );
+
+        let errors = resolve(&spec, &[]).unwrap_err();
+        assert_eq!(
+            errors,
+            vec!["services.vision.image: the release has no container image \"vision\""]
+        );
+    }
+}
@@ -0,0 +1,31 @@
+use smith::utils::schema::ReleaseHooks;
+use sqlx::types::Json as SqlxJson;
+use sqlx::{PgPool, Postgres, Transaction};
+
+/// The release's hooks; none if it was never given any.
+pub async fn get_hooks(release_id: i32, pool: &PgPool) -> sqlx::Result<ReleaseHooks> {
+    let hooks = sqlx::query_scalar!(
+        r#"SELECT hooks as "hooks: SqlxJson<ReleaseHooks>" FROM release_hooks WHERE release_id = $1"#,
+        release_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(hooks.map(|hooks| hooks.0).unwrap_or_default())
+}
+
+pub async fn save_hooks(
+    release_id: i32,
+    hooks: &ReleaseHooks,
+    tx: &mut Transaction<'_, Postgres>,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        "INSERT INTO release_hooks (release_id, hooks)
+         VALUES ($1, $2)
+         ON CONFLICT (release_id) DO UPDATE SET hooks = EXCLUDED.hooks, updated_at = NOW()",
+        release_id,
+        SqlxJson(hooks) as _
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
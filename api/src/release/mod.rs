@@ -1,5 +1,7 @@
 use models::release::Release;
 
+pub mod containers;
+pub mod hooks;
 pub mod route;
 
 pub async fn get_release_by_id(
@@ -1,6 +1,6 @@
 use crate::State;
 use crate::package::{Package, extract_services_from_deb};
-use crate::release::{Release, get_release_by_id};
+use crate::release::{Release, containers, get_release_by_id, hooks};
 use crate::storage::Storage;
 use crate::user::CurrentUser;
 use axum::extract::Path;
@@ -8,6 +8,7 @@ use axum::http::StatusCode;
 use axum::{Extension, Json};
 use models::release::UpdateRelease;
 use serde::{Deserialize, Serialize};
+use smith::utils::schema::{ContainerSpec, ReleaseHooks};
 use sqlx::types::chrono;
 use tracing::{error, warn};
 
@@ -595,6 +596,213 @@ pub async fn delete_release_service(
     Ok(StatusCode::NO_CONTENT)
 }
 
+#[utoipa::path(
+    get,
+    path = "/releases/{release_id}/containers",
+    params(
+        ("release_id" = i32, Path, description = "Release ID")
+    ),
+    responses(
+        (status = StatusCode::OK, description = "The containers the release runs, images named by package", body = Object),
+        (status = StatusCode::NOT_FOUND, description = "Release not found"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve the container spec"),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = RELEASES_TAG
+)]
+pub async fn get_release_containers(
+    Path(release_id): Path<i32>,
+    Extension(state): Extension<State>,
+) -> axum::response::Result<Json<ContainerSpec>, StatusCode> {
+    get_release_by_id(release_id, &state.pg_pool)
+        .await
+        .map_err(|err| {
+            error!("Failed to get release: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let spec = containers::get_spec(release_id, &state.pg_pool)
+        .await
+        .map_err(|err| {
+            error!("Failed to get container spec: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(spec))
+}
+
+#[utoipa::path(
+    put,
+    path = "/releases/{release_id}/containers",
+    params(
+        ("release_id" = i32, Path, description = "Release ID")
+    ),
+    request_body(content = Object, description = "Compose-like services, each naming a container image package of the release"),
+    responses(
+        (status = StatusCode::OK, description = "Container spec saved, with a service check per container", body = Object),
+        (status = StatusCode::BAD_REQUEST, description = "Invalid spec, or an image the release does not have"),
+        (status = StatusCode::NOT_FOUND, description = "Release not found"),
+        (status = StatusCode::CONFLICT, description = "Release is yanked or not in draft"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to save the container spec"),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = RELEASES_TAG
+)]
+pub async fn set_release_containers(
+    Path(release_id): Path<i32>,
+    Extension(state): Extension<State>,
+    Json(spec): Json<ContainerSpec>,
+) -> axum::response::Result<Json<ContainerSpec>, StatusCode> {
+    let release = get_release_by_id(release_id, &state.pg_pool)
+        .await
+        .map_err(|err| {
+            error!("Failed to get release: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if release.yanked || !release.draft {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let images = containers::get_images(release_id, &state.pg_pool)
+        .await
+        .map_err(|err| {
+            error!("Failed to get release images: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if let Err(errors) = spec
+        .validate()
+        .and_then(|()| containers::resolve(&spec, &images).map(|_| ()))
+    {
+        warn!(
+            "Rejected container spec for release {release_id}: {}",
+            errors.join("; ")
+        );
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let mut tx = state.pg_pool.begin().await.map_err(|err| {
+        error!("Failed to start transaction: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    containers::save_spec(release_id, &spec, &mut tx)
+        .await
+        .map_err(|err| {
+            error!("Failed to save container spec: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    tx.commit().await.map_err(|err| {
+        error!("Failed to commit transaction: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(spec))
+}
+
+#[utoipa::path(
+    get,
+    path = "/releases/{release_id}/hooks",
+    params(
+        ("release_id" = i32, Path, description = "Release ID")
+    ),
+    responses(
+        (status = StatusCode::OK, description = "The scripts the release runs around its install", body = Object),
+        (status = StatusCode::NOT_FOUND, description = "Release not found"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve the release hooks"),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = RELEASES_TAG
+)]
+pub async fn get_release_hooks(
+    Path(release_id): Path<i32>,
+    Extension(state): Extension<State>,
+) -> axum::response::Result<Json<ReleaseHooks>, StatusCode> {
+    get_release_by_id(release_id, &state.pg_pool)
+        .await
+        .map_err(|err| {
+            error!("Failed to get release: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let release_hooks = hooks::get_hooks(release_id, &state.pg_pool)
+        .await
+        .map_err(|err| {
+            error!("Failed to get release hooks: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(release_hooks))
+}
+
+#[utoipa::path(
+    put,
+    path = "/releases/{release_id}/hooks",
+    params(
+        ("release_id" = i32, Path, description = "Release ID")
+    ),
+    request_body(content = Object, description = "Optional pre_install, post_install and verify scripts, each with a timeout_sec"),
+    responses(
+        (status = StatusCode::OK, description = "Release hooks saved", body = Object),
+        (status = StatusCode::BAD_REQUEST, description = "An empty script or an out of range timeout"),
+        (status = StatusCode::NOT_FOUND, description = "Release not found"),
+        (status = StatusCode::CONFLICT, description = "Release is yanked or not in draft"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to save the release hooks"),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = RELEASES_TAG
+)]
+pub async fn set_release_hooks(
+    Path(release_id): Path<i32>,
+    Extension(state): Extension<State>,
+    Json(release_hooks): Json<ReleaseHooks>,
+) -> axum::response::Result<Json<ReleaseHooks>, StatusCode> {
+    let release = get_release_by_id(release_id, &state.pg_pool)
+        .await
+        .map_err(|err| {
+            error!("Failed to get release: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if release.yanked || !release.draft {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    if let Err(errors) = release_hooks.validate() {
+        warn!(
+            "Rejected hooks for release {release_id}: {}",
+            errors.join("; ")
+        );
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let mut tx = state.pg_pool.begin().await.map_err(|err| {
+        error!("Failed to start transaction: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    hooks::save_hooks(release_id, &release_hooks, &mut tx)
+        .await
+        .map_err(|err| {
+            error!("Failed to save release hooks: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    tx.commit().await.map_err(|err| {
+        error!("Failed to commit transaction: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(release_hooks))
+}
+
 #[derive(Debug, Deserialize, utoipa::ToSchema)]
 pub struct PromoteReleaseRequest {
     pub version: String,
@@ -692,6 +900,33 @@ pub async fn promote_release(
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
 
+    // Copy containers; their service checks came with the services
+    sqlx::query!(
+        "INSERT INTO release_container_spec (release_id, spec)
+         SELECT $1, spec FROM release_container_spec WHERE release_id = $2",
+        new_release.id,
+        release_id,
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|err| {
+        error!("Failed to copy container spec: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    sqlx::query!(
+        "INSERT INTO release_hooks (release_id, hooks)
+         SELECT $1, hooks FROM release_hooks WHERE release_id = $2",
+        new_release.id,
+        release_id,
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|err| {
+        error!("Failed to copy release hooks: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
     tx.commit().await.map_err(|err| {
         error!("Failed to commit transaction: {err}");
         StatusCode::INTERNAL_SERVER_ERROR
@@ -0,0 +1,92 @@
+use crate::home::add_commands_in_tx;
+use serde::{Deserialize, Serialize};
+use smith::utils::schema::{SafeCommandRequest, SafeCommandTx, SealedSecret, SecretDelivery};
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::{Postgres, Transaction};
+use utoipa::ToSchema;
+
+pub mod route;
+
+/// Queued command id for `UpdateSecrets`, matching the id smithd reports
+/// `GetSecrets` under.
+const UPDATE_SECRETS_CMD_ID: i32 = -7;
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SecretSummary {
+    pub name: String,
+    pub delivery: String,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SetSecret {
+    pub value: String,
+    /// `file` (default) writes `/etc/smith/secrets/<name>`, `env` adds a line to
+    /// `/etc/smith/secrets.env`.
+    #[serde(default)]
+    #[schema(value_type = String)]
+    pub delivery: SecretDelivery,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RotateSecret {
+    pub value: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RotateSecretResult {
+    pub rotated: Vec<i32>,
+    /// Devices holding the secret that have not reported a public key since it
+    /// was set, so there is nothing to seal the new value to.
+    pub skipped: Vec<i32>,
+}
+
+pub fn delivery_str(delivery: SecretDelivery) -> &'static str {
+    match delivery {
+        SecretDelivery::File => "file",
+        SecretDelivery::Env => "env",
+    }
+}
+
+fn parse_delivery(delivery: &str) -> SecretDelivery {
+    match delivery {
+        "env" => SecretDelivery::Env,
+        _ => SecretDelivery::File,
+    }
+}
+
+/// Queues the device's complete secret set, as `tx` has it. The daemon treats
+/// it as the whole truth, so this is what both adding and removing a secret send.
+pub async fn queue_update_secrets(
+    device_id: i32,
+    serial_number: &str,
+    tx: &mut Transaction<'_, Postgres>,
+) -> anyhow::Result<()> {
+    let secrets = sqlx::query!(
+        "SELECT name, delivery, ciphertext FROM device_secret WHERE device_id = $1 ORDER BY name",
+        device_id
+    )
+    .fetch_all(&mut **tx)
+    .await?
+    .into_iter()
+    .map(|row| SealedSecret {
+        name: row.name,
+        ciphertext: row.ciphertext,
+        delivery: parse_delivery(&row.delivery),
+    })
+    .collect();
+
+    add_commands_in_tx(
+        serial_number,
+        vec![SafeCommandRequest {
+            id: UPDATE_SECRETS_CMD_ID,
+            command: SafeCommandTx::UpdateSecrets { secrets },
+            continue_on_error: false,
+        }],
+        tx,
+        None,
+    )
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,358 @@
+use crate::State;
+use crate::middlewares::authorization;
+use crate::secret::{
+    RotateSecret, RotateSecretResult, SecretSummary, SetSecret, delivery_str, queue_update_secrets,
+};
+use crate::user::CurrentUser;
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::{Extension, Json};
+use smith::secrets;
+use tracing::{error, warn};
+
+const TAG: &str = "secrets";
+
+#[utoipa::path(
+    get,
+    path = "/devices/{device_id}/secrets",
+    params(
+        ("device_id" = i32, Path),
+    ),
+    responses(
+        (status = StatusCode::OK, description = "Secrets set on the device, without their values", body = Vec<SecretSummary>),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to read devices"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve secrets"),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn get_secrets_for_device(
+    Path(device_id): Path<i32>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Result<Json<Vec<SecretSummary>>, StatusCode> {
+    if !authorization::check(current_user, "devices", "read") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let secrets = sqlx::query_as!(
+        SecretSummary,
+        "SELECT name, delivery, updated_at FROM device_secret WHERE device_id = $1 ORDER BY name",
+        device_id
+    )
+    .fetch_all(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("Failed to get secrets for device {device_id}: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(secrets))
+}
+
+#[utoipa::path(
+    put,
+    path = "/devices/{device_id}/secrets/{name}",
+    params(
+        ("device_id" = i32, Path),
+        ("name" = String, Path),
+    ),
+    request_body = SetSecret,
+    responses(
+        (status = StatusCode::NO_CONTENT, description = "Secret sealed and queued for the device"),
+        (status = StatusCode::BAD_REQUEST, description = "Secret name is not [A-Za-z0-9_]"),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to manage secrets"),
+        (status = StatusCode::NOT_FOUND, description = "Device not found"),
+        (status = StatusCode::CONFLICT, description = "Device has not reported a secrets key yet"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to set secret"),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn set_secret_for_device(
+    Path((device_id, name)): Path<(i32, String)>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(body): Json<SetSecret>,
+) -> Result<StatusCode, StatusCode> {
+    if !authorization::check(current_user, "commands", "secrets") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    if let Err(err) = secrets::validate_name(&name) {
+        warn!("Rejected secret name {name:?}: {err}");
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let device = sqlx::query!(
+        "SELECT serial_number, secrets_public_key FROM device WHERE id = $1",
+        device_id
+    )
+    .fetch_optional(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        error!("Failed to fetch device {device_id}: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    let public_key = device.secrets_public_key.ok_or(StatusCode::CONFLICT)?;
+    let ciphertext = secrets::seal(&public_key, body.value.as_bytes()).map_err(|err| {
+        error!("Failed to seal secret for device {device_id}: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let mut tx = state.pg_pool.begin().await.map_err(|err| {
+        error!("Failed to start transaction {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO device_secret (device_id, name, delivery, ciphertext)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (device_id, name) DO UPDATE SET
+            delivery = EXCLUDED.delivery,
+            ciphertext = EXCLUDED.ciphertext,
+            updated_at = NOW()
+        "#,
+        device_id,
+        name,
+        delivery_str(body.delivery),
+        ciphertext
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|err| {
+        error!("Failed to save secret for device {device_id}: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    sqlx::query!(
+        r#"INSERT INTO ledger (device_id, "class", "text") VALUES ($1, $2, $3)"#,
+        device_id,
+        "secret",
+        format!("Secret \"{name}\" set.")
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|err| {
+        error!("Failed to insert ledger entry for device {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    queue_update_secrets(device_id, &device.serial_number, &mut tx)
+        .await
+        .map_err(|err| {
+            error!("Failed to queue secrets for device {device_id}: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    tx.commit().await.map_err(|err| {
+        error!("Failed to commit transaction {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/devices/{device_id}/secrets/{name}",
+    params(
+        ("device_id" = i32, Path),
+        ("name" = String, Path),
+    ),
+    responses(
+        (status = StatusCode::NO_CONTENT, description = "Secret removed and the device told to drop it"),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to manage secrets"),
+        (status = StatusCode::NOT_FOUND, description = "Secret not found"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to delete secret"),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn delete_secret_for_device(
+    Path((device_id, name)): Path<(i32, String)>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Result<StatusCode, StatusCode> {
+    if !authorization::check(current_user, "commands", "secrets") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let mut tx = state.pg_pool.begin().await.map_err(|err| {
+        error!("Failed to start transaction {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let serial_number = sqlx::query_scalar!(
+        r#"
+        DELETE FROM device_secret s
+        USING device d
+        WHERE s.device_id = $1 AND s.name = $2 AND d.id = s.device_id
+        RETURNING d.serial_number
+        "#,
+        device_id,
+        name
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|err| {
+        error!("Failed to delete secret for device {device_id}: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    sqlx::query!(
+        r#"INSERT INTO ledger (device_id, "class", "text") VALUES ($1, $2, $3)"#,
+        device_id,
+        "secret",
+        format!("Secret \"{name}\" deleted.")
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|err| {
+        error!("Failed to insert ledger entry for device {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    queue_update_secrets(device_id, &serial_number, &mut tx)
+        .await
+        .map_err(|err| {
+            error!("Failed to queue secrets for device {device_id}: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    tx.commit().await.map_err(|err| {
+        error!("Failed to commit transaction {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    post,
+    path = "/secrets/{name}/rotate",
+    params(
+        ("name" = String, Path),
+    ),
+    request_body = RotateSecret,
+    responses(
+        (status = StatusCode::OK, description = "New value sealed for every device holding the secret", body = RotateSecretResult),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to manage secrets"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to rotate secret; no device was changed"),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn rotate_secret(
+    Path(name): Path<String>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(body): Json<RotateSecret>,
+) -> Result<Json<RotateSecretResult>, StatusCode> {
+    if !authorization::check(current_user, "commands", "secrets") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    // One transaction for every device, so a failure rotates none of them
+    // rather than leaving the caller to guess which ones took the new value.
+    let mut tx = state.pg_pool.begin().await.map_err(|err| {
+        error!("Failed to start transaction {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let devices = sqlx::query!(
+        r#"
+        SELECT d.id, d.serial_number, d.secrets_public_key
+        FROM device_secret s
+        JOIN device d ON d.id = s.device_id
+        WHERE s.name = $1
+        ORDER BY d.id
+        FOR UPDATE OF s
+        "#,
+        name
+    )
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(|err| {
+        error!("Failed to fetch devices holding secret {name}: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let mut result = RotateSecretResult {
+        rotated: Vec::new(),
+        skipped: Vec::new(),
+    };
+
+    for device in devices {
+        let Some(public_key) = device.secrets_public_key else {
+            result.skipped.push(device.id);
+            continue;
+        };
+
+        let ciphertext = match secrets::seal(&public_key, body.value.as_bytes()) {
+            Ok(ciphertext) => ciphertext,
+            Err(err) => {
+                warn!(
+                    "Failed to seal secret {name} for device {}: {err}",
+                    device.id
+                );
+                result.skipped.push(device.id);
+                continue;
+            }
+        };
+
+        sqlx::query!(
+            "UPDATE device_secret SET ciphertext = $3, updated_at = NOW() WHERE device_id = $1 AND name = $2",
+            device.id,
+            name,
+            ciphertext
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|err| {
+            error!("Failed to rotate secret {name} for device {}: {err}", device.id);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+        sqlx::query!(
+            r#"INSERT INTO ledger (device_id, "class", "text") VALUES ($1, $2, $3)"#,
+            device.id,
+            "secret",
+            format!("Secret \"{name}\" rotated.")
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|err| {
+            error!("Failed to insert ledger entry for device {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+        queue_update_secrets(device.id, &device.serial_number, &mut tx)
+            .await
+            .map_err(|err| {
+                error!("Failed to queue secrets for device {}: {err}", device.id);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+
+        result.rotated.push(device.id);
+    }
+
+    tx.commit().await.map_err(|err| {
+        error!("Failed to commit transaction {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(result))
+}
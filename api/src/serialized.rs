@@ -0,0 +1,22 @@
+use serde::Serialize;
+
+/// The name an enum of the schema is serialized as, e.g. `critical`.
+/// Stored in place of the Rust name so the database matches the wire format.
+pub(crate) fn serialized_name(value: impl Serialize) -> String {
+    serde_json::to_value(value)
+        .ok()
+        .and_then(|name| name.as_str().map(str::to_owned))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smith::utils::schema::{AuditSeverity, IntegrityChangeKind};
+
+    #[test]
+    fn enums_are_named_as_serialized() {
+        assert_eq!(serialized_name(AuditSeverity::Critical), "critical");
+        assert_eq!(serialized_name(IntegrityChangeKind::Removed), "removed");
+    }
+}
@@ -3,6 +3,7 @@ use crate::device::{
 };
 use crate::handlers::AuthedDevice;
 use crate::ip_address::extract_client_ip;
+use crate::release::{containers, hooks};
 use crate::storage::Storage;
 use crate::{State, storage};
 use axum::body::{Body, Bytes};
@@ -13,8 +14,8 @@ use axum::{Extension, Json};
 use futures::stream;
 use serde::{Deserialize, Serialize};
 use smith::utils::schema::{
-    DeviceRegistration, DeviceRegistrationResponse, HomePost, HomePostResponse, Package,
-    ServiceCheck,
+    ContainerSpec, DeviceRegistration, DeviceRegistrationResponse, HomePost, HomePostResponse,
+    Package, ReleaseHooks, ServiceCheck,
 };
 use std::convert::Infallible;
 use std::net::SocketAddr;
@@ -28,6 +29,7 @@ use utoipa::{IntoParams, ToSchema};
   responses(
         (status = 200, description = "Device registration successful"),
         (status = 403, description = "Device not approved"),
+        (status = 401, description = "Device identity could not be verified"),
         (status = 409, description = "Device already has token"),
         (status = 500, description = "Internal server error")
   )
@@ -45,6 +47,7 @@ pub async fn register_device(
             let status_code = match e {
                 RegistrationError::NotNullTokenError => StatusCode::CONFLICT,
                 RegistrationError::NotApprovedDevice => StatusCode::FORBIDDEN,
+                RegistrationError::InvalidIdentity => StatusCode::UNAUTHORIZED,
                 _ => StatusCode::INTERNAL_SERVER_ERROR,
             };
 
@@ -91,6 +94,20 @@ pub async fn home(
     };
     let release_id = payload.release_id;
     let service_statuses = std::mem::take(&mut payload.service_statuses);
+    let vpn_status = payload.vpn.take();
+    // Stored before responding: `connectivity_saved` is what tells the device
+    // it may forget these transitions.
+    let connectivity_saved = match payload.connectivity.take() {
+        Some(connectivity) => {
+            crate::connectivity::save_report(device.id, &connectivity, &state.pg_pool)
+                .await
+                .inspect_err(|err| {
+                    error!("Error saving connectivity: {:?}", err);
+                })
+                .is_ok()
+        }
+        None => false,
+    };
     let _ = crate::home::save_responses(device.id, &device.serial_number, payload, &state.pg_pool)
         .await
         .inspect_err(|err| {
@@ -122,6 +139,8 @@ pub async fn home(
             .unwrap_or(Vec::new()),
         target_release_id,
         services,
+        mirrors: state.config.download_mirrors.clone(),
+        connectivity_saved,
     };
 
     let client_ip = Some(extract_client_ip(&headers, addr));
@@ -131,6 +150,13 @@ pub async fn home(
             .inspect_err(|err| {
                 error!("Error saving release_id: {:?}", err);
             });
+        if let Some(status) = vpn_status {
+            let _ = crate::vpn::save_status(device.id, &status, &state.pg_pool)
+                .await
+                .inspect_err(|err| {
+                    error!("Error saving VPN status: {:?}", err);
+                });
+        }
         let _ = save_last_ping_with_ip(device.id, client_ip, &state.pg_pool, state.config)
             .await
             .inspect_err(|err| {
@@ -386,6 +412,75 @@ pub async fn list_release_packages(
     Ok(Json(packages))
 }
 
+#[utoipa::path(
+  get,
+  path = "/smith/releases/{release_id}/containers",
+  params(
+        ("release_id" = i32, Path, description = "Release ID")
+  ),
+  responses(
+        (status = 200, description = "The containers the release runs, images pinned by digest"),
+        (status = 500, description = "Internal server error")
+  ),
+  security(
+        ("device_token" = [])
+  ),
+)]
+pub async fn list_release_containers(
+    _device: AuthedDevice,
+    Path(release_id): Path<i32>,
+    Extension(state): Extension<State>,
+) -> Result<Json<ContainerSpec>, StatusCode> {
+    let (spec, images) = tokio::try_join!(
+        containers::get_spec(release_id, &state.pg_pool),
+        containers::get_images(release_id, &state.pg_pool)
+    )
+    .map_err(|err| {
+        error!("Failed to get containers for release {release_id}: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    // A package removed from the release after its spec was saved.
+    let spec = containers::resolve(&spec, &images).map_err(|errors| {
+        error!(
+            "Container spec of release {release_id} no longer resolves: {}",
+            errors.join("; ")
+        );
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(spec))
+}
+
+#[utoipa::path(
+  get,
+  path = "/smith/releases/{release_id}/hooks",
+  params(
+        ("release_id" = i32, Path, description = "Release ID")
+  ),
+  responses(
+        (status = 200, description = "The scripts the release runs around its install"),
+        (status = 500, description = "Internal server error")
+  ),
+  security(
+        ("device_token" = [])
+  ),
+)]
+pub async fn list_release_hooks(
+    _device: AuthedDevice,
+    Path(release_id): Path<i32>,
+    Extension(state): Extension<State>,
+) -> Result<Json<ReleaseHooks>, StatusCode> {
+    let release_hooks = hooks::get_hooks(release_id, &state.pg_pool)
+        .await
+        .map_err(|err| {
+            error!("Failed to get hooks for release {release_id}: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(release_hooks))
+}
+
 #[utoipa::path(
     get,
     path = "/smith/network/test-file",
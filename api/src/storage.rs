@@ -2,6 +2,7 @@ use axum::response::Response;
 use cloudfront_sign::{SignedOptions, get_signed_url};
 use s3::creds::Credentials;
 use s3::{Bucket, Region};
+use sha2::{Digest, Sha256};
 use std::borrow::Cow;
 use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -16,7 +17,10 @@ impl Storage {
     ) -> anyhow::Result<()> {
         let region = Region::from_default_env()?;
         let credentials = Credentials::default()?;
-        let bucket = Bucket::new(bucket_name, region, credentials)?;
+        let mut bucket = Bucket::new(bucket_name, region, credentials)?;
+        // Kept as object metadata so downloads can be verified by digest,
+        // whichever mirror ends up serving them.
+        bucket.add_header("x-amz-meta-sha256", &sha256_hex(data));
 
         let object_key = match path {
             Some(p) => format!("{}/{}", p, file_name),
@@ -120,6 +124,11 @@ impl Storage {
             .e_tag
             .ok_or_else(|| anyhow::anyhow!("ETag missing"))?;
 
+        // Objects uploaded before digests were recorded have none.
+        let sha256 = head_object
+            .metadata
+            .and_then(|mut metadata| metadata.remove("sha256"));
+
         let cloudfront_url = format!("{}/package-download/{}", cdn_domain, object_key);
 
         // Generate CDN signed URL
@@ -135,13 +144,37 @@ impl Storage {
 
         let signed_url = get_signed_url(&cloudfront_url, &options)?;
 
-        let response = axum::response::Response::builder()
+        let mut response = axum::response::Response::builder()
             .header(axum::http::header::LOCATION, signed_url)
             .header("X-File-Size", content_length)
-            .header(axum::http::header::ETAG, etag)
+            .header(axum::http::header::ETAG, etag);
+        if let Some(sha256) = sha256 {
+            response = response.header("X-Content-Sha256", sha256);
+        }
+        let response = response
             .body(axum::body::Body::empty())
             .map_err(anyhow::Error::from)?;
 
         Ok(response)
     }
 }
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sha256_is_lowercase_hex() {
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
@@ -0,0 +1,318 @@
+use crate::home::add_commands_in_tx;
+use serde::{Deserialize, Serialize};
+use smith::utils::schema::{SafeCommandRequest, SafeCommandTx, VpnConfig, VpnStatus};
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::types::ipnetwork::IpNetwork;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::HashSet;
+use std::net::IpAddr;
+use utoipa::ToSchema;
+
+pub mod route;
+
+/// Queued command id for `ApplyVpn`, matching the id smithd reports its
+/// WireGuard key under.
+const APPLY_VPN_CMD_ID: i32 = -14;
+
+/// A WireGuard concentrator devices peer with.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Vpn {
+    pub id: i32,
+    pub name: String,
+    /// `host:port` devices connect to.
+    pub endpoint: String,
+    pub public_key: String,
+    /// Where device addresses come from. The first host is the concentrator's.
+    pub address_pool: String,
+    /// What devices route through the tunnel.
+    pub allowed_ips: Vec<String>,
+    pub dns: Vec<String>,
+    pub persistent_keepalive: Option<i32>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewVpn {
+    pub name: String,
+    pub endpoint: String,
+    pub public_key: String,
+    /// e.g. `10.90.0.0/16`.
+    pub address_pool: String,
+    pub allowed_ips: Vec<String>,
+    #[serde(default)]
+    pub dns: Vec<String>,
+    pub persistent_keepalive: Option<u16>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SetVpnIntent {
+    pub vpn_id: i32,
+}
+
+/// A device's place on a concentrator, and its tunnel as last reported.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct VpnPeer {
+    pub device_id: i32,
+    pub serial_number: String,
+    pub vpn_id: i32,
+    /// Missing until the device reports its key; the concentrator can't add
+    /// the peer before then.
+    pub public_key: Option<String>,
+    /// The device's tunnel address, e.g. `10.90.0.7/32`.
+    pub address: String,
+    pub latest_handshake_at: Option<DateTime<Utc>>,
+    pub rx_bytes: Option<i64>,
+    pub tx_bytes: Option<i64>,
+    pub status_updated_at: Option<DateTime<Utc>>,
+}
+
+/// The first address of `pool` that isn't taken, skipping the network
+/// address, the concentrator's and, for IPv4, the broadcast address.
+fn free_address(pool: IpNetwork, taken: &HashSet<IpAddr>) -> Option<IpAddr> {
+    let broadcast = pool.is_ipv4().then(|| pool.broadcast());
+    pool.iter()
+        .skip(2)
+        .filter(|ip| Some(*ip) != broadcast)
+        .find(|ip| !taken.contains(ip))
+}
+
+/// The device's side of the tunnel, given its address on `vpn`.
+pub fn device_config(vpn: &Vpn, address: IpAddr) -> VpnConfig {
+    VpnConfig {
+        address: IpNetwork::from(address).to_string(),
+        peer_public_key: vpn.public_key.clone(),
+        endpoint: vpn.endpoint.clone(),
+        allowed_ips: vpn.allowed_ips.clone(),
+        dns: vpn.dns.clone(),
+        persistent_keepalive: vpn
+            .persistent_keepalive
+            .and_then(|secs| u16::try_from(secs).ok()),
+    }
+}
+
+/// Checks a new concentrator with the rules smithd applies, using the
+/// concentrator's own address in place of a device's.
+pub fn validate(vpn: &NewVpn) -> Result<(), String> {
+    let pool: IpNetwork = vpn
+        .address_pool
+        .parse()
+        .map_err(|_| format!("{} is not a valid address pool", vpn.address_pool))?;
+    if pool.ip() != pool.network() {
+        return Err(format!("{} has host bits set", vpn.address_pool));
+    }
+    let concentrator = pool
+        .iter()
+        .nth(1)
+        .ok_or_else(|| format!("{} has no room for peers", vpn.address_pool))?;
+    if free_address(pool, &HashSet::new()).is_none() {
+        return Err(format!("{} has no room for peers", vpn.address_pool));
+    }
+    VpnConfig {
+        address: IpNetwork::from(concentrator).to_string(),
+        peer_public_key: vpn.public_key.clone(),
+        endpoint: vpn.endpoint.clone(),
+        allowed_ips: vpn.allowed_ips.clone(),
+        dns: vpn.dns.clone(),
+        persistent_keepalive: vpn.persistent_keepalive,
+    }
+    .validate()
+}
+
+async fn fetch_vpn(
+    vpn_id: i32,
+    tx: &mut Transaction<'_, Postgres>,
+) -> Result<Option<Vpn>, sqlx::Error> {
+    sqlx::query_as!(
+        Vpn,
+        r#"
+        SELECT id, name, endpoint, public_key, address_pool::TEXT as "address_pool!",
+               allowed_ips, dns, persistent_keepalive, created_at
+        FROM vpn
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        vpn_id
+    )
+    .fetch_optional(&mut **tx)
+    .await
+}
+
+/// Puts the device on `vpn`, keeping its address if it already was. The vpn
+/// row is locked so two devices can't be given the same address. `None` if
+/// there is no such vpn, or its pool is exhausted.
+pub async fn assign(
+    device_id: i32,
+    vpn_id: i32,
+    tx: &mut Transaction<'_, Postgres>,
+) -> anyhow::Result<Option<(Vpn, IpAddr)>> {
+    let Some(vpn) = fetch_vpn(vpn_id, tx).await? else {
+        return Ok(None);
+    };
+
+    let current = sqlx::query!(
+        "SELECT vpn_id, address FROM device_vpn_peer WHERE device_id = $1",
+        device_id
+    )
+    .fetch_optional(&mut **tx)
+    .await?;
+    if let Some(current) = current
+        && current.vpn_id == vpn_id
+    {
+        return Ok(Some((vpn, current.address.ip())));
+    }
+
+    let taken: HashSet<IpAddr> = sqlx::query_scalar!(
+        "SELECT address FROM device_vpn_peer WHERE vpn_id = $1",
+        vpn_id
+    )
+    .fetch_all(&mut **tx)
+    .await?
+    .into_iter()
+    .map(|address| address.ip())
+    .collect();
+    let pool: IpNetwork = vpn.address_pool.parse()?;
+    let Some(address) = free_address(pool, &taken) else {
+        return Ok(None);
+    };
+
+    sqlx::query!(
+        r#"
+        INSERT INTO device_vpn_peer (device_id, vpn_id, address)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (device_id) DO UPDATE SET
+            vpn_id = EXCLUDED.vpn_id,
+            address = EXCLUDED.address,
+            latest_handshake_at = NULL,
+            rx_bytes = NULL,
+            tx_bytes = NULL,
+            status_updated_at = NULL,
+            created_at = NOW()
+        "#,
+        device_id,
+        vpn_id,
+        IpNetwork::from(address)
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(Some((vpn, address)))
+}
+
+/// The config the device should run, `None` if it has no VPN intent.
+pub async fn config_for_device(
+    device_id: i32,
+    tx: &mut Transaction<'_, Postgres>,
+) -> anyhow::Result<Option<VpnConfig>> {
+    let Some(peer) = sqlx::query!(
+        "SELECT vpn_id, address FROM device_vpn_peer WHERE device_id = $1",
+        device_id
+    )
+    .fetch_optional(&mut **tx)
+    .await?
+    else {
+        return Ok(None);
+    };
+    let vpn = fetch_vpn(peer.vpn_id, tx).await?;
+    Ok(vpn.map(|vpn| device_config(&vpn, peer.address.ip())))
+}
+
+/// Queues the tunnel the device should run, or its removal, within `tx`.
+pub async fn queue_apply_vpn(
+    serial_number: &str,
+    vpn: Option<VpnConfig>,
+    tx: &mut Transaction<'_, Postgres>,
+) -> anyhow::Result<()> {
+    add_commands_in_tx(
+        serial_number,
+        vec![SafeCommandRequest {
+            id: APPLY_VPN_CMD_ID,
+            command: SafeCommandTx::ApplyVpn { vpn },
+            continue_on_error: false,
+        }],
+        tx,
+        None,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Records the tunnel the device reported, if it is meant to have one.
+pub async fn save_status(device_id: i32, status: &VpnStatus, pool: &PgPool) -> anyhow::Result<()> {
+    let handshake = status
+        .latest_handshake
+        .and_then(|at| DateTime::from_timestamp(i64::try_from(at).ok()?, 0));
+    sqlx::query!(
+        r#"
+        UPDATE device_vpn_peer
+        SET latest_handshake_at = $2, rx_bytes = $3, tx_bytes = $4, status_updated_at = NOW()
+        WHERE device_id = $1
+        "#,
+        device_id,
+        handshake,
+        status.rx_bytes as i64,
+        status.tx_bytes as i64
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocates_after_the_concentrator_and_skips_taken_addresses() {
+        let pool: IpNetwork = "10.90.0.0/29".parse().unwrap();
+        let mut taken = HashSet::new();
+        assert_eq!(
+            free_address(pool, &taken),
+            Some("10.90.0.2".parse().unwrap())
+        );
+
+        taken.extend(
+            ["10.90.0.2", "10.90.0.3", "10.90.0.5"].map(|ip| ip.parse::<IpAddr>().unwrap()),
+        );
+        assert_eq!(
+            free_address(pool, &taken),
+            Some("10.90.0.4".parse().unwrap())
+        );
+
+        taken.extend(["10.90.0.4", "10.90.0.6"].map(|ip| ip.parse::<IpAddr>().unwrap()));
+        assert_eq!(free_address(pool, &taken), None, "10.90.0.7 is broadcast");
+    }
+
+    #[test]
+    fn rejects_pools_and_peers_smithd_would_refuse() {
+        let vpn = || NewVpn {
+            name: "office".into(),
+            endpoint: "vpn.example.org:51820".into(),
+            public_key: "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=".into(),
+            address_pool: "10.90.0.0/16".into(),
+            allowed_ips: vec!["10.90.0.0/16".into()],
+            dns: vec![],
+            persistent_keepalive: Some(25),
+        };
+        assert!(validate(&vpn()).is_ok());
+        for bad in [
+            NewVpn {
+                address_pool: "10.90.0.1/16".into(),
+                ..vpn()
+            },
+            NewVpn {
+                address_pool: "10.90.0.0/31".into(),
+                ..vpn()
+            },
+            NewVpn {
+                public_key: "not a key".into(),
+                ..vpn()
+            },
+            NewVpn {
+                endpoint: "vpn.example.org".into(),
+                ..vpn()
+            },
+        ] {
+            assert!(validate(&bad).is_err(), "{bad:?} should be rejected");
+        }
+    }
+}
@@ -0,0 +1,364 @@
+use crate::State;
+use crate::middlewares::authorization;
+use crate::user::CurrentUser;
+use crate::vpn::{self, NewVpn, SetVpnIntent, Vpn, VpnPeer};
+use axum::{Extension, Json, extract::Path};
+use axum::{http::StatusCode, response::Result};
+use sqlx::PgPool;
+use sqlx::types::ipnetwork::IpNetwork;
+use tracing::{error, warn};
+
+const TAG: &str = "vpns";
+
+fn internal_error(context: &'static str) -> impl Fn(sqlx::Error) -> StatusCode {
+    move |err| {
+        error!("{context}: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/vpns",
+    responses(
+        (status = StatusCode::OK, description = "The VPN concentrators devices can peer with", body = Vec<Vpn>),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to read devices"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve VPNs"),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn get_vpns(
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Result<Json<Vec<Vpn>>, StatusCode> {
+    if !authorization::check(current_user, "devices", "read") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let vpns = sqlx::query_as!(
+        Vpn,
+        r#"
+        SELECT id, name, endpoint, public_key, address_pool::TEXT as "address_pool!",
+               allowed_ips, dns, persistent_keepalive, created_at
+        FROM vpn
+        ORDER BY name
+        "#
+    )
+    .fetch_all(&state.pg_pool)
+    .await
+    .map_err(internal_error("Failed to get VPNs"))?;
+
+    Ok(Json(vpns))
+}
+
+#[utoipa::path(
+    post,
+    path = "/vpns",
+    request_body = NewVpn,
+    responses(
+        (status = StatusCode::CREATED, description = "VPN created", body = Vpn),
+        (status = StatusCode::BAD_REQUEST, description = "Invalid pool, key, endpoint or routes"),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to write devices"),
+        (status = StatusCode::CONFLICT, description = "A VPN with this name already exists"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to create the VPN"),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn create_vpn(
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(body): Json<NewVpn>,
+) -> Result<(StatusCode, Json<Vpn>), StatusCode> {
+    if !authorization::check(current_user, "devices", "write") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    if let Err(message) = vpn::validate(&body) {
+        warn!(name = body.name, "Rejected VPN: {message}");
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let pool: IpNetwork = body
+        .address_pool
+        .parse()
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    let vpn = sqlx::query_as!(
+        Vpn,
+        r#"
+        INSERT INTO vpn (name, endpoint, public_key, address_pool, allowed_ips, dns, persistent_keepalive)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id, name, endpoint, public_key, address_pool::TEXT as "address_pool!",
+                  allowed_ips, dns, persistent_keepalive, created_at
+        "#,
+        body.name,
+        body.endpoint,
+        body.public_key,
+        pool,
+        &body.allowed_ips,
+        &body.dns,
+        body.persistent_keepalive.map(i32::from)
+    )
+    .fetch_one(&state.pg_pool)
+    .await
+    .map_err(|err| {
+        if err.to_string().contains("vpn_name_key") {
+            StatusCode::CONFLICT
+        } else {
+            error!("Failed to create VPN: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    })?;
+
+    Ok((StatusCode::CREATED, Json(vpn)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/vpns/{vpn_id}/peers",
+    params(
+        ("vpn_id" = i32, Path),
+    ),
+    responses(
+        (status = StatusCode::OK, description = "The devices peered with the VPN, for the concentrator to sync", body = Vec<VpnPeer>),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to read devices"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve peers"),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn get_vpn_peers(
+    Path(vpn_id): Path<i32>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Result<Json<Vec<VpnPeer>>, StatusCode> {
+    if !authorization::check(current_user, "devices", "read") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let peers = sqlx::query_as!(
+        VpnPeer,
+        r#"
+        SELECT p.device_id, d.serial_number, p.vpn_id, d.wireguard_public_key as public_key,
+               p.address::TEXT as "address!", p.latest_handshake_at, p.rx_bytes, p.tx_bytes,
+               p.status_updated_at
+        FROM device_vpn_peer p
+        JOIN device d ON d.id = p.device_id
+        WHERE p.vpn_id = $1
+        ORDER BY p.address
+        "#,
+        vpn_id
+    )
+    .fetch_all(&state.pg_pool)
+    .await
+    .map_err(internal_error("Failed to get VPN peers"))?;
+
+    Ok(Json(peers))
+}
+
+async fn fetch_peer(device_id: i32, pool: &PgPool) -> Result<Option<VpnPeer>, StatusCode> {
+    sqlx::query_as!(
+        VpnPeer,
+        r#"
+        SELECT p.device_id, d.serial_number, p.vpn_id, d.wireguard_public_key as public_key,
+               p.address::TEXT as "address!", p.latest_handshake_at, p.rx_bytes, p.tx_bytes,
+               p.status_updated_at
+        FROM device_vpn_peer p
+        JOIN device d ON d.id = p.device_id
+        WHERE p.device_id = $1
+        "#,
+        device_id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(internal_error("Failed to get VPN peer"))
+}
+
+#[utoipa::path(
+    get,
+    path = "/devices/{device_id}/vpn",
+    params(
+        ("device_id" = i32, Path),
+    ),
+    responses(
+        (status = StatusCode::OK, description = "The device's VPN peer and its last reported tunnel", body = VpnPeer),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to read devices"),
+        (status = StatusCode::NOT_FOUND, description = "The device has no VPN intent"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to retrieve the VPN peer"),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn get_vpn_for_device(
+    Path(device_id): Path<i32>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Result<Json<VpnPeer>, StatusCode> {
+    if !authorization::check(current_user, "devices", "read") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    fetch_peer(device_id, &state.pg_pool)
+        .await?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+#[utoipa::path(
+    put,
+    path = "/devices/{device_id}/vpn",
+    params(
+        ("device_id" = i32, Path),
+    ),
+    request_body = SetVpnIntent,
+    responses(
+        (status = StatusCode::OK, description = "Address allocated; the tunnel is applied once the device has reported its key", body = VpnPeer),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to write devices"),
+        (status = StatusCode::NOT_FOUND, description = "Device or VPN not found"),
+        (status = StatusCode::CONFLICT, description = "The VPN's address pool is exhausted"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to set the VPN intent"),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn set_vpn_for_device(
+    Path(device_id): Path<i32>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(body): Json<SetVpnIntent>,
+) -> Result<Json<VpnPeer>, StatusCode> {
+    if !authorization::check(current_user, "devices", "write") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let mut tx = state.pg_pool.begin().await.map_err(internal_error(
+        "Failed to begin set_vpn_for_device transaction",
+    ))?;
+
+    let device = sqlx::query!(
+        "SELECT serial_number, wireguard_public_key FROM device WHERE id = $1",
+        device_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(internal_error("Failed to get device"))?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    let Some((vpn, address)) =
+        vpn::assign(device_id, body.vpn_id, &mut tx)
+            .await
+            .map_err(|err| {
+                error!(
+                    "Failed to assign device {device_id} to VPN {}: {err}",
+                    body.vpn_id
+                );
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?
+    else {
+        let exists = sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM vpn WHERE id = $1) AS "exists!""#,
+            body.vpn_id
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(internal_error("Failed to check VPN"))?;
+        return Err(if exists {
+            StatusCode::CONFLICT
+        } else {
+            StatusCode::NOT_FOUND
+        });
+    };
+
+    // Without a key the concentrator can't add the peer yet. The config goes
+    // out when the device reports one, see `home::save_responses`.
+    if device.wireguard_public_key.is_some() {
+        vpn::queue_apply_vpn(
+            &device.serial_number,
+            Some(vpn::device_config(&vpn, address)),
+            &mut tx,
+        )
+        .await
+        .map_err(|err| {
+            error!("Failed to queue ApplyVpn for device {device_id}: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    }
+
+    tx.commit().await.map_err(internal_error(
+        "Failed to commit set_vpn_for_device transaction",
+    ))?;
+
+    fetch_peer(device_id, &state.pg_pool)
+        .await?
+        .map(Json)
+        .ok_or(StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/devices/{device_id}/vpn",
+    params(
+        ("device_id" = i32, Path),
+    ),
+    responses(
+        (status = StatusCode::NO_CONTENT, description = "Peer removed and the tunnel's removal queued"),
+        (status = StatusCode::FORBIDDEN, description = "Not allowed to write devices"),
+        (status = StatusCode::NOT_FOUND, description = "The device has no VPN intent"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to remove the VPN intent"),
+    ),
+    security(
+        ("auth_token" = [])
+    ),
+    tag = TAG
+)]
+pub async fn delete_vpn_for_device(
+    Path(device_id): Path<i32>,
+    Extension(state): Extension<State>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Result<StatusCode, StatusCode> {
+    if !authorization::check(current_user, "devices", "write") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let mut tx = state.pg_pool.begin().await.map_err(internal_error(
+        "Failed to begin delete_vpn_for_device transaction",
+    ))?;
+
+    let serial_number = sqlx::query_scalar!(
+        r#"
+        DELETE FROM device_vpn_peer p
+        USING device d
+        WHERE p.device_id = $1 AND d.id = p.device_id
+        RETURNING d.serial_number
+        "#,
+        device_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(internal_error("Failed to delete VPN peer"))?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    vpn::queue_apply_vpn(&serial_number, None, &mut tx)
+        .await
+        .map_err(|err| {
+            error!("Failed to queue VPN removal for device {device_id}: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    tx.commit().await.map_err(internal_error(
+        "Failed to commit delete_vpn_for_device transaction",
+    ))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
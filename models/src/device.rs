@@ -15,6 +15,31 @@ pub struct DeviceNetwork {
     pub upload_speed_mbps: Option<f64>,
     pub source: Option<String>,
     pub updated_at: Option<DateTime<Utc>>,
+    /// The last interval of background latency probing.
+    pub link_quality: Option<LinkQuality>,
+}
+
+/// Round trips and loss a device measured over an interval, to the API host
+/// and to its default gateway, with the Wi-Fi link they ran on.
+#[derive(Debug, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct LinkQuality {
+    pub started_at: DateTime<Utc>,
+    pub ended_at: DateTime<Utc>,
+    pub bssid: Option<String>,
+    /// Wi-Fi signal, 0-100.
+    pub signal: Option<i16>,
+    pub api_sent: Option<i32>,
+    pub api_received: Option<i32>,
+    pub api_rtt_p50_ms: Option<f64>,
+    pub api_rtt_p90_ms: Option<f64>,
+    pub api_rtt_p99_ms: Option<f64>,
+    pub api_jitter_ms: Option<f64>,
+    pub gateway_sent: Option<i32>,
+    pub gateway_received: Option<i32>,
+    pub gateway_rtt_p50_ms: Option<f64>,
+    pub gateway_rtt_p90_ms: Option<f64>,
+    pub gateway_rtt_p99_ms: Option<f64>,
+    pub gateway_jitter_ms: Option<f64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
@@ -101,6 +126,24 @@ pub struct DeviceFilter {
     pub distribution_id: Option<i32>,
     /// Filter by service health. If true, only devices with at least one monitored service not in 'active' state.
     pub service_not_running: Option<bool>,
+    /// Filter by CPU model. Devices whose reported model contains this,
+    /// ignoring case, are included.
+    pub cpu_model: Option<String>,
+    /// Filter by memory. Only devices with at least this many MiB usable; the
+    /// kernel reserves some, so a 4 GiB device reports a little less.
+    pub min_memory_mb: Option<i64>,
+    /// Filter by storage kind: `emmc`, `sd`, `nvme`, `usb`, `sata` or `other`.
+    pub storage: Option<String>,
+    /// Filter by USB device. Format: vendor:product in hex, e.g. 046d:0825.
+    /// Devices with all of them attached are included.
+    #[serde(default)]
+    pub usb: Vec<String>,
+    /// Filter by PCI device. Format: vendor:device in hex, e.g. 10ec:8168.
+    /// Devices with all of them are included.
+    #[serde(default)]
+    pub pci: Vec<String>,
+    /// Filter by display. If true, only devices with a display attached.
+    pub display_connected: Option<bool>,
     /// Column to sort by. If None, devices are ordered by last_ping (default).
     #[param(inline)]
     pub sort: Option<DeviceSortField>,
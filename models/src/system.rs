@@ -68,4 +68,14 @@ pub struct SystemInfo {
     pub device_tree: DeviceTree,
     #[serde(default)]
     pub connection_statuses: Vec<ConnectionStatus>,
+    #[serde(default)]
+    pub cache: CacheUsage,
+}
+
+/// What the device's package cache holds.
+#[derive(Debug, Serialize, Deserialize, Default, ToSchema)]
+pub struct CacheUsage {
+    pub bytes: u64,
+    pub blobs: usize,
+    pub releases: usize,
 }
@@ -1,22 +1,23 @@
+use super::checks;
+use super::integrity::{self, INTEGRITY_BASELINE_PATH};
 use crate::commander::CommanderHandle;
+use crate::magic::MagicHandle;
+use crate::magic::structure::ConfigAudit;
 use crate::shutdown::ShutdownSignals;
 use crate::utils::schema::{SafeCommandResponse, SafeCommandRx};
-use tokio::process::Command;
+use std::path::Path;
 use tokio::sync::mpsc;
 use tokio::time::{self, Duration};
-use tracing::{error, info, warn};
+use tracing::info;
 
 /// Run a full audit every 12 hours.
 const AUDIT_INTERVAL_SECS: u64 = 12 * 60 * 60;
 
-/// Hard cap on each compliance probe subprocess so a hung command can't block
-/// audit processing or delay actor shutdown indefinitely.
-const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
-
 /// Synthetic command id for autonomously-reported audits (daemon start and the
 /// periodic timer), following the negative-id convention the postman uses for
 /// unsolicited device state.
 const AUDIT_RESULT_ID: i32 = -5;
+const INTEGRITY_RESULT_ID: i32 = -11;
 
 pub enum ActorMessage {
     RunAudit,
@@ -29,6 +30,7 @@ pub struct Actor {
     shutdown: ShutdownSignals,
     receiver: mpsc::Receiver<ActorMessage>,
     commander: CommanderHandle,
+    magic: MagicHandle,
 }
 
 impl Actor {
@@ -36,11 +38,13 @@ impl Actor {
         shutdown: ShutdownSignals,
         receiver: mpsc::Receiver<ActorMessage>,
         commander: CommanderHandle,
+        magic: MagicHandle,
     ) -> Self {
         Self {
             shutdown,
             receiver,
             commander,
+            magic,
         }
     }
 
@@ -51,14 +55,20 @@ impl Actor {
     }
 
     async fn run_audit(&self) {
-        let (disk_encrypted, password_access_disabled) = run_audit_checks().await;
+        let profile = self.magic.get_audit().await;
         self.commander
             .insert_result(vec![SafeCommandResponse {
                 id: AUDIT_RESULT_ID,
-                command: SafeCommandRx::AuditReport {
-                    disk_encrypted,
-                    password_access_disabled,
-                },
+                command: run_audit_checks(&profile).await,
+                status: 0,
+            }])
+            .await;
+        let integrity =
+            integrity::check(&profile.integrity_paths, Path::new(INTEGRITY_BASELINE_PATH)).await;
+        self.commander
+            .insert_result(vec![SafeCommandResponse {
+                id: INTEGRITY_RESULT_ID,
+                command: integrity,
                 status: 0,
             }])
             .await;
@@ -90,129 +100,26 @@ impl Actor {
     }
 }
 
-/// Run the host compliance checks, returning `(disk_encrypted,
-/// password_access_disabled)`. Each value is `None` when its probe couldn't be
-/// run or parsed.
-pub async fn run_audit_checks() -> (Option<bool>, Option<bool>) {
-    let disk_encrypted = check_disk_encrypted().await;
-    let password_access_disabled = check_password_auth_disabled().await;
-
-    info!(?disk_encrypted, ?password_access_disabled, "Audit complete");
-
-    (disk_encrypted, password_access_disabled)
-}
-
-/// True if any block device is a LUKS/crypt mapping. `None` if lsblk can't be
-/// run or its output can't be parsed.
-async fn check_disk_encrypted() -> Option<bool> {
-    let output = time::timeout(
-        PROBE_TIMEOUT,
-        Command::new("lsblk").args(["-J", "-o", "TYPE"]).output(),
-    )
-    .await
-    .inspect_err(|_| error!("lsblk timed out after {PROBE_TIMEOUT:?}"))
-    .ok()?
-    .inspect_err(|e| error!("Failed to run lsblk: {e}"))
-    .ok()?;
-
-    if !output.status.success() {
-        warn!("lsblk exited with status {:?}", output.status);
-        return None;
-    }
-
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let json: serde_json::Value = serde_json::from_str(&stdout)
-        .inspect_err(|e| error!("Failed to parse lsblk JSON: {e}"))
-        .ok()?;
-
-    Some(json_has_crypt(&json))
-}
-
-/// Recursively scans lsblk JSON for any node whose `type` is `crypt`.
-fn json_has_crypt(value: &serde_json::Value) -> bool {
-    match value {
-        serde_json::Value::Object(map) => {
-            if map.get("type").and_then(|t| t.as_str()) == Some("crypt") {
-                return true;
-            }
-            map.values().any(json_has_crypt)
-        }
-        serde_json::Value::Array(items) => items.iter().any(json_has_crypt),
-        _ => false,
-    }
-}
-
-/// True if sshd has password authentication disabled and public-key
-/// authentication enabled. `None` if `sshd -T` can't be run.
-async fn check_password_auth_disabled() -> Option<bool> {
-    let output = time::timeout(PROBE_TIMEOUT, Command::new("sshd").arg("-T").output())
-        .await
-        .inspect_err(|_| error!("sshd -T timed out after {PROBE_TIMEOUT:?}"))
-        .ok()?
-        .inspect_err(|e| error!("Failed to run sshd -T: {e}"))
-        .ok()?;
-
-    if !output.status.success() {
-        warn!("sshd -T exited with status {:?}", output.status);
-        return None;
-    }
-
-    // `sshd -T` prints effective directives as lowercase `key value` lines.
-    let stdout = String::from_utf8_lossy(&output.stdout).to_lowercase();
-    Some(parse_password_auth_disabled(&stdout))
-}
-
-fn parse_password_auth_disabled(sshd_config: &str) -> bool {
-    let mut password_off = false;
-    let mut pubkey_on = false;
-    for line in sshd_config.lines() {
-        match line.trim() {
-            "passwordauthentication no" => password_off = true,
-            "pubkeyauthentication yes" => pubkey_on = true,
-            _ => {}
-        }
-    }
-    // Refuse to claim "hardened" unless key login is actually possible.
-    password_off && pubkey_on
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn detects_crypt_node_in_lsblk_tree() {
-        let json = serde_json::json!({
-            "blockdevices": [
-                { "type": "disk", "children": [
-                    { "type": "part", "children": [ { "type": "crypt" } ] }
-                ]}
-            ]
-        });
-        assert!(json_has_crypt(&json));
-    }
-
-    #[test]
-    fn no_crypt_node_when_unencrypted() {
-        let json = serde_json::json!({
-            "blockdevices": [
-                { "type": "disk", "children": [ { "type": "part" } ] }
-            ]
-        });
-        assert!(!json_has_crypt(&json));
-    }
-
-    #[test]
-    fn password_auth_disabled_requires_both_directives() {
-        assert!(parse_password_auth_disabled(
-            "passwordauthentication no\npubkeyauthentication yes\n"
-        ));
-        // Password off but key login also off => not safely hardened.
-        assert!(!parse_password_auth_disabled(
-            "passwordauthentication no\npubkeyauthentication no\n"
-        ));
-        assert!(!parse_password_auth_disabled(
-            "passwordauthentication yes\npubkeyauthentication yes\n"
-        ));
+/// Runs the checks of the audit profile. The disk encryption and SSH
+/// password flags are also reported on their own, for apis from before
+/// profiles.
+pub async fn run_audit_checks(profile: &ConfigAudit) -> SafeCommandRx {
+    let checks = checks::run_checks(profile).await;
+    let passed = |id: &str| checks.iter().find(|c| c.id == id).and_then(|c| c.passed);
+    let disk_encrypted = passed("disk_encryption");
+    let password_access_disabled = passed("ssh_password_auth");
+
+    let failed = checks.iter().filter(|c| c.passed == Some(false)).count();
+    info!(
+        ?disk_encrypted,
+        ?password_access_disabled,
+        "Audit complete, {failed} of {} checks failed",
+        checks.len()
+    );
+
+    SafeCommandRx::AuditReport {
+        disk_encrypted,
+        password_access_disabled,
+        checks,
     }
 }
@@ -0,0 +1,509 @@
+//! Compliance checks.
+//!
+//! Every check has a stable id, a severity and remediation text, and a probe
+//! that says whether the host passes it and what it saw. The audit profile in
+//! magic.toml skips checks and tunes what some probes accept.
+use crate::magic::structure::ConfigAudit;
+use crate::utils::schema::{AuditCheckResult, AuditSeverity};
+use std::process::Output;
+use tokio::process::Command;
+use tokio::time::{self, Duration};
+use tracing::{info, warn};
+
+/// Hard cap on each compliance probe subprocess so a hung command can't block
+/// audit processing or delay actor shutdown indefinitely.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+const AUTO_UPGRADES_CONF: &str = "/etc/apt/apt.conf.d/20auto-upgrades";
+const SECURE_BOOT_VAR: &str =
+    "/sys/firmware/efi/efivars/SecureBoot-8be4df61-93ca-11d2-aa0d-e0a6c9b9e6a8";
+
+pub struct Check {
+    pub id: &'static str,
+    pub title: &'static str,
+    pub severity: AuditSeverity,
+    pub remediation: &'static str,
+    probe: Probe,
+}
+
+#[derive(Clone, Copy)]
+enum Probe {
+    DiskEncryption,
+    SshPasswordAuth,
+    RootLogin,
+    Firewall,
+    ListeningPorts,
+    UnattendedUpgrades,
+    KernelVersion,
+    SecureBoot,
+}
+
+/// What a probe saw. `passed` is `None` when it could not tell.
+struct Finding {
+    passed: Option<bool>,
+    evidence: String,
+}
+
+impl Finding {
+    fn new(passed: bool, evidence: impl Into<String>) -> Self {
+        Self {
+            passed: Some(passed),
+            evidence: evidence.into(),
+        }
+    }
+
+    fn unknown(evidence: impl Into<String>) -> Self {
+        Self {
+            passed: None,
+            evidence: evidence.into(),
+        }
+    }
+}
+
+pub const CHECKS: &[Check] = &[
+    Check {
+        id: "disk_encryption",
+        title: "Disk encryption",
+        severity: AuditSeverity::High,
+        remediation: "Put the data partitions on LUKS volumes.",
+        probe: Probe::DiskEncryption,
+    },
+    Check {
+        id: "ssh_password_auth",
+        title: "SSH password login disabled",
+        severity: AuditSeverity::High,
+        remediation: "Set `PasswordAuthentication no` and `PubkeyAuthentication yes` in sshd_config.",
+        probe: Probe::SshPasswordAuth,
+    },
+    Check {
+        id: "root_login",
+        title: "SSH root login disabled",
+        severity: AuditSeverity::High,
+        remediation: "Set `PermitRootLogin no` in sshd_config.",
+        probe: Probe::RootLogin,
+    },
+    Check {
+        id: "firewall_active",
+        title: "Firewall active",
+        severity: AuditSeverity::Medium,
+        remediation: "Enable ufw, nftables or firewalld.",
+        probe: Probe::Firewall,
+    },
+    Check {
+        id: "listening_ports",
+        title: "Only allowed ports listening",
+        severity: AuditSeverity::Medium,
+        remediation: "Stop the services behind the other ports, bind them to loopback, or add the ports to `audit.allowed_ports`.",
+        probe: Probe::ListeningPorts,
+    },
+    Check {
+        id: "unattended_upgrades",
+        title: "Unattended upgrades enabled",
+        severity: AuditSeverity::Low,
+        remediation: "Install unattended-upgrades and set `APT::Periodic::Unattended-Upgrade \"1\";`.",
+        probe: Probe::UnattendedUpgrades,
+    },
+    Check {
+        id: "kernel_version",
+        title: "Kernel up to date",
+        severity: AuditSeverity::Medium,
+        remediation: "Upgrade to a kernel at least as new as `audit.min_kernel` and reboot.",
+        probe: Probe::KernelVersion,
+    },
+    Check {
+        id: "secure_boot",
+        title: "Secure Boot enabled",
+        severity: AuditSeverity::Medium,
+        remediation: "Enroll keys and enable Secure Boot in the firmware setup.",
+        probe: Probe::SecureBoot,
+    },
+];
+
+/// Whether `id` names a check.
+pub fn is_check(id: &str) -> bool {
+    CHECKS.iter().any(|check| check.id == id)
+}
+
+/// Runs the checks `profile` does not skip.
+pub async fn run_checks(profile: &ConfigAudit) -> Vec<AuditCheckResult> {
+    let mut results = Vec::new();
+    for check in CHECKS
+        .iter()
+        .filter(|c| !profile.skip.iter().any(|s| s == c.id))
+    {
+        let finding = match check.probe {
+            Probe::DiskEncryption => disk_encryption().await,
+            Probe::SshPasswordAuth => ssh_password_auth().await,
+            Probe::RootLogin => root_login().await,
+            Probe::Firewall => firewall().await,
+            Probe::ListeningPorts => listening_ports(&profile.allowed_ports).await,
+            Probe::UnattendedUpgrades => unattended_upgrades().await,
+            Probe::KernelVersion => kernel_version(profile.min_kernel.as_deref()).await,
+            Probe::SecureBoot => secure_boot().await,
+        };
+        info!(check = check.id, passed = ?finding.passed, "{}", finding.evidence);
+        results.push(AuditCheckResult {
+            id: check.id.to_owned(),
+            title: check.title.to_owned(),
+            severity: check.severity,
+            passed: finding.passed,
+            evidence: finding.evidence,
+            remediation: check.remediation.to_owned(),
+        });
+    }
+    results
+}
+
+/// Runs a probe command, or says why it could not be run.
+async fn run(program: &str, args: &[&str]) -> Result<Output, String> {
+    match time::timeout(PROBE_TIMEOUT, Command::new(program).args(args).output()).await {
+        Ok(Ok(output)) => Ok(output),
+        Ok(Err(err)) => Err(format!("failed to run {program}: {err}")),
+        Err(_) => Err(format!("{program} timed out after {PROBE_TIMEOUT:?}")),
+    }
+}
+
+/// The stdout of a probe command that must succeed.
+async fn stdout(program: &str, args: &[&str]) -> Result<String, String> {
+    let output = run(program, args).await?;
+    if !output.status.success() {
+        warn!("{program} exited with status {:?}", output.status);
+        return Err(format!("{program} exited with {}", output.status));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Passes if any block device is a LUKS/crypt mapping.
+async fn disk_encryption() -> Finding {
+    let output = match stdout("lsblk", &["-J", "-o", "TYPE"]).await {
+        Ok(output) => output,
+        Err(err) => return Finding::unknown(err),
+    };
+    match serde_json::from_str::<serde_json::Value>(&output) {
+        Ok(json) if json_has_crypt(&json) => Finding::new(true, "a crypt volume is mapped"),
+        Ok(_) => Finding::new(false, "no block device is a crypt mapping"),
+        Err(err) => Finding::unknown(format!("failed to parse lsblk JSON: {err}")),
+    }
+}
+
+/// Recursively scans lsblk JSON for any node whose `type` is `crypt`.
+fn json_has_crypt(value: &serde_json::Value) -> bool {
+    match value {
+        serde_json::Value::Object(map) => {
+            if map.get("type").and_then(|t| t.as_str()) == Some("crypt") {
+                return true;
+            }
+            map.values().any(json_has_crypt)
+        }
+        serde_json::Value::Array(items) => items.iter().any(json_has_crypt),
+        _ => false,
+    }
+}
+
+/// The effective sshd configuration, as lowercase `key value` lines.
+async fn sshd_config() -> Result<String, String> {
+    stdout("sshd", &["-T"]).await.map(|c| c.to_lowercase())
+}
+
+fn sshd_setting<'a>(sshd_config: &'a str, key: &str) -> Option<&'a str> {
+    sshd_config.lines().find_map(|line| {
+        let (k, v) = line.trim().split_once(' ')?;
+        (k == key).then_some(v.trim())
+    })
+}
+
+/// Passes if password login is off and key login is on.
+async fn ssh_password_auth() -> Finding {
+    match sshd_config().await {
+        Ok(config) => Finding::new(
+            parse_password_auth_disabled(&config),
+            format!(
+                "passwordauthentication {}, pubkeyauthentication {}",
+                sshd_setting(&config, "passwordauthentication").unwrap_or("unset"),
+                sshd_setting(&config, "pubkeyauthentication").unwrap_or("unset"),
+            ),
+        ),
+        Err(err) => Finding::unknown(err),
+    }
+}
+
+fn parse_password_auth_disabled(sshd_config: &str) -> bool {
+    // Refuse to claim "hardened" unless key login is actually possible.
+    sshd_setting(sshd_config, "passwordauthentication") == Some("no")
+        && sshd_setting(sshd_config, "pubkeyauthentication") == Some("yes")
+}
+
+/// Passes only for `PermitRootLogin no`; `prohibit-password` still lets root
+/// in with a key.
+async fn root_login() -> Finding {
+    match sshd_config().await {
+        Ok(config) => {
+            let setting = sshd_setting(&config, "permitrootlogin").unwrap_or("unset");
+            Finding::new(setting == "no", format!("permitrootlogin {setting}"))
+        }
+        Err(err) => Finding::unknown(err),
+    }
+}
+
+async fn firewall() -> Finding {
+    if let Ok(status) = stdout("ufw", &["status"]).await
+        && status.to_lowercase().contains("status: active")
+    {
+        return Finding::new(true, "ufw is active");
+    }
+    for unit in ["nftables", "firewalld"] {
+        if run("systemctl", &["is-active", "--quiet", unit])
+            .await
+            .is_ok_and(|output| output.status.success())
+        {
+            return Finding::new(true, format!("{unit} is active"));
+        }
+    }
+    Finding::new(false, "neither ufw, nftables nor firewalld is active")
+}
+
+async fn listening_ports(allowed: &[u16]) -> Finding {
+    let output = match stdout("ss", &["-Hltun"]).await {
+        Ok(output) => output,
+        Err(err) => return Finding::unknown(err),
+    };
+    let listening = parse_listening_ports(&output);
+    let offending: Vec<String> = listening
+        .iter()
+        .filter(|(_, port)| !allowed.contains(port))
+        .map(|(proto, port)| format!("{proto}/{port}"))
+        .collect();
+    if offending.is_empty() {
+        let all: Vec<String> = listening
+            .iter()
+            .map(|(proto, port)| format!("{proto}/{port}"))
+            .collect();
+        Finding::new(
+            true,
+            format!("listening outside loopback: {}", list_or_none(&all)),
+        )
+    } else {
+        Finding::new(
+            false,
+            format!("not allowed but listening: {}", offending.join(", ")),
+        )
+    }
+}
+
+fn list_or_none(items: &[String]) -> String {
+    if items.is_empty() {
+        "none".to_owned()
+    } else {
+        items.join(", ")
+    }
+}
+
+/// The protocol and port of every socket in `ss -Hltun` output that listens
+/// on more than loopback, sorted and without duplicates.
+fn parse_listening_ports(ss: &str) -> Vec<(String, u16)> {
+    let mut ports: Vec<(String, u16)> = ss
+        .lines()
+        .filter_map(|line| {
+            let mut columns = line.split_whitespace();
+            let proto = columns.next()?;
+            let local = columns.nth(3)?;
+            let (address, port) = local.rsplit_once(':')?;
+            let loopback = address.starts_with("127.")
+                || address.starts_with("[::1]")
+                || address.starts_with("[::ffff:127.")
+                || address.ends_with("%lo");
+            if loopback {
+                return None;
+            }
+            Some((proto.to_owned(), port.parse().ok()?))
+        })
+        .collect();
+    ports.sort();
+    ports.dedup();
+    ports
+}
+
+async fn unattended_upgrades() -> Finding {
+    match tokio::fs::read_to_string(AUTO_UPGRADES_CONF).await {
+        Ok(conf) => match parse_unattended_upgrade(&conf) {
+            Some(value) => Finding::new(
+                value != "0",
+                format!("APT::Periodic::Unattended-Upgrade is {value:?}"),
+            ),
+            None => Finding::new(
+                false,
+                format!("{AUTO_UPGRADES_CONF} does not set APT::Periodic::Unattended-Upgrade"),
+            ),
+        },
+        Err(err) => Finding::new(false, format!("{AUTO_UPGRADES_CONF}: {err}")),
+    }
+}
+
+/// The value of `APT::Periodic::Unattended-Upgrade "1";` in apt config.
+fn parse_unattended_upgrade(conf: &str) -> Option<&str> {
+    conf.lines().find_map(|line| {
+        let rest = line
+            .trim()
+            .strip_prefix("APT::Periodic::Unattended-Upgrade")?;
+        Some(rest.trim().trim_end_matches(';').trim().trim_matches('"'))
+    })
+}
+
+async fn kernel_version(min_kernel: Option<&str>) -> Finding {
+    let running = match tokio::fs::read_to_string("/proc/sys/kernel/osrelease").await {
+        Ok(release) => release.trim().to_owned(),
+        Err(err) => return Finding::unknown(format!("failed to read the kernel release: {err}")),
+    };
+    let Some(min_kernel) = min_kernel else {
+        return Finding::new(true, format!("running {running}, no minimum is set"));
+    };
+    match (
+        parse_kernel_version(&running),
+        parse_kernel_version(min_kernel),
+    ) {
+        (Some(version), Some(min)) => Finding::new(
+            version >= min,
+            format!("running {running}, the minimum is {min_kernel}"),
+        ),
+        _ => Finding::unknown(format!("cannot compare {running} to {min_kernel}")),
+    }
+}
+
+/// The major, minor and patch numbers a kernel release starts with, e.g.
+/// `(5, 15, 0)` for `5.15.0-91-generic`. The patch defaults to 0.
+pub fn parse_kernel_version(release: &str) -> Option<(u32, u32, u32)> {
+    let numbers = release
+        .split(|c: char| !c.is_ascii_digit() && c != '.')
+        .next()?;
+    let mut parts = numbers.split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    let patch = parts.next().and_then(|p| p.parse().ok()).unwrap_or(0);
+    Some((major, minor, patch))
+}
+
+async fn secure_boot() -> Finding {
+    if tokio::fs::metadata("/sys/firmware/efi").await.is_err() {
+        return Finding::unknown("not booted with UEFI");
+    }
+    match tokio::fs::read(SECURE_BOOT_VAR).await {
+        Ok(var) => match parse_secure_boot(&var) {
+            Some(true) => Finding::new(true, "SecureBoot is 1"),
+            Some(false) => Finding::new(false, "SecureBoot is 0"),
+            None => Finding::unknown("the SecureBoot variable is malformed"),
+        },
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            Finding::new(false, "the firmware has no SecureBoot variable")
+        }
+        Err(err) => Finding::unknown(format!("failed to read the SecureBoot variable: {err}")),
+    }
+}
+
+/// An efivarfs variable is 4 bytes of attributes followed by its value.
+fn parse_secure_boot(var: &[u8]) -> Option<bool> {
+    match var {
+        [_, _, _, _, value] => Some(*value == 1),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_crypt_node_in_lsblk_tree() {
+        let json = serde_json::json!({
+            "blockdevices": [
+                { "type": "disk", "children": [
+                    { "type": "part", "children": [ { "type": "crypt" } ] }
+                ]}
+            ]
+        });
+        assert!(json_has_crypt(&json));
+    }
+
+    #[test]
+    fn no_crypt_node_when_unencrypted() {
+        let json = serde_json::json!({
+            "blockdevices": [
+                { "type": "disk", "children": [ { "type": "part" } ] }
+            ]
+        });
+        assert!(!json_has_crypt(&json));
+    }
+
+    #[test]
+    fn password_auth_disabled_requires_both_directives() {
+        assert!(parse_password_auth_disabled(
+            "passwordauthentication no\npubkeyauthentication yes\n"
+        ));
+        // Password off but key login also off => not safely hardened.
+        assert!(!parse_password_auth_disabled(
+            "passwordauthentication no\npubkeyauthentication no\n"
+        ));
+        assert!(!parse_password_auth_disabled(
+            "passwordauthentication yes\npubkeyauthentication yes\n"
+        ));
+        assert_eq!(
+            sshd_setting(
+                "port 22\npermitrootlogin prohibit-password\n",
+                "permitrootlogin"
+            ),
+            Some("prohibit-password")
+        );
+    }
+
+    #[test]
+    fn loopback_listeners_are_not_reported() {
+        let ss = "\
+tcp   LISTEN 0      4096       127.0.0.53%lo:53        0.0.0.0:*
+tcp   LISTEN 0      128              0.0.0.0:22        0.0.0.0:*
+tcp   LISTEN 0      128                 [::]:22           [::]:*
+tcp   LISTEN 0      511            127.0.0.1:8080      0.0.0.0:*
+tcp   LISTEN 0      511                [::1]:8080         [::]:*
+udp   UNCONN 0      0                0.0.0.0:5353      0.0.0.0:*
+tcp   LISTEN 0      511                    *:9100            *:*
+";
+        assert_eq!(
+            parse_listening_ports(ss),
+            vec![
+                ("tcp".to_owned(), 22),
+                ("tcp".to_owned(), 9100),
+                ("udp".to_owned(), 5353),
+            ]
+        );
+    }
+
+    #[test]
+    fn unattended_upgrade_setting_is_read() {
+        let conf = "APT::Periodic::Update-Package-Lists \"1\";\nAPT::Periodic::Unattended-Upgrade \"0\";\n";
+        assert_eq!(parse_unattended_upgrade(conf), Some("0"));
+        assert_eq!(parse_unattended_upgrade(""), None);
+    }
+
+    #[test]
+    fn kernel_versions_compare_numerically() {
+        assert_eq!(parse_kernel_version("5.15.0-91-generic"), Some((5, 15, 0)));
+        assert_eq!(parse_kernel_version("5.10.120-tegra"), Some((5, 10, 120)));
+        assert_eq!(parse_kernel_version("6.1"), Some((6, 1, 0)));
+        assert_eq!(parse_kernel_version("latest"), None);
+        assert!(parse_kernel_version("5.9.0") < parse_kernel_version("5.15"));
+    }
+
+    #[test]
+    fn secure_boot_is_the_last_byte() {
+        assert_eq!(parse_secure_boot(&[6, 0, 0, 0, 1]), Some(true));
+        assert_eq!(parse_secure_boot(&[6, 0, 0, 0, 0]), Some(false));
+        assert_eq!(parse_secure_boot(&[6, 0]), None);
+    }
+
+    #[test]
+    fn check_ids_are_unique() {
+        for (i, check) in CHECKS.iter().enumerate() {
+            assert!(CHECKS[..i].iter().all(|c| c.id != check.id), "{}", check.id);
+        }
+        assert!(is_check("secure_boot"));
+        assert!(!is_check("telnet"));
+    }
+}
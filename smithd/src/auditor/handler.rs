@@ -1,5 +1,6 @@
 use super::actor::{Actor, ActorMessage};
 use crate::commander::CommanderHandle;
+use crate::magic::MagicHandle;
 use crate::shutdown::ShutdownSignals;
 use tokio::sync::mpsc;
 use tracing::error;
@@ -10,9 +11,9 @@ pub struct AuditorHandle {
 }
 
 impl AuditorHandle {
-    pub fn new(shutdown: ShutdownSignals, commander: CommanderHandle) -> Self {
+    pub fn new(shutdown: ShutdownSignals, commander: CommanderHandle, magic: MagicHandle) -> Self {
         let (sender, receiver) = mpsc::channel(8);
-        let mut actor = Actor::new(shutdown, receiver, commander);
+        let mut actor = Actor::new(shutdown, receiver, commander, magic);
         tokio::spawn(async move { actor.run().await });
 
         Self { sender }
@@ -0,0 +1,395 @@
+//! File integrity monitoring.
+//!
+//! The baseline holds the SHA-256, owner and mode of every file under the
+//! watched paths. Each audit scans them again and reports what was added,
+//! removed or changed since. Files only change the baseline when the api
+//! accepts them, or when a path starts or stops being watched.
+//!
+//! Losing the baseline would quietly reset detection, so once one has been
+//! taken a marker stays next to it. A baseline taken again while the marker is
+//! there is reported as a change of its own until the api accepts it.
+use crate::downloader::mirror::sha256_file;
+use crate::utils::files::write_file_atomic;
+use crate::utils::schema::{FileMetadata, IntegrityChange, IntegrityChangeKind, SafeCommandRx};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+pub const INTEGRITY_BASELINE_PATH: &str = "/etc/smith/integrity.json";
+
+type Files = BTreeMap<String, FileMetadata>;
+
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+struct Baseline {
+    /// The watched paths the baseline was taken of.
+    roots: Vec<PathBuf>,
+    files: Files,
+    /// Why the baseline was taken again, reported until accepted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reset: Option<IntegrityChange>,
+}
+
+/// Written once the first baseline is taken.
+fn marker_path(baseline_path: &Path) -> PathBuf {
+    baseline_path.with_extension("initialized")
+}
+
+/// Compares the watched paths to the baseline, taking the baseline first if
+/// there is none.
+pub async fn check(roots: &[PathBuf], baseline_path: &Path) -> SafeCommandRx {
+    let current = scan(roots).await;
+    let marker = marker_path(baseline_path);
+    let mut baseline = match load(baseline_path) {
+        Some(baseline) => baseline,
+        None => {
+            let reset = marker.exists().then(|| {
+                warn!(
+                    "Integrity baseline {} is gone or unreadable, taking it again",
+                    baseline_path.display()
+                );
+                IntegrityChange {
+                    path: baseline_path.display().to_string(),
+                    kind: IntegrityChangeKind::BaselineReset,
+                    old: None,
+                    new: None,
+                }
+            });
+            if reset.is_none() {
+                info!("Taking integrity baseline of {} files", current.len());
+            }
+            Baseline {
+                roots: roots.to_vec(),
+                files: current.clone(),
+                reset,
+            }
+        }
+    };
+    adopt_roots(&mut baseline, roots, &current);
+    persist(&baseline, baseline_path).await;
+    if !marker.exists()
+        && let Err(err) = write_file_atomic(&marker, "", 0o600).await
+    {
+        warn!("Failed to mark the integrity baseline as taken: {err:#}");
+    }
+    report(&baseline, current)
+}
+
+/// Takes the accepted changes into the baseline, then compares again. A file
+/// that changed once more since it was reported is left out, so it is
+/// reported again rather than accepted unseen.
+pub async fn accept(
+    roots: &[PathBuf],
+    baseline_path: &Path,
+    changes: &[IntegrityChange],
+) -> SafeCommandRx {
+    let current = scan(roots).await;
+    let mut baseline = load(baseline_path).unwrap_or_default();
+    adopt_roots(&mut baseline, roots, &current);
+    for change in changes {
+        if change.kind == IntegrityChangeKind::BaselineReset {
+            baseline.reset = None;
+            continue;
+        }
+        if current.get(&change.path) != change.new.as_ref() {
+            warn!("{} changed again, not accepting it", change.path);
+            continue;
+        }
+        match &change.new {
+            Some(metadata) => baseline.files.insert(change.path.clone(), metadata.clone()),
+            None => baseline.files.remove(&change.path),
+        };
+    }
+    persist(&baseline, baseline_path).await;
+    report(&baseline, current)
+}
+
+fn report(baseline: &Baseline, current: Files) -> SafeCommandRx {
+    let mut changes = diff(&baseline.files, &current);
+    changes.extend(baseline.reset.clone());
+    if !changes.is_empty() {
+        warn!("{} monitored files differ from the baseline", changes.len());
+    }
+    SafeCommandRx::IntegrityReport {
+        files: current.len(),
+        changes,
+    }
+}
+
+/// Files under newly watched paths join the baseline as they are; files
+/// under paths no longer watched leave it.
+fn adopt_roots(baseline: &mut Baseline, roots: &[PathBuf], current: &Files) {
+    let under =
+        |file: &str, roots: &[PathBuf]| roots.iter().any(|root| Path::new(file).starts_with(root));
+    let added: Vec<PathBuf> = roots
+        .iter()
+        .filter(|root| !baseline.roots.contains(root))
+        .cloned()
+        .collect();
+    baseline.files.retain(|file, _| under(file, roots));
+    for (file, metadata) in current {
+        if under(file, &added) {
+            baseline.files.insert(file.clone(), metadata.clone());
+        }
+    }
+    baseline.roots = roots.to_vec();
+}
+
+fn diff(baseline: &Files, current: &Files) -> Vec<IntegrityChange> {
+    let mut changes = Vec::new();
+    for (path, old) in baseline {
+        match current.get(path) {
+            None => changes.push(IntegrityChange {
+                path: path.clone(),
+                kind: IntegrityChangeKind::Removed,
+                old: Some(old.clone()),
+                new: None,
+            }),
+            Some(new) if new != old => changes.push(IntegrityChange {
+                path: path.clone(),
+                kind: IntegrityChangeKind::Changed,
+                old: Some(old.clone()),
+                new: Some(new.clone()),
+            }),
+            Some(_) => {}
+        }
+    }
+    for (path, new) in current {
+        if !baseline.contains_key(path) {
+            changes.push(IntegrityChange {
+                path: path.clone(),
+                kind: IntegrityChangeKind::Added,
+                old: None,
+                new: Some(new.clone()),
+            });
+        }
+    }
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    changes
+}
+
+/// Every file under `roots`. Symlinks are recorded, not followed. A watched
+/// path that does not exist has no files.
+async fn scan(roots: &[PathBuf]) -> Files {
+    let roots = roots.to_vec();
+    let entries = tokio::task::spawn_blocking(move || {
+        roots
+            .iter()
+            .flat_map(|root| walkdir::WalkDir::new(root).follow_links(false))
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| !entry.file_type().is_dir())
+            .map(|entry| entry.into_path())
+            .collect::<Vec<_>>()
+    })
+    .await
+    .unwrap_or_default();
+
+    let mut files = Files::new();
+    for path in entries {
+        match file_metadata(&path).await {
+            Ok(metadata) => {
+                files.insert(path.display().to_string(), metadata);
+            }
+            Err(err) => warn!("Failed to read {}: {err:#}", path.display()),
+        }
+    }
+    files
+}
+
+async fn file_metadata(path: &Path) -> anyhow::Result<FileMetadata> {
+    let metadata = tokio::fs::symlink_metadata(path).await?;
+    let sha256 = if metadata.file_type().is_symlink() {
+        let target = tokio::fs::read_link(path).await?;
+        let mut hex = String::with_capacity(64);
+        for byte in Sha256::digest(target.as_os_str().as_bytes()) {
+            _ = write!(hex, "{byte:02x}");
+        }
+        hex
+    } else {
+        sha256_file(path).await?
+    };
+    Ok(FileMetadata {
+        sha256,
+        uid: metadata.uid(),
+        gid: metadata.gid(),
+        mode: metadata.mode() & 0o7777,
+    })
+}
+
+fn load(path: &Path) -> Option<Baseline> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents)
+            .inspect_err(|err| warn!("Ignoring unreadable {}: {err}", path.display()))
+            .ok(),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+        Err(err) => {
+            warn!("Failed to read {}: {err}", path.display());
+            None
+        }
+    }
+}
+
+async fn persist(baseline: &Baseline, path: &Path) {
+    let written = async {
+        let contents = serde_json::to_string(baseline).context("serializing baseline")?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .with_context(|| format!("creating {}", parent.display()))?;
+        }
+        write_file_atomic(path, &contents, 0o600).await
+    };
+    if let Err(err) = written.await {
+        warn!("Failed to save the integrity baseline: {err:#}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn changes(report: SafeCommandRx) -> Vec<(String, IntegrityChangeKind)> {
+        let SafeCommandRx::IntegrityReport { changes, .. } = report else {
+            panic!("not an integrity report");
+        };
+        changes
+            .into_iter()
+            .map(|change| {
+                let name = Path::new(&change.path).file_name().unwrap();
+                (name.to_string_lossy().into_owned(), change.kind)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn drift_is_reported_until_accepted() {
+        let dir = tempfile::tempdir().unwrap();
+        let watched = dir.path().join("etc");
+        std::fs::create_dir_all(watched.join("sudoers.d")).unwrap();
+        std::fs::write(watched.join("sudoers"), "root ALL=(ALL) ALL\n").unwrap();
+        std::fs::write(
+            watched.join("sudoers.d/app"),
+            "app ALL=(ALL) NOPASSWD: ALL\n",
+        )
+        .unwrap();
+        let roots = vec![watched.clone()];
+        let baseline = dir.path().join("integrity.json");
+
+        assert_eq!(changes(check(&roots, &baseline).await), vec![]);
+
+        std::fs::write(
+            watched.join("sudoers"),
+            "root ALL=(ALL) ALL\nevil ALL=(ALL) ALL\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(
+            watched.join("sudoers.d/app"),
+            std::fs::Permissions::from_mode(0o666),
+        )
+        .unwrap();
+        std::fs::write(watched.join("sudoers.d/new"), "").unwrap();
+        std::os::unix::fs::symlink("/etc/passwd", watched.join("link")).unwrap();
+
+        let SafeCommandRx::IntegrityReport {
+            files,
+            changes: drift,
+        } = check(&roots, &baseline).await
+        else {
+            panic!("not an integrity report");
+        };
+        assert_eq!(files, 4);
+        let kinds: Vec<_> = drift.iter().map(|c| c.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                IntegrityChangeKind::Added,
+                IntegrityChangeKind::Changed,
+                IntegrityChangeKind::Changed,
+                IntegrityChangeKind::Added,
+            ]
+        );
+        let app = &drift[2];
+        assert_eq!(
+            app.old.as_ref().unwrap().sha256,
+            app.new.as_ref().unwrap().sha256
+        );
+        assert_eq!(app.new.as_ref().unwrap().mode, 0o666);
+
+        // The new file changes once more before the drift is accepted.
+        std::fs::write(watched.join("sudoers.d/new"), "more").unwrap();
+        assert_eq!(
+            changes(accept(&roots, &baseline, &drift).await),
+            vec![("new".to_owned(), IntegrityChangeKind::Added)]
+        );
+
+        std::fs::remove_file(watched.join("sudoers")).unwrap();
+        assert_eq!(
+            changes(check(&roots, &baseline).await),
+            vec![
+                ("sudoers".to_owned(), IntegrityChangeKind::Removed),
+                ("new".to_owned(), IntegrityChangeKind::Added),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn a_lost_baseline_is_reported_until_accepted() {
+        let dir = tempfile::tempdir().unwrap();
+        let watched = dir.path().join("etc");
+        std::fs::create_dir_all(&watched).unwrap();
+        std::fs::write(watched.join("sudoers"), "root ALL=(ALL) ALL\n").unwrap();
+        let roots = vec![watched.clone()];
+        let baseline = dir.path().join("integrity.json");
+
+        assert_eq!(changes(check(&roots, &baseline).await), vec![]);
+        std::fs::write(&baseline, "not json").unwrap();
+        std::fs::write(watched.join("sudoers"), "evil ALL=(ALL) ALL\n").unwrap();
+
+        let SafeCommandRx::IntegrityReport { changes: reset, .. } = check(&roots, &baseline).await
+        else {
+            panic!("not an integrity report");
+        };
+        assert_eq!(reset.len(), 1);
+        assert_eq!(reset[0].kind, IntegrityChangeKind::BaselineReset);
+        assert_eq!(reset[0].path, baseline.display().to_string());
+        assert_eq!(
+            changes(check(&roots, &baseline).await),
+            vec![(
+                "integrity.json".to_owned(),
+                IntegrityChangeKind::BaselineReset
+            )]
+        );
+
+        assert_eq!(changes(accept(&roots, &baseline, &reset).await), vec![]);
+        assert_eq!(changes(check(&roots, &baseline).await), vec![]);
+    }
+
+    #[tokio::test]
+    async fn newly_watched_paths_join_the_baseline() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("ssh");
+        let second = dir.path().join("app");
+        std::fs::create_dir_all(&first).unwrap();
+        std::fs::create_dir_all(&second).unwrap();
+        std::fs::write(first.join("sshd_config"), "PermitRootLogin no\n").unwrap();
+        std::fs::write(second.join("config.toml"), "debug = false\n").unwrap();
+        let baseline = dir.path().join("integrity.json");
+
+        check(std::slice::from_ref(&first), &baseline).await;
+        assert_eq!(
+            changes(check(&[first.clone(), second.clone()], &baseline).await),
+            vec![]
+        );
+        assert_eq!(
+            changes(check(std::slice::from_ref(&second), &baseline).await),
+            vec![]
+        );
+        assert_eq!(load(&baseline).unwrap().files.len(), 1);
+    }
+}
@@ -1,5 +1,8 @@
 mod actor;
+mod checks;
 mod handler;
+pub mod integrity;
 
 pub use actor::run_audit_checks;
+pub use checks::{is_check, parse_kernel_version};
 pub use handler::AuditorHandle;
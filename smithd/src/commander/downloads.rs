@@ -0,0 +1,60 @@
+use crate::downloader::DownloaderHandle;
+use crate::utils::schema::{BandwidthPolicy, SafeCommandResponse, SafeCommandRx};
+
+pub(super) async fn list(
+    id: i32,
+    downloader: &DownloaderHandle,
+    ids: Vec<u64>,
+) -> SafeCommandResponse {
+    let downloads = downloader
+        .list()
+        .await
+        .into_iter()
+        .filter(|download| ids.is_empty() || ids.contains(&download.id))
+        .collect();
+
+    SafeCommandResponse {
+        id,
+        command: SafeCommandRx::Downloads { downloads },
+        status: 0,
+    }
+}
+
+pub(super) async fn cancel(
+    id: i32,
+    downloader: &DownloaderHandle,
+    download_id: u64,
+) -> SafeCommandResponse {
+    let cancelled = downloader.cancel(download_id).await;
+
+    SafeCommandResponse {
+        id,
+        command: SafeCommandRx::DownloadCancelled {
+            id: download_id,
+            cancelled,
+        },
+        status: if cancelled { 0 } else { -1 },
+    }
+}
+
+pub(super) async fn update_bandwidth_policy(
+    id: i32,
+    downloader: &DownloaderHandle,
+    policy: Option<BandwidthPolicy>,
+) -> SafeCommandResponse {
+    match downloader.set_bandwidth_policy(policy).await {
+        Ok(usage) => SafeCommandResponse {
+            id,
+            command: SafeCommandRx::BandwidthUsage { usage },
+            status: 0,
+        },
+        Err(err) => SafeCommandResponse {
+            id,
+            command: SafeCommandRx::FreeForm {
+                stdout: String::new(),
+                stderr: format!("Failed to apply bandwidth policy: {err:#}"),
+            },
+            status: -1,
+        },
+    }
+}
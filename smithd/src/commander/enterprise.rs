@@ -0,0 +1,261 @@
+//! 802.1X (WPA2/WPA3-Enterprise) Wi-Fi profiles of the network intent. Their
+//! certificates arrive sealed to the device's secrets key and are written to a
+//! root-only directory the nmcli profile points at.
+use crate::secrets::{self, KEYS_DIR};
+use crate::utils::schema::{EapCredentials, NMProfile};
+use anyhow::{Context, Result};
+use crypto_box::SecretKey;
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// One directory per profile. Files are named after their content, so a
+/// profile being replaced keeps pointing at its own until the new one is up.
+const CERTS_DIR: &str = "/etc/smith/certs";
+
+pub(super) struct EapProfile {
+    /// nmcli property/value pairs for the security and 802-1x settings.
+    pub properties: Vec<String>,
+    /// Whether a certificate differs from the ones the profile used so far.
+    pub certificates_changed: bool,
+    files: Vec<PathBuf>,
+}
+
+/// Opens and writes the profile's certificates, returning the nmcli
+/// properties that reference them.
+pub(super) async fn install(profile_name: &str, eap: &EapCredentials) -> Result<EapProfile> {
+    let dir = profile_dir(Path::new(CERTS_DIR), profile_name);
+    let eap = eap.clone();
+    tokio::task::spawn_blocking(move || {
+        let sealed = [&eap.ca_cert, &eap.client_cert, &eap.private_key];
+        let key = if sealed.iter().any(|cert| cert.is_some()) {
+            Some(secrets::load_or_create_key(Path::new(KEYS_DIR))?)
+        } else {
+            None
+        };
+        install_in(&dir, key.as_ref(), &eap)
+    })
+    .await?
+}
+
+fn install_in(dir: &Path, key: Option<&SecretKey>, eap: &EapCredentials) -> Result<EapProfile> {
+    let mut certificates_changed = false;
+    let mut files = Vec::new();
+    let mut write = |kind: &str, sealed: &Option<String>| -> Result<String> {
+        let (Some(sealed), Some(key)) = (sealed, key) else {
+            return Ok(String::new());
+        };
+        let pem = secrets::open(key, sealed).with_context(|| format!("opening the {kind}"))?;
+        let mut name = format!("{kind}-");
+        for byte in &Sha256::digest(&pem)[..8] {
+            _ = write!(name, "{byte:02x}");
+        }
+        let path = dir.join(format!("{name}.pem"));
+        if !path.exists() {
+            std::fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+            std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))
+                .with_context(|| format!("securing {}", dir.display()))?;
+            secrets::write_private(&path, &pem)?;
+            certificates_changed = true;
+        }
+        files.push(path.clone());
+        Ok(path.display().to_string())
+    };
+    let ca_cert = write("ca", &eap.ca_cert)?;
+    let client_cert = write("client", &eap.client_cert)?;
+    let private_key = write("key", &eap.private_key)?;
+
+    let optional = |value: &Option<String>| value.clone().unwrap_or_default();
+    let properties = [
+        ("wifi-sec.key-mgmt", "wpa-eap".to_string()),
+        (
+            "wifi-sec.pmf",
+            if eap.pmf_required {
+                "required"
+            } else {
+                "default"
+            }
+            .to_string(),
+        ),
+        ("802-1x.eap", eap.method.clone()),
+        ("802-1x.identity", eap.identity.clone()),
+        (
+            "802-1x.anonymous-identity",
+            optional(&eap.anonymous_identity),
+        ),
+        ("802-1x.phase2-auth", optional(&eap.phase2_auth)),
+        ("802-1x.password", optional(&eap.password)),
+        ("802-1x.ca-cert", ca_cert),
+        ("802-1x.client-cert", client_cert),
+        ("802-1x.private-key", private_key),
+        (
+            "802-1x.private-key-password",
+            optional(&eap.private_key_password),
+        ),
+    ]
+    .into_iter()
+    .flat_map(|(key, value)| [key.to_string(), value])
+    .collect();
+
+    Ok(EapProfile {
+        properties,
+        certificates_changed,
+        files,
+    })
+}
+
+/// Removes the certificates the profile no longer uses, once it is applied.
+pub(super) async fn prune(profile_name: &str, installed: &EapProfile) {
+    let dir = profile_dir(Path::new(CERTS_DIR), profile_name);
+    let Ok(mut entries) = tokio::fs::read_dir(&dir).await else {
+        return;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        if !installed.files.contains(&entry.path())
+            && let Err(e) = tokio::fs::remove_file(entry.path()).await
+        {
+            tracing::warn!("Failed to remove {}: {e}", entry.path().display());
+        }
+    }
+}
+
+/// Removes the certificates of a profile that was deleted.
+pub(super) async fn remove(profile_name: &str) {
+    let dir = profile_dir(Path::new(CERTS_DIR), profile_name);
+    match tokio::fs::remove_dir_all(&dir).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            tracing::warn!("Failed to remove {}: {e}", dir.display());
+        }
+        _ => {}
+    }
+}
+
+/// Profile names are free text, so the directory name keeps only what is safe
+/// in a path and adds a hash of the full name to keep distinct names apart.
+fn profile_dir(base: &Path, profile_name: &str) -> PathBuf {
+    let mut name: String = profile_name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    name.push('-');
+    for byte in &Sha256::digest(profile_name.as_bytes())[..4] {
+        _ = write!(name, "{byte:02x}");
+    }
+    base.join(name)
+}
+
+/// Whether an existing profile authenticates the way the intent entry does.
+/// The password is a secret NM doesn't report, so it can't take part.
+pub(super) fn matches(profile: &NMProfile, eap: &EapCredentials) -> bool {
+    profile.eap.as_deref() == Some(eap.method.as_str())
+        && profile.eap_identity.as_deref() == Some(eap.identity.as_str())
+        && profile.phase2_auth == eap.phase2_auth
+        && profile.anonymous_identity == eap.anonymous_identity
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto_box::aead::OsRng;
+
+    fn tls(key: &SecretKey, client_pem: &str) -> EapCredentials {
+        let public = secrets::public_key_base64(key);
+        EapCredentials {
+            method: "tls".into(),
+            identity: "device-17".into(),
+            ca_cert: Some(secrets::seal(&public, b"CA PEM").unwrap()),
+            client_cert: Some(secrets::seal(&public, client_pem.as_bytes()).unwrap()),
+            private_key: Some(secrets::seal(&public, b"KEY PEM").unwrap()),
+            private_key_password: Some("hunter2".into()),
+            pmf_required: true,
+            ..Default::default()
+        }
+    }
+
+    fn property<'a>(profile: &'a EapProfile, key: &str) -> &'a str {
+        let index = profile.properties.iter().position(|p| p == key).unwrap();
+        &profile.properties[index + 1]
+    }
+
+    #[test]
+    fn installs_opened_certificates_and_references_them() {
+        let base = tempfile::tempdir().unwrap();
+        let dir = base.path().join("ward");
+        let key = SecretKey::generate(&mut OsRng);
+
+        let installed = install_in(&dir, Some(&key), &tls(&key, "CLIENT PEM")).unwrap();
+        assert!(installed.certificates_changed);
+        assert_eq!(property(&installed, "wifi-sec.key-mgmt"), "wpa-eap");
+        assert_eq!(property(&installed, "wifi-sec.pmf"), "required");
+        assert_eq!(property(&installed, "802-1x.eap"), "tls");
+        assert_eq!(property(&installed, "802-1x.password"), "");
+        let client = property(&installed, "802-1x.client-cert");
+        assert_eq!(std::fs::read(client).unwrap(), b"CLIENT PEM");
+        let mode = std::fs::metadata(client).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // Sealing again gives new ciphertext but the same files.
+        let again = install_in(&dir, Some(&key), &tls(&key, "CLIENT PEM")).unwrap();
+        assert!(!again.certificates_changed);
+        assert_eq!(property(&again, "802-1x.client-cert"), client);
+
+        let renewed = install_in(&dir, Some(&key), &tls(&key, "NEW CLIENT PEM")).unwrap();
+        assert!(renewed.certificates_changed);
+        assert_ne!(property(&renewed, "802-1x.client-cert"), client);
+    }
+
+    #[test]
+    fn a_certificate_sealed_to_another_device_is_refused() {
+        let base = tempfile::tempdir().unwrap();
+        let key = SecretKey::generate(&mut OsRng);
+        let other = SecretKey::generate(&mut OsRng);
+        assert!(install_in(base.path(), Some(&key), &tls(&other, "CLIENT PEM")).is_err());
+    }
+
+    #[test]
+    fn profile_dirs_stay_inside_the_base() {
+        let base = Path::new("/etc/smith/certs");
+        let dir = profile_dir(base, "../../etc/Ward 4");
+        assert_eq!(dir.parent(), Some(base));
+        assert_ne!(profile_dir(base, "Ward 4"), profile_dir(base, "Ward_4"));
+    }
+
+    #[test]
+    fn matches_on_method_identity_and_phase2() {
+        let profile = NMProfile {
+            eap: Some("peap".into()),
+            eap_identity: Some("nurse".into()),
+            phase2_auth: Some("mschapv2".into()),
+            ..Default::default()
+        };
+        let eap = EapCredentials {
+            method: "peap".into(),
+            identity: "nurse".into(),
+            phase2_auth: Some("mschapv2".into()),
+            password: Some("secret".into()),
+            ..Default::default()
+        };
+        assert!(matches(&profile, &eap));
+        assert!(!matches(
+            &profile,
+            &EapCredentials {
+                identity: "doctor".into(),
+                ..eap.clone()
+            }
+        ));
+        assert!(!matches(
+            &profile,
+            &EapCredentials {
+                method: "ttls".into(),
+                ..eap
+            }
+        ));
+    }
+}
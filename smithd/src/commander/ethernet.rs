@@ -0,0 +1,379 @@
+//! Wired profiles of the network intent. They go through the same nmcli
+//! primitives as Wi-Fi, but carry addressing instead of credentials, so they
+//! are matched on their IP settings rather than an SSID and PSK.
+use super::network::{
+    execute_nmcli_command, guarded_replace, nmcli_add_profile, nmcli_delete_profile,
+    nmcli_modify_properties, nmcli_update_priority, split_terse_line,
+};
+use crate::utils::schema::{ConditionReason, IntentEthernet, IpConfig, IpMethod};
+use anyhow::Result;
+use std::collections::HashMap;
+use tokio::process::Command;
+
+const ETHERNET_TYPE: &str = "802-3-ethernet";
+const VLAN_TYPE: &str = "vlan";
+
+const DETAIL_FIELDS: &str = "connection.type,connection.interface-name,vlan.parent,vlan.id,\
+    ipv4.method,ipv4.addresses,ipv4.gateway,ipv4.dns,ipv4.ignore-auto-dns,\
+    ipv6.method,ipv6.addresses,ipv6.gateway,ipv6.dns,ipv6.ignore-auto-dns";
+
+/// A wired NM profile, keyed by name in [`wired_profiles`].
+pub(super) struct WiredProfile {
+    pub is_active: bool,
+}
+
+/// Lists the Ethernet and VLAN profiles NetworkManager knows about.
+pub(super) async fn wired_profiles() -> Result<HashMap<String, WiredProfile>> {
+    let mut cmd = Command::new("nmcli");
+    cmd.args(["-t", "-f", "NAME,TYPE,DEVICE", "connection", "show"]);
+    let output = execute_nmcli_command(cmd).await?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow::anyhow!("nmcli connection show failed: {stderr}"));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(parse_wired_connection_line)
+        .map(|(name, is_active)| (name, WiredProfile { is_active }))
+        .collect())
+}
+
+// Same DEVICE-based activity check as parse_nm_connection_list_line, for the
+// NetworkManager versions whose ACTIVE field is always "no".
+fn parse_wired_connection_line(line: &str) -> Option<(String, bool)> {
+    let parts = split_terse_line(line, 3);
+    if parts.len() == 3 && (parts[1] == ETHERNET_TYPE || parts[1] == VLAN_TYPE) {
+        let is_active = !parts[2].is_empty() && parts[2] != "--";
+        Some((parts[0].clone(), is_active))
+    } else {
+        None
+    }
+}
+
+/// The settings of one address family, in nmcli's vocabulary.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct FamilySettings {
+    method: String,
+    addresses: Vec<String>,
+    gateway: Option<String>,
+    dns: Vec<String>,
+    ignore_auto_dns: bool,
+}
+
+/// What an intent entry sets on its profile, comparable with what NM has.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct WiredSettings {
+    /// Parent interface and id for a VLAN.
+    vlan: Option<(String, u16)>,
+    /// The interface a plain Ethernet profile is bound to.
+    interface: Option<String>,
+    ipv4: FamilySettings,
+    ipv6: FamilySettings,
+}
+
+impl WiredSettings {
+    fn from_intent(intent: &IntentEthernet) -> Self {
+        let family = |config: &IpConfig, v6: bool| {
+            let dns: Vec<String> = intent
+                .dns
+                .iter()
+                .filter(|server| server.contains(':') == v6)
+                .cloned()
+                .collect();
+            FamilySettings {
+                method: match config.method {
+                    IpMethod::Auto => "auto",
+                    IpMethod::Manual => "manual",
+                    IpMethod::Disabled => "disabled",
+                }
+                .to_string(),
+                addresses: config.addresses.clone(),
+                gateway: config.gateway.clone(),
+                ignore_auto_dns: !intent.dns.is_empty(),
+                dns,
+            }
+        };
+        match intent.vlan_id {
+            Some(id) => Self {
+                vlan: intent.interface.clone().map(|parent| (parent, id)),
+                interface: None,
+                ipv4: family(&intent.ipv4, false),
+                ipv6: family(&intent.ipv6, true),
+            },
+            None => Self {
+                vlan: None,
+                interface: intent.interface.clone(),
+                ipv4: family(&intent.ipv4, false),
+                ipv6: family(&intent.ipv6, true),
+            },
+        }
+    }
+
+    /// The profile type as NM reports it.
+    fn kind(&self) -> &'static str {
+        if self.vlan.is_some() {
+            VLAN_TYPE
+        } else {
+            ETHERNET_TYPE
+        }
+    }
+
+    /// The profile type as `nmcli connection add` takes it.
+    fn add_type(&self) -> &'static str {
+        if self.vlan.is_some() {
+            "vlan"
+        } else {
+            "ethernet"
+        }
+    }
+
+    /// Property/value pairs for `nmcli connection add` or `modify`, without
+    /// autoconnect, which the callers set themselves.
+    fn properties(&self) -> Vec<String> {
+        let mut props: Vec<String> = Vec::new();
+        let mut set = |key: &str, value: String| {
+            props.push(key.to_string());
+            props.push(value);
+        };
+        match &self.vlan {
+            Some((parent, id)) => {
+                set("vlan.parent", parent.clone());
+                set("vlan.id", id.to_string());
+            }
+            None => set(
+                "connection.interface-name",
+                self.interface.clone().unwrap_or_default(),
+            ),
+        }
+        for (family, settings) in [("ipv4", &self.ipv4), ("ipv6", &self.ipv6)] {
+            set(&format!("{family}.method"), settings.method.clone());
+            set(&format!("{family}.addresses"), settings.addresses.join(","));
+            set(
+                &format!("{family}.gateway"),
+                settings.gateway.clone().unwrap_or_default(),
+            );
+            set(&format!("{family}.dns"), settings.dns.join(","));
+            set(
+                &format!("{family}.ignore-auto-dns"),
+                if settings.ignore_auto_dns {
+                    "yes"
+                } else {
+                    "no"
+                }
+                .to_string(),
+            );
+        }
+        props
+    }
+}
+
+/// Parses `nmcli -t -f <DETAIL_FIELDS> connection show <name>`, returning
+/// the profile type alongside its settings.
+fn parse_wired_detail(stdout: &str) -> (String, WiredSettings) {
+    let mut fields: HashMap<String, String> = HashMap::new();
+    for line in stdout.lines() {
+        let parts = split_terse_line(line, 2);
+        if let [key, value] = parts.as_slice() {
+            let value = value.trim();
+            if !value.is_empty() && value != "--" {
+                fields.insert(key.clone(), value.to_string());
+            }
+        }
+    }
+    let get = |key: &str| fields.get(key).cloned();
+    let list = |key: &str| -> Vec<String> {
+        get(key)
+            .map(|v| v.split(',').map(|s| s.trim().to_string()).collect())
+            .unwrap_or_default()
+    };
+    let family = |name: &str| FamilySettings {
+        method: get(&format!("{name}.method")).unwrap_or_default(),
+        addresses: list(&format!("{name}.addresses")),
+        gateway: get(&format!("{name}.gateway")),
+        dns: list(&format!("{name}.dns")),
+        ignore_auto_dns: get(&format!("{name}.ignore-auto-dns")).as_deref() == Some("yes"),
+    };
+
+    let kind = get("connection.type").unwrap_or_default();
+    let vlan = if kind == VLAN_TYPE {
+        get("vlan.parent").zip(get("vlan.id").and_then(|id| id.parse().ok()))
+    } else {
+        None
+    };
+    let interface = if kind == VLAN_TYPE {
+        None
+    } else {
+        get("connection.interface-name")
+    };
+    (
+        kind,
+        WiredSettings {
+            vlan,
+            interface,
+            ipv4: family("ipv4"),
+            ipv6: family("ipv6"),
+        },
+    )
+}
+
+async fn read_wired_settings(name: &str) -> Result<(String, WiredSettings)> {
+    let mut cmd = Command::new("nmcli");
+    cmd.args(["-t", "-f", DETAIL_FIELDS, "connection", "show", name]);
+    let output = execute_nmcli_command(cmd).await?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow::anyhow!("nmcli show {name} failed: {stderr}"));
+    }
+    Ok(parse_wired_detail(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Brings a wired profile in line with its intent entry. An active profile
+/// that changes goes through the connectivity guard, like a Wi-Fi profile
+/// whose credentials change.
+pub(super) async fn apply(
+    intent: &IntentEthernet,
+    existing: Option<&WiredProfile>,
+    priority: i32,
+) -> Result<(), ConditionReason> {
+    let name = &intent.profile_name;
+    let wanted = WiredSettings::from_intent(intent);
+    let nmcli_error = |e: anyhow::Error| {
+        tracing::error!("execute_apply_networks: wired profile {name}: {e:#}");
+        ConditionReason::NmcliError
+    };
+
+    let Some(existing) = existing else {
+        return nmcli_add_profile(name, wanted.add_type(), &wanted.properties(), priority)
+            .await
+            .map_err(nmcli_error);
+    };
+    let (kind, current) = read_wired_settings(name).await.map_err(nmcli_error)?;
+
+    if !existing.is_active {
+        // The type of a profile can't be modified, only replaced.
+        if kind != wanted.kind() {
+            nmcli_delete_profile(name).await.map_err(nmcli_error)?;
+            return nmcli_add_profile(name, wanted.add_type(), &wanted.properties(), priority)
+                .await
+                .map_err(nmcli_error);
+        }
+        return nmcli_modify_properties(name, &wanted.properties(), priority)
+            .await
+            .map_err(nmcli_error);
+    }
+
+    if kind == wanted.kind() && current == wanted {
+        // Nothing changed: only update priority to avoid an unnecessary reconnect.
+        return nmcli_update_priority(name, priority)
+            .await
+            .map_err(nmcli_error);
+    }
+    guarded_replace(name, wanted.add_type(), &wanted.properties(), priority).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn intent(json: &str) -> IntentEthernet {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn wired_connection_lines() {
+        assert_eq!(
+            parse_wired_connection_line("Wired connection 1:802-3-ethernet:eth0"),
+            Some(("Wired connection 1".to_string(), true))
+        );
+        assert_eq!(
+            parse_wired_connection_line("ward-vlan:vlan:"),
+            Some(("ward-vlan".to_string(), false))
+        );
+        assert_eq!(
+            parse_wired_connection_line("HC-Teton:802-11-wireless:wlan0"),
+            None
+        );
+    }
+
+    #[test]
+    fn static_vlan_properties() {
+        let settings = WiredSettings::from_intent(&intent(
+            r#"{"profile_name":"ward","interface":"eth0","vlan_id":20,"priority":0,
+            "ipv4":{"method":"manual","addresses":["10.0.20.5/24"],"gateway":"10.0.20.1"},
+            "ipv6":{"method":"disabled"},"dns":["10.0.0.53","2001:db8::53"]}"#,
+        ));
+        assert_eq!(settings.kind(), VLAN_TYPE);
+        assert_eq!(
+            settings.properties(),
+            [
+                "vlan.parent",
+                "eth0",
+                "vlan.id",
+                "20",
+                "ipv4.method",
+                "manual",
+                "ipv4.addresses",
+                "10.0.20.5/24",
+                "ipv4.gateway",
+                "10.0.20.1",
+                "ipv4.dns",
+                "10.0.0.53",
+                "ipv4.ignore-auto-dns",
+                "yes",
+                "ipv6.method",
+                "disabled",
+                "ipv6.addresses",
+                "",
+                "ipv6.gateway",
+                "",
+                "ipv6.dns",
+                "2001:db8::53",
+                "ipv6.ignore-auto-dns",
+                "yes",
+            ]
+        );
+    }
+
+    #[test]
+    fn unchanged_profile_matches_what_nm_reports() {
+        let wanted = WiredSettings::from_intent(&intent(
+            r#"{"profile_name":"lan","interface":"eth0","priority":0,
+            "ipv4":{"method":"manual","addresses":["10.0.0.5/24","10.0.1.5/24"],"gateway":"10.0.0.1"},
+            "dns":["10.0.0.53"]}"#,
+        ));
+        let nm = "connection.type:802-3-ethernet\n\
+            connection.interface-name:eth0\n\
+            ipv4.method:manual\n\
+            ipv4.addresses:10.0.0.5/24,10.0.1.5/24\n\
+            ipv4.gateway:10.0.0.1\n\
+            ipv4.dns:10.0.0.53\n\
+            ipv4.ignore-auto-dns:yes\n\
+            ipv6.method:auto\n\
+            ipv6.addresses:\n\
+            ipv6.gateway:--\n\
+            ipv6.dns:\n\
+            ipv6.ignore-auto-dns:yes\n";
+        let (kind, current) = parse_wired_detail(nm);
+        assert_eq!(kind, ETHERNET_TYPE);
+        assert_eq!(current, wanted);
+
+        // A changed gateway no longer matches.
+        let (_, current) = parse_wired_detail(&nm.replace("10.0.0.1", "10.0.0.254"));
+        assert_ne!(current, wanted);
+    }
+
+    #[test]
+    fn escaped_ipv6_values_are_read_back() {
+        let nm = "connection.type:vlan\n\
+            vlan.parent:eth1\n\
+            vlan.id:30\n\
+            ipv6.method:manual\n\
+            ipv6.addresses:2001\\:db8\\:\\:5/64\n\
+            ipv6.gateway:2001\\:db8\\:\\:1\n";
+        let (kind, current) = parse_wired_detail(nm);
+        assert_eq!(kind, VLAN_TYPE);
+        assert_eq!(current.vlan, Some(("eth1".to_string(), 30)));
+        assert_eq!(current.ipv6.addresses, ["2001:db8::5/64"]);
+        assert_eq!(current.ipv6.gateway.as_deref(), Some("2001:db8::1"));
+    }
+}
@@ -1,26 +1,36 @@
+use crate::auditor::integrity::{self, INTEGRITY_BASELINE_PATH};
 use crate::auditor::run_audit_checks;
 use crate::downloader::DownloaderHandle;
 use crate::filebrowser::FileBrowserHandle;
 use crate::filemanager::FileManagerHandle;
 use crate::logstream::LogStreamHandle;
 use crate::magic::MagicHandle;
+use crate::modem::ModemAction;
+use crate::ota::OtaHandle;
 use crate::shutdown::ShutdownSignals;
 use crate::tunnel::TunnelHandle;
 use crate::updater::UpdaterHandle;
 use crate::utils::schema::{SafeCommandRequest, SafeCommandResponse, SafeCommandRx, SafeCommandTx};
 use std::collections::HashMap;
+use std::path::Path;
 use tokio::sync::{mpsc, oneshot};
 use tracing::{info, warn};
 
+mod downloads;
+mod enterprise;
+mod ethernet;
 mod files;
 mod free;
 mod logs;
+mod modem;
 pub(crate) mod network;
 mod ota;
 mod restart;
+mod secrets;
 mod tunnel;
 mod upgrade;
 mod variable;
+mod vpn;
 
 pub struct Handles {
     pub magic: MagicHandle,
@@ -30,6 +40,7 @@ pub struct Handles {
     pub filemanager: FileManagerHandle,
     pub logstream: LogStreamHandle,
     pub filebrowser: FileBrowserHandle,
+    pub ota: OtaHandle,
 }
 
 struct CommandQueueExecutor {
@@ -73,26 +84,42 @@ impl CommandQueueExecutor {
             } => tunnel::open_port(action.id, &self.handles.tunnel, port, user, pub_key).await,
             SafeCommandTx::CloseTunnel => tunnel::close_ssh(action.id, &self.handles.tunnel).await,
             SafeCommandTx::Upgrade => upgrade::upgrade(action.id, &self.handles.updater).await,
+            SafeCommandTx::UpgradePlan { release_id } => {
+                upgrade::plan(action.id, &self.handles.updater, release_id).await
+            }
             SafeCommandTx::UpdateNetwork { network } => network::execute(action.id, network).await,
             SafeCommandTx::DownloadOTA {
                 tools,
                 payload,
                 rate,
+                version,
+                sha256,
             } => {
                 ota::download_ota(
                     action.id,
-                    &self.handles.downloader,
-                    &self.handles.filemanager,
-                    &tools,
-                    &payload,
+                    &self.handles.ota,
+                    tools,
+                    payload,
                     rate,
+                    version,
+                    sha256,
                 )
                 .await
             }
             SafeCommandTx::CheckOTAStatus => {
-                ota::check_ota(action.id, &self.handles.downloader).await
+                ota::check_ota(action.id, &self.handles.ota, &self.handles.downloader).await
+            }
+            SafeCommandTx::StartOTA => ota::start_ota(action.id, &self.handles.ota).await,
+            SafeCommandTx::ListDownloads { ids } => {
+                downloads::list(action.id, &self.handles.downloader, ids).await
+            }
+            SafeCommandTx::CancelDownload { id } => {
+                downloads::cancel(action.id, &self.handles.downloader, id).await
+            }
+            SafeCommandTx::UpdateBandwidthPolicy { policy } => {
+                downloads::update_bandwidth_policy(action.id, &self.handles.downloader, policy)
+                    .await
             }
-            SafeCommandTx::StartOTA => ota::start_ota(action.id, &self.handles.filemanager).await,
             SafeCommandTx::TestNetwork => {
                 let server = self.handles.magic.get_server().await;
                 network::test_network(action.id, &server).await
@@ -118,27 +145,50 @@ impl CommandQueueExecutor {
                 grep,
             } => free::execute_get_logs(action.id, unit, since, until, grep).await,
             SafeCommandTx::RunAudit => {
-                let (disk_encrypted, password_access_disabled) = run_audit_checks().await;
+                let profile = self.handles.magic.get_audit().await;
+                SafeCommandResponse {
+                    id: action.id,
+                    command: run_audit_checks(&profile).await,
+                    status: 0,
+                }
+            }
+            SafeCommandTx::AcceptIntegrityBaseline { changes } => {
+                let profile = self.handles.magic.get_audit().await;
                 SafeCommandResponse {
                     id: action.id,
-                    command: SafeCommandRx::AuditReport {
-                        disk_encrypted,
-                        password_access_disabled,
-                    },
+                    command: integrity::accept(
+                        &profile.integrity_paths,
+                        Path::new(INTEGRITY_BASELINE_PATH),
+                        &changes,
+                    )
+                    .await,
                     status: 0,
                 }
             }
             SafeCommandTx::ReportNMProfiles => network::execute_report_nm_profiles(action.id).await,
             SafeCommandTx::WifiScan => network::execute_wifi_scan(action.id).await,
-            SafeCommandTx::ApplyNetworks { version, networks } => {
-                network::execute_apply_networks(action.id, version, networks).await
-            }
+            SafeCommandTx::ApplyNetworks {
+                version,
+                networks,
+                ethernet,
+            } => network::execute_apply_networks(action.id, version, networks, ethernet).await,
             SafeCommandTx::OpenFileSession { session_id } => {
                 files::open_session(action.id, &self.handles.filebrowser, session_id).await
             }
             SafeCommandTx::CloseFileSession { session_id } => {
                 files::close_session(action.id, &self.handles.filebrowser, session_id).await
             }
+            SafeCommandTx::UpdateSecrets { secrets } => secrets::execute(action.id, secrets).await,
+            SafeCommandTx::SetModemApn { apn } => {
+                modem::execute(action.id, ModemAction::SetApn(apn)).await
+            }
+            SafeCommandTx::SetModemEnabled { enabled } => {
+                modem::execute(action.id, ModemAction::SetEnabled(enabled)).await
+            }
+            SafeCommandTx::PowerCycleModem => {
+                modem::execute(action.id, ModemAction::PowerCycle).await
+            }
+            SafeCommandTx::ApplyVpn { vpn } => vpn::execute(action.id, vpn).await,
             // Issued by a newer api than this daemon understands. Report a
             // failure so the operator sees why the command did nothing instead
             // of it silently disappearing.
@@ -0,0 +1,22 @@
+use crate::modem::{self, ModemAction};
+use crate::utils::schema::{SafeCommandResponse, SafeCommandRx};
+
+pub(super) async fn execute(id: i32, action: ModemAction) -> SafeCommandResponse {
+    match modem::apply(action).await {
+        Ok(status) => SafeCommandResponse {
+            id,
+            command: SafeCommandRx::ModemStatus {
+                modem: Some(status),
+            },
+            status: 0,
+        },
+        Err(err) => SafeCommandResponse {
+            id,
+            command: SafeCommandRx::FreeForm {
+                stdout: String::new(),
+                stderr: format!("Modem command failed: {err:#}"),
+            },
+            status: -1,
+        },
+    }
+}
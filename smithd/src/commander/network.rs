@@ -1,8 +1,9 @@
+use super::{enterprise, ethernet};
 use crate::utils::files::{load_last_applied_networks, save_last_applied_networks};
 use crate::utils::schema::{
-    ConditionReason, ConditionState, IntentNetwork, InterfaceType, NMProfile, Network,
-    NetworkCondition, NetworkDetails, NetworkInfo, SafeCommandResponse, SafeCommandRx, SpeedSample,
-    WifiNetwork,
+    ConditionReason, ConditionState, EapCredentials, IntentEthernet, IntentNetwork, InterfaceType,
+    NMProfile, Network, NetworkCondition, NetworkDetails, NetworkInfo, SafeCommandResponse,
+    SafeCommandRx, SpeedSample, WifiNetwork,
 };
 use anyhow::{Context, Result};
 use chrono::Utc;
@@ -114,7 +115,7 @@ pub(super) async fn execute(id: i32, network: Network) -> SafeCommandResponse {
     }
 }
 
-async fn execute_nmcli_command(mut cmd: Command) -> Result<std::process::Output> {
+pub(crate) async fn execute_nmcli_command(mut cmd: Command) -> Result<std::process::Output> {
     let future = cmd.kill_on_drop(true).output();
 
     match timeout(Duration::from_secs(60), future).await {
@@ -636,7 +637,7 @@ fn unescape_terse(s: &str) -> String {
     out
 }
 
-fn split_terse_line(line: &str, max_fields: usize) -> Vec<String> {
+pub(crate) fn split_terse_line(line: &str, max_fields: usize) -> Vec<String> {
     let mut fields = Vec::new();
     let mut current = String::new();
     let mut chars = line.chars().peekable();
@@ -923,7 +924,7 @@ fn calculate_mbps(bytes: usize, duration_ms: u64) -> f64 {
 
 // Network info collection
 
-async fn collect_network_info() -> Result<NetworkInfo> {
+pub(crate) async fn collect_network_info() -> Result<NetworkInfo> {
     // Try WiFi first (most common on edge devices)
     if let Ok(wifi_info) = collect_wifi_info().await {
         return Ok(wifi_info);
@@ -1286,7 +1287,7 @@ fn parse_mmcli_output(output: &str) -> (Option<String>, Option<i32>, Option<Stri
     (operator, signal_quality, access_technology)
 }
 
-async fn nmcli_delete_profile(name: &str) -> Result<()> {
+pub(super) async fn nmcli_delete_profile(name: &str) -> Result<()> {
     let mut cmd = Command::new("nmcli");
     cmd.args(["connection", "delete", name]);
     let output = execute_nmcli_command(cmd).await?;
@@ -1297,7 +1298,7 @@ async fn nmcli_delete_profile(name: &str) -> Result<()> {
     Ok(())
 }
 
-async fn nmcli_update_priority(name: &str, priority: i32) -> Result<()> {
+pub(super) async fn nmcli_update_priority(name: &str, priority: i32) -> Result<()> {
     let mut cmd = Command::new("nmcli");
     cmd.args([
         "connection",
@@ -1389,6 +1390,58 @@ async fn nmcli_create_profile(
     Ok(())
 }
 
+/// Creates profile `name` of type `kind` from property/value pairs, set to
+/// autoconnect at `priority`.
+pub(super) async fn nmcli_add_profile(
+    name: &str,
+    kind: &str,
+    properties: &[String],
+    priority: i32,
+) -> Result<()> {
+    let mut cmd = Command::new("nmcli");
+    cmd.args(["connection", "add", "type", kind, "con-name", name]);
+    cmd.args(properties);
+    cmd.args([
+        "connection.autoconnect",
+        "yes",
+        "connection.autoconnect-priority",
+        &priority.to_string(),
+        "save",
+        "yes",
+    ]);
+    let output = execute_nmcli_command(cmd).await?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow::anyhow!("nmcli add {name} failed: {stderr}"));
+    }
+    Ok(())
+}
+
+/// Sets property/value pairs on profile `name`, which autoconnects at
+/// `priority` afterwards. An active profile keeps its connection until it is
+/// next activated.
+pub(super) async fn nmcli_modify_properties(
+    name: &str,
+    properties: &[String],
+    priority: i32,
+) -> Result<()> {
+    let mut cmd = Command::new("nmcli");
+    cmd.args(["connection", "modify", name]);
+    cmd.args(properties);
+    cmd.args([
+        "connection.autoconnect",
+        "yes",
+        "connection.autoconnect-priority",
+        &priority.to_string(),
+    ]);
+    let output = execute_nmcli_command(cmd).await?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow::anyhow!("nmcli modify {name} failed: {stderr}"));
+    }
+    Ok(())
+}
+
 /// The profile's true security type, in the same vocabulary `map_key_mgmt`
 /// uses server-side — distinct from the already-degraded `credentials.key_mgmt`
 /// an intent entry carries.
@@ -1438,8 +1491,9 @@ fn active_profile_matches(
 /// Finds a pre-existing NM profile that's the same network under a different
 /// name, so execute_apply_networks can adopt (rename) it instead of creating
 /// a duplicate. Matches on the same identity fields the DB's
-/// network_find_by_content uses (ssid, hidden, security_type, psk), so
-/// smithd's notion of "same network" can't diverge from the catalog's.
+/// network_find_by_content uses (ssid, hidden, security_type, psk, and the EAP
+/// method and identity for enterprise networks), so smithd's notion of "same
+/// network" can't diverge from the catalog's.
 #[allow(clippy::too_many_arguments)]
 fn select_adoption_candidate<'a>(
     current_profiles: &'a HashMap<String, NMProfile>,
@@ -1450,6 +1504,7 @@ fn select_adoption_candidate<'a>(
     hidden: bool,
     security_type: &str,
     intent_psk: Option<&str>,
+    intent_eap: Option<&EapCredentials>,
 ) -> Option<&'a NMProfile> {
     let is_open = security_type == "open";
     current_profiles
@@ -1462,6 +1517,7 @@ fn select_adoption_candidate<'a>(
                 && p.hidden.unwrap_or(false) == hidden
                 && profile_security_type(p) == Some(security_type)
                 && psk_matches(p.password.as_deref(), intent_psk, is_open)
+                && intent_eap.is_none_or(|eap| enterprise::matches(p, eap))
         })
         // Prefer the active candidate (lower disruption); otherwise fall back
         // to the lexically-first name for a deterministic pick, since HashMap
@@ -1486,32 +1542,47 @@ async fn connectivity_guard(
     psk: &str,
     priority: i32,
     hidden: bool,
+) -> Result<(), ConditionReason> {
+    guarded_replace(
+        profile_name,
+        "wifi",
+        &[
+            "ssid",
+            ssid,
+            "802-11-wireless.hidden",
+            if hidden { "yes" } else { "no" },
+            "wifi-sec.key-mgmt",
+            "wpa-psk",
+            "wifi-sec.psk",
+            psk,
+        ],
+        priority,
+    )
+    .await
+}
+
+/// Replaces an active profile through a temporary one of type `kind` built
+/// from `properties`: the old profile is only deleted once the new one comes
+/// up, and is brought back up otherwise.
+pub(super) async fn guarded_replace<S: AsRef<std::ffi::OsStr>>(
+    profile_name: &str,
+    kind: &str,
+    properties: &[S],
+    priority: i32,
 ) -> Result<(), ConditionReason> {
     let tmp_name = format!("tmp-{profile_name}");
 
-    // Create temporary profile with new credentials.
+    // Create temporary profile with the new settings.
     let mut add_cmd = Command::new("nmcli");
+    add_cmd.args(["connection", "add", "type", kind, "con-name", &tmp_name]);
+    add_cmd.args(properties);
     add_cmd.args([
-        "connection",
-        "add",
-        "type",
-        "wifi",
-        "con-name",
-        &tmp_name,
-        "ssid",
-        ssid,
-        "802-11-wireless.hidden",
-        if hidden { "yes" } else { "no" },
         "autoconnect",
         "no",
         "connection.autoconnect-priority",
         &priority.to_string(),
         "save",
         "yes",
-        "wifi-sec.key-mgmt",
-        "wpa-psk",
-        "wifi-sec.psk",
-        psk,
     ]);
     match execute_nmcli_command(add_cmd).await {
         Ok(out) if !out.status.success() => {
@@ -1641,23 +1712,23 @@ async fn adopt_matching_profile(
 
 async fn try_connect_to_fallback(
     active_profile_name: &str,
-    networks: &[IntentNetwork],
+    intent_names: &[&str],
     current_profiles: &HashMap<String, NMProfile>,
 ) -> bool {
     // Intent profiles first, in priority order (as delivered by the API).
-    for network in networks {
-        if network.profile_name == active_profile_name {
+    for name in intent_names {
+        if *name == active_profile_name {
             continue;
         }
         let mut cmd = Command::new("nmcli");
-        cmd.args(["c", "up", &network.profile_name]);
+        cmd.args(["c", "up", name]);
         if matches!(execute_nmcli_command(cmd).await, Ok(o) if o.status.success()) {
             return true;
         }
     }
 
     // Then any remaining NM profiles not in the intent.
-    let intent_names: HashSet<&str> = networks.iter().map(|n| n.profile_name.as_str()).collect();
+    let intent_names: HashSet<&str> = intent_names.iter().copied().collect();
     for name in current_profiles.keys() {
         if name == active_profile_name || intent_names.contains(name.as_str()) {
             continue;
@@ -1672,10 +1743,90 @@ async fn try_connect_to_fallback(
     false
 }
 
+/// Applies a `wpa-eap` intent entry: installs its certificates, then creates,
+/// modifies or guard-replaces the profile like the wpa-psk branch does.
+async fn apply_enterprise_network(
+    network: &IntentNetwork,
+    eap: &EapCredentials,
+    resolved: Option<&NMProfile>,
+    priority: i32,
+) -> Result<(), ConditionReason> {
+    let profile_name = &network.profile_name;
+    let installed = enterprise::install(profile_name, eap).await.map_err(|e| {
+        tracing::error!(
+            "execute_apply_networks: failed to install certificates of {profile_name}: {e:#}"
+        );
+        ConditionReason::NmcliError
+    })?;
+    let mut properties: Vec<String> = vec![
+        "802-11-wireless.ssid".to_string(),
+        network.ssid.clone(),
+        "802-11-wireless.hidden".to_string(),
+        if network.hidden { "yes" } else { "no" }.to_string(),
+    ];
+    properties.extend(installed.properties.iter().cloned());
+
+    let nmcli_error = |e: anyhow::Error| {
+        tracing::error!("execute_apply_networks: {e:#}");
+        ConditionReason::NmcliError
+    };
+    let result = match resolved {
+        Some(profile)
+            if profile.is_active
+                && (installed.certificates_changed
+                    || !active_profile_matches(profile, &network.ssid, network.hidden, None)
+                    || !enterprise::matches(profile, eap)) =>
+        {
+            // Validate the new settings before committing, as for a changed PSK.
+            guarded_replace(profile_name, "wifi", &properties, priority).await
+        }
+        // An unchanged active profile only gets its password and priority
+        // rewritten, which doesn't reconnect it.
+        Some(_) => nmcli_modify_properties(profile_name, &properties, priority)
+            .await
+            .map_err(nmcli_error),
+        None => nmcli_add_profile(profile_name, "wifi", &properties, priority)
+            .await
+            .map_err(nmcli_error),
+    };
+    if result.is_ok() {
+        enterprise::prune(profile_name, &installed).await;
+    }
+    result
+}
+
+fn record_result(
+    conditions: &mut Vec<NetworkCondition>,
+    applied_profile_names: &mut Vec<String>,
+    profile_name: &str,
+    result: Result<(), ConditionReason>,
+) {
+    match result {
+        Ok(()) => {
+            conditions.push(NetworkCondition {
+                profile_name: profile_name.to_string(),
+                state: ConditionState::Applied,
+                reason: None,
+                message: None,
+            });
+            applied_profile_names.push(profile_name.to_string());
+        }
+        Err(reason) => {
+            conditions.push(NetworkCondition {
+                profile_name: profile_name.to_string(),
+                state: ConditionState::Failed,
+                reason: Some(reason),
+                message: None,
+            });
+        }
+    }
+}
+
 pub(crate) async fn execute_apply_networks(
     id: i32,
     version: i32,
     networks: Vec<IntentNetwork>,
+    ethernet: Vec<IntentEthernet>,
 ) -> SafeCommandResponse {
     let all_profiles = match get_nm_wifi_profiles().await {
         Ok(profiles) => profiles,
@@ -1698,6 +1849,21 @@ pub(crate) async fn execute_apply_networks(
         .map(|p| (p.name.clone(), p))
         .collect();
 
+    let wired_profiles = match ethernet::wired_profiles().await {
+        Ok(profiles) => profiles,
+        Err(e) => {
+            tracing::error!("execute_apply_networks: failed to list wired NM profiles: {e:#}");
+            return SafeCommandResponse {
+                id,
+                command: SafeCommandRx::ApplyNetworksResult {
+                    applied_version: version,
+                    conditions: vec![],
+                },
+                status: -1,
+            };
+        }
+    };
+
     let last_applied = match load_last_applied_networks().await {
         Ok(list) => list.into_iter().collect::<HashSet<String>>(),
         Err(e) => {
@@ -1714,8 +1880,11 @@ pub(crate) async fn execute_apply_networks(
     };
 
     let n = networks.len();
-    let intent_profile_names: HashSet<String> =
-        networks.iter().map(|nw| nw.profile_name.clone()).collect();
+    let intent_profile_names: HashSet<String> = networks
+        .iter()
+        .map(|nw| nw.profile_name.clone())
+        .chain(ethernet.iter().map(|wired| wired.profile_name.clone()))
+        .collect();
     let mut conditions: Vec<NetworkCondition> = Vec::new();
     let mut applied_profile_names: Vec<String> = Vec::new();
     // Pre-existing profiles adopted (renamed) into an intent name this pass, so a
@@ -1728,8 +1897,10 @@ pub(crate) async fn execute_apply_networks(
         let profile_name = &network.profile_name;
         let is_open = network.credentials.key_mgmt == "none";
         let psk = network.credentials.psk.as_deref();
+        let is_enterprise = network.credentials.key_mgmt == "wpa-eap";
+        let eap = network.credentials.eap.as_ref().filter(|_| is_enterprise);
 
-        if !is_open && network.credentials.key_mgmt != "wpa-psk" {
+        if !is_open && !is_enterprise && network.credentials.key_mgmt != "wpa-psk" {
             tracing::error!(
                 "execute_apply_networks: unsupported key_mgmt '{}' for {profile_name}",
                 network.credentials.key_mgmt
@@ -1746,9 +1917,27 @@ pub(crate) async fn execute_apply_networks(
             continue;
         }
 
+        if is_enterprise {
+            let valid = eap
+                .ok_or_else(|| "wpa-eap network has no EAP settings".to_string())
+                .and_then(|eap| eap.validate());
+            if let Err(message) = valid {
+                tracing::error!(
+                    "execute_apply_networks: wpa-eap network {profile_name}: {message}"
+                );
+                conditions.push(NetworkCondition {
+                    profile_name: profile_name.clone(),
+                    state: ConditionState::Failed,
+                    reason: Some(ConditionReason::NmcliError),
+                    message: Some(message),
+                });
+                continue;
+            }
+        }
+
         // Reject wpa-psk networks without a PSK before branching. The nmcli helpers treat
         // a None PSK as "open", which would silently create an insecure profile.
-        if !is_open && psk.is_none() {
+        if !is_open && !is_enterprise && psk.is_none() {
             tracing::error!("execute_apply_networks: wpa-psk network {profile_name} has no psk");
             conditions.push(NetworkCondition {
                 profile_name: profile_name.clone(),
@@ -1772,6 +1961,7 @@ pub(crate) async fn execute_apply_networks(
                 network.hidden,
                 &network.security_type,
                 psk,
+                eap,
             )
         } else {
             None
@@ -1793,6 +1983,17 @@ pub(crate) async fn execute_apply_networks(
 
         let resolved: Option<&NMProfile> = exact_match.or(adoption_candidate);
 
+        if let Some(eap) = eap {
+            let result = apply_enterprise_network(network, eap, resolved, priority).await;
+            record_result(
+                &mut conditions,
+                &mut applied_profile_names,
+                profile_name,
+                result,
+            );
+            continue;
+        }
+
         let result = match resolved {
             Some(profile) if profile.is_active => {
                 if is_open {
@@ -1857,25 +2058,41 @@ pub(crate) async fn execute_apply_networks(
                 }),
         };
 
-        match result {
-            Ok(()) => {
-                conditions.push(NetworkCondition {
-                    profile_name: profile_name.clone(),
-                    state: ConditionState::Applied,
-                    reason: None,
-                    message: None,
-                });
-                applied_profile_names.push(profile_name.clone());
-            }
-            Err(reason) => {
-                conditions.push(NetworkCondition {
-                    profile_name: profile_name.clone(),
-                    state: ConditionState::Failed,
-                    reason: Some(reason),
-                    message: None,
-                });
-            }
+        record_result(
+            &mut conditions,
+            &mut applied_profile_names,
+            profile_name,
+            result,
+        );
+    }
+
+    // Wired profiles are ordered among themselves: NM only weighs autoconnect
+    // priorities of profiles that can use the same device.
+    for (index, wired) in ethernet.iter().enumerate() {
+        let priority = ((ethernet.len() - index) * 10) as i32;
+        let profile_name = &wired.profile_name;
+
+        // The api validates too, but a command queued by an older api may not be.
+        if let Err(message) = wired.validate() {
+            tracing::error!(
+                "execute_apply_networks: invalid wired profile {profile_name}: {message}"
+            );
+            conditions.push(NetworkCondition {
+                profile_name: profile_name.clone(),
+                state: ConditionState::Failed,
+                reason: Some(ConditionReason::NmcliError),
+                message: Some(message),
+            });
+            continue;
         }
+
+        let result = ethernet::apply(wired, wired_profiles.get(profile_name), priority).await;
+        record_result(
+            &mut conditions,
+            &mut applied_profile_names,
+            profile_name,
+            result,
+        );
     }
 
     // Delete all NM profiles Smith previously managed that are no longer in intent.
@@ -1887,13 +2104,21 @@ pub(crate) async fn execute_apply_networks(
     let mut inactive_to_delete: Vec<String> = Vec::new();
 
     for profile_name in last_applied.difference(&intent_profile_names) {
-        match current_profiles.get(profile_name.as_str()) {
+        let is_active = current_profiles
+            .get(profile_name.as_str())
+            .map(|p| p.is_active)
+            .or_else(|| {
+                wired_profiles
+                    .get(profile_name.as_str())
+                    .map(|p| p.is_active)
+            });
+        match is_active {
             None => {
                 // Already gone from NM; mark deleted without an nmcli call.
                 successfully_deleted.insert(profile_name.clone());
             }
-            Some(p) if p.is_active => active_to_delete.push(profile_name.clone()),
-            Some(_) => inactive_to_delete.push(profile_name.clone()),
+            Some(true) => active_to_delete.push(profile_name.clone()),
+            Some(false) => inactive_to_delete.push(profile_name.clone()),
         }
     }
 
@@ -1914,8 +2139,13 @@ pub(crate) async fn execute_apply_networks(
     // For active profiles, try to connect to a fallback before deleting so the
     // device is not left without connectivity. Intent profiles are tried first
     // (in priority order), then any remaining NM profiles.
+    let fallback_order: Vec<&str> = networks
+        .iter()
+        .map(|nw| nw.profile_name.as_str())
+        .chain(ethernet.iter().map(|wired| wired.profile_name.as_str()))
+        .collect();
     for profile_name in &active_to_delete {
-        if try_connect_to_fallback(profile_name, &networks, &current_profiles).await {
+        if try_connect_to_fallback(profile_name, &fallback_order, &current_profiles).await {
             if nmcli_delete_profile(profile_name)
                 .await
                 .inspect_err(|e| {
@@ -1941,6 +2171,10 @@ pub(crate) async fn execute_apply_networks(
         }
     }
 
+    for profile_name in &successfully_deleted {
+        enterprise::remove(profile_name).await;
+    }
+
     // Persist: union of previous and newly applied, minus what was cleanly deleted.
     // This prevents a failed run from erasing tracking of still-existing profiles.
     let mut new_last_applied = last_applied;
@@ -2552,6 +2786,7 @@ connection.autoconnect-priority:500\n";
             false,
             "wpa-psk",
             Some("secret"),
+            None,
         );
 
         assert_eq!(
@@ -2587,6 +2822,7 @@ connection.autoconnect-priority:500\n";
             false,
             "wpa-psk",
             Some("secret"),
+            None,
         );
 
         assert!(candidate.is_none());
@@ -2619,6 +2855,7 @@ connection.autoconnect-priority:500\n";
             false,
             "wpa-psk",
             Some("secret"),
+            None,
         );
 
         assert!(candidate.is_none());
@@ -2648,6 +2885,7 @@ connection.autoconnect-priority:500\n";
             false,
             "wpa-psk",
             Some("secret"),
+            None,
         );
 
         assert!(candidate.is_none());
@@ -2677,6 +2915,7 @@ connection.autoconnect-priority:500\n";
             false,
             "wpa-psk",
             Some("secret"),
+            None,
         );
 
         assert!(candidate.is_none());
@@ -2701,6 +2940,7 @@ connection.autoconnect-priority:500\n";
             false,
             "wpa-psk",
             Some("secret"),
+            None,
         );
 
         assert!(candidate.is_none());
@@ -2730,6 +2970,7 @@ connection.autoconnect-priority:500\n";
             false,
             "wpa-psk",
             Some("new-secret"),
+            None,
         );
 
         assert!(candidate.is_none());
@@ -2752,11 +2993,52 @@ connection.autoconnect-priority:500\n";
             false,
             "wpa-psk",
             Some("secret"),
+            None,
         );
 
         assert_eq!(candidate.map(|p| p.name.as_str()), Some("unreadable-psk"));
     }
 
+    #[test]
+    fn select_adoption_candidate_matches_enterprise_on_eap_fields() {
+        let mut current_profiles = HashMap::new();
+        for (name, identity) in [("ward-nurse", "nurse"), ("ward-doctor", "doctor")] {
+            let mut profile = nm_profile(name, "HC-Ward", "wpa-eap", None, false, false);
+            profile.eap = Some("peap".to_string());
+            profile.phase2_auth = Some("mschapv2".to_string());
+            profile.eap_identity = Some(identity.to_string());
+            current_profiles.insert(name.to_string(), profile);
+        }
+        let eap = EapCredentials {
+            method: "peap".to_string(),
+            identity: "doctor".to_string(),
+            phase2_auth: Some("mschapv2".to_string()),
+            password: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let select = |eap: &EapCredentials| {
+            select_adoption_candidate(
+                &current_profiles,
+                &HashSet::new(),
+                &HashSet::new(),
+                "HC-Ward",
+                "HC-Ward",
+                false,
+                "wpa-eap",
+                None,
+                Some(eap),
+            )
+            .map(|p| p.name.clone())
+        };
+
+        assert_eq!(select(&eap).as_deref(), Some("ward-doctor"));
+        let ttls = EapCredentials {
+            method: "ttls".to_string(),
+            ..eap
+        };
+        assert_eq!(select(&ttls), None);
+    }
+
     #[test]
     fn select_adoption_candidate_open_network_ignores_psk() {
         let mut current_profiles = HashMap::new();
@@ -2774,6 +3056,7 @@ connection.autoconnect-priority:500\n";
             false,
             "open",
             None,
+            None,
         );
 
         assert_eq!(candidate.map(|p| p.name.as_str()), Some("open-net"));
@@ -2814,6 +3097,7 @@ connection.autoconnect-priority:500\n";
             false,
             "wpa-psk",
             Some("secret"),
+            None,
         );
 
         assert_eq!(candidate.map(|p| p.name.as_str()), Some("active-one"));
@@ -2854,6 +3138,7 @@ connection.autoconnect-priority:500\n";
             false,
             "wpa-psk",
             Some("secret"),
+            None,
         );
 
         assert_eq!(candidate.map(|p| p.name.as_str()), Some("aaa-profile"));
@@ -0,0 +1,44 @@
+use crate::secrets;
+use crate::utils::schema::{SafeCommandResponse, SafeCommandRx, SealedSecret};
+use std::path::Path;
+use tracing::error;
+
+pub(super) async fn execute(id: i32, sealed: Vec<SealedSecret>) -> SafeCommandResponse {
+    let result = tokio::task::spawn_blocking(move || {
+        let key = secrets::load_or_create_key(Path::new(secrets::KEYS_DIR))?;
+        Ok::<_, anyhow::Error>(secrets::apply(
+            &key,
+            &sealed,
+            Path::new(secrets::SECRETS_DIR),
+            Path::new(secrets::SECRETS_ENV_FILE),
+        ))
+    })
+    .await;
+
+    let failed = match result {
+        Ok(Ok(failed)) => failed,
+        Ok(Err(err)) => {
+            error!("Failed to load device secrets key: {err:#}");
+            return SafeCommandResponse {
+                id,
+                command: SafeCommandRx::UpdateSecrets { failed: vec![] },
+                status: -1,
+            };
+        }
+        Err(err) => {
+            error!("Secrets task panicked: {err}");
+            return SafeCommandResponse {
+                id,
+                command: SafeCommandRx::UpdateSecrets { failed: vec![] },
+                status: -1,
+            };
+        }
+    };
+
+    let status = if failed.is_empty() { 0 } else { -1 };
+    SafeCommandResponse {
+        id,
+        command: SafeCommandRx::UpdateSecrets { failed },
+        status,
+    }
+}
@@ -10,3 +10,25 @@ pub(super) async fn upgrade(id: i32, updater_handle: &UpdaterHandle) -> SafeComm
         status: 0,
     }
 }
+
+pub(super) async fn plan(
+    id: i32,
+    updater_handle: &UpdaterHandle,
+    release_id: Option<i32>,
+) -> SafeCommandResponse {
+    match updater_handle.plan_upgrade(release_id).await {
+        Ok(plan) => SafeCommandResponse {
+            id,
+            command: SafeCommandRx::UpgradePlan { plan },
+            status: 0,
+        },
+        Err(err) => SafeCommandResponse {
+            id,
+            command: SafeCommandRx::FreeForm {
+                stdout: String::new(),
+                stderr: format!("Failed to plan upgrade: {err:#}"),
+            },
+            status: -1,
+        },
+    }
+}
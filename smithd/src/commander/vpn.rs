@@ -0,0 +1,20 @@
+use crate::utils::schema::{SafeCommandResponse, SafeCommandRx, VpnConfig};
+use crate::vpn;
+
+pub(super) async fn execute(id: i32, config: Option<VpnConfig>) -> SafeCommandResponse {
+    match vpn::apply(config.as_ref()).await {
+        Ok(()) => SafeCommandResponse {
+            id,
+            command: SafeCommandRx::VpnApplied,
+            status: 0,
+        },
+        Err(err) => SafeCommandResponse {
+            id,
+            command: SafeCommandRx::FreeForm {
+                stdout: String::new(),
+                stderr: format!("VPN command failed: {err:#}"),
+            },
+            status: -1,
+        },
+    }
+}
@@ -0,0 +1,224 @@
+//! Probes upstream connectivity the way NetworkManager does, so a device on
+//! Wi-Fi behind a captive portal or a dead uplink can be told apart from one
+//! that is down. Transitions are kept until the API acknowledges them, so the
+//! ones that happened while offline arrive once the device is back.
+use crate::magic::MagicHandle;
+use crate::shutdown::ShutdownSignals;
+use crate::utils::schema::{ConnectivityReport, ConnectivityState, ConnectivityTransition};
+use std::collections::VecDeque;
+use std::time::SystemTime;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{Duration, sleep};
+use tracing::info;
+
+mod probe;
+
+/// Transitions kept while the API is unreachable. A flapping uplink can
+/// change state every probe; past this the oldest are dropped.
+const MAX_TRANSITIONS: usize = 500;
+
+enum ConnectivityMessage {
+    Observed {
+        state: ConnectivityState,
+        at: u64,
+    },
+    Report {
+        rpc: oneshot::Sender<Option<ConnectivitySnapshot>>,
+    },
+    Acknowledge {
+        cursor: u64,
+    },
+}
+
+/// What to send home, and how to tell the tracker it got there.
+#[derive(Debug)]
+pub struct ConnectivitySnapshot {
+    pub report: ConnectivityReport,
+    cursor: u64,
+}
+
+#[derive(Clone)]
+pub struct ConnectivityHandle {
+    sender: mpsc::Sender<ConnectivityMessage>,
+}
+
+impl ConnectivityHandle {
+    pub fn new(shutdown: ShutdownSignals, magic: MagicHandle) -> Self {
+        let (sender, mut receiver) = mpsc::channel(8);
+
+        let observed = sender.clone();
+        tokio::spawn(async move {
+            info!("Connectivity probe starting");
+            loop {
+                let config = magic.get_connectivity().await;
+                let state = probe::probe(&config).await;
+                let msg = ConnectivityMessage::Observed {
+                    state,
+                    at: unix_now(),
+                };
+                if observed.send(msg).await.is_err() {
+                    break;
+                }
+                tokio::select! {
+                    _ = shutdown.token.cancelled() => break,
+                    _ = sleep(Duration::from_secs(config.interval_secs)) => {}
+                }
+            }
+            info!("Connectivity probe shutting down");
+        });
+
+        tokio::spawn(async move {
+            let mut tracker = Tracker::default();
+            while let Some(msg) = receiver.recv().await {
+                match msg {
+                    ConnectivityMessage::Observed { state, at } => tracker.observe(state, at),
+                    ConnectivityMessage::Report { rpc } => _ = rpc.send(tracker.snapshot()),
+                    ConnectivityMessage::Acknowledge { cursor } => tracker.acknowledge(cursor),
+                }
+            }
+        });
+
+        Self { sender }
+    }
+
+    /// The current state and pending transitions, `None` until the first
+    /// probe finished.
+    pub async fn report(&self) -> Option<ConnectivitySnapshot> {
+        let (rpc, receiver) = oneshot::channel();
+        _ = self.sender.send(ConnectivityMessage::Report { rpc }).await;
+        receiver.await.ok().flatten()
+    }
+
+    /// Forgets the transitions in `snapshot`, once the API has stored them.
+    pub async fn acknowledge(&self, snapshot: &ConnectivitySnapshot) {
+        let msg = ConnectivityMessage::Acknowledge {
+            cursor: snapshot.cursor,
+        };
+        _ = self.sender.send(msg).await;
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// The current state and the transitions not acknowledged yet. Transitions
+/// are numbered by how many came before them, so an acknowledgement only
+/// drops what was reported even when new ones arrived in between.
+#[derive(Default)]
+struct Tracker {
+    state: ConnectivityState,
+    since: u64,
+    pending: VecDeque<ConnectivityTransition>,
+    /// Transitions acknowledged or dropped so far.
+    removed: u64,
+}
+
+impl Tracker {
+    fn observe(&mut self, state: ConnectivityState, at: u64) {
+        if state == self.state {
+            return;
+        }
+        info!("Connectivity changed from {:?} to {:?}", self.state, state);
+        self.state = state;
+        self.since = at;
+        self.pending.push_back(ConnectivityTransition { at, state });
+        if self.pending.len() > MAX_TRANSITIONS {
+            self.pending.pop_front();
+            self.removed += 1;
+        }
+    }
+
+    fn snapshot(&self) -> Option<ConnectivitySnapshot> {
+        if self.state == ConnectivityState::Unknown {
+            return None;
+        }
+        Some(ConnectivitySnapshot {
+            report: ConnectivityReport {
+                state: self.state,
+                since: self.since,
+                transitions: self.pending.iter().copied().collect(),
+            },
+            cursor: self.removed + self.pending.len() as u64,
+        })
+    }
+
+    fn acknowledge(&mut self, cursor: u64) {
+        let count = cursor.saturating_sub(self.removed) as usize;
+        let count = count.min(self.pending.len());
+        self.pending.drain(..count);
+        self.removed += count as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn states(snapshot: &ConnectivitySnapshot) -> Vec<(u64, ConnectivityState)> {
+        snapshot
+            .report
+            .transitions
+            .iter()
+            .map(|t| (t.at, t.state))
+            .collect()
+    }
+
+    #[test]
+    fn records_transitions_until_acknowledged() {
+        let mut tracker = Tracker::default();
+        assert!(tracker.snapshot().is_none());
+
+        tracker.observe(ConnectivityState::Full, 100);
+        tracker.observe(ConnectivityState::Full, 160);
+        tracker.observe(ConnectivityState::Portal, 220);
+        let sent = tracker.snapshot().unwrap();
+        assert_eq!(sent.report.state, ConnectivityState::Portal);
+        assert_eq!(sent.report.since, 220);
+        assert_eq!(
+            states(&sent),
+            vec![
+                (100, ConnectivityState::Full),
+                (220, ConnectivityState::Portal)
+            ]
+        );
+
+        // A transition while the post is in flight survives its acknowledgement.
+        tracker.observe(ConnectivityState::Full, 280);
+        tracker.acknowledge(sent.cursor);
+        let next = tracker.snapshot().unwrap();
+        assert_eq!(states(&next), vec![(280, ConnectivityState::Full)]);
+        assert_eq!(next.report.since, 280);
+
+        // A stale acknowledgement changes nothing.
+        tracker.acknowledge(sent.cursor);
+        assert_eq!(
+            states(&tracker.snapshot().unwrap()),
+            vec![(280, ConnectivityState::Full)]
+        );
+    }
+
+    #[test]
+    fn drops_the_oldest_transitions_past_the_limit() {
+        let mut tracker = Tracker::default();
+        for at in 0..(MAX_TRANSITIONS as u64 + 10) {
+            tracker.observe(
+                if at % 2 == 0 {
+                    ConnectivityState::Limited
+                } else {
+                    ConnectivityState::None
+                },
+                at,
+            );
+        }
+        let snapshot = tracker.snapshot().unwrap();
+        assert_eq!(snapshot.report.transitions.len(), MAX_TRANSITIONS);
+        assert_eq!(snapshot.report.transitions[0].at, 10);
+
+        tracker.acknowledge(snapshot.cursor);
+        assert!(tracker.snapshot().unwrap().report.transitions.is_empty());
+    }
+}
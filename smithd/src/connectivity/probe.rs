@@ -0,0 +1,176 @@
+use crate::magic::structure::ConfigConnectivity;
+use crate::utils::schema::ConnectivityState;
+use tokio::time::{Duration, timeout};
+
+const DNS_TIMEOUT: Duration = Duration::from_secs(5);
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Classifies the device's connectivity, the way NetworkManager does.
+pub(super) async fn probe(config: &ConfigConnectivity) -> ConnectivityState {
+    let has_route = has_default_route(
+        &tokio::fs::read_to_string("/proc/net/route")
+            .await
+            .unwrap_or_default(),
+        &tokio::fs::read_to_string("/proc/net/ipv6_route")
+            .await
+            .unwrap_or_default(),
+    );
+    if !has_route {
+        return ConnectivityState::None;
+    }
+    check_upstream(config).await
+}
+
+/// Resolves and fetches the check URL. Anything but the expected answer
+/// means something between the device and the internet answered instead.
+async fn check_upstream(config: &ConfigConnectivity) -> ConnectivityState {
+    let Ok(url) = url::Url::parse(&config.check_url) else {
+        return ConnectivityState::Limited;
+    };
+    let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+        return ConnectivityState::Limited;
+    };
+    let resolves = match timeout(DNS_TIMEOUT, tokio::net::lookup_host((host, port))).await {
+        Ok(Ok(mut addrs)) => addrs.next().is_some(),
+        _ => false,
+    };
+    if !resolves {
+        return ConnectivityState::Limited;
+    }
+
+    // A portal answers with a redirect to its login page, which must not be
+    // followed, and a proxy would hide the portal.
+    let client = match reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .no_proxy()
+        .timeout(HTTP_TIMEOUT)
+        .build()
+    {
+        Ok(client) => client,
+        Err(_) => return ConnectivityState::Limited,
+    };
+    let response = match client.get(url.as_str()).send().await {
+        Ok(response) => response,
+        Err(_) => return ConnectivityState::Limited,
+    };
+    let expected_status = if config.expected_body.is_empty() {
+        response.status() == reqwest::StatusCode::NO_CONTENT
+    } else {
+        response.status().is_success()
+    };
+    match response.text().await {
+        Ok(body) if expected_status && body.trim() == config.expected_body.trim() => {
+            ConnectivityState::Full
+        }
+        Ok(_) => ConnectivityState::Portal,
+        Err(_) => ConnectivityState::Limited,
+    }
+}
+
+/// Whether either routing table has a default route off the loopback
+/// interface.
+fn has_default_route(ipv4: &str, ipv6: &str) -> bool {
+    // Iface Destination Gateway Flags ... Mask ...
+    let v4 = ipv4.lines().skip(1).any(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        fields.len() > 7 && fields[0] != "lo" && fields[1] == "00000000" && fields[7] == "00000000"
+    });
+    // Destination PrefixLen Source PrefixLen NextHop Metric RefCnt Use Flags Iface
+    let v6 = ipv6.lines().any(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        fields.len() > 9
+            && fields[9] != "lo"
+            && fields[1] == "00"
+            && fields[0].bytes().all(|b| b == b'0')
+    });
+    v4 || v6
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Serves one canned HTTP response and returns the URL to fetch.
+    async fn serve(response: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            _ = stream.read(&mut request).await;
+            _ = stream.write_all(response.as_bytes()).await;
+        });
+        format!("http://{addr}/check_network_status.txt")
+    }
+
+    fn config(check_url: String) -> ConfigConnectivity {
+        ConfigConnectivity {
+            check_url,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn classifies_what_answers_the_check() {
+        let online = serve(
+            "HTTP/1.1 200 OK\r\nContent-Length: 25\r\nConnection: close\r\n\r\nNetworkManager is online\n",
+        )
+        .await;
+        assert_eq!(
+            check_upstream(&config(online)).await,
+            ConnectivityState::Full
+        );
+
+        let portal = serve(
+            "HTTP/1.1 302 Found\r\nLocation: http://portal.example/login\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert_eq!(
+            check_upstream(&config(portal)).await,
+            ConnectivityState::Portal
+        );
+
+        let hijacked = serve(
+            "HTTP/1.1 200 OK\r\nContent-Length: 13\r\nConnection: close\r\n\r\n<html>Login</",
+        )
+        .await;
+        assert_eq!(
+            check_upstream(&config(hijacked)).await,
+            ConnectivityState::Portal
+        );
+    }
+
+    #[tokio::test]
+    async fn an_unanswered_check_is_limited() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        assert_eq!(
+            check_upstream(&config(format!("http://{addr}/"))).await,
+            ConnectivityState::Limited
+        );
+        assert_eq!(
+            check_upstream(&config("http://nonexistent.invalid/".into())).await,
+            ConnectivityState::Limited
+        );
+    }
+
+    #[test]
+    fn finds_default_routes_in_either_table() {
+        let header =
+            "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT\n";
+        let link_only =
+            format!("{header}wlan0\t0001A8C0\t00000000\t0001\t0\t0\t600\t00FFFFFF\t0\t0\t0\n");
+        let default =
+            format!("{link_only}wlan0\t00000000\t0101A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0\n");
+        let v6_default = "00000000000000000000000000000000 00 00000000000000000000000000000000 00 fe800000000000000000000000000001 00000400 00000000 00000000 00000003 eth0\n";
+        let v6_loopback = "00000000000000000000000000000000 00 00000000000000000000000000000000 00 00000000000000000000000000000000 ffffffff 00000001 00000000 00200200 lo\n";
+
+        assert!(!has_default_route(&link_only, v6_loopback));
+        assert!(has_default_route(&default, ""));
+        assert!(has_default_route(header, v6_default));
+        assert!(!has_default_route("", ""));
+    }
+}
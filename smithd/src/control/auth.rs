@@ -0,0 +1,239 @@
+//! Caller authorization for the control socket.
+//!
+//! The socket's file mode decides who can connect; this decides what they may
+//! call. The peer's `SO_PEERCRED` is captured once per accepted connection, and
+//! every request is checked against the `[control]` policy in magic.toml.
+//! Root is always allowed, since that is who the `smithd` subcommands run as.
+
+use super::ErrorResponse;
+use crate::magic::MagicHandle;
+use crate::magic::structure::{ConfigControl, ConfigControlRule};
+use axum::Json;
+use axum::extract::connect_info::Connected;
+use axum::extract::{ConnectInfo, MatchedPath, Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::serve::IncomingStream;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::net::UnixListener;
+use tracing::warn;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PeerCredentials {
+    pub uid: u32,
+    pub gid: u32,
+    /// Supplementary groups, read from `/proc/<pid>/status`.
+    pub groups: Vec<u32>,
+    /// `/proc/<pid>/exe`. `None` when the peer has already exited or lives in
+    /// another pid namespace; rules naming executables then do not match.
+    pub exe: Option<PathBuf>,
+}
+
+impl PeerCredentials {
+    fn in_group(&self, gid: u32) -> bool {
+        self.gid == gid || self.groups.contains(&gid)
+    }
+}
+
+/// Connection info for the control socket. `None` if the kernel would not
+/// tell us who is calling, which is treated as a caller nothing is granted to.
+#[derive(Clone, Debug)]
+pub struct Peer(pub Option<PeerCredentials>);
+
+impl Connected<IncomingStream<'_, UnixListener>> for Peer {
+    fn connect_info(stream: IncomingStream<'_, UnixListener>) -> Self {
+        let cred = match stream.io().peer_cred() {
+            Ok(cred) => cred,
+            Err(e) => {
+                warn!("Failed to read control socket peer credentials: {e}");
+                return Self(None);
+            }
+        };
+
+        let (groups, exe) = match cred.pid() {
+            Some(pid) => (
+                supplementary_groups(pid),
+                std::fs::read_link(format!("/proc/{pid}/exe")).ok(),
+            ),
+            None => (Vec::new(), None),
+        };
+
+        Self(Some(PeerCredentials {
+            uid: cred.uid(),
+            gid: cred.gid(),
+            groups,
+            exe,
+        }))
+    }
+}
+
+fn supplementary_groups(pid: i32) -> Vec<u32> {
+    std::fs::read_to_string(format!("/proc/{pid}/status"))
+        .ok()
+        .and_then(|status| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix("Groups:"))
+                .map(|groups| {
+                    groups
+                        .split_whitespace()
+                        .filter_map(|gid| gid.parse().ok())
+                        .collect()
+                })
+        })
+        .unwrap_or_default()
+}
+
+fn rule_matches(rule: &ConfigControlRule, peer: &PeerCredentials, route: &str) -> bool {
+    let route_ok = rule.routes.iter().any(|r| r == "*" || r == route);
+    let uid_ok = rule.uids.is_empty() || rule.uids.contains(&peer.uid);
+    let gid_ok = rule.gids.is_empty() || rule.gids.iter().any(|gid| peer.in_group(*gid));
+    let exe_ok =
+        rule.exes.is_empty() || peer.exe.as_ref().is_some_and(|exe| rule.exes.contains(exe));
+
+    route_ok && uid_ok && gid_ok && exe_ok
+}
+
+pub fn allows(policy: &ConfigControl, peer: &PeerCredentials, route: &str) -> bool {
+    peer.uid == 0
+        || policy
+            .rules
+            .iter()
+            .any(|rule| rule_matches(rule, peer, route))
+}
+
+/// Denied requests per route since the daemon started.
+#[derive(Clone, Default)]
+pub struct Denials(Arc<Mutex<BTreeMap<String, u64>>>);
+
+impl Denials {
+    fn record(&self, route: &str) -> u64 {
+        let mut counts = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let count = counts.entry(route.to_owned()).or_default();
+        *count += 1;
+        *count
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, u64> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+#[derive(Clone)]
+pub struct AuthState {
+    pub magic: MagicHandle,
+    pub denials: Denials,
+}
+
+pub async fn authorize(
+    State(state): State<AuthState>,
+    ConnectInfo(Peer(peer)): ConnectInfo<Peer>,
+    request: Request,
+    next: Next,
+) -> Response {
+    // Counted by matched route, so probing made-up paths cannot grow the map.
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_else(|| "(unmatched)".to_owned());
+
+    let policy = state.magic.get_control_policy().await;
+    if let Some(peer) = &peer
+        && allows(&policy, peer, request.uri().path())
+    {
+        return next.run(request).await;
+    }
+
+    let count = state.denials.record(&route);
+    match &peer {
+        Some(peer) => warn!(
+            uid = peer.uid,
+            gid = peer.gid,
+            exe = ?peer.exe,
+            "Denied control request to {route} ({count} denied so far)"
+        ),
+        None => warn!("Denied control request to {route} from unknown peer ({count} so far)"),
+    }
+
+    let body = ErrorResponse {
+        error: format!("caller is not permitted to use {route}"),
+    };
+    (StatusCode::FORBIDDEN, Json(body)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kiosk() -> PeerCredentials {
+        PeerCredentials {
+            uid: 1001,
+            gid: 1001,
+            groups: vec![44],
+            exe: Some(PathBuf::from("/opt/kiosk/bin/kiosk")),
+        }
+    }
+
+    fn policy(rule: ConfigControlRule) -> ConfigControl {
+        ConfigControl { rules: vec![rule] }
+    }
+
+    #[test]
+    fn root_is_always_allowed_and_others_only_when_granted() {
+        let root = PeerCredentials {
+            uid: 0,
+            gid: 0,
+            groups: Vec::new(),
+            exe: None,
+        };
+
+        assert!(allows(&ConfigControl::default(), &root, "/ota/start"));
+        assert!(!allows(&ConfigControl::default(), &kiosk(), "/watchdog"));
+    }
+
+    #[test]
+    fn every_selector_in_a_rule_must_match() {
+        let grant = policy(ConfigControlRule {
+            routes: vec!["/watchdog".into(), "/watchdog/hold".into()],
+            uids: vec![1001],
+            exes: vec![PathBuf::from("/opt/kiosk/bin/kiosk")],
+            ..Default::default()
+        });
+
+        assert!(allows(&grant, &kiosk(), "/watchdog/hold"));
+        assert!(!allows(&grant, &kiosk(), "/ota/start"));
+
+        let impostor = PeerCredentials {
+            exe: Some(PathBuf::from("/usr/bin/python3")),
+            ..kiosk()
+        };
+        assert!(!allows(&grant, &impostor, "/watchdog/hold"));
+
+        let vanished = PeerCredentials {
+            exe: None,
+            ..kiosk()
+        };
+        assert!(!allows(&grant, &vanished, "/watchdog/hold"));
+    }
+
+    #[test]
+    fn gids_match_supplementary_groups() {
+        let grant = policy(ConfigControlRule {
+            routes: vec!["*".into()],
+            gids: vec![44],
+            ..Default::default()
+        });
+
+        assert!(allows(&grant, &kiosk(), "/tunnel"));
+
+        let outsider = PeerCredentials {
+            groups: Vec::new(),
+            ..kiosk()
+        };
+        assert!(!allows(&grant, &outsider, "/tunnel"));
+    }
+}
@@ -0,0 +1,30 @@
+use crate::magic::structure::MagicFile;
+use anyhow::{Context, Result};
+
+/// Validates a magic file the way the daemon would load it, without migrating
+/// it on disk or touching the running daemon. Defaults to the file the daemon
+/// would pick.
+pub(super) fn check(path: Option<String>) -> Result<()> {
+    let path = path.unwrap_or_else(|| {
+        if std::path::Path::new("./magic.toml").exists() {
+            "./magic.toml".to_string()
+        } else {
+            "/etc/smith/magic.toml".to_string()
+        }
+    });
+
+    let contents =
+        std::fs::read_to_string(&path).with_context(|| format!("Failed to read {path}"))?;
+    let (magic, migrated_from) =
+        MagicFile::parse(&contents).with_context(|| format!("{path} is invalid"))?;
+
+    match migrated_from {
+        Some(from) => println!(
+            "{path}: ok, will be migrated from v{from} to v{} on load",
+            magic.meta.magic_version
+        ),
+        None => println!("{path}: ok (v{})", magic.meta.magic_version),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,662 @@
+//! `smithd doctor`: one command a technician with local access can run to see
+//! what is wrong with a device. Every check runs even if an earlier one fails,
+//! except those that need something the failed check would have provided.
+
+use super::{client, control_url};
+use crate::magic::structure::MagicFile;
+use crate::police::RebootStatus;
+use anyhow::{Result, anyhow};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{DateTime, Utc};
+use nix::fcntl::{FcntlArg, fcntl};
+use nix::sys::statvfs::statvfs;
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::process::Command;
+
+const MAGIC_PATHS: [&str; 2] = ["./magic.toml", "/etc/smith/magic.toml"];
+const PACKAGES_DIR: &str = "/etc/smith/packages";
+const DPKG_LOCKS: [&str; 2] = ["/var/lib/dpkg/lock-frontend", "/var/lib/dpkg/lock"];
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Below this the updater cannot stage a typical release.
+const DISK_FAIL_BYTES: u64 = 256 * 1024 * 1024;
+const DISK_WARN_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// JWTs and TLS certificates start failing well before this, but a few seconds
+/// of drift is normal on a device without a synced RTC.
+const CLOCK_WARN_SECS: i64 = 30;
+const CLOCK_FAIL_SECS: i64 = 300;
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Verdict {
+    Pass,
+    Warn,
+    Fail,
+    /// Not run because a check it depends on failed.
+    Skip,
+}
+
+#[derive(Debug, Serialize)]
+struct Check {
+    name: &'static str,
+    verdict: Verdict,
+    detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hint: Option<&'static str>,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            verdict: Verdict::Pass,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, hint: &'static str) -> Self {
+        Self {
+            name,
+            verdict: Verdict::Warn,
+            detail: detail.into(),
+            hint: Some(hint),
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, hint: &'static str) -> Self {
+        Self {
+            name,
+            verdict: Verdict::Fail,
+            detail: detail.into(),
+            hint: Some(hint),
+        }
+    }
+
+    fn skip(name: &'static str, reason: &str) -> Self {
+        Self {
+            name,
+            verdict: Verdict::Skip,
+            detail: format!("skipped: {reason}"),
+            hint: None,
+        }
+    }
+}
+
+pub(super) async fn doctor(json: bool) -> Result<()> {
+    let checks = run_checks().await;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&checks)?);
+    } else {
+        print_report(&checks);
+    }
+
+    let failed = checks
+        .iter()
+        .filter(|check| check.verdict == Verdict::Fail)
+        .count();
+    if failed > 0 {
+        return Err(anyhow!("{failed} check(s) failed"));
+    }
+    Ok(())
+}
+
+async fn run_checks() -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    let (daemon, watchdog) = check_daemon().await;
+    checks.push(daemon);
+    checks.push(watchdog);
+
+    let (magic, magic_file) = check_magic();
+    checks.push(magic);
+
+    let server = magic_file.as_ref().map(|magic| magic.get_server());
+    let url = server
+        .as_deref()
+        .and_then(|server| url::Url::parse(server).ok());
+
+    let addrs = match &url {
+        Some(url) => {
+            let (check, addrs) = check_dns(url).await;
+            checks.push(check);
+            addrs
+        }
+        None => {
+            checks.push(Check::skip("dns", "no valid server in magic.toml"));
+            None
+        }
+    };
+
+    match &addrs {
+        Some(addrs) => checks.push(check_tcp(addrs).await),
+        None => checks.push(Check::skip("tcp", "server did not resolve")),
+    }
+
+    let base = server.as_deref().map(api_base);
+    let server_date = match (&base, &addrs) {
+        (Some(base), Some(_)) => {
+            let (check, date) = check_health(base).await;
+            checks.push(check);
+            date
+        }
+        _ => {
+            checks.push(Check::skip("api health", "server did not resolve"));
+            None
+        }
+    };
+
+    checks.push(match server_date {
+        Some(date) => check_clock(date),
+        None => Check::skip("clock", "no Date header from the api"),
+    });
+
+    let token = magic_file.as_ref().and_then(|magic| magic.get_token());
+    match (&base, token, &addrs) {
+        (Some(base), Some(token), Some(_)) => {
+            let (check, jwt) = check_token(base, &token).await;
+            checks.push(check);
+            checks.push(match jwt {
+                Some(jwt) => check_jwt(&jwt),
+                None => Check::skip("jwt", "no JWT was minted"),
+            });
+        }
+        (_, None, _) if magic_file.is_some() => {
+            checks.push(Check::fail(
+                "token",
+                "magic.toml has no token",
+                "the device is not registered yet; approve it in the dashboard and wait for it to register",
+            ));
+            checks.push(Check::skip("jwt", "no token"));
+        }
+        _ => {
+            checks.push(Check::skip("token", "api unreachable"));
+            checks.push(Check::skip("jwt", "api unreachable"));
+        }
+    }
+
+    checks.push(check_disk(Path::new(PACKAGES_DIR)));
+    checks.push(check_dpkg_lock());
+    checks.push(check_network_manager().await);
+
+    checks
+}
+
+fn print_report(checks: &[Check]) {
+    for check in checks {
+        let label = match check.verdict {
+            Verdict::Pass => "PASS",
+            Verdict::Warn => "WARN",
+            Verdict::Fail => "FAIL",
+            Verdict::Skip => "SKIP",
+        };
+        println!("{label}  {:<12} {}", check.name, check.detail);
+        if let Some(hint) = check.hint {
+            println!("      {:<12} hint: {hint}", "");
+        }
+    }
+}
+
+async fn check_daemon() -> (Check, Check) {
+    let response = match client() {
+        Ok(client) => {
+            client
+                .get(control_url("/watchdog"))
+                .timeout(NETWORK_TIMEOUT)
+                .send()
+                .await
+        }
+        Err(err) => {
+            return (
+                Check::fail("daemon", format!("{err:#}"), "reinstall smithd"),
+                Check::skip("watchdog", "daemon unreachable"),
+            );
+        }
+    };
+
+    let status = match response {
+        Ok(response) => response.json::<RebootStatus>().await,
+        Err(err) => {
+            return (
+                Check::fail(
+                    "daemon",
+                    format!("control socket did not answer: {err}"),
+                    "check `systemctl status smithd` and `journalctl -u smithd`; doctor must run as root",
+                ),
+                Check::skip("watchdog", "daemon unreachable"),
+            );
+        }
+    };
+
+    let daemon = Check::pass("daemon", "control socket answered");
+    let watchdog = match status {
+        Ok(status) if status.reboot_pending && status.held => Check::warn(
+            "watchdog",
+            format!(
+                "reboot pending, held for another {}s",
+                status.hold_seconds_remaining
+            ),
+            "release the hold with `smithd watchdog release` once it is safe to reboot",
+        ),
+        Ok(status) if status.reboot_pending => Check::warn(
+            "watchdog",
+            format!("reboot pending in {}s", status.seconds_remaining),
+            "the daemon has lost contact with the api for too long; fix the failing network checks or `smithd watchdog hold` while working",
+        ),
+        Ok(_) => Check::pass("watchdog", "no reboot scheduled"),
+        Err(err) => Check::warn(
+            "watchdog",
+            format!("unreadable status: {err}"),
+            "the daemon may be a different version than this binary; restart smithd",
+        ),
+    };
+
+    (daemon, watchdog)
+}
+
+fn check_magic() -> (Check, Option<MagicFile>) {
+    let Some(path) = MAGIC_PATHS.iter().find(|path| Path::new(path).exists()) else {
+        return (
+            Check::fail(
+                "magic.toml",
+                "not found in . or /etc/smith",
+                "reinstall smithd or restore /etc/smith/magic.toml",
+            ),
+            None,
+        );
+    };
+
+    let parsed = std::fs::read_to_string(path)
+        .map_err(anyhow::Error::from)
+        .and_then(|contents| MagicFile::parse(&contents));
+
+    match parsed {
+        Ok((magic, Some(from))) => (
+            Check::pass(
+                "magic.toml",
+                format!("{path} valid, will be migrated from v{from} on next load"),
+            ),
+            Some(magic),
+        ),
+        Ok((magic, None)) => (
+            Check::pass("magic.toml", format!("{path} valid")),
+            Some(magic),
+        ),
+        Err(err) => (
+            Check::fail(
+                "magic.toml",
+                format!("{path}: {err:#}"),
+                "fix the file; `smithd config check` shows the same errors",
+            ),
+            None,
+        ),
+    }
+}
+
+async fn check_dns(url: &url::Url) -> (Check, Option<Vec<SocketAddr>>) {
+    let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+        return (
+            Check::fail(
+                "dns",
+                format!("{url} has no host"),
+                "fix meta.server in magic.toml",
+            ),
+            None,
+        );
+    };
+
+    match tokio::time::timeout(NETWORK_TIMEOUT, tokio::net::lookup_host((host, port))).await {
+        Ok(Ok(addrs)) => {
+            let addrs: Vec<_> = addrs.collect();
+            if addrs.is_empty() {
+                return (
+                    Check::fail("dns", format!("{host}: no addresses"), DNS_HINT),
+                    None,
+                );
+            }
+            let shown: Vec<_> = addrs.iter().map(|addr| addr.ip().to_string()).collect();
+            (
+                Check::pass("dns", format!("{host} -> {}", shown.join(", "))),
+                Some(addrs),
+            )
+        }
+        Ok(Err(err)) => (Check::fail("dns", format!("{host}: {err}"), DNS_HINT), None),
+        Err(_) => (
+            Check::fail("dns", format!("{host}: timed out"), DNS_HINT),
+            None,
+        ),
+    }
+}
+
+const DNS_HINT: &str = "check the uplink and /etc/resolv.conf (`resolvectl status`)";
+
+async fn check_tcp(addrs: &[SocketAddr]) -> Check {
+    let mut errors = Vec::new();
+    for addr in addrs {
+        match tokio::time::timeout(NETWORK_TIMEOUT, TcpStream::connect(addr)).await {
+            Ok(Ok(_)) => return Check::pass("tcp", format!("connected to {addr}")),
+            Ok(Err(err)) => errors.push(format!("{addr}: {err}")),
+            Err(_) => errors.push(format!("{addr}: timed out")),
+        }
+    }
+    Check::fail(
+        "tcp",
+        errors.join("; "),
+        "a firewall or captive portal may be blocking outbound traffic to the api",
+    )
+}
+
+/// TLS is exercised here rather than separately: a handshake or certificate
+/// failure surfaces as the request error.
+async fn check_health(base: &str) -> (Check, Option<DateTime<Utc>>) {
+    let http = match reqwest::Client::builder().timeout(NETWORK_TIMEOUT).build() {
+        Ok(http) => http,
+        Err(err) => {
+            return (
+                Check::fail("api health", format!("{err}"), "reinstall smithd"),
+                None,
+            );
+        }
+    };
+
+    match http.get(format!("{base}/health")).send().await {
+        Ok(response) => {
+            let date = response
+                .headers()
+                .get(reqwest::header::DATE)
+                .and_then(|date| date.to_str().ok())
+                .and_then(|date| DateTime::parse_from_rfc2822(date).ok())
+                .map(|date| date.with_timezone(&Utc));
+            let status = response.status();
+            let check = if status.is_success() {
+                Check::pass("api health", format!("{base}/health returned {status}"))
+            } else {
+                Check::fail(
+                    "api health",
+                    format!("{base}/health returned {status}"),
+                    "the api is reachable but unhealthy; check the backend, not the device",
+                )
+            };
+            (check, date)
+        }
+        Err(err) => (
+            Check::fail(
+                "api health",
+                format!("{base}/health: {err}"),
+                "TLS failures usually mean a wrong clock or an intercepting proxy; check the clock check below",
+            ),
+            None,
+        ),
+    }
+}
+
+fn check_clock(server: DateTime<Utc>) -> Check {
+    let skew = (Utc::now() - server).num_seconds();
+    let detail = format!("local clock is {skew:+}s from the api");
+    if skew.abs() >= CLOCK_FAIL_SECS {
+        Check::fail("clock", detail, CLOCK_HINT)
+    } else if skew.abs() >= CLOCK_WARN_SECS {
+        Check::warn("clock", detail, CLOCK_HINT)
+    } else {
+        Check::pass("clock", detail)
+    }
+}
+
+const CLOCK_HINT: &str = "check `timedatectl`; NTP must be reachable for JWTs and TLS to work";
+
+async fn check_token(base: &str, token: &str) -> (Check, Option<String>) {
+    #[derive(serde::Deserialize)]
+    struct Session {
+        token: String,
+    }
+
+    let http = reqwest::Client::new();
+    let response = http
+        .get(format!("{base}/auth/session"))
+        .bearer_auth(token)
+        .timeout(NETWORK_TIMEOUT)
+        .send()
+        .await;
+
+    match response {
+        Ok(response) if response.status().is_success() => match response.json::<Session>().await {
+            Ok(session) => (
+                Check::pass("token", "accepted by the api"),
+                Some(session.token),
+            ),
+            Err(err) => (
+                Check::warn(
+                    "token",
+                    format!("accepted, but the session response was unreadable: {err}"),
+                    "the api may be a different version than this daemon",
+                ),
+                None,
+            ),
+        },
+        Ok(response) if response.status() == reqwest::StatusCode::UNAUTHORIZED => (
+            Check::fail(
+                "token",
+                "rejected by the api (401)",
+                "the device was revoked or its token deleted; the daemon re-registers on its own once it is approved again",
+            ),
+            None,
+        ),
+        Ok(response) => (
+            Check::fail(
+                "token",
+                format!("api returned {}", response.status()),
+                "check the api logs",
+            ),
+            None,
+        ),
+        Err(err) => (
+            Check::fail("token", format!("{err}"), "see the api health check"),
+            None,
+        ),
+    }
+}
+
+/// Only the claims are checked; the signature is the api's business and was
+/// just minted by it.
+fn check_jwt(jwt: &str) -> Check {
+    let claims = jwt
+        .split('.')
+        .nth(1)
+        .and_then(|payload| URL_SAFE_NO_PAD.decode(payload).ok())
+        .and_then(|payload| serde_json::from_slice::<serde_json::Value>(&payload).ok());
+
+    let Some(exp) = claims
+        .as_ref()
+        .and_then(|claims| claims.get("exp"))
+        .and_then(|exp| exp.as_i64())
+    else {
+        return Check::warn(
+            "jwt",
+            "minted JWT has no readable exp claim",
+            "the api may be a different version than this daemon",
+        );
+    };
+
+    let remaining = exp - Utc::now().timestamp();
+    if remaining <= 0 {
+        Check::fail(
+            "jwt",
+            format!("freshly minted JWT already expired {}s ago", -remaining),
+            CLOCK_HINT,
+        )
+    } else {
+        Check::pass("jwt", format!("minted, valid for {remaining}s"))
+    }
+}
+
+fn check_disk(dir: &Path) -> Check {
+    let stat = match statvfs(dir) {
+        Ok(stat) => stat,
+        Err(err) => {
+            return Check::warn(
+                "disk",
+                format!("{}: {err}", dir.display()),
+                "the packages dir is created on the first update",
+            );
+        }
+    };
+
+    let available = stat.blocks_available() as u64 * stat.fragment_size() as u64;
+    let detail = format!(
+        "{} MiB free in {}",
+        available / (1024 * 1024),
+        dir.display()
+    );
+    let hint =
+        "free space or let the updater clean old packages; updates cannot be staged without room";
+
+    if available < DISK_FAIL_BYTES {
+        Check::fail("disk", detail, hint)
+    } else if available < DISK_WARN_BYTES {
+        Check::warn("disk", detail, hint)
+    } else {
+        Check::pass("disk", detail)
+    }
+}
+
+/// Asks for the lock holder with F_GETLK instead of taking the lock, so doctor
+/// can never get in the way of a real install. dpkg uses fcntl locks, which an
+/// flock-based check would not see.
+fn check_dpkg_lock() -> Check {
+    for lock in DPKG_LOCKS {
+        let file = match std::fs::File::open(lock) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => {
+                return Check::warn(
+                    "dpkg",
+                    format!("{lock}: {err}"),
+                    "doctor must run as root to inspect the dpkg lock",
+                );
+            }
+        };
+
+        let mut flock = nix::libc::flock {
+            l_type: nix::libc::F_WRLCK as _,
+            l_whence: nix::libc::SEEK_SET as _,
+            l_start: 0,
+            l_len: 0,
+            l_pid: 0,
+        };
+        if let Err(err) = fcntl(&file, FcntlArg::F_GETLK(&mut flock)) {
+            return Check::warn("dpkg", format!("{lock}: {err}"), "retry as root");
+        }
+        if flock.l_type != nix::libc::F_UNLCK as nix::libc::c_short {
+            return Check::warn(
+                "dpkg",
+                format!("{lock} is held by pid {}", flock.l_pid),
+                "an install is in progress; wait for it, or inspect the pid if it never finishes",
+            );
+        }
+    }
+
+    Check::pass("dpkg", "not locked")
+}
+
+async fn check_network_manager() -> Check {
+    let output = Command::new("nmcli")
+        .args(["-t", "-f", "STATE,CONNECTIVITY", "general"])
+        .output()
+        .await;
+
+    let output = match output {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            return Check::fail(
+                "network",
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+                "start NetworkManager: `systemctl start NetworkManager`",
+            );
+        }
+        Err(err) => {
+            return Check::fail(
+                "network",
+                format!("nmcli: {err}"),
+                "NetworkManager is required; install network-manager",
+            );
+        }
+    };
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let (state, connectivity) = stdout.trim().split_once(':').unwrap_or((stdout.trim(), ""));
+    let detail = format!("state {state}, connectivity {connectivity}");
+
+    match connectivity {
+        "full" => Check::pass("network", detail),
+        "portal" => Check::fail(
+            "network",
+            detail,
+            "behind a captive portal; someone has to accept it from a browser on the same network",
+        ),
+        "limited" | "none" => Check::fail(
+            "network",
+            detail,
+            "no route to the internet; check cabling, wifi credentials or the modem with `nmcli device`",
+        ),
+        _ => Check::warn(
+            "network",
+            detail,
+            "NetworkManager could not determine connectivity; check `nmcli general`",
+        ),
+    }
+}
+
+/// Magic stores the server as e.g. `https://api.example.com/smith`; `/health`
+/// and `/auth/session` live at the api root.
+fn api_base(server: &str) -> String {
+    let trimmed = server.trim_end_matches('/');
+    trimmed
+        .strip_suffix("/smith")
+        .unwrap_or(trimmed)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn api_base_strips_smith_suffix() {
+        assert_eq!(
+            api_base("https://api.example.com/smith/"),
+            "https://api.example.com"
+        );
+        assert_eq!(api_base("http://api:8080"), "http://api:8080");
+    }
+
+    #[test]
+    fn clock_skew_thresholds() {
+        let now = Utc::now();
+        assert_eq!(check_clock(now).verdict, Verdict::Pass);
+        assert_eq!(
+            check_clock(now - chrono::Duration::seconds(CLOCK_WARN_SECS + 5)).verdict,
+            Verdict::Warn
+        );
+        assert_eq!(
+            check_clock(now + chrono::Duration::seconds(CLOCK_FAIL_SECS + 5)).verdict,
+            Verdict::Fail
+        );
+    }
+
+    #[test]
+    fn expired_jwt_fails() {
+        let claims = URL_SAFE_NO_PAD.encode(br#"{"exp":1}"#);
+        assert_eq!(check_jwt(&format!("h.{claims}.s")).verdict, Verdict::Fail);
+
+        let exp = Utc::now().timestamp() + 3600;
+        let claims = URL_SAFE_NO_PAD.encode(format!(r#"{{"exp":{exp}}}"#));
+        assert_eq!(check_jwt(&format!("h.{claims}.s")).verdict, Verdict::Pass);
+    }
+}
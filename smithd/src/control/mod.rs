@@ -1,16 +1,22 @@
 use crate::police::RebootStatus;
+use crate::utils::schema::DownloadPriority;
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use tracing::{error, info};
 
+mod auth;
+mod config;
+mod doctor;
 mod server;
 mod status;
 mod upload;
 pub use server::ControlHandle;
 use status::status;
 
-/// Unix socket the daemon serves its local control API on. Root-only (0660).
+/// Unix socket the daemon serves its local control API on (0660). Callers other
+/// than root are limited to the routes magic.toml's `[control]` grants them.
 pub const CONTROL_SOCKET: &str = "/run/smithd/smithd.sock";
 
 // Request/response bodies are shared by the server and the CLI client below so
@@ -41,6 +47,14 @@ pub struct DownloadRequest {
     pub remote_file: String,
     pub local_file: String,
     pub rate_mb: f64,
+    #[serde(default)]
+    pub priority: DownloadPriority,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DownloadQueuedResponse {
+    pub id: u64,
+    pub message: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -48,6 +62,12 @@ pub struct ErrorResponse {
     pub error: String,
 }
 
+/// Requests refused by the control policy since the daemon started.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DenialsResponse {
+    pub by_route: BTreeMap<String, u64>,
+}
+
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct HoldRequest {
     /// Lease length in seconds. Omitted → the daemon's default TTL. Values are
@@ -82,6 +102,25 @@ enum Commands {
         #[arg(help = "Expose a port to the internet", long)]
         port: u16,
     },
+    /// Diagnose the daemon, its configuration and its connection to the api
+    Doctor {
+        #[arg(long, help = "Print the report as JSON")]
+        json: bool,
+    },
+    /// Inspect magic.toml
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigAction {
+    /// Parse, migrate and validate a magic file without applying it
+    Check {
+        #[arg(help = "File to check (defaults to the one the daemon loads)")]
+        path: Option<String>,
+    },
 }
 
 #[derive(Subcommand, Debug)]
@@ -135,6 +174,12 @@ pub async fn execute() -> Outcome {
         Some(Commands::Tunnel { port }) => expose_port(port)
             .await
             .inspect_err(|e| error!("Failed to expose port {port}: {e:#}")),
+        Some(Commands::Doctor { json }) => doctor::doctor(json)
+            .await
+            .inspect_err(|e| error!("Doctor found problems: {e:#}")),
+        Some(Commands::Config {
+            action: ConfigAction::Check { path },
+        }) => config::check(path).inspect_err(|e| error!("{e:#}")),
         None => return Outcome::RunDaemon,
     };
 
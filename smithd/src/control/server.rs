@@ -4,37 +4,58 @@
 //! subcommands and other services on the device can query and drive the running
 //! daemon. This replaces the former `ai.teton.smith.Packages1` D-Bus interface.
 //!
-//! The socket is root-only (0660): this surface can flash an OS image and open
-//! tunnels to the internet, so it is deliberately narrower than the D-Bus policy
-//! it replaces, which allowed any local user to call every method.
+//! The socket is 0660 in a 0750 directory, so only root and the directory's
+//! group can connect. That alone would let any group member flash an OS image
+//! or open tunnels, so every request is also checked against the caller's peer
+//! credentials and the `[control]` policy in magic.toml (see [`super::auth`]).
+//!
+//! `GET /events` streams the daemon's [`DaemonEvent`]s as server-sent events,
+//! for services that need to react to a reboot or upgrade as it happens.
 
+use super::auth::{self, AuthState, Denials, Peer};
 use super::{
-    CONTROL_SOCKET, CheckResponse, DownloadRequest, ErrorResponse, HoldRequest, MessageResponse,
-    TunnelRequest, TunnelResponse,
+    CONTROL_SOCKET, CheckResponse, DenialsResponse, DownloadQueuedResponse, DownloadRequest,
+    ErrorResponse, HoldRequest, MessageResponse, TunnelRequest, TunnelResponse,
 };
-use crate::downloader::DownloaderHandle;
-use crate::filemanager::FileManagerHandle;
+use crate::downloader::{DownloadJob, DownloaderHandle};
+use crate::events::{DaemonEvent, EventBus};
+use crate::magic::MagicHandle;
+use crate::ota::OtaHandle;
 use crate::police::{PoliceHandle, RebootStatus};
 use crate::shutdown::ShutdownSignals;
 use crate::tunnel::TunnelHandle;
 use crate::updater::UpdaterHandle;
+use crate::utils::schema::{DownloadInfo, OtaState};
 use anyhow::Context;
-use axum::extract::State;
+use axum::extract::{Path as UrlPath, State};
 use axum::http::StatusCode;
+use axum::response::sse::{Event, KeepAlive, Sse};
 use axum::response::{IntoResponse, Response};
 use axum::routing::{get, post};
-use axum::{Json, Router};
+use axum::{Json, Router, middleware};
+use futures::{Stream, StreamExt};
+use std::convert::Infallible;
 use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
 use tokio::net::UnixListener;
-use tracing::{error, info};
+use tokio::sync::broadcast::error::RecvError;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
 
 #[derive(Clone)]
 struct ControlState {
     updater: UpdaterHandle,
     downloader: DownloaderHandle,
     tunnel: TunnelHandle,
-    filemanager: FileManagerHandle,
+    ota: OtaHandle,
+}
+
+/// Subscribers hold their connection open indefinitely, so the stream also
+/// ends on shutdown; otherwise graceful shutdown would wait on them forever.
+#[derive(Clone)]
+struct EventsState {
+    events: EventBus,
+    shutdown: CancellationToken,
 }
 
 /// Any handler failure becomes a 500 with a JSON body, and is logged once here
@@ -76,6 +97,43 @@ async fn watchdog_release(State(police): State<PoliceHandle>) -> Json<RebootStat
     Json(police.release_hold().await)
 }
 
+/// A subscriber that falls behind skips the events it missed rather than being
+/// disconnected; the next event it sees is still current state.
+async fn events(
+    State(state): State<EventsState>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let receiver = state.events.subscribe();
+
+    let stream = futures::stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => return Some((event, receiver)),
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Event subscriber lagged, skipped {skipped} events");
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+    .filter_map(|event: DaemonEvent| async move {
+        Event::default()
+            .event(event.name())
+            .json_data(&event)
+            .inspect_err(|e| error!("Failed to encode event: {e}"))
+            .ok()
+            .map(Ok)
+    })
+    .take_until(state.shutdown.cancelled_owned());
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+async fn denials(State(denials): State<Denials>) -> Json<DenialsResponse> {
+    Json(DenialsResponse {
+        by_route: denials.snapshot(),
+    })
+}
+
 async fn updater_status(State(state): State<ControlState>) -> String {
     state.updater.status().await
 }
@@ -106,47 +164,52 @@ async fn open_tunnel(
 async fn start_download(
     State(state): State<ControlState>,
     Json(request): Json<DownloadRequest>,
-) -> Result<Json<MessageResponse>, ApiError> {
-    state
-        .downloader
-        .download(&request.remote_file, &request.local_file, request.rate_mb)
-        .await?;
-
-    Ok(Json(MessageResponse {
-        message: "Download started. Not waiting for result".to_owned(),
+) -> Result<Json<DownloadQueuedResponse>, ApiError> {
+    let job = DownloadJob::new(&request.remote_file, &request.local_file, request.rate_mb)
+        .priority(request.priority);
+    let id = state.downloader.download(job).await?;
+
+    Ok(Json(DownloadQueuedResponse {
+        id,
+        message: "Download queued. Not waiting for result".to_owned(),
     }))
 }
 
-/// Applies a staged OTA payload and reboots the device on success.
-async fn start_ota(State(state): State<ControlState>) -> Result<Json<MessageResponse>, ApiError> {
+async fn list_downloads(State(state): State<ControlState>) -> Json<Vec<DownloadInfo>> {
+    Json(state.downloader.list().await)
+}
+
+async fn get_download(
+    State(state): State<ControlState>,
+    UrlPath(id): UrlPath<u64>,
+) -> Result<Json<DownloadInfo>, StatusCode> {
     state
-        .filemanager
-        .extract_here("/otatool/ota_tools.tbz2")
-        .await
-        .context("Failed to extract OTA tools")?;
-
-    let script_result = state
-        .filemanager
-        .execute_script(
-            "nv_ota_start.sh",
-            vec!["/ota/ota_payload_package.tar.gz".to_owned()],
-            Some("/otatool/Linux_for_Tegra/tools/ota_tools/version_upgrade/"),
-        )
+        .downloader
+        .get(id)
         .await
-        .context("Script execution failed")?;
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
 
-    // Only proceed with reboot if script execution was successful
-    if let Err(e) = state
-        .filemanager
-        .execute_system_command("reboot", Vec::new(), None)
-        .await
-    {
-        error!("Failed to reboot after OTA: {e:#}");
+async fn cancel_download(
+    State(state): State<ControlState>,
+    UrlPath(id): UrlPath<u64>,
+) -> StatusCode {
+    if state.downloader.cancel(id).await {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
     }
+}
 
-    Ok(Json(MessageResponse {
-        message: script_result,
-    }))
+async fn ota_state(State(state): State<ControlState>) -> Json<OtaState> {
+    Json(state.ota.state())
+}
+
+/// Applies the staged OTA payload and reboots the device on success.
+async fn start_ota(State(state): State<ControlState>) -> Result<Json<OtaState>, ApiError> {
+    let ota = state.ota.start().await.context("Failed to start OTA")?;
+    Ok(Json(ota))
 }
 
 /// The police owns its own state, so the watchdog route is kept separate from
@@ -161,17 +224,39 @@ fn watchdog_router(police: PoliceHandle) -> Router {
         .with_state(police)
 }
 
-fn router(state: ControlState, police: PoliceHandle) -> Router {
-    watchdog_router(police).merge(
-        Router::new()
-            .route("/updater/status", get(updater_status))
-            .route("/updater/check", post(updater_check))
-            .route("/updater/upgrade", post(updater_upgrade))
-            .route("/tunnel", post(open_tunnel))
-            .route("/downloads", post(start_download))
-            .route("/ota/start", post(start_ota))
-            .with_state(state),
-    )
+fn events_router(events: EventBus, shutdown: CancellationToken) -> Router {
+    Router::new()
+        .route("/events", get(self::events))
+        .with_state(EventsState { events, shutdown })
+}
+
+fn router(
+    state: ControlState,
+    police: PoliceHandle,
+    events: EventBus,
+    shutdown: CancellationToken,
+    auth: AuthState,
+) -> Router {
+    watchdog_router(police)
+        .merge(events_router(events, shutdown))
+        .merge(
+            Router::new()
+                .route("/control/denials", get(denials))
+                .with_state(auth.denials.clone()),
+        )
+        .merge(
+            Router::new()
+                .route("/updater/status", get(updater_status))
+                .route("/updater/check", post(updater_check))
+                .route("/updater/upgrade", post(updater_upgrade))
+                .route("/tunnel", post(open_tunnel))
+                .route("/downloads", post(start_download).get(list_downloads))
+                .route("/downloads/{id}", get(get_download).delete(cancel_download))
+                .route("/ota", get(ota_state))
+                .route("/ota/start", post(start_ota))
+                .with_state(state),
+        )
+        .layer(middleware::from_fn_with_state(auth, auth::authorize))
 }
 
 async fn serve(socket: &Path, app: Router, shutdown: ShutdownSignals) -> anyhow::Result<()> {
@@ -181,10 +266,11 @@ async fn serve(socket: &Path, app: Router, shutdown: ShutdownSignals) -> anyhow:
             .with_context(|| format!("Failed to create {}", parent.display()))?;
 
         // bind() creates the socket with the process umask, so it is briefly
-        // world-accessible before the chmod below. Locking the directory down
-        // first closes that window, and also covers a pre-existing directory
-        // created with looser permissions.
-        tokio::fs::set_permissions(parent, std::fs::Permissions::from_mode(0o700))
+        // world-accessible before the chmod below. Keeping the directory closed
+        // to others shuts everyone but the group out for that window, and also
+        // covers a pre-existing directory created with looser permissions. The
+        // group is still subject to the per-request policy.
+        tokio::fs::set_permissions(parent, std::fs::Permissions::from_mode(0o750))
             .await
             .with_context(|| format!("Failed to set permissions on {}", parent.display()))?;
     }
@@ -206,7 +292,7 @@ async fn serve(socket: &Path, app: Router, shutdown: ShutdownSignals) -> anyhow:
 
     info!("Control API listening on {}", socket.display());
 
-    axum::serve(listener, app)
+    axum::serve(listener, app.into_make_service_with_connect_info::<Peer>())
         .with_graceful_shutdown(async move { shutdown.token.cancelled().await })
         .await
         .context("Control API server failed")?;
@@ -219,23 +305,30 @@ async fn serve(socket: &Path, app: Router, shutdown: ShutdownSignals) -> anyhow:
 pub struct ControlHandle;
 
 impl ControlHandle {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         shutdown: ShutdownSignals,
         updater: UpdaterHandle,
         downloader: DownloaderHandle,
         tunnel: TunnelHandle,
-        filemanager: FileManagerHandle,
+        ota: OtaHandle,
         police: PoliceHandle,
+        events: EventBus,
+        magic: MagicHandle,
     ) -> Self {
         let state = ControlState {
             updater,
             downloader,
             tunnel,
-            filemanager,
+            ota,
         };
 
         tokio::spawn(async move {
-            let app = router(state, police);
+            let auth = AuthState {
+                magic,
+                denials: Denials::default(),
+            };
+            let app = router(state, police, events, shutdown.token.clone(), auth);
             if let Err(e) = serve(Path::new(CONTROL_SOCKET), app, shutdown).await {
                 error!("Control API stopped: {e:#}");
             }
@@ -258,7 +351,7 @@ mod tests {
         let socket = dir.path().join("s");
 
         let shutdown = ShutdownHandler::new();
-        let police = PoliceHandle::new(shutdown.signals());
+        let police = PoliceHandle::new(shutdown.signals(), EventBus::new());
 
         let app = watchdog_router(police);
         let serve_socket = socket.clone();
@@ -328,4 +421,110 @@ mod tests {
         assert!(!status.held);
         assert_eq!(status.hold_seconds_remaining, 0);
     }
+
+    #[tokio::test]
+    async fn events_are_streamed_until_shutdown() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let socket = dir.path().join("s");
+
+        let shutdown = ShutdownHandler::new();
+        let bus = EventBus::new();
+
+        let app = events_router(bus.clone(), shutdown.signals().token);
+        let serve_socket = socket.clone();
+        let signals = shutdown.signals();
+        tokio::spawn(async move { serve(&serve_socket, app, signals).await });
+
+        let client = reqwest::Client::builder()
+            .unix_socket(socket.as_path())
+            .build()
+            .expect("client");
+
+        let mut response = None;
+        for _ in 0..100 {
+            if let Ok(r) = client.get("http://localhost/events").send().await {
+                response = Some(r);
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let response = response.expect("request");
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/event-stream",
+            "events must be served as SSE"
+        );
+
+        // The handler subscribes before it answers, so nothing published from
+        // here on can be missed.
+        bus.publish(DaemonEvent::RebootScheduled {
+            schedule_id: 3,
+            seconds_remaining: 600,
+        });
+
+        let mut body = response.bytes_stream();
+        let mut received = String::new();
+        while !received.ends_with("\n\n") {
+            let chunk = tokio::time::timeout(std::time::Duration::from_secs(5), body.next())
+                .await
+                .expect("event before timeout")
+                .expect("stream open")
+                .expect("chunk");
+            received.push_str(std::str::from_utf8(&chunk).expect("utf8"));
+        }
+        assert_eq!(
+            received,
+            "event: reboot_scheduled\n\
+             data: {\"event\":\"reboot_scheduled\",\"schedule_id\":3,\"seconds_remaining\":600}\n\n"
+        );
+
+        // Shutdown must end the stream, or graceful shutdown would hang on it.
+        shutdown.signals().token.cancel();
+        let end = tokio::time::timeout(std::time::Duration::from_secs(5), body.next())
+            .await
+            .expect("stream ends on shutdown");
+        assert!(end.is_none_or(|chunk| chunk.is_err()));
+    }
+
+    /// Runs the authorization layer over a real connection, so the peer
+    /// credentials must come through `ConnectInfo` for the request to pass.
+    #[tokio::test]
+    async fn root_passes_the_control_policy() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let socket = dir.path().join("s");
+
+        let shutdown = ShutdownHandler::new();
+        let auth = AuthState {
+            magic: MagicHandle::new(shutdown.signals()),
+            denials: Denials::default(),
+        };
+        let app = watchdog_router(PoliceHandle::new(shutdown.signals(), EventBus::new()))
+            .layer(middleware::from_fn_with_state(auth, auth::authorize));
+        let serve_socket = socket.clone();
+        let signals = shutdown.signals();
+        tokio::spawn(async move { serve(&serve_socket, app, signals).await });
+
+        let client = reqwest::Client::builder()
+            .unix_socket(socket.as_path())
+            .build()
+            .expect("client");
+
+        let mut response = None;
+        for _ in 0..100 {
+            if let Ok(r) = client.get("http://localhost/watchdog").send().await {
+                response = Some(r);
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let response = response.expect("request");
+
+        // Tests may run unprivileged, where the empty policy must refuse.
+        let expected = if nix::unistd::Uid::effective().is_root() {
+            StatusCode::OK
+        } else {
+            StatusCode::FORBIDDEN
+        };
+        assert_eq!(response.status().as_u16(), expected.as_u16());
+    }
 }
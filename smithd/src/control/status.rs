@@ -45,27 +45,7 @@ pub async fn status() -> Result<()> {
     let content = tokio::fs::read(&release_cache).await?;
     let content = std::str::from_utf8(&content)?;
 
-    let packages: Vec<ConfigPackage> = content
-        .lines()
-        .filter(|line| !line.trim().is_empty())
-        .map(|line| {
-            let mut parts = line.splitn(3, ' ');
-            Ok::<_, anyhow::Error>(ConfigPackage {
-                name: parts
-                    .next()
-                    .ok_or_else(|| anyhow::anyhow!("missing name"))?
-                    .to_string(),
-                version: parts
-                    .next()
-                    .ok_or_else(|| anyhow::anyhow!("missing version"))?
-                    .to_string(),
-                file: parts
-                    .next()
-                    .ok_or_else(|| anyhow::anyhow!("missing file"))?
-                    .to_string(),
-            })
-        })
-        .collect::<Result<_, _>>()?;
+    let packages = ConfigPackage::parse_manifest(content)?;
 
     // check the system version of the packages in the magic file
     for package in packages {
@@ -1,12 +1,17 @@
 use crate::auditor::AuditorHandle;
 use crate::commander::{CommanderHandle, Handles};
+use crate::connectivity::ConnectivityHandle;
 use crate::control::ControlHandle;
 use crate::downloader::DownloaderHandle;
+use crate::events::EventBus;
 use crate::filebrowser::FileBrowserHandle;
 use crate::filemanager::FileManagerHandle;
+use crate::latency::LatencyHandle;
 use crate::logstream::LogStreamHandle;
 use crate::magic::MagicHandle;
+use crate::modem::ModemHandle;
 use crate::nm_watcher::NMWatcherHandle;
+use crate::ota::OtaHandle;
 use crate::police::PoliceHandle;
 use crate::postman::PostmanHandle;
 use crate::session::SessionHandle;
@@ -21,6 +26,9 @@ pub async fn run() {
 
     let shutdown = ShutdownHandler::new();
 
+    // Shared by every actor that publishes state changes to the control socket.
+    let events = EventBus::new();
+
     let configuration = MagicHandle::new(shutdown.signals());
 
     configuration.load(None).await;
@@ -34,22 +42,35 @@ pub async fn run() {
         tracing::error!("Failed to disable SSH password auth: {err:#}");
     }
 
-    let tunnel = TunnelHandle::new(shutdown.signals(), configuration.clone());
+    let tunnel = TunnelHandle::new(shutdown.signals(), configuration.clone(), events.clone());
 
-    let police = PoliceHandle::new(shutdown.signals());
+    let police = PoliceHandle::new(shutdown.signals(), events.clone());
 
-    let downloader =
-        DownloaderHandle::new(shutdown.signals(), configuration.clone(), session.clone());
+    let downloader = DownloaderHandle::new(
+        shutdown.signals(),
+        configuration.clone(),
+        session.clone(),
+        events.clone(),
+    );
 
     let updater = UpdaterHandle::new(
         shutdown.signals(),
         configuration.clone(),
         downloader.clone(),
         session.clone(),
+        events.clone(),
     );
 
     let filemanager = FileManagerHandle::new(shutdown.signals(), configuration.clone());
 
+    let ota = OtaHandle::new(
+        shutdown.signals(),
+        downloader.clone(),
+        filemanager.clone(),
+        events.clone(),
+        configuration.get_ota().await,
+    );
+
     let logstream =
         LogStreamHandle::new(shutdown.signals(), configuration.clone(), session.clone());
 
@@ -66,31 +87,45 @@ pub async fn run() {
             filemanager: filemanager.clone(),
             logstream: logstream.clone(),
             filebrowser: filebrowser.clone(),
+            ota: ota.clone(),
         },
     );
 
     // The auditor stages its results on the commander, so it must be created
     // after it. Audit on boot, once SSH hardening has been (re)applied above.
-    let auditor = AuditorHandle::new(shutdown.signals(), commander.clone());
+    let auditor = AuditorHandle::new(shutdown.signals(), commander.clone(), configuration.clone());
     auditor.run_audit().await;
 
+    let connectivity = ConnectivityHandle::new(shutdown.signals(), configuration.clone());
+
     let _postman = PostmanHandle::new(
         shutdown.signals(),
         police.clone(),
+        events.clone(),
         commander.clone(),
+        downloader.clone(),
+        ota.clone(),
+        updater.clone(),
         configuration.clone(),
         session.clone(),
+        connectivity,
     );
 
     let _nm_watcher = NMWatcherHandle::new(shutdown.signals(), commander.clone());
 
+    let _modem = ModemHandle::new(shutdown.signals(), commander.clone(), configuration.clone());
+
+    let _latency = LatencyHandle::new(shutdown.signals(), commander.clone(), configuration.clone());
+
     let _control = ControlHandle::new(
         shutdown.signals(),
         updater.clone(),
         downloader.clone(),
         tunnel.clone(),
-        filemanager.clone(),
+        ota,
         police.clone(),
+        events,
+        configuration.clone(),
     );
 
     // this will ensure we have a token
@@ -0,0 +1,284 @@
+//! Bandwidth policy and data usage.
+//!
+//! The api sets a [`BandwidthPolicy`] per device label. The [`Meter`] counts
+//! every downloaded byte against the link it went over, and decides from the
+//! policy, the link and the local hour how fast a download may go or whether
+//! it has to wait. Usage is kept per calendar month and persisted, so a spent
+//! LTE budget stays spent across restarts.
+use crate::utils::files::write_file_atomic;
+use crate::utils::schema::{BandwidthPolicy, BandwidthUsage, InterfaceType};
+use anyhow::Context;
+use chrono::{DateTime, Local, Timelike};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use tracing::warn;
+
+pub const BANDWIDTH_PATH: &str = "/etc/smith/bandwidth.json";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Allowance {
+    /// MB/s
+    Rate(f64),
+    Paused(String),
+}
+
+/// The error a download ends with when the policy stops it, so the downloader
+/// queues it again instead of failing it.
+#[derive(Debug)]
+pub struct Paused(pub String);
+
+impl std::fmt::Display for Paused {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Paused by bandwidth policy: {}", self.0)
+    }
+}
+
+impl std::error::Error for Paused {}
+
+fn month(now: &DateTime<Local>) -> String {
+    now.format("%Y-%m").to_string()
+}
+
+/// Starts the counters over when `month` is a new one.
+fn roll_over(usage: &mut BandwidthUsage, month: String) -> bool {
+    if usage.month == month {
+        return false;
+    }
+    usage.month = month;
+    usage.ethernet_bytes = 0;
+    usage.wifi_bytes = 0;
+    usage.lte_bytes = 0;
+    usage.other_bytes = 0;
+    true
+}
+
+fn allowance(usage: &BandwidthUsage, hour: u8, requested: f64) -> Allowance {
+    let Some(policy) = &usage.policy else {
+        return Allowance::Rate(requested);
+    };
+
+    if usage.link == InterfaceType::Lte
+        && let Some(budget) = policy.lte_monthly_budget_bytes
+        && usage.lte_bytes >= budget
+    {
+        return Allowance::Paused(format!(
+            "LTE data budget of {budget} bytes for {} is spent",
+            usage.month
+        ));
+    }
+
+    match policy.cap(usage.link, hour) {
+        Some(cap) if cap <= 0.0 => Allowance::Paused(format!(
+            "no downloads allowed over {:?} at {hour:02}:00",
+            usage.link
+        )),
+        Some(cap) => Allowance::Rate(requested.min(cap)),
+        None => Allowance::Rate(requested),
+    }
+}
+
+struct State {
+    usage: BandwidthUsage,
+    dirty: bool,
+}
+
+pub struct Meter {
+    path: PathBuf,
+    state: Mutex<State>,
+}
+
+impl Meter {
+    /// Loads the policy and this month's usage from `path`, starting from
+    /// nothing if it is missing or unreadable.
+    pub fn load(path: &Path) -> Self {
+        let usage = match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|err| {
+                warn!("Ignoring unreadable {}: {err}", path.display());
+                BandwidthUsage::default()
+            }),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => BandwidthUsage::default(),
+            Err(err) => {
+                warn!("Failed to read {}: {err}", path.display());
+                BandwidthUsage::default()
+            }
+        };
+
+        Self {
+            path: path.to_owned(),
+            state: Mutex::new(State {
+                usage,
+                dirty: false,
+            }),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if roll_over(&mut state.usage, month(&Local::now())) {
+            state.dirty = true;
+        }
+        state
+    }
+
+    pub fn set_policy(&self, policy: Option<BandwidthPolicy>) {
+        let mut state = self.lock();
+        state.usage.policy = policy;
+        state.dirty = true;
+    }
+
+    pub fn set_link(&self, link: InterfaceType) {
+        self.lock().usage.link = link;
+    }
+
+    /// Counts `bytes` against the current link.
+    pub fn record(&self, bytes: u64) {
+        let mut state = self.lock();
+        let usage = &mut state.usage;
+        let counter = match usage.link {
+            InterfaceType::Ethernet => &mut usage.ethernet_bytes,
+            InterfaceType::Wifi => &mut usage.wifi_bytes,
+            InterfaceType::Lte => &mut usage.lte_bytes,
+            InterfaceType::Unknown => &mut usage.other_bytes,
+        };
+        *counter += bytes;
+        state.dirty = true;
+    }
+
+    /// How fast a download that asked for `requested` MB/s may go right now.
+    pub fn allowance(&self, requested: f64) -> Allowance {
+        let hour = Local::now().hour() as u8;
+        allowance(&self.lock().usage, hour, requested)
+    }
+
+    /// Why downloads have to wait, if they do.
+    pub fn paused(&self) -> Option<String> {
+        match self.allowance(f64::INFINITY) {
+            Allowance::Paused(reason) => Some(reason),
+            Allowance::Rate(_) => None,
+        }
+    }
+
+    pub fn usage(&self) -> BandwidthUsage {
+        let paused = self.paused();
+        BandwidthUsage {
+            paused,
+            ..self.lock().usage.clone()
+        }
+    }
+
+    /// Writes the policy and usage out if they changed since the last time.
+    pub async fn persist(&self) -> anyhow::Result<()> {
+        let contents = {
+            let mut state = self.lock();
+            if !state.dirty {
+                return Ok(());
+            }
+            state.dirty = false;
+            serde_json::to_string(&state.usage).context("serializing bandwidth usage")?
+        };
+
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .with_context(|| format!("creating {}", parent.display()))?;
+        }
+
+        let result = write_file_atomic(&self.path, &contents, 0o600).await;
+        if result.is_err() {
+            self.lock().dirty = true;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::schema::{BandwidthSchedule, LinkRates};
+
+    fn usage(link: InterfaceType, lte_bytes: u64) -> BandwidthUsage {
+        BandwidthUsage {
+            month: "2026-10".into(),
+            lte_bytes,
+            link,
+            policy: Some(BandwidthPolicy {
+                rates: LinkRates {
+                    ethernet: None,
+                    wifi: Some(4.0),
+                    lte: Some(0.5),
+                },
+                schedules: vec![BandwidthSchedule {
+                    start_hour: 8,
+                    end_hour: 18,
+                    rates: LinkRates {
+                        wifi: Some(1.0),
+                        lte: Some(0.0),
+                        ..Default::default()
+                    },
+                }],
+                lte_monthly_budget_bytes: Some(1_000),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn caps_follow_the_link_and_the_hour() {
+        assert_eq!(
+            allowance(&usage(InterfaceType::Ethernet, 0), 12, 2.0),
+            Allowance::Rate(2.0)
+        );
+        assert_eq!(
+            allowance(&usage(InterfaceType::Wifi, 0), 22, 10.0),
+            Allowance::Rate(4.0)
+        );
+        assert_eq!(
+            allowance(&usage(InterfaceType::Wifi, 0), 9, 10.0),
+            Allowance::Rate(1.0)
+        );
+        assert_eq!(
+            allowance(&usage(InterfaceType::Lte, 0), 3, 2.0),
+            Allowance::Rate(0.5)
+        );
+        assert!(matches!(
+            allowance(&usage(InterfaceType::Lte, 0), 9, 2.0),
+            Allowance::Paused(_)
+        ));
+    }
+
+    #[test]
+    fn spent_lte_budget_pauses_only_lte() {
+        assert!(matches!(
+            allowance(&usage(InterfaceType::Lte, 1_000), 3, 2.0),
+            Allowance::Paused(reason) if reason.contains("budget")
+        ));
+        assert_eq!(
+            allowance(&usage(InterfaceType::Ethernet, 1_000), 3, 2.0),
+            Allowance::Rate(2.0)
+        );
+    }
+
+    #[tokio::test]
+    async fn usage_is_counted_per_link_and_persisted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bandwidth.json");
+
+        let meter = Meter::load(&path);
+        meter.set_policy(usage(InterfaceType::Lte, 0).policy);
+        meter.set_link(InterfaceType::Lte);
+        meter.record(600);
+        meter.set_link(InterfaceType::Wifi);
+        meter.record(50);
+        meter.persist().await.unwrap();
+
+        let reloaded = Meter::load(&path);
+        reloaded.set_link(InterfaceType::Lte);
+        reloaded.record(400);
+        let usage = reloaded.usage();
+
+        assert_eq!(usage.month, month(&Local::now()));
+        assert_eq!(usage.lte_bytes, 1_000);
+        assert_eq!(usage.wifi_bytes, 50);
+        assert!(usage.paused.is_some());
+    }
+}
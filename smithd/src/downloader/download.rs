@@ -1,17 +1,87 @@
+use crate::downloader::DownloadJob;
+use crate::downloader::bandwidth::{Allowance, Meter, Paused};
+use crate::downloader::mirror::{MirrorHealth, mirror_url, parse_digest, sha256_file};
+use crate::events::{DaemonEvent, EventBus};
 use crate::magic::MagicHandle;
 use crate::session::SessionHandle;
 use anyhow;
 use futures::StreamExt;
 use governor::{Quota, RateLimiter};
-use reqwest::{Client, Url};
+use nix::errno::Errno;
+use nix::sys::statvfs::statvfs;
+use reqwest::{Client, Response, StatusCode, Url};
 use std::num::NonZeroU32;
 use std::path::Path;
-use std::sync::Arc;
-use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use tokio::fs;
 use tokio::io::AsyncWriteExt;
 use tracing::{error, info, warn};
 
+/// Progress is published on the event bus at most this often per download.
+const PROGRESS_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Extended attributes tagging a `.part` or finished file with the content it
+/// holds, so one holding other content is neither resumed nor reused.
+const XATTR_SHA256: &str = "user.sha256";
+const XATTR_ETAG: &str = "user.etag";
+
+/// Live counters for one download, read by the actor when it is queried.
+#[derive(Debug, Default)]
+pub struct Progress {
+    /// Bytes on disk, including any resumed from an earlier attempt.
+    pub downloaded: AtomicU64,
+    /// Size of the file, once the server has told us.
+    pub total: AtomicU64,
+    /// Bytes fetched by this attempt only, for computing the rate.
+    pub transferred: AtomicU64,
+    /// The mirror currently being fetched from.
+    pub mirror: Mutex<Option<String>>,
+}
+
+impl Progress {
+    pub fn mirror(&self) -> Option<String> {
+        self.mirror
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    fn set_mirror(&self, mirror: &str) {
+        *self.mirror.lock().unwrap_or_else(|e| e.into_inner()) = Some(mirror.to_owned());
+    }
+}
+
+/// Everything a transfer reports to or is stopped through.
+pub struct Monitor {
+    pub force_stop: Arc<AtomicBool>,
+    pub events: EventBus,
+    pub progress: Arc<Progress>,
+    pub meter: Arc<Meter>,
+    pub health: Arc<MirrorHealth>,
+    /// The rate the job asked for, before the bandwidth policy capped it.
+    pub requested_rate: f64,
+}
+
+impl Monitor {
+    /// Why the bandwidth policy wants this transfer stopped, if it does:
+    /// downloads are paused, or the policy now allows a different rate than
+    /// the one it is running at and it has to restart at that one.
+    fn interrupted_by_policy(&self, bytes_per_second: u64) -> Option<String> {
+        match self.meter.allowance(self.requested_rate) {
+            Allowance::Paused(reason) => Some(reason),
+            Allowance::Rate(rate) if rate_to_bytes(rate) != bytes_per_second => {
+                Some(format!("rate changed to {rate:.2} MB/s"))
+            }
+            Allowance::Rate(_) => None,
+        }
+    }
+}
+
+fn rate_to_bytes(rate: f64) -> u64 {
+    ((rate * 1_000_000.0).ceil() as u64).max(1)
+}
+
 #[derive(Debug, Clone)]
 pub struct DownloadStats {
     pub bytes_downloaded: u64,
@@ -33,66 +103,95 @@ impl Default for DownloadStats {
     }
 }
 
+/// Whether a file on disk holds a target's content, as far as can be told.
+#[derive(Debug, PartialEq)]
+enum Held {
+    Same,
+    Other,
+    /// Nothing identifies the content, or the filesystem keeps no xattrs.
+    Unknown,
+}
+
+/// Whether `err` says the filesystem does not support extended attributes,
+/// as tmpfs without `user_xattr` and some vfat and overlay setups.
+fn xattrs_unsupported(err: &std::io::Error) -> bool {
+    err.raw_os_error() == Some(Errno::ENOTSUP as i32)
+}
+
+/// What is known about the file before any of it is fetched.
+#[derive(Debug, Default)]
+struct Target {
+    size: Option<u64>,
+    sha256: Option<String>,
+    etag: Option<String>,
+    /// The signed link the api redirects to.
+    location: Option<String>,
+}
+
+impl Target {
+    /// The xattr, and its value, that files holding this content are tagged
+    /// with. The digest when known, since it holds whichever mirror served
+    /// the bytes; the api's etag otherwise.
+    fn identity(&self) -> Option<(&'static str, &str)> {
+        match (&self.sha256, &self.etag) {
+            (Some(sha256), _) => Some((XATTR_SHA256, sha256)),
+            (None, Some(etag)) => Some((XATTR_ETAG, etag)),
+            (None, None) => None,
+        }
+    }
+
+    fn held_in(&self, path: &str) -> Held {
+        let Some((key, value)) = self.identity() else {
+            return Held::Unknown;
+        };
+        match xattr::get(path, key) {
+            Ok(Some(stored)) if stored == value.as_bytes() => Held::Same,
+            Err(err) if xattrs_unsupported(&err) => Held::Unknown,
+            _ => Held::Other,
+        }
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        self.held_in(path) == Held::Same
+    }
+
+    /// Tags `path` with the content it is to hold. Without xattr support the
+    /// file just goes untagged, and is resumed by its size alone.
+    fn tag(&self, path: &str) -> std::io::Result<()> {
+        let Some((key, value)) = self.identity() else {
+            return Ok(());
+        };
+        match xattr::set(path, key, value.as_bytes()) {
+            Err(err) if xattrs_unsupported(&err) => {
+                warn!("{path} cannot be tagged with its content, the filesystem has no xattrs");
+                Ok(())
+            }
+            result => result,
+        }
+    }
+}
+
 pub async fn download_file_mb(
     magic: MagicHandle,
     session: SessionHandle,
-    remote_file: String,
-    local_file: String,
+    job: DownloadJob,
     rate: f64,
-    force_stop: Arc<AtomicBool>,
+    monitor: Monitor,
 ) -> anyhow::Result<DownloadStats> {
     // Convert the MB rate to bytes/sec
-    let bytes_per_second = ((rate * 1_000_000.0).ceil() as u64).max(1);
-
-    // Example: download at 1MB per second
-    let result = download_file(
-        magic,
-        session,
-        local_file.as_str(),
-        remote_file.as_str(),
-        bytes_per_second,
-        force_stop,
-        None,
-    )
-    .await?;
+    let bytes_per_second = rate_to_bytes(rate);
 
-    Ok(result)
+    download_file(magic, session, &job, bytes_per_second, &monitor).await
 }
 
-async fn download_file(
-    magic: MagicHandle,
-    session: SessionHandle,
-    local_path: &str,
+/// Asks the api where to get `remote_path` and what it should look like.
+async fn ask_api(
+    client: &Client,
+    magic: &MagicHandle,
+    session: &SessionHandle,
     remote_path: &str,
-    bytes_per_second: u64,
-    force_stop: Arc<AtomicBool>,
-    recurse: Option<u32>,
-) -> anyhow::Result<DownloadStats> {
-    let mut rec_track = 0;
-    let mut stats = DownloadStats::default();
-    let configuration = magic.clone();
-    let client = Client::new();
-    let server_api_url = configuration.get_server().await;
-
-    // Use .part file for atomic downloads
-    let part_path = format!("{}.part", local_path);
-    let part_path_str = part_path.as_str();
-
-    if let Some(r) = recurse {
-        if r > 1 {
-            // Break out of the recursion loop
-            stats.error_message = Some("Downloaded 0 bytes too many times".to_owned());
-
-            // Clean up .part file before giving up
-            let _ = tokio::fs::remove_file(part_path_str)
-                .await
-                .inspect_err(|e| warn!("Failed to clean up .part file: {}", e));
-
-            return Ok(stats.clone());
-        } else {
-            rec_track = r + 1
-        }
-    }
+) -> anyhow::Result<Target> {
+    let server_api_url = magic.get_server().await;
 
     // Prefer the short-lived device JWT; falls back to the opaque token when no
     // valid JWT is cached (see SessionHandle::bearer_token).
@@ -107,232 +206,390 @@ async fn download_file(
         url.to_string()
     };
 
-    // Create local file path if it does not exist
-    if let Some(parent) = Path::new(local_path).parent()
-        && !parent.exists()
-    {
-        fs::create_dir_all(parent).await?;
-    }
-
-    // Check if .part file already exists and get its size for resuming
-    let mut downloaded: u64 = 0;
-    let mut resume_download = false;
-
-    if let Ok(metadata) = tokio::fs::metadata(part_path_str).await {
-        downloaded = metadata.len();
-    }
-
-    let initial_response = client
+    let response = client
         .get(&url)
         .header("Authorization", format!("Bearer {}", token))
         .send()
         .await?;
 
-    if !initial_response.status().is_success() && !initial_response.status().is_redirection() {
+    if !response.status().is_success() && !response.status().is_redirection() {
         return Err(anyhow::anyhow!(
             "Failed to download file: {:?}",
-            initial_response.status()
+            response.status()
         ));
     }
 
-    // Get the etag for verification
-    let etag = initial_response
-        .headers()
-        .get("etag")
-        .and_then(|value| value.to_str().ok());
-
-    // Get the total content length of the object
-    let file_length = initial_response
-        .headers()
-        .get("x-file-size")
-        .and_then(|value| value.to_str().ok())
-        .and_then(|value| value.parse::<u64>().ok());
-
-    let content_length = match file_length {
-        Some(length) => length,
-        None => {
-            return Err(anyhow::anyhow!("x-file-size header missing or invalid"));
-        }
+    let header = |name: &str| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned)
     };
 
-    // Extract the pre-signed URL from the Location header
-    let presigned_url = match initial_response.headers().get("Location") {
-        Some(location) => location
-            .to_str()
-            .map_err(|e| anyhow::anyhow!("Invalid location header: {:?}", e))?,
-        None => {
-            return Err(anyhow::anyhow!(
-                "No pre-signed URL provided in response headers"
-            ));
-        }
-    };
+    let size = header("x-file-size")
+        .and_then(|value| value.parse::<u64>().ok())
+        .ok_or_else(|| anyhow::anyhow!("x-file-size header missing or invalid"))?;
+
+    Ok(Target {
+        size: Some(size),
+        sha256: header("x-content-sha256").and_then(|value| parse_digest(&value)),
+        etag: header("etag"),
+        location: Some(
+            header("location")
+                .ok_or_else(|| anyhow::anyhow!("No pre-signed URL provided in response headers"))?,
+        ),
+    })
+}
 
-    // Check if final file already exists and is complete
-    if let Ok(final_metadata) = tokio::fs::metadata(local_path).await
-        && final_metadata.len() == content_length
-        && let Ok(Some(stored)) = xattr::get(local_path, "user.etag")
+async fn download_file(
+    magic: MagicHandle,
+    session: SessionHandle,
+    job: &DownloadJob,
+    bytes_per_second: u64,
+    monitor: &Monitor,
+) -> anyhow::Result<DownloadStats> {
+    let local_path = job.local_file.as_str();
+    let remote_path = job.remote_file.as_str();
+    let client = Client::new();
+
+    // Create local file path if it does not exist
+    if let Some(parent) = Path::new(local_path).parent()
+        && !parent.exists()
     {
-        let stored_etag = String::from_utf8_lossy(&stored);
-        if let Some(current_etag) = etag
-            && stored_etag.as_ref() == current_etag
-        {
-            info!("File already fully downloaded at {}", local_path);
-            stats.success = true;
-            stats.bytes_downloaded = content_length;
-            return Ok(stats);
-        }
+        fs::create_dir_all(parent).await?;
     }
 
-    // Build get based on if some of the file has already been downloaded
-    let mut request = client.get(presigned_url);
+    let mirrors = monitor.health.order(magic.get_mirrors().await);
+
+    // Mirrors are only used when the api or the job says what the bytes must
+    // hash to; a digest published by the mirror itself proves nothing.
+    let mut target = ask_api(&client, &magic, &session, remote_path)
+        .await
+        .inspect_err(|err| warn!("Could not ask the api about {remote_path}: {err:#}"))
+        .unwrap_or_default();
+    if job.sha256.is_some() {
+        target.sha256 = job.sha256.clone();
+    }
+    if let Some(size) = target.size {
+        monitor.progress.total.store(size, Ordering::Relaxed);
+    }
 
-    if downloaded > 0
-        && let Some(stored) = xattr::get(part_path_str, "user.etag")?
+    // Check if final file already exists and is complete
+    if let Some(size) = target.size
+        && let Ok(final_metadata) = fs::metadata(local_path).await
+        && final_metadata.len() == size
+        && target.matches(local_path)
     {
-        let stored_etag = String::from_utf8_lossy(&stored);
-
-        match etag {
-            Some(etag) if stored_etag == etag => {
-                // Check if the .part file is complete (just needs finalization)
-                if downloaded == content_length {
-                    info!("Part file complete, finalizing: {}", local_path);
-                    tokio::fs::rename(part_path_str, local_path).await?;
-                    stats.success = true;
-                    stats.bytes_downloaded = downloaded;
-                    return Ok(stats);
-                }
+        info!("File already fully downloaded at {}", local_path);
+        monitor.progress.downloaded.store(size, Ordering::Relaxed);
+        return Ok(DownloadStats {
+            success: true,
+            bytes_downloaded: size,
+            ..Default::default()
+        });
+    }
 
-                if downloaded > content_length {
-                    // Different file somehow (this should never hit)
-                    resume_download = false;
-                    downloaded = 0;
-                } else {
-                    resume_download = true;
-                    info!("Resuming download from byte {}", downloaded);
-                    request = request.header("Range", format!("bytes={}-", downloaded));
+    if let Some(size) = target.size {
+        let reserve = magic.get_cache().await.reserve_bytes();
+        ensure_space(local_path, size, reserve).await?;
+    }
+
+    let mut failures = Vec::new();
+    for mirror in mirrors {
+        let url = match &mirror.url {
+            None => match &target.location {
+                Some(location) => location.clone(),
+                None => {
+                    failures.push(format!("{}: api unreachable", mirror.name));
+                    continue;
+                }
+            },
+            Some(base) => {
+                if target.sha256.is_none() {
+                    failures.push(format!(
+                        "{}: no trusted sha256 to verify it against",
+                        mirror.name
+                    ));
+                    continue;
                 }
+                mirror_url(base, remote_path)
             }
-            Some(etag) => {
-                warn!(
-                    "ETag mismatch (local: {}, remote: {}), restarting download",
-                    stored_etag, etag
-                );
-                // Remove the existing .part file and start over
-                tokio::fs::remove_file(part_path_str).await?;
-
-                resume_download = false;
-                downloaded = 0;
+        };
+
+        info!("Downloading {remote_path} from mirror {}", mirror.name);
+        monitor.progress.set_mirror(&mirror.name);
+
+        match fetch(
+            &client,
+            &url,
+            local_path,
+            &target,
+            bytes_per_second,
+            monitor,
+        )
+        .await
+        {
+            Ok(stats) => {
+                monitor.health.record_success(&mirror.name);
+                return Ok(stats);
             }
-            None => {
-                // No stored ETag, cannot verify, restart download
-                warn!("No ETag provided by server, restarting download");
-                tokio::fs::remove_file(part_path_str).await?;
-
-                resume_download = false;
-                downloaded = 0;
+            // Neither says anything about the mirror.
+            Err(err) if err.is::<Paused>() || monitor.force_stop.load(Ordering::SeqCst) => {
+                return Err(err);
+            }
+            Err(err) => {
+                warn!("Mirror {} failed for {remote_path}: {err:#}", mirror.name);
+                monitor.health.record_failure(&mirror.name);
+                failures.push(format!("{}: {err:#}", mirror.name));
             }
         }
     }
 
-    // Send the request to download the file
-    let mut response = request.send().await?;
-
-    let expected_status = if resume_download && downloaded > 0 {
-        reqwest::StatusCode::PARTIAL_CONTENT
-    } else {
-        reqwest::StatusCode::OK
-    };
-
-    if response.status() != expected_status {
-        if resume_download && response.status() == reqwest::StatusCode::OK {
-            warn!("Server does not support resume, starting from beginning");
+    Err(anyhow::anyhow!(
+        "No mirror could provide {remote_path}: {}",
+        failures.join("; ")
+    ))
+}
 
-            tokio::fs::remove_file(part_path_str).await?;
+/// Refuses a download of `size` bytes into `local_path` that would leave the
+/// filesystem with less than `reserve` bytes free. What a `.part` file already
+/// holds is not downloaded again.
+async fn ensure_space(local_path: &str, size: u64, reserve: u64) -> anyhow::Result<()> {
+    let partial = fs::metadata(format!("{local_path}.part"))
+        .await
+        .map(|metadata| metadata.len())
+        .unwrap_or(0);
+    let needed = size.saturating_sub(partial);
+    let dir = Path::new(local_path).parent().unwrap_or(Path::new("."));
+    let stat = statvfs(dir)
+        .map_err(|e| anyhow::anyhow!("Failed to check free space in {}: {e}", dir.display()))?;
+    let free = stat.blocks_available() * stat.fragment_size();
+
+    if free < needed.saturating_add(reserve) {
+        return Err(anyhow::anyhow!(
+            "Not enough space for {local_path}: {} MB needed plus a {} MB reserve, {} MB free",
+            needed / 1024 / 1024,
+            reserve / 1024 / 1024,
+            free / 1024 / 1024
+        ));
+    }
+    Ok(())
+}
 
-            downloaded = 0;
-            resume_download = false;
+/// Fetches `url` into `<local_path>.part`, resuming what an earlier attempt
+/// left there if it was for the same content, then verifies and finalizes it.
+async fn fetch(
+    client: &Client,
+    url: &str,
+    local_path: &str,
+    target: &Target,
+    bytes_per_second: u64,
+    monitor: &Monitor,
+) -> anyhow::Result<DownloadStats> {
+    let mut stats = DownloadStats::default();
 
-            response = client.get(presigned_url).send().await?;
+    // Use .part file for atomic downloads
+    let part_path = format!("{}.part", local_path);
+    let part_path_str = part_path.as_str();
 
-            if !response.status().is_success() {
-                return Err(anyhow::anyhow!(
-                    "Failed to download file from pre-signed URL: {:?}",
-                    response.status()
-                ));
+    // Check if .part file already exists and get its size for resuming
+    let mut downloaded: u64 = 0;
+    if let Ok(metadata) = fs::metadata(part_path_str).await {
+        match target.held_in(part_path_str) {
+            Held::Same => downloaded = metadata.len(),
+            // Taken to be an earlier attempt at the same path. The server has
+            // to confirm the offset in its range, the size has to add up and
+            // a known digest is still checked, so a mixed-up file is caught
+            // wherever the content can be told apart at all.
+            Held::Unknown => {
+                info!("Resuming {local_path} by size, nothing identifies its content");
+                downloaded = metadata.len();
+            }
+            Held::Other => {
+                warn!("Partial download of {local_path} is for other content, restarting download");
+                fs::remove_file(part_path_str).await?;
             }
-        } else {
-            return Err(anyhow::anyhow!(
-                "Failed to download file from pre-signed URL: {:?}",
-                response.status()
-            ));
         }
     }
 
-    if resume_download && downloaded > 0 {
-        if let Some(content_range) = response.headers().get("content-range") {
-            let range_str = content_range.to_str().unwrap_or("");
-            // Should be like "bytes 5000-9999/1000"
-            if !range_str.starts_with(&format!("bytes {}-", downloaded)) {
-                warn!(
-                    "Server sent wrong range: {}, expected to start at {}. Restarting download.",
-                    range_str, downloaded
-                );
-
-                tokio::fs::remove_file(part_path_str).await?;
-                downloaded = 0;
-                resume_download = false;
-
-                // Request again without range header
-                response = client.get(presigned_url).send().await?;
-
-                if !response.status().is_success() {
-                    return Err(anyhow::anyhow!(
-                        "Failed to download file from pre-signed URL: {:?}",
-                        response.status()
-                    ));
-                }
-            }
-        } else {
-            warn!("No content-range header on 206 response, restarting download");
-            tokio::fs::remove_file(part_path_str).await?;
-            downloaded = 0;
-            resume_download = false;
-
-            // Request again without range header
-            response = client.get(presigned_url).send().await?;
+    if let Some(size) = target.size
+        && downloaded >= size
+    {
+        if downloaded == size {
+            info!("Part file complete, finalizing: {}", local_path);
+            return finalize(part_path_str, local_path, target, stats).await;
+        }
+        // Different file somehow (this should never hit)
+        fs::remove_file(part_path_str).await?;
+        downloaded = 0;
+    }
 
-            if !response.status().is_success() {
+    // Build get based on if some of the file has already been downloaded
+    let mut request = client.get(url);
+    if downloaded > 0 {
+        info!("Resuming download from byte {}", downloaded);
+        request = request.header("Range", format!("bytes={}-", downloaded));
+    }
+    let response = request.send().await?;
+
+    match response.status() {
+        StatusCode::PARTIAL_CONTENT if downloaded > 0 => {
+            // Should be like "bytes 5000-9999/10000"
+            let range = response
+                .headers()
+                .get("content-range")
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or("");
+            if !range.starts_with(&format!("bytes {}-", downloaded)) {
+                fs::remove_file(part_path_str).await?;
                 return Err(anyhow::anyhow!(
-                    "Failed to download file from pre-signed URL: {:?}",
-                    response.status()
+                    "Server sent wrong range: {range:?}, expected to start at {downloaded}"
                 ));
             }
         }
+        StatusCode::OK => {
+            if downloaded > 0 {
+                warn!("Server does not support resume, starting from beginning");
+                downloaded = 0;
+            }
+        }
+        status => {
+            return Err(anyhow::anyhow!("Failed to download file: {:?}", status));
+        }
+    }
+
+    let size = target
+        .size
+        .or_else(|| response.content_length().map(|length| length + downloaded));
+    if let Some(size) = size {
+        monitor.progress.total.store(size, Ordering::Relaxed);
     }
 
     // Open the .part file for writing
-    let mut file = if resume_download && downloaded > 0 {
-        tokio::fs::OpenOptions::new()
-            .write(true)
+    let mut file = if downloaded > 0 {
+        fs::OpenOptions::new()
             .append(true)
             .open(part_path_str)
             .await?
     } else {
-        let f = tokio::fs::File::create(part_path_str).await?;
-        if let Some(etag) = etag {
-            xattr::set(part_path_str, "user.etag", etag.as_bytes())?;
-        }
-
+        let f = fs::File::create(part_path_str).await?;
+        target.tag(part_path_str)?;
         f
     };
 
+    let start = std::time::Instant::now();
+    let session_downloaded = stream_to_file(
+        response,
+        &mut file,
+        local_path,
+        downloaded,
+        size.unwrap_or(0),
+        bytes_per_second,
+        monitor,
+    )
+    .await;
+    file.flush().await?;
+    drop(file);
+    let session_downloaded = session_downloaded?;
+
+    // Calculate and log final statistics
+    let elapsed = start.elapsed().as_secs_f64();
+
+    let avg_speed = if elapsed > 0.0 {
+        session_downloaded as f64 / elapsed
+    } else {
+        0.0
+    };
+
+    stats.bytes_downloaded = session_downloaded;
+    stats.elapsed_seconds = elapsed;
+    stats.average_speed_mbps = avg_speed / 1_000_000.0;
+
+    let file_size = fs::metadata(part_path_str)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to verify file size on disk: {}", e))?
+        .len();
+
+    if let Some(size) = size
+        && file_size != size
+    {
+        // Clean up .part file on size mismatch
+        let _ = fs::remove_file(part_path_str)
+            .await
+            .inspect_err(|e| warn!("Failed to clean up .part file: {}", e));
+
+        return Err(anyhow::anyhow!(
+            "Size mismatch: file on disk ({}), downloaded amount ({}), expected content length ({})",
+            file_size,
+            session_downloaded,
+            size
+        ));
+    }
+
+    let stats = finalize(part_path_str, local_path, target, stats).await?;
+    monitor.events.publish(DaemonEvent::DownloadProgress {
+        file: local_path.to_owned(),
+        downloaded_bytes: file_size,
+        total_bytes: file_size,
+    });
+    Ok(stats)
+}
+
+/// Checks the digest, if one is known, and moves the `.part` file into place.
+async fn finalize(
+    part_path: &str,
+    local_path: &str,
+    target: &Target,
+    mut stats: DownloadStats,
+) -> anyhow::Result<DownloadStats> {
+    if let Some(expected) = &target.sha256 {
+        let actual = sha256_file(Path::new(part_path)).await?;
+        if &actual != expected {
+            let _ = fs::remove_file(part_path)
+                .await
+                .inspect_err(|e| warn!("Failed to clean up .part file: {}", e));
+            return Err(anyhow::anyhow!(
+                "sha256 mismatch: expected {expected}, got {actual}"
+            ));
+        }
+    }
+
+    info!("Downloaded file verification passed for {}", local_path);
+
+    // Atomically finalize - rename .part to final name
+    fs::rename(part_path, local_path)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to finalize download: {}", e))?;
+
+    info!("Download finalized: {}", local_path);
+    stats.success = true;
+    Ok(stats)
+}
+
+/// Writes the response body to `file` at `bytes_per_second`, returning how
+/// many bytes it wrote. `downloaded` is what the file already held.
+async fn stream_to_file(
+    response: Response,
+    file: &mut fs::File,
+    local_path: &str,
+    downloaded: u64,
+    size: u64,
+    bytes_per_second: u64,
+    monitor: &Monitor,
+) -> anyhow::Result<u64> {
     let bytes_per_second_u32 = (bytes_per_second.min(u32::MAX as u64)) as u32;
     let quota = Quota::per_second(NonZeroU32::new(bytes_per_second_u32).unwrap());
 
     let limiter = RateLimiter::direct(quota);
     let mut stream = response.bytes_stream();
     let mut session_downloaded: u64 = 0;
-    let start = std::time::Instant::now();
+    let mut last_progress = std::time::Instant::now();
+    let progress = |downloaded_bytes| DaemonEvent::DownloadProgress {
+        file: local_path.to_owned(),
+        downloaded_bytes,
+        total_bytes: size,
+    };
 
     // Force rate limiter to start empty so we don't have a large burst when starting download
     let max_burst = bytes_per_second_u32;
@@ -345,22 +602,16 @@ async fn download_file(
 
     while let Some(chunk_result) = stream.next().await {
         // Check if download should be forcefully stopped
-        if force_stop.load(std::sync::atomic::Ordering::SeqCst) {
+        if monitor.force_stop.load(Ordering::SeqCst) {
             warn!("Timeout interrupt - download stopping forcefully");
-            file.flush().await?;
-            drop(file);
-
-            // Clean up .part file on force_stop
-            // let _ = tokio::fs::remove_file(part_path_str)
-            //     .await
-            //     .inspect_err(|e| warn!("Failed to clean up .part file: {}", e));
-
             return Err(anyhow::anyhow!("Download interrupted by force_stop"));
         }
 
         match chunk_result {
             Ok(chunk) => {
-                let chunk_size = NonZeroU32::new(chunk.len() as u32).unwrap();
+                let Some(chunk_size) = NonZeroU32::new(chunk.len() as u32) else {
+                    continue;
+                };
 
                 // Wait for rate limiter
                 if let Err(e) = limiter.until_n_ready(chunk_size).await {
@@ -370,89 +621,37 @@ async fn download_file(
                 // Write chunk to file
                 file.write_all(&chunk).await?;
                 session_downloaded += chunk.len() as u64;
+                monitor
+                    .progress
+                    .downloaded
+                    .store(downloaded + session_downloaded, Ordering::Relaxed);
+                monitor
+                    .progress
+                    .transferred
+                    .fetch_add(chunk.len() as u64, Ordering::Relaxed);
+                monitor.meter.record(chunk.len() as u64);
+
+                if last_progress.elapsed() >= PROGRESS_INTERVAL {
+                    last_progress = std::time::Instant::now();
+                    monitor
+                        .events
+                        .publish(progress(downloaded + session_downloaded));
+
+                    // The .part file is kept, so the download resumes from
+                    // here once it is started again.
+                    if let Some(reason) = monitor.interrupted_by_policy(bytes_per_second) {
+                        info!("Stopping download of {local_path}: {reason}");
+                        return Err(Paused(reason).into());
+                    }
+                }
             }
 
             Err(e) => {
                 error!("Error downloading chunk: {}", e);
-                file.flush().await?;
-                drop(file);
-
-                // Clean up .part file on chunk error
-                // let _ = tokio::fs::remove_file(part_path_str)
-                //     .await
-                //     .inspect_err(|e| warn!("Failed to clean up .part file: {}", e));
-
                 return Err(anyhow::anyhow!("Download error: {}", e));
             }
         }
     }
 
-    file.flush().await?;
-    drop(file);
-
-    // Calculate and log final statistics
-    let elapsed = start.elapsed().as_secs_f64();
-
-    let avg_speed = if elapsed > 0.0 {
-        session_downloaded as f64 / elapsed
-    } else {
-        0.0
-    };
-
-    stats.bytes_downloaded = session_downloaded;
-    stats.elapsed_seconds = elapsed;
-    stats.average_speed_mbps = avg_speed / 1_000_000.0;
-
-    match tokio::fs::metadata(part_path_str).await {
-        Ok(metadata) => {
-            let file_size = metadata.len();
-
-            if file_size != content_length {
-                // Clean up .part file on size mismatch
-                let _ = tokio::fs::remove_file(part_path_str)
-                    .await
-                    .inspect_err(|e| warn!("Failed to clean up .part file: {}", e));
-
-                return Err(anyhow::anyhow!(
-                    "Size mismatch: file on disk ({}), downloaded amount ({}), expected content length ({:?})",
-                    file_size,
-                    session_downloaded,
-                    content_length
-                ));
-            } else if file_size == 0 {
-                // We know the file is completely busted here, try again
-                error!("File did not download properly. Re-trying");
-
-                return Box::pin(download_file(
-                    magic,
-                    session,
-                    local_path,
-                    remote_path,
-                    bytes_per_second,
-                    force_stop,
-                    Some(rec_track),
-                ))
-                .await;
-            } else {
-                info!("Downloaded file verification passed for {}", local_path);
-
-                // Atomically finalize - rename .part to final name
-                tokio::fs::rename(part_path_str, local_path)
-                    .await
-                    .map_err(|e| anyhow::anyhow!("Failed to finalize download: {}", e))?;
-
-                info!("Download finalized: {}", local_path);
-                stats.success = true;
-            }
-        }
-
-        Err(e) => {
-            error!("Failed to verify file size on disk: {}", e);
-
-            stats.error_message = Some(format!("Failed to verify file size on disk: {}", e));
-            return Err(anyhow::anyhow!("Failed to verify file size on disk: {}", e));
-        }
-    }
-
-    Ok(stats.clone())
+    Ok(session_downloaded)
 }
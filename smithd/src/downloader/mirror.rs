@@ -0,0 +1,179 @@
+//! Mirror selection and content verification.
+//!
+//! A download tries each mirror in turn until one delivers. A mirror that just
+//! failed goes to the back of the line for a while, longer the more often it
+//! has failed in a row, so one outage does not cost every download a timeout
+//! before it gets to a mirror that works. Since any mirror may serve the bytes,
+//! what decides whether they are kept is the file's sha256.
+use crate::utils::schema::DownloadMirror;
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use tokio::time::{Duration, Instant};
+
+/// How long a mirror is passed over after its first failure in a row. Doubles
+/// with every further one, up to [`MAX_BACKOFF`].
+const BASE_BACKOFF: Duration = Duration::from_secs(30);
+const MAX_BACKOFF: Duration = Duration::from_secs(30 * 60);
+
+#[derive(Debug, Default)]
+struct Score {
+    consecutive_failures: u32,
+    last_failure: Option<Instant>,
+}
+
+impl Score {
+    fn backing_off(&self, now: Instant) -> bool {
+        let Some(last_failure) = self.last_failure else {
+            return false;
+        };
+        let backoff = BASE_BACKOFF
+            .saturating_mul(1 << self.consecutive_failures.saturating_sub(1).min(6))
+            .min(MAX_BACKOFF);
+        now.duration_since(last_failure) < backoff
+    }
+}
+
+/// Recent outcomes per mirror, shared by every download.
+#[derive(Debug, Default)]
+pub struct MirrorHealth {
+    scores: Mutex<HashMap<String, Score>>,
+}
+
+impl MirrorHealth {
+    pub fn record_success(&self, mirror: &str) {
+        self.scores
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(mirror);
+    }
+
+    pub fn record_failure(&self, mirror: &str) {
+        let mut scores = self.scores.lock().unwrap_or_else(|e| e.into_inner());
+        let score = scores.entry(mirror.to_owned()).or_default();
+        score.consecutive_failures += 1;
+        score.last_failure = Some(Instant::now());
+    }
+
+    /// `mirrors` in the order to try them: the configured order, except that
+    /// mirrors backing off go last, those that failed least often first. They
+    /// are still tried, in case everything else is down too.
+    pub fn order(&self, mut mirrors: Vec<DownloadMirror>) -> Vec<DownloadMirror> {
+        let now = Instant::now();
+        let scores = self.scores.lock().unwrap_or_else(|e| e.into_inner());
+        mirrors.sort_by_key(|mirror| match scores.get(&mirror.name) {
+            Some(score) if score.backing_off(now) => score.consecutive_failures,
+            _ => 0,
+        });
+        mirrors
+    }
+}
+
+/// `<base>/<path>`, keeping any query string `base` has.
+pub fn mirror_url(base: &str, path: &str) -> String {
+    let (base, query) = match base.split_once('?') {
+        Some((base, query)) => (base, Some(query)),
+        None => (base, None),
+    };
+    let mut url = format!(
+        "{}/{}",
+        base.trim_end_matches('/'),
+        path.trim_start_matches('/')
+    );
+    if let Some(query) = query {
+        url.push('?');
+        url.push_str(query);
+    }
+    url
+}
+
+/// A hex sha256, alone or as a `sha256sum` line gives it, lowercased.
+pub fn parse_digest(text: &str) -> Option<String> {
+    let digest = text.split_whitespace().next()?;
+    (digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit()))
+        .then(|| digest.to_ascii_lowercase())
+}
+
+pub async fn sha256_file(path: &Path) -> anyhow::Result<String> {
+    let path = path.to_owned();
+    tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::File::open(&path)
+            .with_context(|| format!("opening {} to verify it", path.display()))?;
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut file, &mut hasher)
+            .with_context(|| format!("reading {} to verify it", path.display()))?;
+
+        let mut hex = String::with_capacity(64);
+        for byte in hasher.finalize() {
+            _ = write!(hex, "{byte:02x}");
+        }
+        Ok(hex)
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mirror(name: &str) -> DownloadMirror {
+        DownloadMirror {
+            name: name.to_string(),
+            url: Some(format!("http://{name}.local")),
+        }
+    }
+
+    fn names(mirrors: &[DownloadMirror]) -> Vec<&str> {
+        mirrors.iter().map(|m| m.name.as_str()).collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failing_mirrors_back_off_then_return() {
+        let health = MirrorHealth::default();
+        let mirrors = vec![mirror("cache"), mirror("cdn"), mirror("api")];
+
+        health.record_failure("cache");
+        health.record_failure("cache");
+        health.record_failure("cdn");
+        let ordered = health.order(mirrors.clone());
+        assert_eq!(names(&ordered), ["api", "cdn", "cache"]);
+
+        // cdn's single failure has aged out, cache's second one has not.
+        tokio::time::advance(Duration::from_secs(45)).await;
+        let ordered = health.order(mirrors.clone());
+        assert_eq!(names(&ordered), ["cdn", "api", "cache"]);
+
+        health.record_success("cache");
+        assert_eq!(names(&health.order(mirrors)), ["cache", "cdn", "api"]);
+    }
+
+    #[test]
+    fn mirror_urls_keep_the_query() {
+        assert_eq!(
+            mirror_url("http://cache.local/", "/packages/a.deb"),
+            "http://cache.local/packages/a.deb"
+        );
+        assert_eq!(
+            mirror_url("https://cdn.example.com/pkg?Policy=p&Signature=s", "a.deb"),
+            "https://cdn.example.com/pkg/a.deb?Policy=p&Signature=s"
+        );
+    }
+
+    #[tokio::test]
+    async fn digests_are_parsed_and_computed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("blob");
+        std::fs::write(&path, b"abc").unwrap();
+
+        let digest = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(sha256_file(&path).await.unwrap(), digest);
+        assert_eq!(
+            parse_digest(&format!("{}  blob\n", digest.to_uppercase())).as_deref(),
+            Some(digest)
+        );
+        assert_eq!(parse_digest("not a digest"), None);
+    }
+}
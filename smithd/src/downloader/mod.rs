@@ -1,165 +1,543 @@
+//! Downloader actor
+//!
+//! Downloads are queued with a priority and run up to
+//! [`MAX_CONCURRENT_DOWNLOADS`] at a time, highest priority first and oldest
+//! first within a priority, so a small urgent package no longer waits behind a
+//! multi-gigabyte OTA payload. Every download gets an id that can be used to
+//! query its progress or cancel it.
+//!
+//! Rates are capped by the bandwidth policy for the link the device is on, see
+//! [`bandwidth`]. Downloads the policy does not allow right now are paused and
+//! resume, from where they stopped, once it does.
+//!
+//! Files can come from any of several mirrors, see [`mirror`]. The api and
+//! magic.toml list them; each download fails over between them and keeps
+//! the bytes only if they match the file's digest.
+//!
+//! Only one download writes a given file at a time. Asking again for a file
+//! that is already queued or downloading from the same place joins that
+//! download; asking for it from elsewhere waits for the first to finish.
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, Ordering};
+pub mod bandwidth;
 mod download;
-use crate::downloader::download::DownloadStats;
+pub(crate) mod mirror;
+use crate::commander::network::collect_network_info;
+use crate::downloader::bandwidth::{Allowance, BANDWIDTH_PATH, Meter, Paused};
+use crate::downloader::download::{DownloadStats, Monitor, Progress};
+use crate::downloader::mirror::MirrorHealth;
+use crate::events::EventBus;
 use crate::magic::MagicHandle;
 use crate::session::SessionHandle;
 use crate::shutdown::ShutdownSignals;
-use crate::utils::network::NetworkClient;
+use crate::utils::schema::{
+    BandwidthPolicy, BandwidthUsage, DownloadInfo, DownloadPriority, DownloadState,
+};
 use anyhow::{self, Context};
 use download::download_file_mb;
-use tokio::sync::Mutex;
 use tokio::{
     sync::{mpsc, oneshot},
-    time,
+    time::{self, Instant},
 };
-use tracing::info;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// Downloads running at once. Each is rate limited on its own, so this bounds
+/// the combined bandwidth as much as the number of open connections.
+const MAX_CONCURRENT_DOWNLOADS: usize = 2;
+
+/// Finished downloads kept around so their outcome can still be queried.
+const FINISHED_HISTORY: usize = 32;
+
+/// How often the link is detected again, usage saved and paused downloads
+/// reconsidered.
+const POLICY_INTERVAL: time::Duration = time::Duration::from_secs(60);
+
+/// What to download, and how.
+#[derive(Debug, Clone)]
+pub struct DownloadJob {
+    pub remote_file: String,
+    pub local_file: String,
+    /// MB/s
+    pub rate: f64,
+    pub priority: DownloadPriority,
+    pub tag: Option<String>,
+    /// Expected sha256, hex. Otherwise the api's is used; without either,
+    /// only the api serves the file.
+    pub sha256: Option<String>,
+}
+
+impl DownloadJob {
+    pub fn new(remote_file: &str, local_file: &str, rate: f64) -> Self {
+        Self {
+            remote_file: remote_file.to_string(),
+            local_file: local_file.to_string(),
+            rate,
+            priority: DownloadPriority::default(),
+            tag: None,
+            sha256: None,
+        }
+    }
+
+    pub fn priority(mut self, priority: DownloadPriority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn tag(mut self, tag: &str) -> Self {
+        self.tag = Some(tag.to_string());
+        self
+    }
+
+    pub fn sha256(mut self, sha256: &str) -> Self {
+        self.sha256 = Some(sha256.to_ascii_lowercase());
+        self
+    }
+}
+
+type DownloadResult = anyhow::Result<DownloadStats>;
 
 #[derive(Debug)]
 enum DownloaderMessage {
     Download {
-        remote_file: String,
-        local_file: String,
-        rate: f64,
-        rpc: Option<oneshot::Sender<anyhow::Result<DownloadStats>>>,
+        job: DownloadJob,
+        id: oneshot::Sender<u64>,
+        rpc: Option<oneshot::Sender<DownloadResult>>,
+    },
+    List {
+        rpc: oneshot::Sender<Vec<DownloadInfo>>,
     },
-    CheckStatus {
-        rpc: oneshot::Sender<anyhow::Result<DownloadingStatus>>,
+    Cancel {
+        id: u64,
+        rpc: oneshot::Sender<bool>,
+    },
+    SetBandwidthPolicy {
+        policy: Option<BandwidthPolicy>,
+        rpc: oneshot::Sender<anyhow::Result<BandwidthUsage>>,
+    },
+    BandwidthUsage {
+        rpc: oneshot::Sender<BandwidthUsage>,
+    },
+    /// Sent by a download task when it ends, successfully or not.
+    Finished {
+        id: u64,
+        result: DownloadResult,
     },
 }
 
-#[derive(Debug)]
-pub enum DownloadingStatus {
-    Failed,
-    Downloading,
-    Success,
+struct Entry {
+    job: DownloadJob,
+    state: DownloadState,
+    error: Option<String>,
+    progress: Arc<Progress>,
+    started_at: Option<Instant>,
+    cancel: CancellationToken,
+    /// Everyone waiting on the outcome, more than one when requests joined.
+    waiters: Vec<oneshot::Sender<DownloadResult>>,
+}
+
+impl Entry {
+    fn info(&self, id: u64) -> DownloadInfo {
+        let downloaded = self.progress.downloaded.load(Ordering::Relaxed);
+        let total = Some(self.progress.total.load(Ordering::Relaxed)).filter(|t| *t > 0);
+
+        let bytes_per_second = match (self.state, self.started_at) {
+            (DownloadState::Downloading, Some(started_at)) => {
+                let elapsed = started_at.elapsed().as_secs_f64();
+                let transferred = self.progress.transferred.load(Ordering::Relaxed);
+                if elapsed > 0.0 {
+                    (transferred as f64 / elapsed) as u64
+                } else {
+                    0
+                }
+            }
+            _ => 0,
+        };
+
+        let eta_seconds = total
+            .filter(|_| bytes_per_second > 0)
+            .map(|total| total.saturating_sub(downloaded) / bytes_per_second);
+
+        DownloadInfo {
+            id,
+            remote_file: self.job.remote_file.clone(),
+            local_file: self.job.local_file.clone(),
+            priority: self.job.priority,
+            tag: self.job.tag.clone(),
+            state: self.state,
+            bytes_downloaded: downloaded,
+            total_bytes: total,
+            bytes_per_second,
+            eta_seconds,
+            error: self.error.clone(),
+            mirror: self.progress.mirror(),
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        matches!(
+            self.state,
+            DownloadState::Completed | DownloadState::Failed | DownloadState::Cancelled
+        )
+    }
+
+    fn is_waiting(&self) -> bool {
+        matches!(self.state, DownloadState::Queued | DownloadState::Paused)
+    }
+
+    /// Whether `job` can share this download's outcome instead of running.
+    fn can_join(&self, job: &DownloadJob) -> bool {
+        !self.is_finished()
+            && self.job.local_file == job.local_file
+            && self.job.remote_file == job.remote_file
+            && (job.sha256.is_none() || self.job.sha256 == job.sha256)
+    }
+}
+
+/// The queued download to start next. Ids increase with submission, so the
+/// smallest id wins a tie. Downloads of a file that is already being written
+/// wait for it to finish, so two never share a `.part` file.
+fn next_queued(downloads: &BTreeMap<u64, Entry>) -> Option<u64> {
+    let busy: BTreeSet<&str> = downloads
+        .values()
+        .filter(|entry| entry.state == DownloadState::Downloading)
+        .map(|entry| entry.job.local_file.as_str())
+        .collect();
+
+    downloads
+        .iter()
+        .filter(|(_, entry)| entry.is_waiting())
+        .filter(|(_, entry)| !busy.contains(entry.job.local_file.as_str()))
+        .max_by_key(|(id, entry)| (entry.job.priority, std::cmp::Reverse(**id)))
+        .map(|(id, _)| *id)
+}
+
+/// A copy of the outcome for each joined waiter; errors don't clone.
+fn copy_result(result: &DownloadResult) -> DownloadResult {
+    match result {
+        Ok(stats) => Ok(stats.clone()),
+        Err(err) => Err(anyhow::anyhow!("{err:#}")),
+    }
 }
 
 struct Downloader {
     shutdown: ShutdownSignals,
     receiver: mpsc::Receiver<DownloaderMessage>,
+    /// Handed to download tasks so they can report back when they end.
+    sender: mpsc::Sender<DownloaderMessage>,
     magic: MagicHandle,
     session: SessionHandle,
-    downloading_count: Arc<AtomicUsize>,
-    network: NetworkClient,
+    events: EventBus,
+    meter: Arc<Meter>,
+    health: Arc<MirrorHealth>,
     force_stop: Arc<AtomicBool>,
-    last_download_status: Arc<AtomicBool>,
     timeout: u64,
-    download_lock: Arc<Mutex<()>>,
+    next_id: u64,
+    downloads: BTreeMap<u64, Entry>,
 }
 
 impl Downloader {
     fn new(
         shutdown: ShutdownSignals,
         receiver: mpsc::Receiver<DownloaderMessage>,
+        sender: mpsc::Sender<DownloaderMessage>,
         magic: MagicHandle,
         session: SessionHandle,
+        events: EventBus,
         timeout: u64,
     ) -> Self {
-        let network = NetworkClient::new();
-        let force_stop = Arc::new(AtomicBool::new(false));
-        let is_downloading = Arc::new(AtomicUsize::new(0));
-        let last_download_status = Arc::new(AtomicBool::new(false));
-        let download_lock = Arc::new(Mutex::new(()));
-
         Self {
             shutdown,
             receiver,
+            sender,
             magic,
             session,
-            network,
-            downloading_count: is_downloading,
-            force_stop,
+            events,
+            meter: Arc::new(Meter::load(Path::new(BANDWIDTH_PATH))),
+            health: Arc::new(MirrorHealth::default()),
+            force_stop: Arc::new(AtomicBool::new(false)),
             timeout,
-            last_download_status,
-            download_lock,
+            next_id: 0,
+            downloads: BTreeMap::new(),
         }
     }
 
-    async fn handle_message(&mut self, msg: DownloaderMessage) {
-        match msg {
-            DownloaderMessage::Download {
-                remote_file,
-                local_file,
-                rate,
-                rpc,
-            } => {
-                self.downloading_count.fetch_add(1, Ordering::SeqCst);
-
-                let magic = self.magic.clone();
-                let session = self.session.clone();
-                let force_stop = self.force_stop.clone();
-                let is_downloading = self.downloading_count.clone();
-                let last_download_status = self.last_download_status.clone();
-                let download_lock = self.download_lock.clone();
-
-                tokio::spawn(async move {
-                    // Aqcuire global lock
-                    let _guard = download_lock.lock().await;
-
-                    // Do the download
-                    let result =
-                        download_file_mb(magic, session, remote_file, local_file, rate, force_stop)
-                            .await;
-
-                    if result.is_ok() {
-                        last_download_status.store(true, Ordering::SeqCst);
-                    } else {
-                        last_download_status.store(false, Ordering::SeqCst);
-                    }
+    fn active_count(&self) -> usize {
+        self.downloads
+            .values()
+            .filter(|entry| entry.state == DownloadState::Downloading)
+            .count()
+    }
 
-                    // Reset status
-                    is_downloading.fetch_sub(1, Ordering::SeqCst);
+    /// Starts queued downloads until the concurrency limit is reached, or
+    /// marks them paused if the bandwidth policy holds them back.
+    fn start_queued(&mut self) {
+        if let Some(reason) = self.meter.paused() {
+            for entry in self.downloads.values_mut().filter(|e| e.is_waiting()) {
+                entry.state = DownloadState::Paused;
+                entry.error = Some(reason.clone());
+            }
+            return;
+        }
 
-                    if let Some(rpc) = rpc {
-                        let _ = rpc.send(result);
-                    }
+        while self.active_count() < MAX_CONCURRENT_DOWNLOADS {
+            let Some(id) = next_queued(&self.downloads) else {
+                break;
+            };
+            if !self.start(id) {
+                break;
+            }
+        }
+    }
 
-                    // Lock released when _gaurd dropped
-                });
+    /// Returns false if the bandwidth policy would not let it start.
+    fn start(&mut self, id: u64) -> bool {
+        let Some(entry) = self.downloads.get_mut(&id) else {
+            return false;
+        };
+        let rate = match self.meter.allowance(entry.job.rate) {
+            Allowance::Rate(rate) => rate,
+            Allowance::Paused(reason) => {
+                entry.state = DownloadState::Paused;
+                entry.error = Some(reason);
+                return false;
             }
-            DownloaderMessage::CheckStatus { rpc } => {
-                // Check if the thread is currently downloading
-                let mut status = DownloadingStatus::Failed;
-                if self.downloading_count.load(Ordering::SeqCst) > 0 {
-                    status = DownloadingStatus::Downloading;
-                } else if self.last_download_status.load(Ordering::SeqCst) {
-                    status = DownloadingStatus::Success;
+        };
+        entry.state = DownloadState::Downloading;
+        entry.error = None;
+        entry.started_at = Some(Instant::now());
+        entry.progress.transferred.store(0, Ordering::Relaxed);
+
+        info!(
+            "Starting download {id} ({:?}) at {rate:.2} MB/s: {}",
+            entry.job.priority, entry.job.remote_file
+        );
+
+        let magic = self.magic.clone();
+        let session = self.session.clone();
+        let job = entry.job.clone();
+        let cancel = entry.cancel.clone();
+        let sender = self.sender.clone();
+        let monitor = Monitor {
+            force_stop: self.force_stop.clone(),
+            events: self.events.clone(),
+            progress: entry.progress.clone(),
+            meter: self.meter.clone(),
+            health: self.health.clone(),
+            requested_rate: job.rate,
+        };
+
+        tokio::spawn(async move {
+            // Dropping the transfer mid-stream leaves the .part file behind, so
+            // a cancelled download resumes if it is queued again.
+            let result = tokio::select! {
+                result = download_file_mb(magic, session, job, rate, monitor) => result,
+                _ = cancel.cancelled() => Err(anyhow::anyhow!("Download cancelled")),
+            };
+
+            _ = sender
+                .send(DownloaderMessage::Finished { id, result })
+                .await;
+        });
+
+        true
+    }
+
+    fn finish(&mut self, id: u64, result: DownloadResult) {
+        let Some(entry) = self.downloads.get_mut(&id) else {
+            return;
+        };
+
+        // Stopped by the bandwidth policy: wait to be started again rather
+        // than fail, keeping the caller waiting on the outcome.
+        if let Err(err) = &result
+            && let Some(Paused(reason)) = err.downcast_ref::<Paused>()
+            && !entry.cancel.is_cancelled()
+        {
+            info!("Download {id} paused: {reason}");
+            entry.state = DownloadState::Paused;
+            entry.error = Some(reason.clone());
+            entry.started_at = None;
+            return;
+        }
+
+        entry.state = match &result {
+            Ok(stats) if stats.success => DownloadState::Completed,
+            _ if entry.cancel.is_cancelled() => DownloadState::Cancelled,
+            _ => DownloadState::Failed,
+        };
+        entry.error = match &result {
+            Ok(stats) => stats.error_message.clone(),
+            Err(err) => Some(format!("{err:#}")),
+        };
+        if entry.state == DownloadState::Completed {
+            let total = entry.progress.total.load(Ordering::Relaxed);
+            entry.progress.downloaded.store(total, Ordering::Relaxed);
+        }
+
+        info!("Download {id} finished: {:?}", entry.state);
+
+        let mut waiters = std::mem::take(&mut entry.waiters);
+        if let Some(last) = waiters.pop() {
+            for rpc in waiters {
+                _ = rpc.send(copy_result(&result));
+            }
+            _ = last.send(result);
+        }
+
+        self.prune_finished();
+    }
+
+    fn prune_finished(&mut self) {
+        let finished: Vec<u64> = self
+            .downloads
+            .iter()
+            .filter(|(_, entry)| entry.is_finished())
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in finished
+            .iter()
+            .take(finished.len().saturating_sub(FINISHED_HISTORY))
+        {
+            self.downloads.remove(id);
+        }
+    }
+
+    async fn refresh_link(&self) {
+        let link = collect_network_info()
+            .await
+            .map(|info| info.interface_type)
+            .unwrap_or_default();
+        self.meter.set_link(link);
+    }
+
+    async fn persist_usage(&self) {
+        if let Err(err) = self.meter.persist().await {
+            warn!("Failed to save bandwidth usage: {err:#}");
+        }
+    }
+
+    async fn handle_message(&mut self, msg: DownloaderMessage) {
+        match msg {
+            DownloaderMessage::Download { job, id, rpc } => {
+                if let Some((&existing, entry)) = self
+                    .downloads
+                    .iter_mut()
+                    .find(|(_, entry)| entry.can_join(&job))
+                {
+                    info!("Download of {} joins download {existing}", job.local_file);
+                    entry.job.priority = entry.job.priority.max(job.priority);
+                    entry.waiters.extend(rpc);
+                    _ = id.send(existing);
+                    self.start_queued();
+                    return;
                 }
 
-                let _ = rpc.send(Ok(status));
+                self.next_id += 1;
+                let download_id = self.next_id;
+
+                self.downloads.insert(
+                    download_id,
+                    Entry {
+                        job,
+                        state: DownloadState::Queued,
+                        error: None,
+                        progress: Arc::new(Progress::default()),
+                        started_at: None,
+                        cancel: CancellationToken::new(),
+                        waiters: rpc.into_iter().collect(),
+                    },
+                );
+                _ = id.send(download_id);
+            }
+            DownloaderMessage::List { rpc } => {
+                let downloads = self
+                    .downloads
+                    .iter()
+                    .map(|(id, entry)| entry.info(*id))
+                    .collect();
+                _ = rpc.send(downloads);
+            }
+            DownloaderMessage::Cancel { id, rpc } => {
+                let cancelled = match self.downloads.get_mut(&id) {
+                    Some(entry) if entry.is_waiting() => {
+                        entry.cancel.cancel();
+                        self.finish(id, Err(anyhow::anyhow!("Download cancelled")));
+                        true
+                    }
+                    // The task notices and reports back through `Finished`.
+                    Some(entry) if entry.state == DownloadState::Downloading => {
+                        entry.cancel.cancel();
+                        true
+                    }
+                    _ => false,
+                };
+                _ = rpc.send(cancelled);
+            }
+            DownloaderMessage::SetBandwidthPolicy { policy, rpc } => {
+                info!("Bandwidth policy updated: {policy:?}");
+                self.meter.set_policy(policy);
+                let result = self.meter.persist().await.map(|_| self.meter.usage());
+                _ = rpc.send(result);
+            }
+            DownloaderMessage::BandwidthUsage { rpc } => {
+                _ = rpc.send(self.meter.usage());
+            }
+            DownloaderMessage::Finished { id, result } => {
+                self.finish(id, result);
             }
         }
+
+        self.start_queued();
     }
 
     async fn run(&mut self) {
         info!("Download task is running");
 
-        let hostname = self.magic.get_server().await;
-
-        self.network.set_hostname(hostname);
+        let mut policy_interval = time::interval(POLICY_INTERVAL);
+        policy_interval.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
 
         loop {
             tokio::select! {
                 Some(msg) = self.receiver.recv() => {
-                    info!("Received message: {:?}", msg);
                     self.handle_message(msg).await;
                 }
 
+                _ = policy_interval.tick() => {
+                    self.refresh_link().await;
+                    self.persist_usage().await;
+                    self.start_queued();
+                }
+
                 _ = self.shutdown.token.cancelled() => {
+                    // Nothing new may start, but running downloads get a chance
+                    // to finish before they are forced to stop.
+                    let queued: Vec<u64> = self
+                        .downloads
+                        .iter()
+                        .filter(|(_, entry)| entry.is_waiting())
+                        .map(|(id, _)| *id)
+                        .collect();
+                    for id in queued {
+                        self.finish(id, Err(anyhow::anyhow!("Daemon shutting down")));
+                    }
+
                     let mut count = 1;
 
-                    loop {
-                        let active_downloads = self.downloading_count.load(Ordering::SeqCst);
+                    while self.active_count() > 0 {
+                        info!("Waiting for download task to finish");
 
-                        if active_downloads == 0 {
-                            info!("All downloads stopped, exiting.");
-                            break;
+                        match time::timeout(time::Duration::from_secs(1), self.receiver.recv()).await {
+                            Ok(Some(DownloaderMessage::Finished { id, result })) => {
+                                self.finish(id, result);
+                            }
+                            Ok(Some(_)) | Err(_) => {}
+                            Ok(None) => break,
                         }
 
-                        info!("Waiting for download task to finish");
-                        time::sleep(time::Duration::from_secs(1)).await;
-
                         if count > self.timeout {
                             info!("Download task did not finish in time. Forcing stop");
                             if !self.force_stop.load(Ordering::SeqCst) {
@@ -167,8 +545,8 @@ impl Downloader {
                             }
                         }
                         count += 1;
-
                     }
+                    self.persist_usage().await;
                     info!("Download task shutting down gracefully");
                     break;
                 }
@@ -178,61 +556,61 @@ impl Downloader {
 }
 
 #[derive(Clone)]
-
 pub struct DownloaderHandle {
     sender: mpsc::Sender<DownloaderMessage>,
 }
 
 impl DownloaderHandle {
-    pub fn new(shutdown: ShutdownSignals, magic: MagicHandle, session: SessionHandle) -> Self {
+    pub fn new(
+        shutdown: ShutdownSignals,
+        magic: MagicHandle,
+        session: SessionHandle,
+        events: EventBus,
+    ) -> Self {
         let (sender, receiver) = mpsc::channel(8);
 
         let timeout = 5; // 5 second timeout
 
-        let mut actor = Downloader::new(shutdown, receiver, magic, session, timeout);
+        let mut actor = Downloader::new(
+            shutdown,
+            receiver,
+            sender.clone(),
+            magic,
+            session,
+            events,
+            timeout,
+        );
 
         tokio::spawn(async move { actor.run().await });
 
         Self { sender }
     }
 
-    pub async fn download(
+    async fn enqueue(
         &self,
-        remote_file: &str,
-        local_file: &str,
-        rate: f64,
-    ) -> anyhow::Result<()> {
-        // unwrap because if this fails then we are in a bad state
+        job: DownloadJob,
+        rpc: Option<oneshot::Sender<DownloadResult>>,
+    ) -> anyhow::Result<u64> {
+        let (id, receiver) = oneshot::channel();
+
         self.sender
-            .send(DownloaderMessage::Download {
-                remote_file: remote_file.to_string(),
-                local_file: local_file.to_string(),
-                rate,
-                rpc: None,
-            })
+            .send(DownloaderMessage::Download { job, id, rpc })
             .await
-            .unwrap();
+            .context("Downloader is not running")?;
 
-        Ok(())
+        receiver.await.context("Downloader dropped the request")
     }
 
-    pub async fn download_blocking(
-        &self,
-        remote_file: &str,
-        local_file: &str,
-        rate: f64,
-    ) -> anyhow::Result<()> {
-        let (rpc, receiver) = oneshot::channel::<anyhow::Result<DownloadStats>>();
+    /// Queues a download and returns its id without waiting for it.
+    pub async fn download(&self, job: DownloadJob) -> anyhow::Result<u64> {
+        self.enqueue(job, None).await
+    }
 
-        self.sender
-            .send(DownloaderMessage::Download {
-                remote_file: remote_file.to_string(),
-                local_file: local_file.to_string(),
-                rate,
-                rpc: Some(rpc),
-            })
-            .await
-            .unwrap();
+    /// Queues a download and waits for it to finish.
+    pub async fn download_blocking(&self, job: DownloadJob) -> anyhow::Result<()> {
+        let (rpc, receiver) = oneshot::channel::<DownloadResult>();
+
+        self.enqueue(job, Some(rpc)).await?;
 
         // Get the stats
         let stats = receiver.await.context("Download task died")??;
@@ -247,8 +625,6 @@ impl DownloaderHandle {
             ));
         }
 
-        // Log success info
-        // TODO: check if this is actually going to log
         info!(
             "Downloaded {} bytes in {:.2}s at {:.2} MB/s",
             stats.bytes_downloaded, stats.elapsed_seconds, stats.average_speed_mbps
@@ -257,15 +633,164 @@ impl DownloaderHandle {
         Ok(())
     }
 
-    pub async fn check_download_status(&self) -> anyhow::Result<DownloadingStatus> {
-        // unwrap because if this fails then we are in a bad state
+    /// Every queued, running and recently finished download, oldest first.
+    pub async fn list(&self) -> Vec<DownloadInfo> {
+        let (rpc, receiver) = oneshot::channel();
+
+        if self
+            .sender
+            .send(DownloaderMessage::List { rpc })
+            .await
+            .is_err()
+        {
+            warn!("Downloader is not running");
+            return Vec::new();
+        }
+
+        receiver.await.unwrap_or_default()
+    }
+
+    /// Replaces the bandwidth policy and returns the usage under the new one.
+    pub async fn set_bandwidth_policy(
+        &self,
+        policy: Option<BandwidthPolicy>,
+    ) -> anyhow::Result<BandwidthUsage> {
+        let (rpc, receiver) = oneshot::channel();
+
+        self.sender
+            .send(DownloaderMessage::SetBandwidthPolicy { policy, rpc })
+            .await
+            .context("Downloader is not running")?;
+
+        receiver.await.context("Downloader dropped the request")?
+    }
+
+    /// This month's data usage and the policy in effect.
+    pub async fn bandwidth_usage(&self) -> Option<BandwidthUsage> {
         let (rpc, receiver) = oneshot::channel();
 
         self.sender
-            .send(DownloaderMessage::CheckStatus { rpc })
+            .send(DownloaderMessage::BandwidthUsage { rpc })
             .await
-            .unwrap();
+            .ok()?;
+
+        receiver.await.ok()
+    }
+
+    pub async fn get(&self, id: u64) -> Option<DownloadInfo> {
+        self.list().await.into_iter().find(|info| info.id == id)
+    }
+
+    /// Cancels a queued or running download. Returns false if there was none
+    /// with that id still in progress.
+    pub async fn cancel(&self, id: u64) -> bool {
+        let (rpc, receiver) = oneshot::channel();
+
+        if self
+            .sender
+            .send(DownloaderMessage::Cancel { id, rpc })
+            .await
+            .is_err()
+        {
+            return false;
+        }
+
+        receiver.await.unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(priority: DownloadPriority, state: DownloadState) -> Entry {
+        entry_for("local", priority, state)
+    }
+
+    fn entry_for(local: &str, priority: DownloadPriority, state: DownloadState) -> Entry {
+        Entry {
+            job: DownloadJob::new("remote", local, 1.0).priority(priority),
+            state,
+            error: None,
+            progress: Arc::new(Progress::default()),
+            started_at: None,
+            cancel: CancellationToken::new(),
+            waiters: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn higher_priority_starts_first_then_oldest() {
+        let mut downloads = BTreeMap::new();
+        for (id, priority, state) in [
+            (1, DownloadPriority::Low, DownloadState::Downloading),
+            (2, DownloadPriority::Low, DownloadState::Queued),
+            (3, DownloadPriority::Normal, DownloadState::Queued),
+            (4, DownloadPriority::High, DownloadState::Queued),
+            (5, DownloadPriority::High, DownloadState::Queued),
+        ] {
+            downloads.insert(id, entry_for(&format!("local-{id}"), priority, state));
+        }
+
+        assert_eq!(next_queued(&downloads), Some(4));
+        downloads.get_mut(&4).unwrap().state = DownloadState::Downloading;
+        assert_eq!(next_queued(&downloads), Some(5));
+        downloads.get_mut(&5).unwrap().state = DownloadState::Cancelled;
+        assert_eq!(next_queued(&downloads), Some(3));
+        downloads.get_mut(&3).unwrap().state = DownloadState::Completed;
+        assert_eq!(next_queued(&downloads), Some(2));
+        downloads.get_mut(&2).unwrap().state = DownloadState::Failed;
+        assert_eq!(next_queued(&downloads), None);
+    }
+
+    #[test]
+    fn one_download_writes_a_file_at_a_time() {
+        let mut downloads = BTreeMap::new();
+        downloads.insert(1, entry(DownloadPriority::Low, DownloadState::Downloading));
+        downloads.insert(2, entry(DownloadPriority::High, DownloadState::Queued));
+        downloads.insert(
+            3,
+            entry_for("other", DownloadPriority::Normal, DownloadState::Queued),
+        );
+
+        assert_eq!(next_queued(&downloads), Some(3));
+        downloads.get_mut(&3).unwrap().state = DownloadState::Downloading;
+        assert_eq!(next_queued(&downloads), None);
+        downloads.get_mut(&1).unwrap().state = DownloadState::Completed;
+        assert_eq!(next_queued(&downloads), Some(2));
+    }
+
+    #[test]
+    fn the_same_file_from_the_same_place_joins() {
+        let running = entry(DownloadPriority::Low, DownloadState::Downloading);
+        assert!(running.can_join(&DownloadJob::new("remote", "local", 1.0)));
+        assert!(!running.can_join(&DownloadJob::new("elsewhere", "local", 1.0)));
+        assert!(!running.can_join(&DownloadJob::new("remote", "local", 1.0).sha256("ab")));
+
+        let done = entry(DownloadPriority::Low, DownloadState::Completed);
+        assert!(!done.can_join(&DownloadJob::new("remote", "local", 1.0)));
+    }
 
-        receiver.await.unwrap()
+    #[tokio::test(start_paused = true)]
+    async fn progress_reports_rate_and_eta() {
+        let mut running = entry(DownloadPriority::Normal, DownloadState::Downloading);
+        running.started_at = Some(Instant::now());
+        running.progress.total.store(10_000, Ordering::Relaxed);
+        // 1000 bytes were already on disk from an earlier attempt.
+        running.progress.downloaded.store(3_000, Ordering::Relaxed);
+        running.progress.transferred.store(2_000, Ordering::Relaxed);
+
+        tokio::time::advance(time::Duration::from_secs(2)).await;
+        let info = running.info(7);
+
+        assert_eq!(info.id, 7);
+        assert_eq!(info.bytes_downloaded, 3_000);
+        assert_eq!(info.total_bytes, Some(10_000));
+        assert_eq!(info.bytes_per_second, 1_000);
+        assert_eq!(info.eta_seconds, Some(7));
+
+        let queued = entry(DownloadPriority::Normal, DownloadState::Queued).info(8);
+        assert_eq!(queued.total_bytes, None);
+        assert_eq!(queued.eta_seconds, None);
     }
 }
@@ -0,0 +1,102 @@
+//! Daemon event bus.
+//!
+//! Actors publish notable state changes here and the control socket streams
+//! them to services on the device at `GET /events`, so they no longer have to
+//! poll `/watchdog` to learn that a reboot is coming.
+//!
+//! Publishing never blocks an actor: with no subscribers an event is dropped,
+//! and a subscriber that falls more than [`CAPACITY`] events behind skips the
+//! ones it missed rather than holding anyone up.
+
+use crate::utils::schema::{HookOutput, OtaPhase};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+/// Events buffered per subscriber before the slowest one starts missing them.
+const CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum DaemonEvent {
+    RebootScheduled {
+        schedule_id: u32,
+        seconds_remaining: u64,
+    },
+    RebootCancelled {
+        schedule_id: u32,
+    },
+    UpgradeStarted,
+    UpgradeFinished {
+        success: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        hooks: Vec<HookOutput>,
+    },
+    DownloadProgress {
+        file: String,
+        downloaded_bytes: u64,
+        total_bytes: u64,
+    },
+    ApiConnectivityLost,
+    ApiConnectivityRestored,
+    TunnelOpened {
+        local_port: u16,
+        remote_port: u16,
+    },
+    TunnelClosed {
+        local_port: u16,
+        remote_port: u16,
+    },
+    OtaStateChanged {
+        phase: OtaPhase,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
+
+impl DaemonEvent {
+    /// The SSE `event:` name, matching the serialized `event` tag so
+    /// subscribers can filter with `addEventListener` or on the JSON alike.
+    pub fn name(&self) -> &'static str {
+        match self {
+            DaemonEvent::RebootScheduled { .. } => "reboot_scheduled",
+            DaemonEvent::RebootCancelled { .. } => "reboot_cancelled",
+            DaemonEvent::UpgradeStarted => "upgrade_started",
+            DaemonEvent::UpgradeFinished { .. } => "upgrade_finished",
+            DaemonEvent::DownloadProgress { .. } => "download_progress",
+            DaemonEvent::ApiConnectivityLost => "api_connectivity_lost",
+            DaemonEvent::ApiConnectivityRestored => "api_connectivity_restored",
+            DaemonEvent::TunnelOpened { .. } => "tunnel_opened",
+            DaemonEvent::TunnelClosed { .. } => "tunnel_closed",
+            DaemonEvent::OtaStateChanged { .. } => "ota_state_changed",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<DaemonEvent>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        Self { sender }
+    }
+
+    pub fn publish(&self, event: DaemonEvent) {
+        // An error only means nobody is subscribed right now.
+        _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<DaemonEvent> {
+        self.sender.subscribe()
+    }
+}
@@ -0,0 +1,156 @@
+//! Device identity.
+//!
+//! Every device holds an Ed25519 key generated on first boot. It registers with
+//! the public half and proves possession by signing a single-use challenge from
+//! the api, so knowing a device's serial and MAC is no longer enough to claim its
+//! token. The api pins the key on first registration and then also accepts a
+//! signed challenge in place of the opaque token when minting session JWTs.
+
+use anyhow::{Context, Result, anyhow};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand::rngs::OsRng;
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+use tracing::info;
+
+const IDENTITY_KEY_FILE: &str = "identity.ed25519";
+
+/// What a signature is for. Part of the signed message so a registration
+/// signature can never be replayed as a session proof or the other way round.
+#[derive(Debug, Clone, Copy)]
+pub enum Purpose {
+    Register,
+    Session,
+}
+
+impl Purpose {
+    fn as_str(self) -> &'static str {
+        match self {
+            Purpose::Register => "register",
+            Purpose::Session => "session",
+        }
+    }
+}
+
+/// Loads the identity key from `dir`, generating and persisting one on first
+/// use. Lives next to the secrets key in `secrets::KEYS_DIR`.
+pub fn load_or_create_key(dir: &Path) -> Result<SigningKey> {
+    let path = dir.join(IDENTITY_KEY_FILE);
+
+    match std::fs::read(&path) {
+        Ok(bytes) => {
+            let bytes: [u8; 32] = bytes
+                .try_into()
+                .map_err(|_| anyhow!("{} is not a 32 byte key", path.display()))?;
+            return Ok(SigningKey::from_bytes(&bytes));
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err).with_context(|| format!("reading {}", path.display())),
+    }
+
+    std::fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+    std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))
+        .with_context(|| format!("securing {}", dir.display()))?;
+
+    let key = SigningKey::generate(&mut OsRng);
+
+    let tmp = dir.join(format!(".{IDENTITY_KEY_FILE}.tmp"));
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp)
+        .with_context(|| format!("creating {}", tmp.display()))?;
+    file.write_all(&key.to_bytes())?;
+    file.sync_all()?;
+    drop(file);
+    std::fs::rename(&tmp, &path).with_context(|| format!("writing {}", path.display()))?;
+
+    info!("Generated device identity key at {}", path.display());
+    Ok(key)
+}
+
+pub fn public_key_base64(key: &SigningKey) -> String {
+    BASE64.encode(key.verifying_key().as_bytes())
+}
+
+/// The exact bytes signed: purpose, serial and challenge, newline separated.
+pub fn signed_message(purpose: Purpose, serial_number: &str, challenge: &str) -> String {
+    format!("smith-{}\n{serial_number}\n{challenge}", purpose.as_str())
+}
+
+pub fn sign(key: &SigningKey, purpose: Purpose, serial_number: &str, challenge: &str) -> String {
+    let message = signed_message(purpose, serial_number, challenge);
+    BASE64.encode(key.sign(message.as_bytes()).to_bytes())
+}
+
+/// Checks `signature` against a base64 public key. Used by the api.
+pub fn verify(
+    public_key: &str,
+    purpose: Purpose,
+    serial_number: &str,
+    challenge: &str,
+    signature: &str,
+) -> Result<()> {
+    let public_key: [u8; 32] = BASE64
+        .decode(public_key.trim())
+        .context("public key is not valid base64")?
+        .try_into()
+        .map_err(|_| anyhow!("public key is not 32 bytes"))?;
+    let public_key = VerifyingKey::from_bytes(&public_key).context("invalid public key")?;
+
+    let signature: [u8; 64] = BASE64
+        .decode(signature.trim())
+        .context("signature is not valid base64")?
+        .try_into()
+        .map_err(|_| anyhow!("signature is not 64 bytes"))?;
+
+    let message = signed_message(purpose, serial_number, challenge);
+    public_key
+        .verify(message.as_bytes(), &Signature::from_bytes(&signature))
+        .map_err(|_| anyhow!("signature does not match"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_is_bound_to_purpose_serial_and_challenge() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = load_or_create_key(dir.path()).unwrap();
+        let public = public_key_base64(&key);
+
+        let signature = sign(&key, Purpose::Register, "SN1", "abc");
+
+        assert!(verify(&public, Purpose::Register, "SN1", "abc", &signature).is_ok());
+        assert!(verify(&public, Purpose::Session, "SN1", "abc", &signature).is_err());
+        assert!(verify(&public, Purpose::Register, "SN2", "abc", &signature).is_err());
+        assert!(verify(&public, Purpose::Register, "SN1", "abd", &signature).is_err());
+
+        let other = SigningKey::generate(&mut OsRng);
+        assert!(
+            verify(
+                &public_key_base64(&other),
+                Purpose::Register,
+                "SN1",
+                "abc",
+                &signature
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn key_is_persisted_across_loads() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = load_or_create_key(dir.path()).unwrap();
+        let second = load_or_create_key(dir.path()).unwrap();
+
+        assert_eq!(public_key_base64(&first), public_key_base64(&second));
+    }
+}
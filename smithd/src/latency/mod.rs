@@ -0,0 +1,238 @@
+//! Measures round-trip time, jitter and loss to the API host and the default
+//! gateway every minute, without the bandwidth a throughput test costs. The
+//! rounds are summed up per interval, next to the Wi-Fi link they ran on.
+use crate::commander::CommanderHandle;
+use crate::magic::MagicHandle;
+use crate::shutdown::ShutdownSignals;
+use crate::utils::schema::{LatencyStats, LinkQualityInterval, SafeCommandResponse, SafeCommandRx};
+use std::net::IpAddr;
+use std::time::SystemTime;
+use tokio::time::{Duration, sleep};
+use tracing::info;
+
+mod ping;
+
+const LINK_QUALITY_RESULT_ID: i32 = -13;
+
+const ROUND_EVERY: Duration = Duration::from_secs(60);
+
+/// Rounds summed up in one interval, unless the device roams before.
+const ROUNDS_PER_INTERVAL: u32 = 15;
+
+pub struct LatencyHandle;
+
+impl LatencyHandle {
+    pub fn new(shutdown: ShutdownSignals, commander: CommanderHandle, magic: MagicHandle) -> Self {
+        tokio::spawn(async move {
+            run(shutdown, commander, magic).await;
+        });
+        Self
+    }
+}
+
+async fn run(shutdown: ShutdownSignals, commander: CommanderHandle, magic: MagicHandle) {
+    info!("Latency prober starting");
+    let mut interval: Option<Interval> = None;
+    loop {
+        let link = ping::wifi_link().await;
+        let (bssid, signal) = link.unzip();
+        if interval.as_ref().is_some_and(|i| i.bssid != bssid)
+            && let Some(done) = interval.take()
+        {
+            report(&commander, done.finish(unix_now())).await;
+        }
+        let current = interval.get_or_insert_with(|| Interval::new(unix_now(), bssid));
+        current.signal(signal);
+
+        if let Some(addr) = api_address(&magic.get_server().await).await {
+            current.api.add(ping::ping(addr).await);
+        }
+        if let Some(addr) = ping::default_gateway().await {
+            current.gateway.add(ping::ping(addr).await);
+        }
+        current.rounds += 1;
+
+        if current.rounds >= ROUNDS_PER_INTERVAL
+            && let Some(done) = interval.take()
+        {
+            report(&commander, done.finish(unix_now())).await;
+        }
+
+        tokio::select! {
+            _ = shutdown.token.cancelled() => break,
+            _ = sleep(ROUND_EVERY) => {}
+        }
+    }
+    info!("Latency prober shutting down");
+}
+
+async fn report(commander: &CommanderHandle, interval: LinkQualityInterval) {
+    commander
+        .insert_result(vec![SafeCommandResponse {
+            id: LINK_QUALITY_RESULT_ID,
+            command: SafeCommandRx::LinkQuality {
+                intervals: vec![interval],
+            },
+            status: 0,
+        }])
+        .await;
+}
+
+/// The API host, resolved here so a DNS failure isn't counted as loss.
+async fn api_address(server: &str) -> Option<IpAddr> {
+    let url = url::Url::parse(server).ok()?;
+    let host = url.host_str()?;
+    let port = url.port_or_known_default()?;
+    let mut addrs = tokio::net::lookup_host((host, port)).await.ok()?;
+    addrs.next().map(|addr| addr.ip())
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+struct Interval {
+    start: u64,
+    bssid: Option<String>,
+    signals: Vec<u8>,
+    rounds: u32,
+    api: Samples,
+    gateway: Samples,
+}
+
+impl Interval {
+    fn new(start: u64, bssid: Option<String>) -> Self {
+        Self {
+            start,
+            bssid,
+            signals: Vec::new(),
+            rounds: 0,
+            api: Samples::default(),
+            gateway: Samples::default(),
+        }
+    }
+
+    fn signal(&mut self, signal: Option<u8>) {
+        self.signals.extend(signal);
+    }
+
+    fn finish(self, end: u64) -> LinkQualityInterval {
+        let signal = (!self.signals.is_empty()).then(|| {
+            let sum: u32 = self.signals.iter().map(|&s| u32::from(s)).sum();
+            (sum / self.signals.len() as u32) as u8
+        });
+        LinkQualityInterval {
+            start: self.start,
+            end,
+            bssid: self.bssid,
+            signal,
+            api: self.api.stats(),
+            gateway: self.gateway.stats(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Samples {
+    sent: u32,
+    rtts_ms: Vec<f64>,
+    /// Differences between consecutive replies of a round.
+    deltas_ms: Vec<f64>,
+}
+
+impl Samples {
+    fn add(&mut self, round: ping::Round) {
+        self.sent += round.sent;
+        self.deltas_ms.extend(
+            round
+                .rtts_ms
+                .windows(2)
+                .map(|pair| (pair[1] - pair[0]).abs()),
+        );
+        self.rtts_ms.extend(round.rtts_ms);
+    }
+
+    /// `None` when the target was never pinged.
+    fn stats(mut self) -> Option<LatencyStats> {
+        if self.sent == 0 {
+            return None;
+        }
+        self.rtts_ms.sort_by(f64::total_cmp);
+        let jitter_ms = (!self.deltas_ms.is_empty())
+            .then(|| self.deltas_ms.iter().sum::<f64>() / self.deltas_ms.len() as f64);
+        Some(LatencyStats {
+            sent: self.sent,
+            received: self.rtts_ms.len() as u32,
+            rtt_p50_ms: percentile(&self.rtts_ms, 50),
+            rtt_p90_ms: percentile(&self.rtts_ms, 90),
+            rtt_p99_ms: percentile(&self.rtts_ms, 99),
+            jitter_ms,
+        })
+    }
+}
+
+/// Nearest-rank percentile of sorted samples.
+fn percentile(sorted: &[f64], p: usize) -> Option<f64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (p * sorted.len()).div_ceil(100).max(1);
+    Some(sorted[rank - 1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sums_up_rounds_into_percentiles_jitter_and_loss() {
+        let mut interval = Interval::new(1_000, Some("AA:BB:CC:DD:EE:FF".into()));
+        interval.signal(Some(70));
+        interval.api.add(ping::Round {
+            sent: 10,
+            rtts_ms: (1..=8).map(|ms| ms as f64 * 10.0).collect(),
+        });
+        interval.signal(Some(61));
+        interval.api.add(ping::Round {
+            sent: 10,
+            rtts_ms: vec![300.0, 100.0],
+        });
+
+        let done = interval.finish(1_900);
+        assert_eq!(done.signal, Some(65));
+        assert_eq!(done.gateway, None);
+        let api = done.api.unwrap();
+        assert_eq!((api.sent, api.received), (20, 10));
+        assert_eq!(api.rtt_p50_ms, Some(50.0));
+        assert_eq!(api.rtt_p90_ms, Some(100.0));
+        assert_eq!(api.rtt_p99_ms, Some(300.0));
+        // Seven steps of 10ms, then 200ms; the gap between rounds isn't one.
+        assert_eq!(api.jitter_ms, Some(270.0 / 8.0));
+    }
+
+    #[test]
+    fn an_unanswered_target_has_loss_but_no_round_trips() {
+        let mut samples = Samples::default();
+        samples.add(ping::Round {
+            sent: 10,
+            rtts_ms: vec![],
+        });
+        let stats = samples.stats().unwrap();
+        assert_eq!((stats.sent, stats.received), (10, 0));
+        assert_eq!(stats.rtt_p50_ms, None);
+        assert_eq!(stats.jitter_ms, None);
+        assert!(Samples::default().stats().is_none());
+    }
+
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        let sorted = [1.0, 2.0, 3.0, 4.0];
+        assert_eq!(percentile(&sorted, 50), Some(2.0));
+        assert_eq!(percentile(&sorted, 90), Some(4.0));
+        assert_eq!(percentile(&[7.0], 99), Some(7.0));
+        assert_eq!(percentile(&[], 50), None);
+    }
+}
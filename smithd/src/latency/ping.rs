@@ -0,0 +1,160 @@
+use crate::commander::network::{execute_nmcli_command, split_terse_line};
+use std::net::{IpAddr, Ipv4Addr};
+use tokio::process::Command;
+use tokio::time::{Duration, timeout};
+
+/// Echo requests per target and round, sent 200ms apart.
+pub(super) const PINGS: u32 = 10;
+
+/// The round trips of one round, in the order sent. A missing reply is lost.
+#[derive(Debug, Default, PartialEq)]
+pub(super) struct Round {
+    pub sent: u32,
+    pub rtts_ms: Vec<f64>,
+}
+
+pub(super) async fn ping(addr: IpAddr) -> Round {
+    let mut cmd = Command::new("ping");
+    cmd.args(["-n", "-c", &PINGS.to_string(), "-i", "0.2", "-W", "1"])
+        .arg(addr.to_string())
+        .kill_on_drop(true);
+    match timeout(Duration::from_secs(15), cmd.output()).await {
+        Ok(Ok(output)) => parse_ping(&String::from_utf8_lossy(&output.stdout)),
+        _ => Round {
+            sent: PINGS,
+            rtts_ms: Vec::new(),
+        },
+    }
+}
+
+/// Reads the replies and the summary of iputils `ping`. Without a summary,
+/// e.g. when the network is unreachable, every request counts as lost.
+fn parse_ping(output: &str) -> Round {
+    let mut round = Round {
+        sent: PINGS,
+        rtts_ms: Vec::new(),
+    };
+    for line in output.lines() {
+        if let Some((_, time)) = line.split_once("time=")
+            && let Some(ms) = time.split_whitespace().next()
+            && let Ok(ms) = ms.parse()
+        {
+            round.rtts_ms.push(ms);
+        } else if let Some((sent, _)) = line.split_once(" packets transmitted")
+            && let Ok(sent) = sent.trim().parse()
+        {
+            round.sent = sent;
+        }
+    }
+    round
+}
+
+/// The gateway of the IPv4 default route with the lowest metric.
+pub(super) async fn default_gateway() -> Option<IpAddr> {
+    let table = tokio::fs::read_to_string("/proc/net/route").await.ok()?;
+    parse_default_gateway(&table).map(IpAddr::V4)
+}
+
+fn parse_default_gateway(table: &str) -> Option<Ipv4Addr> {
+    // Iface Destination Gateway Flags RefCnt Use Metric Mask ...
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 8 || fields[1] != "00000000" || fields[7] != "00000000" {
+                return None;
+            }
+            let gateway = u32::from_str_radix(fields[2], 16).ok()?;
+            let metric: u32 = fields[6].parse().ok()?;
+            // The kernel prints the address in host (little-endian) order.
+            (gateway != 0).then(|| (metric, Ipv4Addr::from(gateway.to_le_bytes())))
+        })
+        .min_by_key(|(metric, _)| *metric)
+        .map(|(_, gateway)| gateway)
+}
+
+/// The access point the device is associated with, and its signal.
+pub(super) async fn wifi_link() -> Option<(String, u8)> {
+    let mut cmd = Command::new("nmcli");
+    cmd.args([
+        "-t",
+        "-f",
+        "IN-USE,BSSID,SIGNAL",
+        "device",
+        "wifi",
+        "list",
+        "--rescan",
+        "no",
+    ]);
+    let output = execute_nmcli_command(cmd).await.ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(parse_wifi_link)
+}
+
+fn parse_wifi_link(line: &str) -> Option<(String, u8)> {
+    let fields = split_terse_line(line, 3);
+    match fields.as_slice() {
+        [in_use, bssid, signal] if in_use == "*" => Some((bssid.clone(), signal.parse().ok()?)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_replies_and_losses() {
+        let output = "PING 10.0.0.1 (10.0.0.1) 56(84) bytes of data.
+64 bytes from 10.0.0.1: icmp_seq=1 ttl=64 time=1.52 ms
+64 bytes from 10.0.0.1: icmp_seq=3 ttl=64 time=12.0 ms
+
+--- 10.0.0.1 ping statistics ---
+3 packets transmitted, 2 received, 33.3333% packet loss, time 402ms
+rtt min/avg/max/mdev = 1.520/6.760/12.000/5.240 ms
+";
+        assert_eq!(
+            parse_ping(output),
+            Round {
+                sent: 3,
+                rtts_ms: vec![1.52, 12.0],
+            }
+        );
+        assert_eq!(
+            parse_ping("ping: connect: Network is unreachable\n"),
+            Round {
+                sent: PINGS,
+                rtts_ms: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn picks_the_preferred_default_gateway() {
+        let table =
+            "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+wwan0\t00000000\t0100000A\t0003\t0\t0\t700\t00000000\t0\t0\t0
+wlan0\t00000000\t0101A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0
+wlan0\t0001A8C0\t00000000\t0001\t0\t0\t600\t00FFFFFF\t0\t0\t0
+";
+        assert_eq!(
+            parse_default_gateway(table),
+            Some(Ipv4Addr::new(192, 168, 1, 1))
+        );
+        assert_eq!(parse_default_gateway(""), None);
+    }
+
+    #[test]
+    fn finds_the_associated_access_point() {
+        assert_eq!(
+            parse_wifi_link(r"*:AA\:BB\:CC\:DD\:EE\:FF:72"),
+            Some(("AA:BB:CC:DD:EE:FF".to_string(), 72))
+        );
+        assert_eq!(parse_wifi_link(r" :11\:22\:33\:44\:55\:66:90"), None);
+    }
+}
@@ -1,17 +1,25 @@
 pub mod auditor;
 pub mod commander;
+pub mod connectivity;
 pub mod control;
 pub mod daemon;
 pub mod downloader;
+pub mod events;
 pub mod filebrowser;
 pub mod filemanager;
+pub mod identity;
+pub mod latency;
 pub mod logstream;
 pub mod magic;
+pub mod modem;
 pub mod nm_watcher;
+pub mod ota;
 pub mod police;
 pub mod postman;
+pub mod secrets;
 pub mod session;
 pub mod shutdown;
 pub mod tunnel;
 pub mod updater;
 pub mod utils;
+pub mod vpn;
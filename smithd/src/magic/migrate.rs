@@ -0,0 +1,111 @@
+//! Upgrades older magic.toml layouts to the one `MagicFile` deserializes.
+//!
+//! Migrations work on the raw TOML table so they can read fields the current
+//! structs no longer have. To change the layout, bump `CURRENT_VERSION` and
+//! append a step to `MIGRATIONS`; never edit a released step.
+
+use anyhow::{Result, anyhow, bail};
+use toml::{Table, Value};
+
+pub const CURRENT_VERSION: i32 = 2;
+
+type Migration = fn(&mut Table) -> Result<()>;
+
+/// `MIGRATIONS[n]` upgrades a version `n + 1` file to version `n + 2`.
+const MIGRATIONS: &[Migration] = &[v1_to_v2];
+
+/// Files written before `magic_version` existed have no key at all and count as
+/// version 1. Their layout is otherwise identical, so the step only stamps the
+/// version (done by `migrate` for every step).
+fn v1_to_v2(_: &mut Table) -> Result<()> {
+    Ok(())
+}
+
+/// Migrates `table` in place to `CURRENT_VERSION`. Returns the version it
+/// started at when anything changed, `None` if it was already current.
+pub fn migrate(table: &mut Table) -> Result<Option<i32>> {
+    let meta = table
+        .get_mut("meta")
+        .ok_or_else(|| anyhow!("missing [meta] table"))?
+        .as_table_mut()
+        .ok_or_else(|| anyhow!("meta: expected a table"))?;
+
+    let version = match meta.get("magic_version") {
+        None => 1,
+        Some(Value::Integer(version)) => i32::try_from(*version)
+            .map_err(|_| anyhow!("meta.magic_version: {version} is out of range"))?,
+        Some(other) => bail!(
+            "meta.magic_version: expected an integer, got {}",
+            other.type_str()
+        ),
+    };
+
+    if version > CURRENT_VERSION {
+        bail!(
+            "meta.magic_version: {version} was written by a newer smithd; this one understands up to {CURRENT_VERSION}"
+        );
+    }
+    if version < 1 {
+        bail!("meta.magic_version: {version} is not a valid version");
+    }
+    if version == CURRENT_VERSION {
+        return Ok(None);
+    }
+
+    for (step, migration) in MIGRATIONS.iter().enumerate().skip(version as usize - 1) {
+        let from = step as i32 + 1;
+        migration(table).map_err(|err| anyhow!("migrating v{from} to v{}: {err}", from + 1))?;
+    }
+
+    if let Some(Value::Table(meta)) = table.get_mut("meta") {
+        meta.insert(
+            "magic_version".to_string(),
+            Value::Integer(CURRENT_VERSION.into()),
+        );
+    }
+
+    Ok(Some(version))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(contents: &str) -> Table {
+        toml::from_str(contents).unwrap()
+    }
+
+    #[test]
+    fn unversioned_file_is_migrated_to_current() {
+        let mut file = table("[meta]\nserver = \"https://api.example.com/smith\"\n");
+
+        assert_eq!(migrate(&mut file).unwrap(), Some(1));
+        assert_eq!(
+            file["meta"]["magic_version"].as_integer(),
+            Some(CURRENT_VERSION.into())
+        );
+    }
+
+    #[test]
+    fn current_file_is_left_alone() {
+        let mut file = table(&format!("[meta]\nmagic_version = {CURRENT_VERSION}\n"));
+
+        assert_eq!(migrate(&mut file).unwrap(), None);
+    }
+
+    #[test]
+    fn newer_file_is_rejected() {
+        let mut file = table(&format!(
+            "[meta]\nmagic_version = {}\n",
+            CURRENT_VERSION + 1
+        ));
+
+        let err = migrate(&mut file).unwrap_err().to_string();
+        assert!(err.contains("newer smithd"), "{err}");
+    }
+
+    #[test]
+    fn every_version_has_a_step() {
+        assert_eq!(MIGRATIONS.len(), CURRENT_VERSION as usize - 1);
+    }
+}
@@ -1,16 +1,42 @@
+mod migrate;
 pub mod structure;
+mod watch;
 
 use crate::shutdown::ShutdownSignals;
+use crate::utils::schema::DownloadMirror;
 use anyhow::Result;
 use std::path::PathBuf;
 use tokio::sync::{mpsc, oneshot};
 use tracing::{debug, error, info, warn};
 
+/// The name the api's own `/download` endpoint goes by when it is not listed.
+pub const API_MIRROR: &str = "api";
+
+fn mirrors(from_api: &[DownloadMirror], configured: Vec<DownloadMirror>) -> Vec<DownloadMirror> {
+    let mut mirrors = if from_api.is_empty() {
+        configured
+    } else {
+        from_api.to_vec()
+    };
+    if !mirrors.iter().any(|m| m.url.is_none()) {
+        mirrors.push(DownloadMirror {
+            name: API_MIRROR.to_string(),
+            url: None,
+        });
+    }
+    mirrors
+}
+
 struct Magic {
     shutdown: ShutdownSignals,
     receiver: mpsc::Receiver<MagicMessage>,
+    /// Handed to the file watcher so it can ask us to reload.
+    sender: mpsc::Sender<MagicMessage>,
     configuration: Option<structure::MagicFile>,
     path: Option<PathBuf>,
+    watching: bool,
+    /// Mirrors the api last sent; they win over the ones in magic.toml.
+    api_mirrors: Vec<DownloadMirror>,
 }
 
 enum MagicMessage {
@@ -24,9 +50,36 @@ enum MagicMessage {
     GetServer {
         sender: oneshot::Sender<String>,
     },
+    GetControlPolicy {
+        rpc: oneshot::Sender<structure::ConfigControl>,
+    },
+    GetMirrors {
+        rpc: oneshot::Sender<Vec<DownloadMirror>>,
+    },
+    SetApiMirrors {
+        mirrors: Vec<DownloadMirror>,
+    },
+    GetOta {
+        rpc: oneshot::Sender<Option<structure::ConfigOta>>,
+    },
+    GetCache {
+        rpc: oneshot::Sender<structure::ConfigCache>,
+    },
+    GetAudit {
+        rpc: oneshot::Sender<structure::ConfigAudit>,
+    },
+    GetModem {
+        rpc: oneshot::Sender<structure::ConfigModem>,
+    },
+    GetConnectivity {
+        rpc: oneshot::Sender<structure::ConfigConnectivity>,
+    },
     GetReleaseId {
         rpc: oneshot::Sender<Option<i32>>,
     },
+    GetPreviousReleaseId {
+        rpc: oneshot::Sender<Option<i32>>,
+    },
     SetReleaseId {
         release_id: i32,
     },
@@ -42,15 +95,44 @@ enum MagicMessage {
     SetToken {
         token: Option<String>,
     },
+    /// magic.toml changed on disk.
+    Reload,
 }
 
 impl Magic {
-    fn new(shutdown: ShutdownSignals, receiver: mpsc::Receiver<MagicMessage>) -> Self {
+    fn new(
+        shutdown: ShutdownSignals,
+        receiver: mpsc::Receiver<MagicMessage>,
+        sender: mpsc::Sender<MagicMessage>,
+    ) -> Self {
         Self {
             shutdown,
             receiver,
+            sender,
             configuration: None,
             path: None,
+            watching: false,
+            api_mirrors: Vec::new(),
+        }
+    }
+
+    fn reload(&mut self) {
+        let (Some(path), Some(conf)) = (&self.path, &mut self.configuration) else {
+            return;
+        };
+
+        match structure::MagicFile::load_from_path(&path.to_string_lossy()) {
+            Ok((new, _)) => {
+                let changed = conf.reload_from(new);
+                if changed.is_empty() {
+                    debug!("magic.toml changed, no reloadable settings differ");
+                } else {
+                    info!("Reloaded magic.toml: {}", changed.join(", "));
+                }
+            }
+            Err(err) => {
+                error!("Ignoring magic.toml change, keeping the running configuration: {err:#}");
+            }
         }
     }
     async fn handle_message(&mut self, msg: MagicMessage) {
@@ -60,9 +142,20 @@ impl Magic {
                     Ok((conf, path)) => {
                         self.configuration = Some(conf);
                         self.path = path;
+
+                        if let Some(path) = &self.path
+                            && !self.watching
+                        {
+                            self.watching = true;
+                            tokio::spawn(watch::watch(
+                                self.shutdown.clone(),
+                                path.clone(),
+                                self.sender.clone(),
+                            ));
+                        }
                     }
                     Err(err) => {
-                        error!("Failed to load Magic from file: {:?}", err);
+                        error!("Failed to load Magic from file: {err:#}");
                     }
                 }
                 signal.send(()).unwrap();
@@ -84,6 +177,77 @@ impl Magic {
                     _ = sender.send("https://api.smith.teton.ai/smith".to_string());
                 }
             }
+            MagicMessage::GetControlPolicy { rpc } => {
+                let policy = self
+                    .configuration
+                    .as_ref()
+                    .map(|conf| conf.get_control_policy())
+                    .unwrap_or_default();
+                _ = rpc.send(policy);
+            }
+            MagicMessage::GetMirrors { rpc } => {
+                let configured = self
+                    .configuration
+                    .as_ref()
+                    .map(|conf| conf.get_mirrors())
+                    .unwrap_or_default();
+                _ = rpc.send(mirrors(&self.api_mirrors, configured));
+            }
+            MagicMessage::SetApiMirrors { mirrors } => {
+                if mirrors != self.api_mirrors {
+                    info!(
+                        "Download mirrors from the api: {}",
+                        mirrors
+                            .iter()
+                            .map(|m| m.name.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    );
+                    self.api_mirrors = mirrors;
+                }
+            }
+            MagicMessage::GetOta { rpc } => {
+                _ = rpc.send(self.configuration.as_ref().and_then(|conf| conf.get_ota()));
+            }
+            MagicMessage::GetCache { rpc } => {
+                _ = rpc.send(
+                    self.configuration
+                        .as_ref()
+                        .map(|conf| conf.get_cache())
+                        .unwrap_or_default(),
+                );
+            }
+            MagicMessage::GetAudit { rpc } => {
+                _ = rpc.send(
+                    self.configuration
+                        .as_ref()
+                        .map(|conf| conf.get_audit())
+                        .unwrap_or_default(),
+                );
+            }
+            MagicMessage::GetModem { rpc } => {
+                _ = rpc.send(
+                    self.configuration
+                        .as_ref()
+                        .map(|conf| conf.get_modem())
+                        .unwrap_or_default(),
+                );
+            }
+            MagicMessage::GetConnectivity { rpc } => {
+                _ = rpc.send(
+                    self.configuration
+                        .as_ref()
+                        .map(|conf| conf.get_connectivity())
+                        .unwrap_or_default(),
+                );
+            }
+            MagicMessage::GetPreviousReleaseId { rpc } => {
+                _ = rpc.send(
+                    self.configuration
+                        .as_ref()
+                        .and_then(|conf| conf.get_previous_release_id()),
+                );
+            }
             MagicMessage::GetReleaseId { rpc } => {
                 debug!("Getting Magic Release Id");
                 if let Some(conf) = &self.configuration {
@@ -161,6 +325,7 @@ impl Magic {
                     }
                 }
             }
+            MagicMessage::Reload => self.reload(),
         }
     }
 
@@ -188,7 +353,7 @@ pub struct MagicHandle {
 impl MagicHandle {
     pub fn new(shutdown: ShutdownSignals) -> Self {
         let (sender, receiver) = mpsc::channel(8);
-        let mut actor = Magic::new(shutdown, receiver);
+        let mut actor = Magic::new(shutdown, receiver, sender.clone());
         tokio::spawn(async move { actor.run().await });
 
         Self { sender }
@@ -241,6 +406,77 @@ impl MagicHandle {
         receiver.await.unwrap()
     }
 
+    /// The running control socket policy. Read per request, so edits to
+    /// magic.toml apply without a restart.
+    pub async fn get_control_policy(&self) -> structure::ConfigControl {
+        let (rpc, receiver) = oneshot::channel();
+        let msg = MagicMessage::GetControlPolicy { rpc };
+        _ = self.sender.send(msg).await;
+        receiver.await.unwrap_or_default()
+    }
+
+    /// Where downloads may come from, most preferred first. The api's own list
+    /// wins over magic.toml's, and the api itself is always tried last if
+    /// neither lists it.
+    pub async fn get_mirrors(&self) -> Vec<DownloadMirror> {
+        let (rpc, receiver) = oneshot::channel();
+        let msg = MagicMessage::GetMirrors { rpc };
+        _ = self.sender.send(msg).await;
+        receiver.await.unwrap_or_else(|_| mirrors(&[], Vec::new()))
+    }
+
+    pub async fn set_api_mirrors(&self, mirrors: Vec<DownloadMirror>) {
+        let msg = MagicMessage::SetApiMirrors { mirrors };
+        _ = self.sender.send(msg).await;
+    }
+
+    /// The OS update backend, read once at startup.
+    pub async fn get_ota(&self) -> Option<structure::ConfigOta> {
+        let (rpc, receiver) = oneshot::channel();
+        let msg = MagicMessage::GetOta { rpc };
+        _ = self.sender.send(msg).await;
+        receiver.await.unwrap_or_default()
+    }
+
+    /// The package cache limits, which follow magic.toml reloads.
+    pub async fn get_cache(&self) -> structure::ConfigCache {
+        let (rpc, receiver) = oneshot::channel();
+        let msg = MagicMessage::GetCache { rpc };
+        _ = self.sender.send(msg).await;
+        receiver.await.unwrap_or_default()
+    }
+
+    /// The audit profile, which follows magic.toml reloads.
+    pub async fn get_audit(&self) -> structure::ConfigAudit {
+        let (rpc, receiver) = oneshot::channel();
+        let msg = MagicMessage::GetAudit { rpc };
+        _ = self.sender.send(msg).await;
+        receiver.await.unwrap_or_default()
+    }
+
+    /// How the modem is watched, which follows magic.toml reloads.
+    pub async fn get_modem(&self) -> structure::ConfigModem {
+        let (rpc, receiver) = oneshot::channel();
+        let msg = MagicMessage::GetModem { rpc };
+        _ = self.sender.send(msg).await;
+        receiver.await.unwrap_or_default()
+    }
+
+    /// How connectivity is probed, which follows magic.toml reloads.
+    pub async fn get_connectivity(&self) -> structure::ConfigConnectivity {
+        let (rpc, receiver) = oneshot::channel();
+        let msg = MagicMessage::GetConnectivity { rpc };
+        _ = self.sender.send(msg).await;
+        receiver.await.unwrap_or_default()
+    }
+
+    pub async fn get_previous_release_id(&self) -> Option<i32> {
+        let (rpc, receiver) = oneshot::channel();
+        let msg = MagicMessage::GetPreviousReleaseId { rpc };
+        _ = self.sender.send(msg).await;
+        receiver.await.unwrap_or_default()
+    }
+
     pub async fn get_token(&self) -> Option<String> {
         let (rpc, receiver) = oneshot::channel();
         let msg = MagicMessage::GetToken { rpc };
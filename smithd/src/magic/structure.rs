@@ -1,9 +1,12 @@
-use anyhow::{Context, Result};
+use super::migrate;
+use crate::utils::schema::DownloadMirror;
+use anyhow::{Context, Result, anyhow};
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::path::PathBuf;
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt; // for write_all()
-use tracing::info;
+use tracing::{info, warn};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct MagicFile {
@@ -11,6 +14,20 @@ pub struct MagicFile {
     pub tunnel: Option<ConfigTunnel>,
     #[serde(rename = "metric")]
     pub metrics: Option<Vec<ConfigMetric>>,
+    pub control: Option<ConfigControl>,
+    /// Download mirrors in order of preference, unless the api sends its own.
+    #[serde(rename = "mirror")]
+    pub mirrors: Option<Vec<DownloadMirror>>,
+    /// How the OS is updated. Without it, NVIDIA's Jetson OTA tools are used.
+    pub ota: Option<ConfigOta>,
+    /// Limits of the package cache.
+    pub cache: Option<ConfigCache>,
+    /// Tunes the compliance audit to what the device is for.
+    pub audit: Option<ConfigAudit>,
+    /// How the cellular modem is watched.
+    pub modem: Option<ConfigModem>,
+    /// How upstream connectivity is probed.
+    pub connectivity: Option<ConfigConnectivity>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -19,16 +36,222 @@ pub struct ConfigMeta {
     pub server: String,
     pub release_id: Option<i32>,
     pub target_release_id: Option<i32>,
+    /// The release before `release_id`, kept cached to roll back to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_release_id: Option<i32>,
     pub token: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+/// The package cache keeps the blobs of the current, target and previous
+/// releases and evicts the least recently used others above `quota_mb`.
+/// Downloads that would leave less than `reserve_mb` free are refused.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ConfigCache {
+    #[serde(default = "ConfigCache::default_quota_mb")]
+    pub quota_mb: u64,
+    #[serde(default = "ConfigCache::default_reserve_mb")]
+    pub reserve_mb: u64,
+}
+
+impl ConfigCache {
+    fn default_quota_mb() -> u64 {
+        4096
+    }
+
+    fn default_reserve_mb() -> u64 {
+        1024
+    }
+
+    pub fn quota_bytes(&self) -> u64 {
+        self.quota_mb.saturating_mul(1024 * 1024)
+    }
+
+    pub fn reserve_bytes(&self) -> u64 {
+        self.reserve_mb.saturating_mul(1024 * 1024)
+    }
+}
+
+/// The audit profile: which checks run and what they accept.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ConfigAudit {
+    /// Ports that may listen on addresses other than loopback.
+    #[serde(default = "ConfigAudit::default_allowed_ports")]
+    pub allowed_ports: Vec<u16>,
+    /// The oldest kernel that passes, e.g. `5.15`. Any passes without one.
+    pub min_kernel: Option<String>,
+    /// Ids of checks not to run, e.g. `secure_boot` on boards without UEFI.
+    #[serde(default)]
+    pub skip: Vec<String>,
+    /// Files and directories whose contents, owners and modes are watched for
+    /// changes against a baseline.
+    #[serde(default = "ConfigAudit::default_integrity_paths")]
+    pub integrity_paths: Vec<PathBuf>,
+}
+
+impl ConfigAudit {
+    fn default_allowed_ports() -> Vec<u16> {
+        vec![22]
+    }
+
+    fn default_integrity_paths() -> Vec<PathBuf> {
+        [
+            "/etc/sudoers",
+            "/etc/sudoers.d",
+            "/etc/ssh/sshd_config",
+            "/etc/ssh/sshd_config.d",
+        ]
+        .into_iter()
+        .map(PathBuf::from)
+        .collect()
+    }
+}
+
+impl Default for ConfigAudit {
+    fn default() -> Self {
+        Self {
+            allowed_ports: Self::default_allowed_ports(),
+            min_kernel: None,
+            skip: Vec::new(),
+            integrity_paths: Self::default_integrity_paths(),
+        }
+    }
+}
+
+/// The modem's signal is sampled every `sample_secs` and reported every
+/// `report_minutes`. A modem registered without data for `watchdog_minutes`
+/// is power-cycled; 0 turns the watchdog off.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ConfigModem {
+    #[serde(default = "ConfigModem::default_sample_secs")]
+    pub sample_secs: u64,
+    #[serde(default = "ConfigModem::default_report_minutes")]
+    pub report_minutes: u64,
+    #[serde(default = "ConfigModem::default_watchdog_minutes")]
+    pub watchdog_minutes: u64,
+}
+
+impl ConfigModem {
+    fn default_sample_secs() -> u64 {
+        60
+    }
+
+    fn default_report_minutes() -> u64 {
+        15
+    }
+
+    fn default_watchdog_minutes() -> u64 {
+        10
+    }
+}
+
+impl Default for ConfigModem {
+    fn default() -> Self {
+        Self {
+            sample_secs: Self::default_sample_secs(),
+            report_minutes: Self::default_report_minutes(),
+            watchdog_minutes: Self::default_watchdog_minutes(),
+        }
+    }
+}
+
+/// Upstream connectivity is probed every `interval_secs` the way
+/// NetworkManager does: `check_url` is resolved and fetched, and only a
+/// response with `expected_body` counts as full connectivity. The URL has to
+/// be plain http, or a captive portal could not answer it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ConfigConnectivity {
+    #[serde(default = "ConfigConnectivity::default_check_url")]
+    pub check_url: String,
+    #[serde(default = "ConfigConnectivity::default_expected_body")]
+    pub expected_body: String,
+    #[serde(default = "ConfigConnectivity::default_interval_secs")]
+    pub interval_secs: u64,
+}
+
+impl ConfigConnectivity {
+    fn default_check_url() -> String {
+        "http://nmcheck.gnome.org/check_network_status.txt".to_string()
+    }
+
+    fn default_expected_body() -> String {
+        "NetworkManager is online".to_string()
+    }
+
+    fn default_interval_secs() -> u64 {
+        60
+    }
+}
+
+impl Default for ConfigConnectivity {
+    fn default() -> Self {
+        Self {
+            check_url: Self::default_check_url(),
+            expected_body: Self::default_expected_body(),
+            interval_secs: Self::default_interval_secs(),
+        }
+    }
+}
+
+impl Default for ConfigCache {
+    fn default() -> Self {
+        Self {
+            quota_mb: Self::default_quota_mb(),
+            reserve_mb: Self::default_reserve_mb(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct ConfigMetric {
     pub log_only: bool,
     pub name: String,
     pub cmd: String,
 }
 
+/// Which non-root callers may use which routes on the local control socket.
+/// Root is always allowed; anyone else is denied unless a rule grants them.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ConfigControl {
+    #[serde(default, rename = "allow")]
+    pub rules: Vec<ConfigControlRule>,
+}
+
+/// Grants `routes` (exact paths, or `"*"` for all) to callers matching every
+/// selector the rule sets. `gids` also matches supplementary groups.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ConfigControlRule {
+    pub routes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub uids: Vec<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub gids: Vec<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exes: Vec<PathBuf>,
+}
+
+/// The backend `DownloadOTA` and `StartOTA` update the OS with.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "backend", rename_all = "snake_case")]
+pub enum ConfigOta {
+    /// NVIDIA's L4T OTA tools, `nv_ota_start.sh`.
+    Jetson,
+    /// A whole root filesystem image written to whichever slot is not running.
+    AbImage(ConfigAbImage),
+}
+
+/// Two root filesystem slots booted through GRUB. The new slot is booted once
+/// with `grub-reboot` and only made the default after it came up healthy, so a
+/// bad image falls back to the old slot on the next reboot.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ConfigAbImage {
+    /// Block devices of the slots, e.g. `/dev/disk/by-partlabel/root_a`.
+    pub slot_a: PathBuf,
+    pub slot_b: PathBuf,
+    /// GRUB menu entries booting each slot.
+    pub grub_entry_a: String,
+    pub grub_entry_b: String,
+}
+
 #[derive(Serialize, Deserialize, Default, Debug, Hash, Eq, PartialEq, Clone)]
 pub struct ConfigPackage {
     pub name: String,
@@ -37,8 +260,71 @@ pub struct ConfigPackage {
 }
 
 impl ConfigPackage {
+    /// Parses a release manifest, one `name\tversion\tfile` line per package.
+    /// Manifests written before AppImages, whose names may contain spaces,
+    /// were separated by single spaces and are still read.
+    pub fn parse_manifest(content: &str) -> Result<Vec<Self>> {
+        content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let mut parts: Box<dyn Iterator<Item = &str>> = if line.contains('\t') {
+                    Box::new(line.splitn(3, '\t'))
+                } else {
+                    Box::new(line.splitn(3, ' '))
+                };
+                Ok(ConfigPackage {
+                    name: parts
+                        .next()
+                        .ok_or_else(|| anyhow!("missing name"))?
+                        .to_string(),
+                    version: parts
+                        .next()
+                        .ok_or_else(|| anyhow!("missing version"))?
+                        .to_string(),
+                    file: parts
+                        .next()
+                        .ok_or_else(|| anyhow!("missing file"))?
+                        .to_string(),
+                })
+            })
+            .collect()
+    }
+
+    /// This package's line in a release manifest, see [`Self::parse_manifest`].
+    pub fn manifest_line(&self) -> String {
+        format!("{}\t{}\t{}", self.name, self.version, self.file)
+    }
+
+    pub fn is_appimage(&self) -> bool {
+        crate::updater::appimage::is_appimage(&self.file)
+    }
+
+    /// Container images have no blob; `file` is the image reference to pull.
+    pub fn is_container_image(&self) -> bool {
+        crate::utils::schema::is_container_image(&self.file)
+    }
+
     /// Returns the dpkg status flags and installed version, e.g. `("ii", "1.2.3")`.
+    /// An installed AppImage, or a container image the runtime has pulled,
+    /// reports `ii` like a fully configured deb.
     pub async fn get_system_state(&self) -> Result<(String, String)> {
+        if self.is_container_image() {
+            let runtime = crate::updater::containers::Runtime::detect()
+                .ok_or_else(|| anyhow!("no container runtime socket found"))?;
+            if !runtime.has_image(&self.file).await? {
+                return Err(anyhow!("{} has not been pulled", self.file));
+            }
+            return Ok(("ii".to_string(), self.version.clone()));
+        }
+
+        if self.is_appimage() {
+            let version = crate::updater::appimage::AppImages::default()
+                .installed_version(&self.name)
+                .await?;
+            return Ok(("ii".to_string(), version));
+        }
+
         let name = &self.name;
         let output = tokio::process::Command::new("dpkg")
             .arg("-l")
@@ -65,7 +351,7 @@ impl ConfigPackage {
         Ok(version)
     }
 }
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct ConfigTunnel {
     pub server: String,
     pub secret: String,
@@ -98,10 +384,11 @@ impl MagicFile {
             info!("Found no magic.toml, creating a default one");
             let string = toml::to_string_pretty(&Self {
                 meta: ConfigMeta {
-                    magic_version: 2,
+                    magic_version: migrate::CURRENT_VERSION,
                     server: "http://api:8080/smith".to_string(),
                     release_id: None,
                     target_release_id: None,
+                    previous_release_id: None,
                     token: None,
                 },
                 tunnel: Some(ConfigTunnel {
@@ -109,6 +396,13 @@ impl MagicFile {
                     secret: "".to_string(),
                 }),
                 metrics: None,
+                control: None,
+                mirrors: None,
+                ota: None,
+                cache: None,
+                audit: None,
+                modem: None,
+                connectivity: None,
             })?;
             std::fs::write(magic_in_cwd, string)?;
             Self::load_from_path(magic_in_cwd.to_str().unwrap())
@@ -125,13 +419,258 @@ impl MagicFile {
     }
 
     pub fn load_from_path(location: &str) -> Result<(Self, Option<PathBuf>)> {
-        let contents = std::fs::read_to_string(location).unwrap();
-        let magic_file: MagicFile = toml::from_str(&contents)
-            .with_context(|| format!("Failed to parse magic file: {}", location))?;
+        let contents = std::fs::read_to_string(location)
+            .with_context(|| format!("Failed to read magic file: {}", location))?;
+        let (magic_file, migrated_from) =
+            Self::parse(&contents).with_context(|| format!("Invalid magic file: {}", location))?;
+
+        if let Some(from) = migrated_from {
+            // Keep the original next to it; a bad migration must be recoverable
+            // by hand on a device nobody can reach.
+            let backup = format!("{location}.v{from}.bak");
+            let written = std::fs::copy(location, &backup)
+                .context("backing up")
+                .and_then(|_| Ok(toml::to_string_pretty(&magic_file)?))
+                .and_then(|string| Ok(std::fs::write(location, string)?));
+            match written {
+                Ok(()) => info!(
+                    "Migrated {} from v{} to v{} (original kept at {})",
+                    location,
+                    from,
+                    migrate::CURRENT_VERSION,
+                    backup
+                ),
+                Err(err) => warn!("Migrated {location} in memory but failed to save it: {err:#}"),
+            }
+        }
 
         Ok((magic_file, Some(PathBuf::from(location))))
     }
 
+    /// Parses, migrates and validates a magic file without touching disk.
+    /// Returns the version it was migrated from, if it was not current.
+    pub fn parse(contents: &str) -> Result<(Self, Option<i32>)> {
+        let mut table: toml::Table = toml::from_str(contents)?;
+        let migrated_from = migrate::migrate(&mut table)?;
+        let magic_file: MagicFile = table.try_into()?;
+        magic_file.validate()?;
+        Ok((magic_file, migrated_from))
+    }
+
+    /// Checks the values serde can't, reporting every problem at once so a
+    /// broken file can be fixed in one pass.
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+
+        match url::Url::parse(&self.meta.server) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+            _ => problems.push(format!(
+                "meta.server: expected an http(s) URL, got {:?}",
+                self.meta.server
+            )),
+        }
+
+        if let Some(tunnel) = &self.tunnel
+            && tunnel.server.trim().is_empty()
+        {
+            problems.push("tunnel.server: must not be empty".to_string());
+        }
+
+        let mut names = HashSet::new();
+        for (index, metric) in self.metrics.iter().flatten().enumerate() {
+            if metric.name.trim().is_empty() {
+                problems.push(format!("metric[{index}].name: must not be empty"));
+            } else if !names.insert(metric.name.as_str()) {
+                problems.push(format!(
+                    "metric[{index}].name: {:?} is used more than once",
+                    metric.name
+                ));
+            }
+            if metric.cmd.trim().is_empty() {
+                problems.push(format!("metric[{index}].cmd: must not be empty"));
+            }
+        }
+
+        for (index, rule) in self.control.iter().flat_map(|c| &c.rules).enumerate() {
+            if rule.routes.is_empty() {
+                problems.push(format!("control.allow[{index}].routes: must not be empty"));
+            }
+            for route in &rule.routes {
+                if route != "*" && !route.starts_with('/') {
+                    problems.push(format!(
+                        "control.allow[{index}].routes: {route:?} is not a path or \"*\""
+                    ));
+                }
+            }
+            // A rule without selectors would hand its routes to every caller
+            // that can reach the socket; that has to be spelled out per uid.
+            if rule.uids.is_empty() && rule.gids.is_empty() && rule.exes.is_empty() {
+                problems.push(format!(
+                    "control.allow[{index}]: set at least one of uids, gids or exes"
+                ));
+            }
+            for exe in &rule.exes {
+                if !exe.is_absolute() {
+                    problems.push(format!(
+                        "control.allow[{index}].exes: {} is not an absolute path",
+                        exe.display()
+                    ));
+                }
+            }
+        }
+
+        let mut names = HashSet::new();
+        for (index, mirror) in self.mirrors.iter().flatten().enumerate() {
+            if mirror.name.trim().is_empty() {
+                problems.push(format!("mirror[{index}].name: must not be empty"));
+            } else if !names.insert(mirror.name.as_str()) {
+                problems.push(format!(
+                    "mirror[{index}].name: {:?} is used more than once",
+                    mirror.name
+                ));
+            }
+            if let Some(url) = &mirror.url {
+                match url::Url::parse(url) {
+                    Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+                    _ => problems.push(format!(
+                        "mirror[{index}].url: expected an http(s) URL, got {url:?}"
+                    )),
+                }
+            }
+        }
+
+        if let Some(ConfigOta::AbImage(ab)) = &self.ota {
+            for (key, slot) in [("slot_a", &ab.slot_a), ("slot_b", &ab.slot_b)] {
+                if !slot.is_absolute() {
+                    problems.push(format!(
+                        "ota.{key}: {} is not an absolute path",
+                        slot.display()
+                    ));
+                }
+            }
+            if ab.slot_a == ab.slot_b {
+                problems.push("ota.slot_b: must differ from slot_a".to_string());
+            }
+            for (key, entry) in [
+                ("grub_entry_a", &ab.grub_entry_a),
+                ("grub_entry_b", &ab.grub_entry_b),
+            ] {
+                if entry.trim().is_empty() {
+                    problems.push(format!("ota.{key}: must not be empty"));
+                }
+            }
+        }
+
+        if let Some(cache) = &self.cache
+            && cache.quota_mb == 0
+        {
+            problems.push("cache.quota_mb: must be greater than 0".to_string());
+        }
+
+        if let Some(audit) = &self.audit {
+            if let Some(min_kernel) = &audit.min_kernel
+                && crate::auditor::parse_kernel_version(min_kernel).is_none()
+            {
+                problems.push(format!(
+                    "audit.min_kernel: expected a version like \"5.15\", got {min_kernel:?}"
+                ));
+            }
+            for id in &audit.skip {
+                if !crate::auditor::is_check(id) {
+                    problems.push(format!("audit.skip: {id:?} is not a check"));
+                }
+            }
+            for path in &audit.integrity_paths {
+                if !path.is_absolute() {
+                    problems.push(format!(
+                        "audit.integrity_paths: {} is not an absolute path",
+                        path.display()
+                    ));
+                }
+            }
+        }
+
+        if let Some(modem) = &self.modem {
+            if modem.sample_secs == 0 {
+                problems.push("modem.sample_secs: must be greater than 0".to_string());
+            }
+            if modem.report_minutes == 0 {
+                problems.push("modem.report_minutes: must be greater than 0".to_string());
+            }
+        }
+
+        if let Some(connectivity) = &self.connectivity {
+            match url::Url::parse(&connectivity.check_url) {
+                Ok(url) if url.scheme() == "http" && url.host_str().is_some() => {}
+                _ => problems.push(format!(
+                    "connectivity.check_url: expected an http URL, got {:?}",
+                    connectivity.check_url
+                )),
+            }
+            if connectivity.interval_secs == 0 {
+                problems.push("connectivity.interval_secs: must be greater than 0".to_string());
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!(problems.join("; ")))
+        }
+    }
+
+    /// Takes the runtime-reloadable settings from `new`. Identity (server and
+    /// token) and the release ids the daemon itself maintains are kept; a
+    /// changed server or token only takes effect after a restart. Returns the
+    /// names of the sections that changed.
+    pub fn reload_from(&mut self, new: MagicFile) -> Vec<&'static str> {
+        if new.meta.server != self.meta.server {
+            warn!("meta.server changed in magic.toml; restart smithd to apply it");
+        }
+        if new.meta.token != self.meta.token {
+            warn!("meta.token changed in magic.toml; restart smithd to apply it");
+        }
+        // Switching backends halfway through an update would strand it.
+        if new.ota != self.ota {
+            warn!("ota changed in magic.toml; restart smithd to apply it");
+        }
+
+        let mut changed = Vec::new();
+        if new.tunnel != self.tunnel {
+            self.tunnel = new.tunnel;
+            changed.push("tunnel");
+        }
+        if new.metrics != self.metrics {
+            self.metrics = new.metrics;
+            changed.push("metric");
+        }
+        if new.control != self.control {
+            self.control = new.control;
+            changed.push("control");
+        }
+        if new.mirrors != self.mirrors {
+            self.mirrors = new.mirrors;
+            changed.push("mirror");
+        }
+        if new.cache != self.cache {
+            self.cache = new.cache;
+            changed.push("cache");
+        }
+        if new.audit != self.audit {
+            self.audit = new.audit;
+            changed.push("audit");
+        }
+        if new.modem != self.modem {
+            self.modem = new.modem;
+            changed.push("modem");
+        }
+        if new.connectivity != self.connectivity {
+            self.connectivity = new.connectivity;
+            changed.push("connectivity");
+        }
+        changed
+    }
+
     pub async fn write_to_file(&self, path: &str) -> Result<()> {
         let string = toml::to_string_pretty(&self)?;
         let mut file = File::create(path).await?;
@@ -147,6 +686,34 @@ impl MagicFile {
         }
     }
 
+    pub fn get_control_policy(&self) -> ConfigControl {
+        self.control.clone().unwrap_or_default()
+    }
+
+    pub fn get_mirrors(&self) -> Vec<DownloadMirror> {
+        self.mirrors.clone().unwrap_or_default()
+    }
+
+    pub fn get_ota(&self) -> Option<ConfigOta> {
+        self.ota.clone()
+    }
+
+    pub fn get_cache(&self) -> ConfigCache {
+        self.cache.clone().unwrap_or_default()
+    }
+
+    pub fn get_audit(&self) -> ConfigAudit {
+        self.audit.clone().unwrap_or_default()
+    }
+
+    pub fn get_modem(&self) -> ConfigModem {
+        self.modem.clone().unwrap_or_default()
+    }
+
+    pub fn get_connectivity(&self) -> ConfigConnectivity {
+        self.connectivity.clone().unwrap_or_default()
+    }
+
     pub fn get_server(&self) -> String {
         self.meta.server.clone()
     }
@@ -157,9 +724,18 @@ impl MagicFile {
 
     pub fn set_release_id(&mut self, release_id: i32) {
         info!("Setting release id: {}", release_id);
+        if let Some(current) = self.meta.release_id
+            && current != release_id
+        {
+            self.meta.previous_release_id = Some(current);
+        }
         self.meta.release_id = Some(release_id);
     }
 
+    pub fn get_previous_release_id(&self) -> Option<i32> {
+        self.meta.previous_release_id
+    }
+
     pub fn get_target_release_id(&self) -> Option<i32> {
         self.meta.target_release_id
     }
@@ -197,4 +773,151 @@ mod tests {
         // test that we can load the default magic file
         super::MagicFile::autoload().unwrap();
     }
+
+    #[test]
+    fn validation_reports_every_problem() {
+        let err = super::MagicFile::parse(
+            r#"
+[meta]
+magic_version = 2
+server = "api.example.com"
+
+[tunnel]
+server = ""
+secret = ""
+
+[[metric]]
+log_only = true
+name = "uptime"
+cmd = "uptime"
+
+[[metric]]
+log_only = true
+name = "uptime"
+cmd = ""
+
+[[control.allow]]
+routes = ["watchdog"]
+
+[[mirror]]
+name = "cache"
+url = "ftp://cache.local/packages"
+
+[[mirror]]
+name = "cache"
+
+[ota]
+backend = "ab_image"
+slot_a = "/dev/sda2"
+slot_b = "sda3"
+grub_entry_a = "root a"
+grub_entry_b = ""
+
+[cache]
+quota_mb = 0
+
+[audit]
+min_kernel = "latest"
+skip = ["secure_boot", "telnet"]
+integrity_paths = ["/etc/sudoers", "etc/hosts"]
+
+[modem]
+report_minutes = 0
+
+[connectivity]
+check_url = "https://nmcheck.gnome.org/check_network_status.txt"
+"#,
+        )
+        .unwrap_err()
+        .to_string();
+
+        assert!(err.contains("meta.server"), "{err}");
+        assert!(err.contains("tunnel.server"), "{err}");
+        assert!(err.contains("metric[1].name"), "{err}");
+        assert!(err.contains("metric[1].cmd"), "{err}");
+        assert!(err.contains("control.allow[0].routes"), "{err}");
+        assert!(err.contains("control.allow[0]: set at least one"), "{err}");
+        assert!(err.contains("mirror[0].url"), "{err}");
+        assert!(err.contains("mirror[1].name"), "{err}");
+        assert!(err.contains("ota.slot_b"), "{err}");
+        assert!(err.contains("ota.grub_entry_b"), "{err}");
+        assert!(err.contains("cache.quota_mb"), "{err}");
+        assert!(err.contains("audit.min_kernel"), "{err}");
+        assert!(err.contains("audit.skip: \"telnet\""), "{err}");
+        assert!(!err.contains("\"secure_boot\""), "{err}");
+        assert!(err.contains("audit.integrity_paths: etc/hosts"), "{err}");
+        assert!(!err.contains("/etc/sudoers"), "{err}");
+        assert!(err.contains("modem.report_minutes"), "{err}");
+        assert!(!err.contains("modem.sample_secs"), "{err}");
+        assert!(err.contains("connectivity.check_url"), "{err}");
+        assert!(!err.contains("connectivity.interval_secs"), "{err}");
+    }
+
+    #[test]
+    fn reload_keeps_identity() {
+        let (mut running, _) = super::MagicFile::parse(
+            "[meta]\nmagic_version = 2\nserver = \"https://a.example.com/smith\"\ntoken = \"t1\"\n",
+        )
+        .unwrap();
+        let (edited, _) = super::MagicFile::parse(
+            "[meta]\nmagic_version = 2\nserver = \"https://b.example.com/smith\"\n\n[tunnel]\nserver = \"bore.example.com\"\nsecret = \"\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(running.reload_from(edited), vec!["tunnel"]);
+        assert_eq!(running.get_server(), "https://a.example.com/smith");
+        assert_eq!(running.get_token().as_deref(), Some("t1"));
+        assert_eq!(running.get_tunnel_details().server, "bore.example.com");
+    }
+
+    #[test]
+    fn release_changes_remember_the_previous_release() {
+        let (mut magic, _) = super::MagicFile::parse(
+            "[meta]\nmagic_version = 2\nserver = \"https://a.example.com\"\n",
+        )
+        .unwrap();
+        assert_eq!(magic.get_cache(), super::ConfigCache::default());
+
+        magic.set_release_id(1);
+        assert_eq!(magic.get_previous_release_id(), None);
+        magic.set_release_id(2);
+        magic.set_release_id(2);
+        assert_eq!(magic.get_previous_release_id(), Some(1));
+        assert!(
+            toml::to_string(&magic)
+                .unwrap()
+                .contains("previous_release_id = 1")
+        );
+    }
+
+    #[test]
+    fn manifests_keep_names_with_spaces() {
+        let packages = [
+            super::ConfigPackage {
+                name: "Teton Craft Table".into(),
+                version: "0.1.0".into(),
+                file: "Teton Craft Table_0.1.0_amd64.AppImage".into(),
+            },
+            super::ConfigPackage {
+                name: "smith".into(),
+                version: "1.2.3".into(),
+                file: "smith_1.2.3_arm64.deb".into(),
+            },
+        ];
+        let manifest: String = packages
+            .iter()
+            .map(|package| package.manifest_line() + "\n")
+            .collect();
+        assert_eq!(
+            super::ConfigPackage::parse_manifest(&manifest).unwrap(),
+            packages
+        );
+        assert!(packages[0].is_appimage());
+        assert!(!packages[1].is_appimage());
+
+        // Manifests cached before the switch to tabs.
+        let legacy =
+            super::ConfigPackage::parse_manifest("smith 1.2.3 smith_1.2.3_arm64.deb\n").unwrap();
+        assert_eq!(legacy, packages[1..]);
+    }
 }
@@ -0,0 +1,80 @@
+use super::MagicMessage;
+use crate::shutdown::ShutdownSignals;
+use futures_util::StreamExt;
+use inotify::{Inotify, WatchMask};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{debug, error, info};
+
+/// Editors write in several steps; wait for them to finish before reloading.
+const SETTLE: Duration = Duration::from_millis(250);
+
+/// Asks the magic actor to reload whenever `path` is written or replaced.
+///
+/// Watches the parent directory rather than the file: editors and the migration
+/// in `load_from_path` replace the file, which would silently end a watch on the
+/// old inode.
+pub(super) async fn watch(
+    shutdown: ShutdownSignals,
+    path: PathBuf,
+    sender: mpsc::Sender<MagicMessage>,
+) {
+    let Some(name) = path.file_name().map(|name| name.to_owned()) else {
+        error!("Not watching {}: no file name", path.display());
+        return;
+    };
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+
+    let inotify = match Inotify::init() {
+        Ok(inotify) => inotify,
+        Err(err) => {
+            error!("Failed to init inotify, magic.toml will not hot reload: {err}");
+            return;
+        }
+    };
+    if let Err(err) = inotify
+        .watches()
+        .add(&dir, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO)
+    {
+        error!("Failed to watch {}: {err}", dir.display());
+        return;
+    }
+    let mut events = match inotify.into_event_stream([0u8; 4096]) {
+        Ok(events) => events,
+        Err(err) => {
+            error!("Failed to read inotify events: {err}");
+            return;
+        }
+    };
+
+    info!("Watching {} for changes", path.display());
+
+    loop {
+        tokio::select! {
+            event = events.next() => {
+                match event {
+                    Some(Ok(event)) if event.name.as_deref() == Some(name.as_os_str()) => {
+                        debug!("{} changed", path.display());
+                        tokio::time::sleep(SETTLE).await;
+                        if sender.send(MagicMessage::Reload).await.is_err() {
+                            break;
+                        }
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(err)) => {
+                        error!("inotify error, magic.toml will no longer hot reload: {err}");
+                        break;
+                    }
+                    None => break,
+                }
+            }
+            _ = shutdown.token.cancelled() => {
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,527 @@
+//! ModemManager over D-Bus.
+//!
+//! Only the first modem ModemManager lists is used; devices have one. Where
+//! NetworkManager manages the modem, the APN goes to its gsm profile instead,
+//! as NetworkManager reconnects with the APN stored there.
+use crate::commander::network::{execute_nmcli_command, split_terse_line};
+use crate::utils::schema::{ModemRegistration, ModemSignalSample, ModemState, ModemStatus};
+use anyhow::{Context, Result, anyhow};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::process::Command;
+use tracing::{info, warn};
+use zbus::Connection;
+use zbus::fdo::ObjectManagerProxy;
+use zbus::proxy::CacheProperties;
+use zbus::zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value};
+
+const SERVICE: &str = "org.freedesktop.ModemManager1";
+const MANAGER_PATH: &str = "/org/freedesktop/ModemManager1";
+const MODEM_INTERFACE: &str = "org.freedesktop.ModemManager1.Modem";
+
+const POWER_STATE_LOW: u32 = 2;
+const POWER_STATE_ON: u32 = 3;
+const POWER_CYCLE_PAUSE: Duration = Duration::from_secs(5);
+
+/// How often the modem refreshes the extended signal values.
+const SIGNAL_RATE_SECS: u32 = 30;
+
+const GSM_TYPE: &str = "gsm";
+
+#[zbus::proxy(
+    interface = "org.freedesktop.ModemManager1.Modem",
+    default_service = "org.freedesktop.ModemManager1"
+)]
+trait Modem {
+    fn enable(&self, enable: bool) -> zbus::Result<()>;
+
+    fn set_power_state(&self, state: u32) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn state(&self) -> zbus::Result<i32>;
+
+    #[zbus(property)]
+    fn manufacturer(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn model(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn equipment_identifier(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn access_technologies(&self) -> zbus::Result<u32>;
+
+    #[zbus(property)]
+    fn signal_quality(&self) -> zbus::Result<(u32, bool)>;
+
+    #[zbus(property)]
+    fn sim(&self) -> zbus::Result<OwnedObjectPath>;
+
+    #[zbus(property)]
+    fn bearers(&self) -> zbus::Result<Vec<OwnedObjectPath>>;
+}
+
+#[zbus::proxy(
+    interface = "org.freedesktop.ModemManager1.Modem.Modem3gpp",
+    default_service = "org.freedesktop.ModemManager1"
+)]
+trait Modem3gpp {
+    fn set_initial_eps_bearer_settings(
+        &self,
+        settings: HashMap<&str, &Value<'_>>,
+    ) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn registration_state(&self) -> zbus::Result<u32>;
+
+    #[zbus(property)]
+    fn operator_name(&self) -> zbus::Result<String>;
+}
+
+#[zbus::proxy(
+    interface = "org.freedesktop.ModemManager1.Modem.Simple",
+    default_service = "org.freedesktop.ModemManager1"
+)]
+trait Simple {
+    fn connect(&self, properties: HashMap<&str, &Value<'_>>) -> zbus::Result<OwnedObjectPath>;
+
+    fn disconnect(&self, bearer: &ObjectPath<'_>) -> zbus::Result<()>;
+}
+
+#[zbus::proxy(
+    interface = "org.freedesktop.ModemManager1.Modem.Signal",
+    default_service = "org.freedesktop.ModemManager1"
+)]
+trait Signal {
+    fn setup(&self, rate: u32) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn rate(&self) -> zbus::Result<u32>;
+
+    #[zbus(property)]
+    fn lte(&self) -> zbus::Result<HashMap<String, OwnedValue>>;
+}
+
+#[zbus::proxy(
+    interface = "org.freedesktop.ModemManager1.Sim",
+    default_service = "org.freedesktop.ModemManager1"
+)]
+trait Sim {
+    #[zbus(property)]
+    fn sim_identifier(&self) -> zbus::Result<String>;
+}
+
+#[zbus::proxy(
+    interface = "org.freedesktop.ModemManager1.Bearer",
+    default_service = "org.freedesktop.ModemManager1"
+)]
+trait Bearer {
+    #[zbus(property)]
+    fn connected(&self) -> zbus::Result<bool>;
+
+    #[zbus(property, name = "Properties")]
+    fn settings(&self) -> zbus::Result<HashMap<String, OwnedValue>>;
+
+    #[zbus(property)]
+    fn stats(&self) -> zbus::Result<HashMap<String, OwnedValue>>;
+}
+
+/// Builds a proxy for the object at `path`, reading properties fresh on
+/// every call.
+macro_rules! proxy {
+    ($proxy:ident, $conn:expr, $path:expr) => {
+        $proxy::builder($conn)
+            .path($path)?
+            .cache_properties(CacheProperties::No)
+            .build()
+            .await?
+    };
+}
+
+/// What the modem commands ask of it.
+pub enum ModemAction {
+    SetApn(String),
+    SetEnabled(bool),
+    PowerCycle,
+}
+
+/// Carries out `action` on the modem and reads it back.
+pub async fn apply(action: ModemAction) -> Result<ModemStatus> {
+    let conn = Connection::system()
+        .await
+        .context("connecting to the system bus")?;
+    let path = find_modem(&conn)
+        .await?
+        .ok_or_else(|| anyhow!("ModemManager has no modem"))?;
+    match action {
+        ModemAction::SetApn(apn) => set_apn(&conn, &path, &apn).await?,
+        ModemAction::SetEnabled(enabled) => {
+            let modem = proxy!(ModemProxy, &conn, &path);
+            modem
+                .enable(enabled)
+                .await
+                .with_context(|| format!("{} the modem", enable_verb(enabled)))?;
+            info!("Modem {}d", enable_verb(enabled));
+        }
+        ModemAction::PowerCycle => power_cycle(&conn, &path).await?,
+    }
+    read_modem(&conn, &path).await
+}
+
+fn enable_verb(enabled: bool) -> &'static str {
+    if enabled { "enable" } else { "disable" }
+}
+
+/// The modem as it is now, `None` if ModemManager has none.
+pub async fn read_status(conn: &Connection) -> Result<Option<ModemStatus>> {
+    match find_modem(conn).await? {
+        Some(path) => read_modem(conn, &path).await.map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Power-cycles the first modem, if there is one.
+pub async fn power_cycle_first(conn: &Connection) -> Result<()> {
+    match find_modem(conn).await? {
+        Some(path) => power_cycle(conn, &path).await,
+        None => Ok(()),
+    }
+}
+
+async fn find_modem(conn: &Connection) -> Result<Option<OwnedObjectPath>> {
+    let manager = ObjectManagerProxy::builder(conn)
+        .destination(SERVICE)?
+        .path(MANAGER_PATH)?
+        .build()
+        .await?;
+    let objects = manager
+        .get_managed_objects()
+        .await
+        .context("listing ModemManager's modems")?;
+    Ok(objects
+        .into_iter()
+        .filter(|(_, interfaces)| {
+            interfaces
+                .keys()
+                .any(|name| name.as_str() == MODEM_INTERFACE)
+        })
+        .map(|(path, _)| path)
+        .min_by(|a, b| a.as_str().cmp(b.as_str())))
+}
+
+async fn read_modem(conn: &Connection, path: &OwnedObjectPath) -> Result<ModemStatus> {
+    let modem = proxy!(ModemProxy, conn, path);
+    let access_technology = access_technology_name(modem.access_technologies().await?);
+    let (quality, _recent) = modem.signal_quality().await?;
+    let mut status = ModemStatus {
+        manufacturer: non_empty(modem.manufacturer().await.ok()),
+        model: non_empty(modem.model().await.ok()),
+        imei: non_empty(modem.equipment_identifier().await.ok()),
+        state: modem_state(modem.state().await?),
+        access_technology: access_technology.clone(),
+        ..Default::default()
+    };
+
+    // Absent on CDMA-only modems.
+    if let Ok(threegpp) = Modem3gppProxy::builder(conn)
+        .path(path)?
+        .cache_properties(CacheProperties::No)
+        .build()
+        .await
+        && let Ok(state) = threegpp.registration_state().await
+    {
+        status.registration = registration(state);
+        status.operator = non_empty(threegpp.operator_name().await.ok());
+    }
+
+    let sim = modem.sim().await?;
+    if sim.as_str() != "/" {
+        let sim = proxy!(SimProxy, conn, &sim);
+        status.iccid = non_empty(sim.sim_identifier().await.ok());
+    }
+
+    for bearer in modem.bearers().await? {
+        let bearer = proxy!(BearerProxy, conn, &bearer);
+        if !bearer.connected().await.unwrap_or(false) {
+            continue;
+        }
+        status.data_connected = true;
+        if let Ok(settings) = bearer.settings().await {
+            status.apn = non_empty(settings.get("apn").and_then(string));
+        }
+        if let Ok(stats) = bearer.stats().await {
+            status.rx_bytes += stats.get("rx-bytes").and_then(u64_value).unwrap_or(0);
+            status.tx_bytes += stats.get("tx-bytes").and_then(u64_value).unwrap_or(0);
+        }
+    }
+
+    let mut sample = ModemSignalSample {
+        at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        quality: quality.min(100) as u8,
+        access_technology,
+        rssi: None,
+        rsrp: None,
+        rsrq: None,
+        snr: None,
+    };
+    if let Ok(signal) = SignalProxy::builder(conn)
+        .path(path)?
+        .cache_properties(CacheProperties::No)
+        .build()
+        .await
+    {
+        // The extended values stay empty until polling is set up, which the
+        // modem forgets when it restarts.
+        if signal.rate().await.unwrap_or(0) == 0
+            && let Err(err) = signal.setup(SIGNAL_RATE_SECS).await
+        {
+            warn!("Failed to set up modem signal polling: {err}");
+        }
+        if let Ok(lte) = signal.lte().await {
+            let value = |key: &str| lte.get(key).and_then(f64_value);
+            sample.rssi = value("rssi");
+            sample.rsrp = value("rsrp");
+            sample.rsrq = value("rsrq");
+            sample.snr = value("snr");
+        }
+    }
+    status.signal.push(sample);
+
+    Ok(status)
+}
+
+async fn set_apn(conn: &Connection, path: &OwnedObjectPath, apn: &str) -> Result<()> {
+    let value = Value::from(apn);
+    let settings = HashMap::from([("apn", &value)]);
+
+    // The initial bearer is what the modem attaches to LTE with. Not every
+    // modem has one, and the data bearer below takes the APN regardless.
+    let threegpp = proxy!(Modem3gppProxy, conn, path);
+    if let Err(err) = threegpp
+        .set_initial_eps_bearer_settings(settings.clone())
+        .await
+    {
+        warn!("Modem did not take the initial bearer APN: {err}");
+    }
+
+    match gsm_profile().await {
+        Ok(Some(profile)) => return set_profile_apn(&profile, apn).await,
+        Ok(None) => info!("NetworkManager has no gsm profile, connecting through ModemManager"),
+        Err(err) => {
+            info!("Connecting through ModemManager, NetworkManager is unavailable: {err:#}")
+        }
+    }
+
+    let simple = proxy!(SimpleProxy, conn, path);
+    // `/` disconnects every bearer. It fails when none is connected.
+    if let Err(err) = simple
+        .disconnect(&ObjectPath::from_static_str_unchecked("/"))
+        .await
+    {
+        info!("No modem bearer disconnected: {err}");
+    }
+    simple
+        .connect(settings)
+        .await
+        .with_context(|| format!("connecting with APN {apn}"))?;
+    info!("Modem connected with APN {apn}");
+    Ok(())
+}
+
+/// The NetworkManager profile of the modem, the active one if there are several.
+async fn gsm_profile() -> Result<Option<String>> {
+    let mut cmd = Command::new("nmcli");
+    cmd.args(["-t", "-f", "NAME,TYPE,DEVICE", "connection", "show"]);
+    let output = execute_nmcli_command(cmd).await?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow!("nmcli connection show failed: {}", stderr.trim()));
+    }
+    Ok(pick_gsm_profile(&String::from_utf8_lossy(&output.stdout)))
+}
+
+// A non-empty, non-"--" DEVICE means the profile is active, as in the network
+// commands, since ACTIVE is always "no" on some NetworkManager versions.
+fn pick_gsm_profile(listing: &str) -> Option<String> {
+    let profiles: Vec<(String, bool)> = listing
+        .lines()
+        .filter_map(|line| {
+            let parts = split_terse_line(line, 3);
+            (parts.len() == 3 && parts[1] == GSM_TYPE)
+                .then(|| (parts[0].clone(), !parts[2].is_empty() && parts[2] != "--"))
+        })
+        .collect();
+    profiles
+        .iter()
+        .find(|(_, active)| *active)
+        .or(profiles.first())
+        .map(|(name, _)| name.clone())
+}
+
+/// Stores `apn` in `profile` and activates it again, which reconnects the
+/// modem with it.
+async fn set_profile_apn(profile: &str, apn: &str) -> Result<()> {
+    let steps: [&[&str]; 2] = [
+        &["connection", "modify", profile, "gsm.apn", apn],
+        &["connection", "up", profile],
+    ];
+    for args in steps {
+        let mut cmd = Command::new("nmcli");
+        cmd.args(args);
+        let output = execute_nmcli_command(cmd).await?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(anyhow!(
+                "nmcli {} failed: {}",
+                args.join(" "),
+                stderr.trim()
+            ));
+        }
+    }
+    info!("Modem profile {profile} connected with APN {apn}");
+    Ok(())
+}
+
+async fn power_cycle(conn: &Connection, path: &OwnedObjectPath) -> Result<()> {
+    let modem = proxy!(ModemProxy, conn, path);
+    modem.enable(false).await.context("disabling the modem")?;
+    modem
+        .set_power_state(POWER_STATE_LOW)
+        .await
+        .context("powering the modem down")?;
+    tokio::time::sleep(POWER_CYCLE_PAUSE).await;
+    modem
+        .set_power_state(POWER_STATE_ON)
+        .await
+        .context("powering the modem up")?;
+    modem.enable(true).await.context("enabling the modem")?;
+    info!("Modem power-cycled");
+    Ok(())
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.filter(|value| !value.trim().is_empty())
+}
+
+fn string(value: &OwnedValue) -> Option<String> {
+    <&str>::try_from(value).ok().map(str::to_owned)
+}
+
+fn u64_value(value: &OwnedValue) -> Option<u64> {
+    u64::try_from(value).ok()
+}
+
+fn f64_value(value: &OwnedValue) -> Option<f64> {
+    f64::try_from(value).ok()
+}
+
+/// `MMModemState`.
+fn modem_state(state: i32) -> ModemState {
+    match state {
+        -1 => ModemState::Failed,
+        1 => ModemState::Initializing,
+        2 => ModemState::Locked,
+        3 => ModemState::Disabled,
+        4 => ModemState::Disabling,
+        5 => ModemState::Enabling,
+        6 => ModemState::Enabled,
+        7 => ModemState::Searching,
+        8 => ModemState::Registered,
+        9 => ModemState::Disconnecting,
+        10 => ModemState::Connecting,
+        11 => ModemState::Connected,
+        _ => ModemState::Unknown,
+    }
+}
+
+/// `MMModem3gppRegistrationState`. The SMS-only and CSFB variants still
+/// count as registered on that network.
+fn registration(state: u32) -> ModemRegistration {
+    match state {
+        0 => ModemRegistration::Idle,
+        1 | 6 | 9 => ModemRegistration::Home,
+        2 => ModemRegistration::Searching,
+        3 => ModemRegistration::Denied,
+        5 | 7 | 10 => ModemRegistration::Roaming,
+        8 => ModemRegistration::EmergencyOnly,
+        _ => ModemRegistration::Unknown,
+    }
+}
+
+/// The most capable of the `MMModemAccessTechnology` flags, named as mmcli
+/// names it.
+fn access_technology_name(flags: u32) -> Option<String> {
+    const NAMES: [&str; 18] = [
+        "pots",
+        "gsm",
+        "gsm-compact",
+        "gprs",
+        "edge",
+        "umts",
+        "hsdpa",
+        "hsupa",
+        "hspa",
+        "hspa-plus",
+        "1xrtt",
+        "evdo0",
+        "evdoa",
+        "evdob",
+        "lte",
+        "5gnr",
+        "lte-cat-m",
+        "lte-nb-iot",
+    ];
+    // Cat-M and NB-IoT come after plain LTE, which they are flagged with, so
+    // they win as the more specific answer.
+    (0..NAMES.len())
+        .rev()
+        .find(|bit| flags & (1 << bit) != 0)
+        .map(|bit| NAMES[bit].to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modem_manager_enums_map_to_the_schema() {
+        assert_eq!(modem_state(-1), ModemState::Failed);
+        assert_eq!(modem_state(8), ModemState::Registered);
+        assert_eq!(modem_state(11), ModemState::Connected);
+        assert_eq!(modem_state(42), ModemState::Unknown);
+
+        assert_eq!(registration(1), ModemRegistration::Home);
+        assert_eq!(registration(9), ModemRegistration::Home);
+        assert_eq!(registration(7), ModemRegistration::Roaming);
+        assert_eq!(registration(3), ModemRegistration::Denied);
+        assert_eq!(registration(11), ModemRegistration::Unknown);
+        assert!(registration(10).is_registered());
+        assert!(!registration(2).is_registered());
+    }
+
+    #[test]
+    fn the_active_gsm_profile_takes_the_apn() {
+        let listing = "Wired:802-3-ethernet:eth0\nlte-backup:gsm:--\nlte:gsm:cdc-wdm0\n";
+        assert_eq!(pick_gsm_profile(listing).as_deref(), Some("lte"));
+        assert_eq!(pick_gsm_profile("lte\\:1:gsm:\n").as_deref(), Some("lte:1"));
+        assert_eq!(pick_gsm_profile("Wired:802-3-ethernet:eth0\n"), None);
+    }
+
+    #[test]
+    fn access_technology_names_the_most_capable() {
+        assert_eq!(access_technology_name(0), None);
+        assert_eq!(access_technology_name(1 << 14).as_deref(), Some("lte"));
+        assert_eq!(
+            access_technology_name((1 << 5) | (1 << 8)).as_deref(),
+            Some("hspa")
+        );
+        assert_eq!(
+            access_technology_name((1 << 14) | (1 << 16)).as_deref(),
+            Some("lte-cat-m")
+        );
+    }
+}
@@ -0,0 +1,150 @@
+//! Watches the cellular modem through ModemManager: samples its signal,
+//! reports it with the registration and data counters, and power-cycles it
+//! when it stays registered without data.
+use crate::commander::CommanderHandle;
+use crate::magic::MagicHandle;
+use crate::shutdown::ShutdownSignals;
+use crate::utils::schema::{
+    ModemRegistration, ModemSignalSample, ModemStatus, SafeCommandResponse, SafeCommandRx,
+};
+use tokio::time::{Duration, Instant, sleep};
+use tracing::{info, warn};
+use watchdog::Watchdog;
+use zbus::Connection;
+
+mod dbus;
+mod watchdog;
+
+pub use dbus::{ModemAction, apply};
+
+const MODEM_RESULT_ID: i32 = -12;
+
+/// Samples kept while reports are not sent, about half a day at the default
+/// rate.
+const MAX_SAMPLES: usize = 720;
+
+pub struct ModemHandle;
+
+impl ModemHandle {
+    pub fn new(shutdown: ShutdownSignals, commander: CommanderHandle, magic: MagicHandle) -> Self {
+        tokio::spawn(async move {
+            Monitor::default().run(shutdown, commander, magic).await;
+        });
+        Self
+    }
+}
+
+#[derive(Default)]
+struct Monitor {
+    conn: Option<Connection>,
+    watchdog: Watchdog,
+    samples: Vec<ModemSignalSample>,
+    /// The registration last reported, `None` while there is no modem.
+    reported: Option<ModemRegistration>,
+    reported_at: Option<Instant>,
+    /// Whether ModemManager was unreachable on the last sample.
+    unavailable: bool,
+}
+
+impl Monitor {
+    async fn run(
+        &mut self,
+        shutdown: ShutdownSignals,
+        commander: CommanderHandle,
+        magic: MagicHandle,
+    ) {
+        info!("Modem monitor starting");
+        loop {
+            let config = magic.get_modem().await;
+            if let Some(report) = self
+                .sample(
+                    Duration::from_secs(config.report_minutes * 60),
+                    Duration::from_secs(config.watchdog_minutes * 60),
+                )
+                .await
+            {
+                commander
+                    .insert_result(vec![SafeCommandResponse {
+                        id: MODEM_RESULT_ID,
+                        command: SafeCommandRx::ModemStatus { modem: report },
+                        status: 0,
+                    }])
+                    .await;
+            }
+
+            tokio::select! {
+                _ = shutdown.token.cancelled() => break,
+                _ = sleep(Duration::from_secs(config.sample_secs)) => {}
+            }
+        }
+        info!("Modem monitor shutting down");
+    }
+
+    async fn connection(&mut self) -> zbus::Result<Connection> {
+        if let Some(conn) = &self.conn {
+            return Ok(conn.clone());
+        }
+        let conn = Connection::system().await?;
+        self.conn = Some(conn.clone());
+        Ok(conn)
+    }
+
+    /// Reads the modem and returns what to report, if anything is due.
+    async fn sample(
+        &mut self,
+        report_every: Duration,
+        watchdog_window: Duration,
+    ) -> Option<Option<ModemStatus>> {
+        let read = match self.connection().await {
+            Ok(conn) => dbus::read_status(&conn).await,
+            Err(err) => Err(err.into()),
+        };
+        let mut status = match read {
+            Ok(Some(status)) => status,
+            Ok(None) => {
+                self.unavailable = false;
+                self.samples.clear();
+                // Only worth reporting if there was a modem before.
+                return self.reported.take().map(|_| None);
+            }
+            Err(err) => {
+                // Most devices have no ModemManager at all.
+                if !self.unavailable {
+                    info!("ModemManager is unavailable: {err:#}");
+                    self.unavailable = true;
+                }
+                self.conn = None;
+                return None;
+            }
+        };
+        self.unavailable = false;
+
+        self.samples.append(&mut status.signal);
+        let excess = self.samples.len().saturating_sub(MAX_SAMPLES);
+        self.samples.drain(..excess);
+
+        if self
+            .watchdog
+            .observe(&status, watchdog_window, Instant::now())
+        {
+            warn!("Modem stays registered without data, power-cycling it");
+            if let Some(conn) = &self.conn
+                && let Err(err) = dbus::power_cycle_first(conn).await
+            {
+                warn!("Failed to power-cycle the modem: {err:#}");
+            }
+        }
+
+        let due = self
+            .reported_at
+            .is_none_or(|at| at.elapsed() >= report_every);
+        if !due && self.reported == Some(status.registration) {
+            return None;
+        }
+        self.reported = Some(status.registration);
+        self.reported_at = Some(Instant::now());
+        status.signal = std::mem::take(&mut self.samples);
+        status.watchdog_power_cycles = self.watchdog.cycles;
+        Some(Some(status))
+    }
+}
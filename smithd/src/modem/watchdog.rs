@@ -0,0 +1,94 @@
+use crate::utils::schema::ModemStatus;
+use tokio::time::{Duration, Instant};
+
+/// Each power cycle that does not bring data back doubles the wait before
+/// the next, up to this many doublings, so a SIM without a data plan is not
+/// cycled every few minutes forever.
+const MAX_DOUBLINGS: u32 = 3;
+
+/// Decides when a modem that is registered but has no data is stuck.
+#[derive(Default)]
+pub(super) struct Watchdog {
+    stalled_since: Option<Instant>,
+    failed_cycles: u32,
+    /// Power cycles since smithd started.
+    pub cycles: u32,
+}
+
+impl Watchdog {
+    /// Whether to power-cycle the modem now. A zero `window` turns the
+    /// watchdog off.
+    pub fn observe(&mut self, status: &ModemStatus, window: Duration, now: Instant) -> bool {
+        if status.data_connected {
+            self.failed_cycles = 0;
+        }
+        if window.is_zero() || status.data_connected || !status.registration.is_registered() {
+            self.stalled_since = None;
+            return false;
+        }
+
+        let since = *self.stalled_since.get_or_insert(now);
+        let wait = window * 2u32.pow(self.failed_cycles.min(MAX_DOUBLINGS));
+        if now.duration_since(since) < wait {
+            return false;
+        }
+        self.stalled_since = None;
+        self.failed_cycles += 1;
+        self.cycles += 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::schema::ModemRegistration;
+
+    fn modem(registration: ModemRegistration, data_connected: bool) -> ModemStatus {
+        ModemStatus {
+            registration,
+            data_connected,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn cycles_a_modem_registered_without_data_with_backoff() {
+        let window = Duration::from_secs(600);
+        let start = Instant::now();
+        let at = |minutes: u64| start + Duration::from_secs(minutes * 60);
+        let stuck = modem(ModemRegistration::Home, false);
+        let mut watchdog = Watchdog::default();
+
+        assert!(!watchdog.observe(&stuck, window, at(0)));
+        assert!(!watchdog.observe(&stuck, window, at(9)));
+        assert!(watchdog.observe(&stuck, window, at(10)));
+
+        // Still no data after the cycle: the next one waits twice as long.
+        assert!(!watchdog.observe(&stuck, window, at(11)));
+        assert!(!watchdog.observe(&stuck, window, at(30)));
+        assert!(watchdog.observe(&stuck, window, at(31)));
+        assert_eq!(watchdog.cycles, 2);
+
+        // Data came back, so the backoff starts over.
+        assert!(!watchdog.observe(&modem(ModemRegistration::Home, true), window, at(32)));
+        assert!(!watchdog.observe(&stuck, window, at(33)));
+        assert!(watchdog.observe(&stuck, window, at(43)));
+    }
+
+    #[test]
+    fn leaves_unregistered_modems_and_a_disabled_watchdog_alone() {
+        let start = Instant::now();
+        let later = start + Duration::from_secs(24 * 60 * 60);
+        let mut watchdog = Watchdog::default();
+
+        let searching = modem(ModemRegistration::Searching, false);
+        assert!(!watchdog.observe(&searching, Duration::from_secs(60), start));
+        assert!(!watchdog.observe(&searching, Duration::from_secs(60), later));
+
+        let roaming = modem(ModemRegistration::Roaming, false);
+        assert!(!watchdog.observe(&roaming, Duration::ZERO, start));
+        assert!(!watchdog.observe(&roaming, Duration::ZERO, later));
+        assert_eq!(watchdog.cycles, 0);
+    }
+}
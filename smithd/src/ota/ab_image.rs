@@ -0,0 +1,451 @@
+//! A/B root filesystem images, for devices without a vendor OTA mechanism.
+//!
+//! The payload is a raw filesystem image, optionally gzipped, written to the
+//! slot that is not running. GRUB boots the new slot once; only once it has
+//! come up healthy and on the expected version is it made the default, so an
+//! image that does not boot falls back to the old slot on the next reboot.
+//!
+//! The image carries no device state, so the daemon's state directory, with
+//! its identity, token and the OTA record, is copied into the new slot before
+//! booting it. Otherwise the new slot could neither report in nor confirm.
+use super::backend::{Download, OsUpdate};
+use super::state::{OTA_STATE_PATH, Record, Request};
+use crate::magic::structure::ConfigAbImage;
+use crate::utils::schema::OtaPhase;
+use anyhow::{Context, anyhow};
+use flate2::read::GzDecoder;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+use tokio::time::{self, Duration};
+use tracing::{info, warn};
+
+const IMAGE_PATH: &str = "/ota/os_image.img";
+const OS_RELEASE: &str = "/etc/os-release";
+
+/// Where the daemon keeps its identity, token and state.
+const STATE_DIR: &str = "/etc/smith";
+/// Entries of the state directory that are caches, fetched again as needed.
+const CACHES: [&str; 1] = ["packages"];
+/// Where the new slot is mounted while the state is copied into it.
+const TARGET_MOUNT: &str = "/run/smith/ota-target";
+
+/// How long the system may take to finish booting before it counts as unhealthy.
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Slot {
+    A,
+    B,
+}
+
+impl Slot {
+    fn name(self) -> &'static str {
+        match self {
+            Slot::A => "a",
+            Slot::B => "b",
+        }
+    }
+
+    fn other(self) -> Slot {
+        match self {
+            Slot::A => Slot::B,
+            Slot::B => Slot::A,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AbImage {
+    config: ConfigAbImage,
+}
+
+impl AbImage {
+    pub fn new(config: ConfigAbImage) -> Self {
+        Self { config }
+    }
+
+    fn device(&self, slot: Slot) -> &Path {
+        match slot {
+            Slot::A => &self.config.slot_a,
+            Slot::B => &self.config.slot_b,
+        }
+    }
+
+    fn grub_entry(&self, slot: Slot) -> &str {
+        match slot {
+            Slot::A => &self.config.grub_entry_a,
+            Slot::B => &self.config.grub_entry_b,
+        }
+    }
+
+    /// The slot `/` is mounted from.
+    async fn running_slot(&self) -> anyhow::Result<Slot> {
+        let source = run("findmnt", &["-n", "-o", "SOURCE", "/"]).await?;
+        slot_of(
+            Path::new(source.trim()),
+            &self.config.slot_a,
+            &self.config.slot_b,
+        )
+    }
+
+    /// Copies the state directory into `target`, with `record` as the OTA
+    /// record it finds on its first boot.
+    async fn carry_state(&self, target: Slot, record: &Record) -> anyhow::Result<()> {
+        tokio::fs::create_dir_all(TARGET_MOUNT)
+            .await
+            .with_context(|| format!("creating {TARGET_MOUNT}"))?;
+        let device = self.device(target).to_string_lossy().into_owned();
+        run("mount", &[&device, TARGET_MOUNT]).await?;
+
+        let to = Path::new(TARGET_MOUNT).join(STATE_DIR.trim_start_matches('/'));
+        let record = record.clone();
+        let copied =
+            tokio::task::spawn_blocking(move || copy_state(Path::new(STATE_DIR), &to, &record))
+                .await;
+        let unmounted = run("umount", &[TARGET_MOUNT]).await;
+        copied??;
+        unmounted?;
+        Ok(())
+    }
+}
+
+impl OsUpdate for AbImage {
+    fn downloads(&self, request: &Request) -> Vec<Download> {
+        vec![Download {
+            remote: format!("assets/ota/{}", request.payload),
+            local: image_path(request),
+        }]
+    }
+
+    async fn running_version(&self) -> Option<String> {
+        let contents = tokio::fs::read_to_string(OS_RELEASE).await.ok()?;
+        parse_os_release_version(&contents)
+    }
+
+    async fn apply(&self, record: &mut Record) -> anyhow::Result<()> {
+        let request = record
+            .request
+            .as_ref()
+            .ok_or_else(|| anyhow!("no OTA was requested"))?;
+        let image = PathBuf::from(image_path(request));
+        let target = self.running_slot().await?.other();
+        let device = self.device(target).to_owned();
+
+        info!(
+            "Writing {} to slot {} ({})",
+            image.display(),
+            target.name(),
+            device.display()
+        );
+        let written = tokio::task::spawn_blocking(move || write_image(&image, &device)).await??;
+        info!("Wrote {written} bytes to slot {}", target.name());
+
+        record.state.slot = Some(target.name().to_owned());
+        // As the actor persists it once this returns, ready to be confirmed.
+        let mut carried = record.clone();
+        carried.enter(OtaPhase::Rebooting);
+        self.carry_state(target, &carried)
+            .await
+            .with_context(|| format!("copying {STATE_DIR} to slot {}", target.name()))?;
+
+        run("grub-reboot", &[self.grub_entry(target)]).await?;
+        Ok(())
+    }
+
+    async fn confirm(&self, record: &mut Record) {
+        let running = match self.running_slot().await {
+            Ok(slot) => slot,
+            Err(err) => {
+                record.fail(format!("{err:#}"));
+                return;
+            }
+        };
+
+        // `--wait` returns once boot has finished, and exits non-zero for
+        // anything but `running`, which the output says anyway.
+        let status = time::timeout(
+            HEALTH_TIMEOUT,
+            Command::new("systemctl")
+                .args(["is-system-running", "--wait"])
+                .output(),
+        )
+        .await;
+        let status = match status {
+            Ok(Ok(output)) => String::from_utf8_lossy(&output.stdout).trim().to_owned(),
+            Ok(Err(err)) => format!("unknown ({err})"),
+            Err(_) => "still starting".to_owned(),
+        };
+        let version = self.running_version().await;
+        if !settle(record, running, &status, version) {
+            return;
+        }
+        if status == "degraded" {
+            let failed = run("systemctl", &["--failed", "--no-legend", "--plain"])
+                .await
+                .unwrap_or_default();
+            warn!(
+                "Slot {} booted degraded, keeping it: {}",
+                running.name(),
+                failed.trim()
+            );
+        }
+
+        if let Err(err) = run("grub-set-default", &[self.grub_entry(running)]).await {
+            record.fail(format!("{err:#}"));
+        }
+    }
+}
+
+/// Settles `record` on the first boot after writing an image, given the slot
+/// that came up, its `is-system-running` status and the OS version it runs.
+/// Returns whether the slot is to be made the default.
+fn settle(record: &mut Record, running: Slot, status: &str, version: Option<String>) -> bool {
+    if let Some(target) = &record.state.slot
+        && target != running.name()
+    {
+        record.fail(format!(
+            "booted slot {}, expected {target}; the new image did not come up",
+            running.name()
+        ));
+        return false;
+    }
+    if let Err(err) = boot_verdict(status) {
+        record.fail(format!(
+            "{err} on slot {}; not making it the default",
+            running.name()
+        ));
+        return false;
+    }
+    record.after_boot(version);
+    record.state.error.is_none()
+}
+
+/// Whether a slot that finished booting in `status`, as `is-system-running`
+/// says it, is healthy enough to keep. `degraded` only means some unit failed,
+/// often one unrelated to the OS image, and the daemon evidently runs; rolling
+/// the OS back for it would take every other fix in the image with it.
+fn boot_verdict(status: &str) -> Result<(), String> {
+    match status {
+        "running" | "degraded" => Ok(()),
+        status => Err(format!("system is {status}")),
+    }
+}
+
+/// Where the image is kept, keeping a `.gz` so it is decompressed on writing.
+fn image_path(request: &Request) -> String {
+    if request.payload.ends_with(".gz") {
+        format!("{IMAGE_PATH}.gz")
+    } else {
+        IMAGE_PATH.to_owned()
+    }
+}
+
+async fn run(program: &str, args: &[&str]) -> anyhow::Result<String> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .await
+        .with_context(|| format!("running {program}"))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{program} failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Which slot `source` is, resolving symlinks such as `/dev/disk/by-partlabel`.
+fn slot_of(source: &Path, slot_a: &Path, slot_b: &Path) -> anyhow::Result<Slot> {
+    let resolve = |path: &Path| std::fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+    let source = resolve(source);
+    if source == resolve(slot_a) {
+        Ok(Slot::A)
+    } else if source == resolve(slot_b) {
+        Ok(Slot::B)
+    } else {
+        Err(anyhow!(
+            "/ is mounted from {}, which is neither slot",
+            source.display()
+        ))
+    }
+}
+
+/// Copies the state directory `from` to `to`, leaving out caches, and writes
+/// `record` as the OTA record there.
+fn copy_state(from: &Path, to: &Path, record: &Record) -> anyhow::Result<()> {
+    copy_dir(from, to, &CACHES)?;
+    let contents = serde_json::to_string(record).context("serializing OTA state")?;
+    let path = to.join(Path::new(OTA_STATE_PATH).file_name().unwrap_or_default());
+    std::fs::write(&path, contents).with_context(|| format!("writing {}", path.display()))
+}
+
+/// Copies `from` into `to` recursively, keeping permissions and symlinks.
+fn copy_dir(from: &Path, to: &Path, skip: &[&str]) -> anyhow::Result<()> {
+    std::fs::create_dir_all(to).with_context(|| format!("creating {}", to.display()))?;
+    let permissions = std::fs::metadata(from)
+        .with_context(|| format!("reading {}", from.display()))?
+        .permissions();
+    std::fs::set_permissions(to, permissions)?;
+
+    for entry in std::fs::read_dir(from).with_context(|| format!("reading {}", from.display()))? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if skip.iter().any(|skipped| name == *skipped) {
+            continue;
+        }
+        let (source, target) = (entry.path(), to.join(&name));
+        let kind = entry.file_type()?;
+        if kind.is_dir() {
+            copy_dir(&source, &target, &[])?;
+        } else if kind.is_symlink() {
+            _ = std::fs::remove_file(&target);
+            std::os::unix::fs::symlink(std::fs::read_link(&source)?, &target)
+                .with_context(|| format!("linking {}", target.display()))?;
+        } else {
+            std::fs::copy(&source, &target)
+                .with_context(|| format!("copying {}", source.display()))?;
+        }
+    }
+    Ok(())
+}
+
+fn write_image(image: &Path, device: &Path) -> anyhow::Result<u64> {
+    let file = File::open(image).with_context(|| format!("opening {}", image.display()))?;
+    let mut reader: Box<dyn Read> = if image.extension().is_some_and(|ext| ext == "gz") {
+        Box::new(GzDecoder::new(BufReader::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+
+    let mut target = OpenOptions::new()
+        .write(true)
+        .open(device)
+        .with_context(|| format!("opening {}", device.display()))?;
+    let written = io::copy(&mut reader, &mut target)
+        .with_context(|| format!("writing {}", device.display()))?;
+    target
+        .sync_all()
+        .with_context(|| format!("syncing {}", device.display()))?;
+    Ok(written)
+}
+
+/// `IMAGE_VERSION` if the image sets one, otherwise `VERSION_ID`.
+fn parse_os_release_version(contents: &str) -> Option<String> {
+    let value = |key: &str| {
+        contents.lines().find_map(|line| {
+            let value = line.strip_prefix(key)?.strip_prefix('=')?;
+            Some(value.trim().trim_matches('"').to_owned())
+        })
+    };
+    value("IMAGE_VERSION")
+        .or_else(|| value("VERSION_ID"))
+        .filter(|version| !version.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    #[test]
+    fn image_version_wins_over_version_id() {
+        let release = "NAME=\"Ubuntu\"\nVERSION_ID=\"22.04\"\n";
+        assert_eq!(parse_os_release_version(release).as_deref(), Some("22.04"));
+        assert_eq!(
+            parse_os_release_version(&format!("{release}IMAGE_VERSION=2026.10.1\n")).as_deref(),
+            Some("2026.10.1")
+        );
+        assert_eq!(parse_os_release_version("NAME=x\n"), None);
+    }
+
+    #[test]
+    fn only_a_system_that_finished_booting_is_kept() {
+        assert!(boot_verdict("running").is_ok());
+        assert!(boot_verdict("degraded").is_ok());
+        for status in ["starting", "still starting", "maintenance", "stopping"] {
+            assert!(boot_verdict(status).is_err(), "{status}");
+        }
+    }
+
+    #[test]
+    fn a_fresh_slot_confirms_the_update_it_was_handed() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = dir.path().join("slot_a/etc/smith");
+        std::fs::create_dir_all(state.join("keys")).unwrap();
+        std::fs::create_dir_all(state.join("packages")).unwrap();
+        std::fs::write(state.join("magic.toml"), "token = \"t\"\n").unwrap();
+        std::fs::write(state.join("keys/device.key"), b"key").unwrap();
+        std::fs::write(state.join("packages/app.deb"), b"cache").unwrap();
+
+        let mut record = Record::default();
+        record.state.from_version = Some("2026.9.1".into());
+        record.state.target_version = Some("2026.10.1".into());
+        record.state.slot = Some("b".into());
+        record.enter(OtaPhase::Rebooting);
+
+        let fresh = dir.path().join("slot_b/etc/smith");
+        std::fs::create_dir_all(&fresh).unwrap();
+        copy_state(&state, &fresh, &record).unwrap();
+        assert!(fresh.join("magic.toml").exists());
+        assert_eq!(
+            std::fs::read(fresh.join("keys/device.key")).unwrap(),
+            b"key"
+        );
+        assert!(!fresh.join("packages").exists());
+
+        let mut booted = Record::load(&fresh.join("ota.json"));
+        assert_eq!(booted.state.phase, OtaPhase::Rebooting);
+        assert!(settle(
+            &mut booted,
+            Slot::B,
+            "running",
+            Some("2026.10.1".into())
+        ));
+        assert_eq!(booted.state.phase, OtaPhase::Verified);
+
+        // Falling back to the old slot is no success, whatever it runs.
+        let mut fell_back = Record::load(&fresh.join("ota.json"));
+        assert!(!settle(
+            &mut fell_back,
+            Slot::A,
+            "running",
+            Some("2026.9.1".into())
+        ));
+        assert_eq!(fell_back.state.phase, OtaPhase::Failed);
+    }
+
+    #[test]
+    fn slots_are_matched_through_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("sda2"), dir.path().join("sda3"));
+        std::fs::write(&a, b"").unwrap();
+        std::fs::write(&b, b"").unwrap();
+        let label = dir.path().join("root_b");
+        std::os::unix::fs::symlink(&b, &label).unwrap();
+
+        assert_eq!(slot_of(&a, &a, &label).unwrap(), Slot::A);
+        assert_eq!(slot_of(&b, &a, &label).unwrap(), Slot::B);
+        assert!(slot_of(&dir.path().join("sda4"), &a, &label).is_err());
+    }
+
+    #[test]
+    fn gzipped_images_are_written_decompressed() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("os_image.img.gz");
+        let device = dir.path().join("slot_b");
+        std::fs::write(&device, b"").unwrap();
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"root filesystem").unwrap();
+        std::fs::write(&image, encoder.finish().unwrap()).unwrap();
+
+        assert_eq!(write_image(&image, &device).unwrap(), 15);
+        assert_eq!(std::fs::read(&device).unwrap(), b"root filesystem");
+    }
+}
@@ -0,0 +1,86 @@
+//! OS update backends.
+//!
+//! The actor drives every update through the same phases; a backend supplies
+//! what differs between devices: which files to fetch, how the staged payload
+//! is installed, and how to tell after the reboot whether it took.
+use super::ab_image::AbImage;
+use super::jetson::Jetson;
+use super::state::{Record, Request};
+use crate::filemanager::FileManagerHandle;
+use crate::magic::structure::ConfigOta;
+
+/// A file an update needs, and where it is kept until it is applied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Download {
+    pub remote: String,
+    pub local: String,
+}
+
+pub(crate) trait OsUpdate {
+    /// The files to fetch for `request`, the payload last. Only the payload is
+    /// checked against the request's sha256.
+    fn downloads(&self, request: &Request) -> Vec<Download>;
+
+    /// The OS version running now, in the form `DownloadOTA`'s `version` uses.
+    async fn running_version(&self) -> Option<String>;
+
+    /// Installs the staged payload so the next boot runs it.
+    async fn apply(&self, record: &mut Record) -> anyhow::Result<()>;
+
+    /// Called on the first start after the reboot. Leaves the record
+    /// `Verified` if the update took, `Failed` otherwise. May take minutes,
+    /// so the actor runs it in a task of its own.
+    async fn confirm(&self, record: &mut Record);
+}
+
+#[derive(Clone)]
+pub enum Backend {
+    Jetson(Jetson),
+    AbImage(AbImage),
+}
+
+impl Backend {
+    pub fn new(config: Option<ConfigOta>, filemanager: FileManagerHandle) -> Self {
+        match config {
+            None | Some(ConfigOta::Jetson) => Backend::Jetson(Jetson::new(filemanager)),
+            Some(ConfigOta::AbImage(config)) => Backend::AbImage(AbImage::new(config)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Backend::Jetson(_) => "jetson",
+            Backend::AbImage(_) => "ab_image",
+        }
+    }
+}
+
+impl OsUpdate for Backend {
+    fn downloads(&self, request: &Request) -> Vec<Download> {
+        match self {
+            Backend::Jetson(backend) => backend.downloads(request),
+            Backend::AbImage(backend) => backend.downloads(request),
+        }
+    }
+
+    async fn running_version(&self) -> Option<String> {
+        match self {
+            Backend::Jetson(backend) => backend.running_version().await,
+            Backend::AbImage(backend) => backend.running_version().await,
+        }
+    }
+
+    async fn apply(&self, record: &mut Record) -> anyhow::Result<()> {
+        match self {
+            Backend::Jetson(backend) => backend.apply(record).await,
+            Backend::AbImage(backend) => backend.apply(record).await,
+        }
+    }
+
+    async fn confirm(&self, record: &mut Record) {
+        match self {
+            Backend::Jetson(backend) => backend.confirm(record).await,
+            Backend::AbImage(backend) => backend.confirm(record).await,
+        }
+    }
+}
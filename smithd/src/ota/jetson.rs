@@ -0,0 +1,97 @@
+//! NVIDIA's L4T OTA tools. The tools archive is unpacked next to the payload
+//! and `nv_ota_start.sh` stages it for the bootloader to apply on the reboot.
+use super::backend::{Download, OsUpdate};
+use super::state::{Record, Request};
+use crate::filemanager::FileManagerHandle;
+use anyhow::Context;
+
+const TOOLS_PATH: &str = "/otatool/ota_tools.tbz2";
+const PAYLOAD_PATH: &str = "/ota/ota_payload_package.tar.gz";
+const OTA_SCRIPT_DIR: &str = "/otatool/Linux_for_Tegra/tools/ota_tools/version_upgrade/";
+
+/// Written by L4T, e.g. `# R35 (release), REVISION: 4.1, GCID: ...`.
+const NV_TEGRA_RELEASE: &str = "/etc/nv_tegra_release";
+
+#[derive(Clone)]
+pub struct Jetson {
+    filemanager: FileManagerHandle,
+}
+
+impl Jetson {
+    pub fn new(filemanager: FileManagerHandle) -> Self {
+        Self { filemanager }
+    }
+}
+
+impl OsUpdate for Jetson {
+    fn downloads(&self, request: &Request) -> Vec<Download> {
+        vec![
+            Download {
+                remote: format!("assets/ota/{}", request.tools),
+                local: TOOLS_PATH.to_owned(),
+            },
+            Download {
+                remote: format!("assets/ota/{}", request.payload),
+                local: PAYLOAD_PATH.to_owned(),
+            },
+        ]
+    }
+
+    async fn running_version(&self) -> Option<String> {
+        let contents = tokio::fs::read_to_string(NV_TEGRA_RELEASE).await.ok()?;
+        parse_l4t_version(&contents)
+    }
+
+    async fn apply(&self, _record: &mut Record) -> anyhow::Result<()> {
+        self.filemanager
+            .extract_here(TOOLS_PATH)
+            .await
+            .context("Failed to extract OTA tools")?;
+
+        self.filemanager
+            .execute_script(
+                "nv_ota_start.sh",
+                vec![PAYLOAD_PATH.to_owned()],
+                Some(OTA_SCRIPT_DIR),
+            )
+            .await
+            .context("OTA script failed")?;
+        Ok(())
+    }
+
+    async fn confirm(&self, record: &mut Record) {
+        record.after_boot(self.running_version().await);
+    }
+}
+
+/// The L4T version in an `nv_tegra_release` file, as `<release>.<revision>`.
+fn parse_l4t_version(contents: &str) -> Option<String> {
+    let line = contents.lines().next()?.trim_start_matches('#').trim();
+    let release = line.split_whitespace().next()?.strip_prefix('R')?;
+    let revision = line
+        .split(',')
+        .find_map(|field| field.trim().strip_prefix("REVISION:"))?
+        .trim();
+    (!release.is_empty() && !revision.is_empty()).then(|| format!("{release}.{revision}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_l4t_version() {
+        assert_eq!(
+            parse_l4t_version(
+                "# R35 (release), REVISION: 4.1, GCID: 33958178, BOARD: t186ref, EABI: aarch64, DATE: Tue Aug  1 19:57:35 UTC 2023\n"
+            )
+            .as_deref(),
+            Some("35.4.1")
+        );
+        assert_eq!(
+            parse_l4t_version("# R32 (release), REVISION: 7.4, GCID: 1\n").as_deref(),
+            Some("32.7.4")
+        );
+        assert_eq!(parse_l4t_version("garbage"), None);
+    }
+}
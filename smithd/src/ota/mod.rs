@@ -0,0 +1,515 @@
+//! OTA actor
+//!
+//! Drives an OS update through [`OtaPhase`]: its files are downloaded, the
+//! payload checked against its digest and staged, then applied on `StartOTA`
+//! and the device rebooted. After the reboot the update counts as verified only
+//! if the device runs the target version.
+//!
+//! How the files are fetched, applied and confirmed depends on the device and
+//! is up to a backend, see [`backend`]: NVIDIA's OTA tools on a Jetson, or an
+//! A/B root filesystem image elsewhere, as set in magic.toml's `[ota]`.
+//!
+//! Every change is persisted, see [`state`], and published on a watch channel
+//! that the postman reports to the api from.
+use crate::downloader::mirror::sha256_file;
+use crate::downloader::{DownloadJob, DownloaderHandle};
+use crate::events::{DaemonEvent, EventBus};
+use crate::filemanager::FileManagerHandle;
+use crate::magic::structure::ConfigOta;
+use crate::shutdown::ShutdownSignals;
+use crate::utils::schema::{DownloadPriority, DownloadState, OtaPhase, OtaState};
+use anyhow::{Context, anyhow};
+use backend::{Backend, Download, OsUpdate};
+use state::{OTA_STATE_PATH, Record, Request};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::time::{self, Duration};
+use tracing::{error, info, warn};
+
+mod ab_image;
+mod backend;
+mod jetson;
+mod state;
+
+/// Tags the tools and payload downloads so their status can be picked out of
+/// the downloader's queue.
+pub const DOWNLOAD_TAG: &str = "ota";
+
+/// How often download progress is read while downloading.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Left between reporting `Rebooting` and rebooting, so the report gets out.
+const REBOOT_DELAY: Duration = Duration::from_secs(10);
+
+enum OtaMessage {
+    Download {
+        request: Request,
+        rpc: oneshot::Sender<anyhow::Result<OtaState>>,
+    },
+    Start {
+        rpc: oneshot::Sender<anyhow::Result<OtaState>>,
+    },
+    /// Sent by the task confirming an update after the reboot, with the
+    /// record as it left it.
+    Confirmed { record: Box<Record> },
+}
+
+/// A tools or payload download handed to the downloader.
+struct Pending {
+    id: u64,
+    download: Download,
+    queued_at: SystemTime,
+}
+
+struct Ota {
+    shutdown: ShutdownSignals,
+    receiver: mpsc::Receiver<OtaMessage>,
+    /// Handed to the confirming task so it can report back.
+    sender: mpsc::Sender<OtaMessage>,
+    downloader: DownloaderHandle,
+    filemanager: FileManagerHandle,
+    backend: Backend,
+    events: EventBus,
+    state: watch::Sender<OtaState>,
+    path: PathBuf,
+    record: Record,
+    /// The tools and payload downloads of this run of the daemon that have
+    /// not completed yet.
+    downloads: Vec<Pending>,
+}
+
+impl Ota {
+    /// Persists the record and tells everyone who is watching.
+    async fn publish(&mut self) {
+        if let Err(err) = self.record.persist(&self.path).await {
+            error!("Failed to persist OTA state: {err:#}");
+        }
+        let state = self.record.state.clone();
+        if self.state.borrow().phase != state.phase {
+            info!("OTA is now {:?}", state.phase);
+            self.events.publish(DaemonEvent::OtaStateChanged {
+                phase: state.phase,
+                error: state.error.clone(),
+            });
+        }
+        self.state.send_replace(state);
+    }
+
+    async fn fail(&mut self, error: String) {
+        warn!("OTA failed: {error}");
+        self.cancel_downloads().await;
+        self.record.fail(error);
+        self.publish().await;
+    }
+
+    async fn cancel_downloads(&mut self) {
+        for pending in self.downloads.drain(..) {
+            self.downloader.cancel(pending.id).await;
+        }
+    }
+
+    /// Picks up where the daemon left off before it was restarted.
+    async fn resume(&mut self) {
+        match self.record.state.phase {
+            OtaPhase::Applying | OtaPhase::Rebooting => {
+                // Confirming can wait minutes for the boot to finish. Meanwhile
+                // the phase stays put, so new updates are refused until then.
+                let backend = self.backend.clone();
+                let mut record = self.record.clone();
+                let sender = self.sender.clone();
+                tokio::spawn(async move {
+                    backend.confirm(&mut record).await;
+                    _ = sender
+                        .send(OtaMessage::Confirmed {
+                            record: Box::new(record),
+                        })
+                        .await;
+                });
+            }
+            OtaPhase::Downloading | OtaPhase::Verifying => {
+                // Completed files are not fetched again and partial ones resume.
+                if let Err(err) = self.queue_downloads().await {
+                    self.fail(format!("{err:#}")).await;
+                    return;
+                }
+                self.record.enter(OtaPhase::Downloading);
+                self.publish().await;
+            }
+            _ => {
+                self.record.state.current_version = self.backend.running_version().await;
+                self.state.send_replace(self.record.state.clone());
+            }
+        }
+    }
+
+    async fn queue_downloads(&mut self) -> anyhow::Result<()> {
+        let request = self
+            .record
+            .request
+            .clone()
+            .ok_or_else(|| anyhow!("no OTA was requested"))?;
+
+        self.cancel_downloads().await;
+
+        let downloads = self.backend.downloads(&request);
+        let last = downloads.len().saturating_sub(1);
+        for (index, download) in downloads.into_iter().enumerate() {
+            if self.record.completed.contains_key(&download.local)
+                && Path::new(&download.local).exists()
+            {
+                continue;
+            }
+            if let Some(dir) = Path::new(&download.local).parent() {
+                tokio::fs::create_dir_all(dir)
+                    .await
+                    .with_context(|| format!("creating {}", dir.display()))?;
+            }
+
+            // OTA payloads run to gigabytes, so they yield to everything else queued.
+            let mut job = DownloadJob::new(&download.remote, &download.local, request.rate)
+                .priority(DownloadPriority::Low)
+                .tag(DOWNLOAD_TAG);
+            if index == last
+                && let Some(sha256) = &request.sha256
+            {
+                job = job.sha256(sha256);
+            }
+
+            let id = self
+                .downloader
+                .download(job)
+                .await
+                .with_context(|| format!("queueing the download of {}", download.remote))?;
+            self.downloads.push(Pending {
+                id,
+                download,
+                queued_at: SystemTime::now(),
+            });
+        }
+        Ok(())
+    }
+
+    async fn download(&mut self, request: Request) -> anyhow::Result<OtaState> {
+        if matches!(
+            self.record.state.phase,
+            OtaPhase::Applying | OtaPhase::Rebooting
+        ) {
+            return Err(anyhow!(
+                "an OTA is {:?}, wait for it to finish",
+                self.record.state.phase
+            ));
+        }
+
+        let current = self.backend.running_version().await;
+        self.record = Record {
+            state: OtaState {
+                backend: Some(self.backend.name().to_owned()),
+                target_version: request.version.clone(),
+                from_version: current.clone(),
+                current_version: current,
+                ..Default::default()
+            },
+            request: Some(request),
+            completed: Default::default(),
+        };
+
+        if let Err(err) = self.queue_downloads().await {
+            self.fail(format!("{err:#}")).await;
+            return Err(err);
+        }
+        self.record.enter(OtaPhase::Downloading);
+        self.publish().await;
+        Ok(self.record.state.clone())
+    }
+
+    /// Updates the progress and moves on once both files are in.
+    async fn poll_downloads(&mut self) {
+        let mut downloaded = 0;
+        let mut total = 0;
+        let mut finished = Vec::new();
+        for pending in &self.downloads {
+            let Some(info) = self.downloader.get(pending.id).await else {
+                // The downloader only keeps so many finished downloads.
+                match self.completed_on_disk(pending).await {
+                    Ok(size) => {
+                        self.record
+                            .completed
+                            .insert(pending.download.local.clone(), size);
+                        finished.push(pending.id);
+                        continue;
+                    }
+                    Err(err) => {
+                        let id = pending.id;
+                        self.fail(format!("download {id} is gone: {err:#}")).await;
+                        return;
+                    }
+                }
+            };
+            match info.state {
+                DownloadState::Failed | DownloadState::Cancelled => {
+                    let reason = info.error.unwrap_or_else(|| format!("{:?}", info.state));
+                    self.fail(format!("download of {} failed: {reason}", info.remote_file))
+                        .await;
+                    return;
+                }
+                DownloadState::Completed => {
+                    let size = info.total_bytes.unwrap_or(info.bytes_downloaded);
+                    self.record
+                        .completed
+                        .insert(pending.download.local.clone(), size);
+                    finished.push(pending.id);
+                }
+                _ => {
+                    downloaded += info.bytes_downloaded;
+                    total += info.total_bytes.unwrap_or(0);
+                }
+            }
+        }
+
+        if !finished.is_empty() {
+            self.downloads
+                .retain(|pending| !finished.contains(&pending.id));
+            if let Err(err) = self.record.persist(&self.path).await {
+                error!("Failed to persist OTA state: {err:#}");
+            }
+        }
+        let done = self.downloads.is_empty();
+        let completed: u64 = self.record.completed.values().sum();
+        downloaded += completed;
+        total += completed;
+
+        let state = &mut self.record.state;
+        state.downloaded_bytes = downloaded;
+        state.total_bytes = total;
+        state.progress_percent = match total {
+            0 => 0,
+            total => (downloaded.min(total) * 100 / total) as u8,
+        };
+
+        if !done {
+            if *self.state.borrow() != self.record.state {
+                // Progress alone is not worth a disk write every few seconds.
+                self.state.send_replace(self.record.state.clone());
+            }
+            return;
+        }
+
+        self.downloads.clear();
+        self.record.enter(OtaPhase::Verifying);
+        self.publish().await;
+
+        match self.verify().await {
+            Ok(digest) => {
+                self.record.state.payload_sha256 = Some(digest);
+                self.record.enter(OtaPhase::Staged);
+                self.publish().await;
+            }
+            Err(err) => {
+                // A corrupt payload must not be resumed from.
+                if let Some(payload) = self.payload() {
+                    _ = tokio::fs::remove_file(payload).await;
+                }
+                self.fail(format!("{err:#}")).await;
+            }
+        }
+    }
+
+    /// The size of a file the downloader no longer knows about, if this
+    /// download left it complete: written since it was queued, with no `.part`
+    /// left beside it, and the payload matching the requested digest.
+    async fn completed_on_disk(&self, pending: &Pending) -> anyhow::Result<u64> {
+        let local = &pending.download.local;
+        let metadata = tokio::fs::metadata(local)
+            .await
+            .with_context(|| format!("{local} is missing"))?;
+        if metadata.modified()? < pending.queued_at {
+            return Err(anyhow!("{local} predates the download"));
+        }
+        if Path::new(&format!("{local}.part")).exists() {
+            return Err(anyhow!("{local} was not finished"));
+        }
+        if self.payload().as_ref() == Some(local) {
+            self.verify().await?;
+        }
+        Ok(metadata.len())
+    }
+
+    /// Where the payload of the requested update is downloaded to.
+    fn payload(&self) -> Option<String> {
+        let request = self.record.request.as_ref()?;
+        Some(self.backend.downloads(request).pop()?.local)
+    }
+
+    async fn verify(&self) -> anyhow::Result<String> {
+        let payload = self
+            .payload()
+            .ok_or_else(|| anyhow!("no OTA was requested"))?;
+        let digest = sha256_file(Path::new(&payload)).await?;
+        let expected = self.record.request.as_ref().and_then(|r| r.sha256.as_ref());
+        match expected {
+            Some(expected) if !expected.eq_ignore_ascii_case(&digest) => {
+                Err(anyhow!("payload sha256 is {digest}, expected {expected}"))
+            }
+            _ => Ok(digest),
+        }
+    }
+
+    async fn start(&mut self) -> anyhow::Result<OtaState> {
+        if self.record.state.phase != OtaPhase::Staged {
+            return Err(anyhow!(
+                "no OTA is staged, it is {:?}",
+                self.record.state.phase
+            ));
+        }
+
+        self.record.enter(OtaPhase::Applying);
+        self.publish().await;
+
+        if let Err(err) = self.backend.apply(&mut self.record).await {
+            self.fail(format!("{err:#}")).await;
+            return Err(err);
+        }
+
+        // Persisted before rebooting, so the next boot knows to verify.
+        self.record.enter(OtaPhase::Rebooting);
+        self.publish().await;
+
+        let filemanager = self.filemanager.clone();
+        tokio::spawn(async move {
+            time::sleep(REBOOT_DELAY).await;
+            if let Err(err) = filemanager
+                .execute_system_command("reboot", Vec::new(), None)
+                .await
+            {
+                error!("Failed to reboot after OTA: {err:#}");
+            }
+        });
+
+        Ok(self.record.state.clone())
+    }
+
+    async fn handle_message(&mut self, msg: OtaMessage) {
+        match msg {
+            OtaMessage::Download { request, rpc } => {
+                _ = rpc.send(self.download(request).await);
+            }
+            OtaMessage::Start { rpc } => {
+                _ = rpc.send(self.start().await);
+            }
+            OtaMessage::Confirmed { record } => {
+                self.record = *record;
+                self.publish().await;
+            }
+        }
+    }
+
+    async fn run(&mut self) {
+        info!("OTA task is running");
+        self.resume().await;
+
+        let mut progress = time::interval(PROGRESS_INTERVAL);
+        progress.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+
+        loop {
+            tokio::select! {
+                Some(msg) = self.receiver.recv() => {
+                    self.handle_message(msg).await;
+                }
+                _ = progress.tick() => {
+                    if self.record.state.phase == OtaPhase::Downloading {
+                        self.poll_downloads().await;
+                    }
+                }
+                _ = self.shutdown.token.cancelled() => {
+                    break;
+                }
+            }
+        }
+
+        info!("OTA task shutting down");
+    }
+}
+
+#[derive(Clone)]
+pub struct OtaHandle {
+    sender: mpsc::Sender<OtaMessage>,
+    state: watch::Receiver<OtaState>,
+}
+
+impl OtaHandle {
+    pub fn new(
+        shutdown: ShutdownSignals,
+        downloader: DownloaderHandle,
+        filemanager: FileManagerHandle,
+        events: EventBus,
+        config: Option<ConfigOta>,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel(8);
+        let backend = Backend::new(config, filemanager.clone());
+        info!("OS updates use the {} backend", backend.name());
+        let path = PathBuf::from(OTA_STATE_PATH);
+        let record = Record::load(&path);
+        let (state_tx, state) = watch::channel(record.state.clone());
+
+        let mut actor = Ota {
+            shutdown,
+            receiver,
+            sender: sender.clone(),
+            downloader,
+            filemanager,
+            backend,
+            events,
+            state: state_tx,
+            path,
+            record,
+            downloads: Vec::new(),
+        };
+        tokio::spawn(async move { actor.run().await });
+
+        Self { sender, state }
+    }
+
+    /// Starts downloading an update, replacing any that is not yet applying.
+    pub async fn download(
+        &self,
+        tools: String,
+        payload: String,
+        rate: f64,
+        version: Option<String>,
+        sha256: Option<String>,
+    ) -> anyhow::Result<OtaState> {
+        let (rpc, receiver) = oneshot::channel();
+        let request = Request {
+            tools,
+            payload,
+            rate,
+            version,
+            sha256,
+        };
+        self.sender
+            .send(OtaMessage::Download { request, rpc })
+            .await
+            .map_err(|_| anyhow!("OTA task is not running"))?;
+        receiver
+            .await
+            .map_err(|_| anyhow!("OTA task dropped the request"))?
+    }
+
+    /// Applies the staged update and reboots into it.
+    pub async fn start(&self) -> anyhow::Result<OtaState> {
+        let (rpc, receiver) = oneshot::channel();
+        self.sender
+            .send(OtaMessage::Start { rpc })
+            .await
+            .map_err(|_| anyhow!("OTA task is not running"))?;
+        receiver
+            .await
+            .map_err(|_| anyhow!("OTA task dropped the request"))?
+    }
+
+    /// Never waits, even while the actor is busy applying an update.
+    pub fn state(&self) -> OtaState {
+        self.state.borrow().clone()
+    }
+}
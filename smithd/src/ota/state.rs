@@ -0,0 +1,163 @@
+//! The OTA update as persisted on disk, so it survives the reboot that
+//! applies it and the daemon can tell afterwards whether it worked.
+use crate::utils::files::write_file_atomic;
+use crate::utils::schema::{OtaPhase, OtaState};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+pub const OTA_STATE_PATH: &str = "/etc/smith/ota.json";
+
+/// What the api asked to download, kept so an interrupted download can be
+/// queued again after a restart.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Request {
+    pub tools: String,
+    pub payload: String,
+    pub rate: f64,
+    pub version: Option<String>,
+    pub sha256: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct Record {
+    pub state: OtaState,
+    #[serde(default)]
+    pub request: Option<Request>,
+    /// Files of the request that finished downloading, with their size, so
+    /// they are neither polled nor fetched again.
+    #[serde(default)]
+    pub completed: BTreeMap<String, u64>,
+}
+
+impl Record {
+    /// Reads the record at `path`, starting idle if it is missing or unreadable.
+    pub fn load(path: &Path) -> Self {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|err| {
+                warn!("Ignoring unreadable {}: {err}", path.display());
+                Record::default()
+            }),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Record::default(),
+            Err(err) => {
+                warn!("Failed to read {}: {err}", path.display());
+                Record::default()
+            }
+        }
+    }
+
+    pub async fn persist(&self, path: &Path) -> anyhow::Result<()> {
+        let contents = serde_json::to_string(self).context("serializing OTA state")?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .with_context(|| format!("creating {}", parent.display()))?;
+        }
+        write_file_atomic(path, &contents, 0o644).await
+    }
+
+    pub fn enter(&mut self, phase: OtaPhase) {
+        self.state.phase = phase;
+        self.state.updated_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        if phase != OtaPhase::Failed {
+            self.state.error = None;
+        }
+    }
+
+    pub fn fail(&mut self, error: String) {
+        self.enter(OtaPhase::Failed);
+        self.state.error = Some(error);
+    }
+
+    /// Settles an update that was applying or rebooting when the daemon last
+    /// ran, given the OS version the device booted into.
+    pub fn after_boot(&mut self, running: Option<String>) {
+        self.state.current_version = running.clone();
+        let Some(running) = running else {
+            self.fail("could not read the running OS version".to_string());
+            return;
+        };
+
+        match (&self.state.target_version, &self.state.from_version) {
+            (Some(target), _) if same_version(target, &running) => self.enter(OtaPhase::Verified),
+            (Some(target), _) => {
+                self.fail(format!("booted version {running}, expected {target}"));
+            }
+            // Nothing to compare against but where it started from.
+            (None, Some(from)) if same_version(from, &running) => {
+                self.fail(format!("still on version {running} after the update"));
+            }
+            (None, _) => self.enter(OtaPhase::Verified),
+        }
+    }
+}
+
+/// Compares versions, ignoring an `R` prefix, as in `R35.4.1`.
+fn same_version(a: &str, b: &str) -> bool {
+    let normalize = |v: &str| v.trim().trim_start_matches(['R', 'r']).to_owned();
+    normalize(a) == normalize(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boot_into_the_target_verifies_the_update() {
+        let mut record = Record::default();
+        record.state.target_version = Some("R35.4.1".into());
+        record.state.from_version = Some("35.3.1".into());
+        record.enter(OtaPhase::Rebooting);
+
+        record.after_boot(Some("35.4.1".into()));
+        assert_eq!(record.state.phase, OtaPhase::Verified);
+
+        record.enter(OtaPhase::Rebooting);
+        record.after_boot(Some("35.3.1".into()));
+        assert_eq!(record.state.phase, OtaPhase::Failed);
+        assert_eq!(
+            record.state.error.as_deref(),
+            Some("booted version 35.3.1, expected R35.4.1")
+        );
+
+        // Without a target, any other version than the one it left counts.
+        record.state.target_version = None;
+        record.enter(OtaPhase::Rebooting);
+        record.after_boot(Some("35.3.1".into()));
+        assert_eq!(record.state.phase, OtaPhase::Failed);
+        record.after_boot(Some("36.3".into()));
+        assert_eq!(record.state.phase, OtaPhase::Verified);
+        assert_eq!(record.state.error, None);
+    }
+
+    #[tokio::test]
+    async fn record_survives_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ota.json");
+
+        let mut record = Record {
+            request: Some(Request {
+                tools: "tools.tbz2".into(),
+                payload: "payload.tar.gz".into(),
+                rate: 2.0,
+                version: Some("35.4.1".into()),
+                sha256: None,
+            }),
+            ..Default::default()
+        };
+        record.completed.insert("/tmp/tools.tbz2".into(), 1024);
+        record.enter(OtaPhase::Staged);
+        record.persist(&path).await.unwrap();
+
+        let loaded = Record::load(&path);
+        assert_eq!(loaded.state, record.state);
+        assert_eq!(loaded.request, record.request);
+        assert_eq!(loaded.completed, record.completed);
+    }
+}
@@ -12,8 +12,10 @@
 //! before the reboot lands. Those services can also place a *hold* — a lease
 //! that defers the reboot while someone is, say, connected to the debug access
 //! point. Holds expire unless renewed: a crashed holder must never disarm the
-//! watchdog forever.
+//! watchdog forever. Scheduling and cancelling are also published on the
+//! [`EventBus`] so subscribers hear about them without polling.
 //!
+use crate::events::{DaemonEvent, EventBus};
 use crate::shutdown::ShutdownSignals;
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
@@ -55,6 +57,7 @@ pub struct RebootStatus {
 
 struct Police {
     shutdown: ShutdownSignals,
+    events: EventBus,
     should_restart: bool,
     restart_at: Option<Instant>,
     scheduled_at: Option<Instant>,
@@ -84,9 +87,14 @@ enum PoliceMessage {
 }
 
 impl Police {
-    fn new(shutdown: ShutdownSignals, receiver: mpsc::Receiver<PoliceMessage>) -> Self {
+    fn new(
+        shutdown: ShutdownSignals,
+        events: EventBus,
+        receiver: mpsc::Receiver<PoliceMessage>,
+    ) -> Self {
         Police {
             shutdown,
+            events,
             should_restart: false,
             restart_at: None,
             scheduled_at: None,
@@ -138,6 +146,10 @@ impl Police {
                             RESTART_DELAY.as_secs(),
                             self.schedule_seq
                         );
+                        self.events.publish(DaemonEvent::RebootScheduled {
+                            schedule_id: self.schedule_seq,
+                            seconds_remaining: RESTART_DELAY.as_secs(),
+                        });
                     } else {
                         warn!("Restart already scheduled");
                     }
@@ -157,6 +169,9 @@ impl Police {
                 if self.problems.is_empty() && self.restart_at.take().is_some() {
                     info!("Problem solved, restart aborted");
                     self.scheduled_at = None;
+                    self.events.publish(DaemonEvent::RebootCancelled {
+                        schedule_id: self.schedule_seq,
+                    });
                 }
             }
             PoliceMessage::Status { respond_to } => {
@@ -258,9 +273,9 @@ pub struct PoliceHandle {
 }
 
 impl PoliceHandle {
-    pub fn new(shutdown: ShutdownSignals) -> Self {
+    pub fn new(shutdown: ShutdownSignals, events: EventBus) -> Self {
         let (sender, receiver) = mpsc::channel(8);
-        let mut actor = Police::new(shutdown, receiver);
+        let mut actor = Police::new(shutdown, events, receiver);
         tokio::spawn(async move { actor.run().await });
 
         Self { sender }
@@ -340,7 +355,7 @@ mod tests {
     #[tokio::test(start_paused = true)]
     async fn restarts_are_not_armed_before_the_arm_window() {
         let shutdown = ShutdownHandler::new();
-        let police = PoliceHandle::new(shutdown.signals());
+        let police = PoliceHandle::new(shutdown.signals(), EventBus::new());
 
         assert!(police.report_problem_starting().await.is_none());
 
@@ -352,7 +367,9 @@ mod tests {
     #[tokio::test(start_paused = true)]
     async fn hold_defers_a_scheduled_reboot_until_released() {
         let shutdown = ShutdownHandler::new();
-        let police = PoliceHandle::new(shutdown.signals());
+        let events = EventBus::new();
+        let mut subscriber = events.subscribe();
+        let police = PoliceHandle::new(shutdown.signals(), events);
 
         // A round-trip guarantees the actor has started (and created its arming
         // interval) before the clock is advanced past it.
@@ -366,6 +383,13 @@ mod tests {
         assert_eq!(status.schedule_id, Some(1));
         assert_eq!(status.delay_seconds, RESTART_DELAY.as_secs());
         assert!(status.seconds_remaining <= RESTART_DELAY.as_secs());
+        assert_eq!(
+            subscriber.try_recv().ok(),
+            Some(DaemonEvent::RebootScheduled {
+                schedule_id: 1,
+                seconds_remaining: RESTART_DELAY.as_secs(),
+            })
+        );
 
         // Two minutes in: elapsed is visible to pollers (plex keys off this).
         tokio::time::advance(Duration::from_secs(120)).await;
@@ -1,17 +1,29 @@
 use crate::commander::{CommanderHandle, network};
+use crate::connectivity::ConnectivityHandle;
+use crate::downloader::DownloaderHandle;
+use crate::events::{DaemonEvent, EventBus};
+use crate::identity::{self, Purpose};
 use crate::magic::MagicHandle;
+use crate::ota::OtaHandle;
 use crate::police::PoliceHandle;
+use crate::secrets;
 use crate::session::{RefreshOutcome, SessionHandle};
 use crate::shutdown::ShutdownSignals;
+use crate::updater::UpdaterHandle;
+use crate::updater::containers::Runtime;
+use crate::utils::hardware;
 use crate::utils::network::NetworkClient;
 use crate::utils::schema::{
-    DeviceRegistration, DeviceRegistrationResponse, HomePost, HomePostResponse,
-    SafeCommandResponse, SafeCommandRx, ServiceCheck, ServiceStatus,
+    CONTAINER_SERVICE_PREFIX, DeviceRegistration, DeviceRegistrationResponse, HomePost,
+    HomePostResponse, IdentityProof, SafeCommandResponse, SafeCommandRx, ServiceCheck,
+    ServiceStatus,
 };
 use crate::utils::system::SystemInfo;
+use crate::vpn;
 use anyhow::{Result, anyhow};
 use reqwest::{Response, StatusCode};
 use std::fmt::Write;
+use std::path::Path;
 use std::time::Duration;
 use tokio::{sync::mpsc, time};
 use tracing::{error, info, warn};
@@ -20,6 +32,12 @@ const CMD_ID_GET_VARIABLES: i32 = -1;
 const CMD_ID_UPDATE_SYSTEM_INFO: i32 = -2;
 const CMD_ID_GET_NETWORK: i32 = -4;
 const CMD_ID_REPORT_NM_PROFILES: i32 = -6;
+const CMD_ID_GET_SECRETS: i32 = -7;
+const CMD_ID_REPORT_BANDWIDTH: i32 = -8;
+const CMD_ID_REPORT_OTA: i32 = -9;
+const CMD_ID_REPORT_UPGRADE: i32 = -10;
+const CMD_ID_REPORT_WIREGUARD_KEY: i32 = -14;
+const CMD_ID_REPORT_HARDWARE: i32 = -15;
 
 enum PollMode {
     Active { ticks_without_commands: u32 },
@@ -29,14 +47,21 @@ enum PollMode {
 struct Postman {
     shutdown: ShutdownSignals,
     police: PoliceHandle,
+    events: EventBus,
     receiver: mpsc::Receiver<PostmanMessage>,
     commander: CommanderHandle,
+    downloader: DownloaderHandle,
+    ota: OtaHandle,
+    updater: UpdaterHandle,
     magic: MagicHandle,
     session: SessionHandle,
+    connectivity: ConnectivityHandle,
     network: NetworkClient,
     hostname: String,
     token: Option<String>,
     problems: Option<u32>,
+    /// Whether the last `/home` post got through; `None` until the first one.
+    api_reachable: Option<bool>,
     poll_mode: PollMode,
     services_to_check: Vec<ServiceCheck>,
 }
@@ -45,27 +70,39 @@ struct Postman {
 enum PostmanMessage {}
 
 impl Postman {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         shutdown: ShutdownSignals,
         police: PoliceHandle,
+        events: EventBus,
         receiver: mpsc::Receiver<PostmanMessage>,
         commander: CommanderHandle,
+        downloader: DownloaderHandle,
+        ota: OtaHandle,
+        updater: UpdaterHandle,
         magic: MagicHandle,
         session: SessionHandle,
+        connectivity: ConnectivityHandle,
     ) -> Self {
         let network = NetworkClient::default();
 
         Self {
             shutdown,
             police,
+            events,
             receiver,
             commander,
+            downloader,
+            ota,
+            updater,
             network,
             magic,
             session,
+            connectivity,
             token: None,
             hostname: "".to_owned(),
             problems: None,
+            api_reachable: None,
             poll_mode: PollMode::Idle,
             services_to_check: Vec::new(),
         }
@@ -81,6 +118,7 @@ impl Postman {
 
         self.token = self.magic.get_token().await;
         let mut system_info = SystemInfo::new().await;
+        let mut hardware = hardware::collect().await;
 
         self.commander
             .insert_result(vec![
@@ -101,6 +139,13 @@ impl Postman {
                     command: SafeCommandRx::GetNetwork,
                     status: 0,
                 },
+                SafeCommandResponse {
+                    id: CMD_ID_REPORT_HARDWARE,
+                    command: SafeCommandRx::HardwareInventory {
+                        inventory: hardware.clone(),
+                    },
+                    status: 0,
+                },
             ])
             .await;
 
@@ -110,6 +155,41 @@ impl Postman {
             commander.insert_result(vec![nm_profiles]).await;
         });
 
+        // Report the secrets public key so the api can (re)seal this device's
+        // secrets to it and queue them back.
+        let key_dir = Path::new(secrets::KEYS_DIR);
+        match tokio::task::spawn_blocking(move || secrets::load_or_create_key(key_dir)).await {
+            Ok(Ok(key)) => {
+                self.commander
+                    .insert_result(vec![SafeCommandResponse {
+                        id: CMD_ID_GET_SECRETS,
+                        command: SafeCommandRx::GetSecrets {
+                            public_key: secrets::public_key_base64(&key),
+                        },
+                        status: 0,
+                    }])
+                    .await;
+            }
+            Ok(Err(err)) => error!("Failed to load device secrets key: {err:#}"),
+            Err(err) => error!("Secrets key task panicked: {err}"),
+        }
+
+        // The api peers the device with a VPN concentrator under this key.
+        match vpn::load_or_create_key().await {
+            Ok(key) => {
+                self.commander
+                    .insert_result(vec![SafeCommandResponse {
+                        id: CMD_ID_REPORT_WIREGUARD_KEY,
+                        command: SafeCommandRx::WireGuardKey {
+                            public_key: secrets::public_key_base64(&key),
+                        },
+                        status: 0,
+                    }])
+                    .await;
+            }
+            Err(err) => error!("Failed to load the WireGuard key: {err:#}"),
+        }
+
         const IDLE_INTERVAL_SECS: u64 = 20;
         const ACTIVE_INTERVAL_SECS: u64 = 1;
         const IDLE_THRESHOLD_TICKS: u32 = 60;
@@ -117,6 +197,14 @@ impl Postman {
         let mut keep_alive_interval = time::interval(Duration::from_secs(IDLE_INTERVAL_SECS));
         keep_alive_interval.set_missed_tick_behavior(time::MissedTickBehavior::Skip); // or ::Delay
         let mut update_interval = time::interval(Duration::from_secs(300));
+        let mut bandwidth_usage = None;
+        // Sampled on the update interval and sent with the next post; `wg`
+        // is not worth running every second in active polling.
+        let mut vpn_status = None;
+        // Nothing reported yet, so the first poll sends the state even if the
+        // OTA actor settled an update from before the reboot already.
+        let mut ota_state = None;
+        let mut last_upgrade = None;
 
         loop {
             tokio::select! {
@@ -129,12 +217,49 @@ impl Postman {
                         continue;
                     }
 
+                    // Picked up on the poll after the change, so fast enough
+                    // for download progress without a report of its own.
+                    let state = self.ota.state();
+                    if ota_state.as_ref() != Some(&state) {
+                        ota_state = Some(state.clone());
+                        self.commander
+                            .insert_result(vec![SafeCommandResponse {
+                                id: CMD_ID_REPORT_OTA,
+                                command: SafeCommandRx::OtaState { state },
+                                status: 0,
+                            }])
+                            .await;
+                    }
+
+                    // A retry that fails the same way is not reported again.
+                    let upgrade = self.updater.last_upgrade();
+                    if upgrade.is_some() && upgrade != last_upgrade {
+                        last_upgrade = upgrade.clone();
+                        if let Some(report) = upgrade {
+                            let status = if report.success { 0 } else { 1 };
+                            self.commander
+                                .insert_result(vec![SafeCommandResponse {
+                                    id: CMD_ID_REPORT_UPGRADE,
+                                    command: SafeCommandRx::UpgradeResult { report },
+                                    status,
+                                }])
+                                .await;
+                        }
+                    }
+
                     let responses = self.commander.get_results().await;
 
                     let release_id = self.magic.get_release_id().await.ok();
                     let service_statuses = self.check_services().await;
-
-                    let ping_home_body = HomePost::new(responses, release_id, service_statuses);
+                    let connectivity = self.connectivity.report().await;
+
+                    let ping_home_body = HomePost::new(
+                        responses,
+                        release_id,
+                        service_statuses,
+                        connectivity.as_ref().map(|snapshot| snapshot.report.clone()),
+                        vpn_status.take(),
+                    );
 
                     let response = self.ping_home(ping_home_body).await;
 
@@ -145,6 +270,17 @@ impl Postman {
                         self.magic.set_target_release_id(target_release_id).await;
                     }
 
+                    // A failed post yields a default response; keep the mirrors
+                    // we had rather than drop them just when they are needed.
+                    if !response.timestamp.is_zero() {
+                        self.magic.set_api_mirrors(response.mirrors).await;
+                    }
+                    if response.connectivity_saved
+                        && let Some(snapshot) = &connectivity
+                    {
+                        self.connectivity.acknowledge(snapshot).await;
+                    }
+
                     let has_commands = !response.commands.is_empty();
                     self.commander.execute_api_batch(response.commands).await;
 
@@ -183,6 +319,39 @@ impl Postman {
                             ])
                             .await;
                     }
+
+                    // Disks, cameras and displays come and go while running.
+                    let new_hardware = hardware::collect().await;
+                    if new_hardware != hardware {
+                        hardware = new_hardware;
+                        self.commander
+                            .insert_result(vec![SafeCommandResponse {
+                                id: CMD_ID_REPORT_HARDWARE,
+                                command: SafeCommandRx::HardwareInventory {
+                                    inventory: hardware.clone(),
+                                },
+                                status: 0,
+                            }])
+                            .await;
+                    }
+
+                    vpn_status = vpn::status().await;
+
+                    // Also sent on the first tick, so the api can tell whether
+                    // the device has the policy its labels call for.
+                    let usage = self.downloader.bandwidth_usage().await;
+                    if usage.is_some() && usage != bandwidth_usage {
+                        bandwidth_usage = usage.clone();
+                        self.commander
+                            .insert_result(vec![SafeCommandResponse {
+                                id: CMD_ID_REPORT_BANDWIDTH,
+                                command: SafeCommandRx::BandwidthUsage {
+                                    usage: usage.unwrap_or_default(),
+                                },
+                                status: 0,
+                            }])
+                            .await;
+                    }
                 }
                 _ = self.shutdown.token.cancelled() => {
                     break;
@@ -203,6 +372,22 @@ impl Postman {
             .map(|service| async move {
                 let id = service.id;
                 let name = service.name;
+
+                if let Some(container) = name.strip_prefix(CONTAINER_SERVICE_PREFIX) {
+                    let status = match Runtime::detect() {
+                        Some(runtime) => runtime.service_status(container).await,
+                        None => Err(anyhow!("no container runtime socket found")),
+                    };
+                    let (active_state, n_restarts) = status.unwrap_or_else(|err| {
+                        error!("Failed to check container {}: {:#}", container, err);
+                        ("unknown".to_string(), 0)
+                    });
+                    return ServiceStatus {
+                        id,
+                        active_state,
+                        n_restarts,
+                    };
+                }
                 let result = tokio::time::timeout(
                     std::time::Duration::from_secs(5),
                     tokio::process::Command::new("systemctl")
@@ -270,10 +455,14 @@ impl Postman {
         if self.token.is_none() {
             warn!("!NO TOKEN! trying to register device");
 
+            let serial_number = self.network.get_serial();
+            let identity = self.identity_proof(&serial_number).await?;
+
             let response = self
                 .register_device(DeviceRegistration {
-                    serial_number: self.network.get_serial(),
+                    serial_number,
                     wifi_mac: self.network.get_mac_wlan0(),
+                    identity,
                 })
                 .await?;
 
@@ -289,6 +478,36 @@ impl Postman {
         Ok(())
     }
 
+    /// Signs a fresh registration challenge with the identity key. Returns
+    /// `None` against an api that does not hand out challenges yet.
+    async fn identity_proof(&self, serial_number: &str) -> Result<Option<IdentityProof>> {
+        let Some(challenge) = self.network.get_challenge().await? else {
+            warn!("Api does not support identity challenges; registering without a key");
+            return Ok(None);
+        };
+
+        let key_dir = Path::new(secrets::KEYS_DIR);
+        let key =
+            tokio::task::spawn_blocking(move || identity::load_or_create_key(key_dir)).await??;
+
+        Ok(Some(IdentityProof {
+            public_key: identity::public_key_base64(&key),
+            signature: identity::sign(&key, Purpose::Register, serial_number, &challenge),
+            challenge,
+        }))
+    }
+
+    /// Publishes a connectivity event when reachability of the api changes.
+    /// Any HTTP response counts as reachable; only transport failures do not.
+    fn set_api_reachable(&mut self, reachable: bool) {
+        let previous = self.api_reachable.replace(reachable);
+        match (previous, reachable) {
+            (Some(false), true) => self.events.publish(DaemonEvent::ApiConnectivityRestored),
+            (None | Some(true), false) => self.events.publish(DaemonEvent::ApiConnectivityLost),
+            _ => {}
+        }
+    }
+
     async fn ping_home(&mut self, message: HomePost) -> HomePostResponse {
         // Prefer the short-lived JWT from session; fall back to the opaque
         // token (which is also what session returns when no JWT is cached).
@@ -304,6 +523,8 @@ impl Postman {
             .send_compressed_post(&token, "/home", &message)
             .await;
 
+        self.set_api_reachable(result.is_ok());
+
         match result {
             Ok((status_code, response)) => match status_code {
                 StatusCode::OK => {
@@ -316,16 +537,16 @@ impl Postman {
                 }
                 StatusCode::UNAUTHORIZED => {
                     // Standard access/refresh-token flow: the bearer (probably
-                    // an expired JWT) was rejected. Try to mint a new JWT from
-                    // the opaque "refresh" token. Only unregister if the
-                    // opaque token itself is also rejected.
+                    // an expired JWT) was rejected. Try to mint a new JWT with
+                    // the identity key. Only unregister if the device itself
+                    // is rejected.
                     warn!("Got 401 on /home; attempting JWT refresh");
                     match self.session.force_refresh().await {
                         RefreshOutcome::Refreshed => {
                             info!("Refreshed JWT after 401; next ping will retry");
                         }
                         RefreshOutcome::Unauthorized => {
-                            warn!("Opaque token rejected by /auth/session; unregistering device");
+                            warn!("Device rejected by /auth/session; unregistering device");
                             self.unregister_device().await;
                         }
                         RefreshOutcome::Transient => {
@@ -383,15 +604,33 @@ pub struct PostmanHandle {
 }
 
 impl PostmanHandle {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         shutdown: ShutdownSignals,
         police: PoliceHandle,
+        events: EventBus,
         commander: CommanderHandle,
+        downloader: DownloaderHandle,
+        ota: OtaHandle,
+        updater: UpdaterHandle,
         magic: MagicHandle,
         session: SessionHandle,
+        connectivity: ConnectivityHandle,
     ) -> Self {
         let (_sender, receiver) = mpsc::channel(8);
-        let mut actor = Postman::new(shutdown, police, receiver, commander, magic, session);
+        let mut actor = Postman::new(
+            shutdown,
+            police,
+            events,
+            receiver,
+            commander,
+            downloader,
+            ota,
+            updater,
+            magic,
+            session,
+            connectivity,
+        );
         tokio::spawn(async move { actor.run().await });
 
         Self { _sender }
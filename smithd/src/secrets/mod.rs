@@ -0,0 +1,380 @@
+//! Per-device secret delivery.
+//!
+//! Every device holds an X25519 keypair generated on first boot. The api seals
+//! each secret to the device's public key (a libsodium sealed box), so what sits
+//! in `device_secret` and `command_queue` is ciphertext only this daemon can
+//! open. The sealing half lives here too so the api and the daemon cannot
+//! disagree on the format.
+
+use crate::utils::schema::{SealedSecret, SecretDelivery};
+use anyhow::{Context, Result, anyhow};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use crypto_box::aead::OsRng;
+use crypto_box::{PublicKey, SecretKey};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+use tracing::{error, info, warn};
+
+/// Root-only directory holding the daemon's private keys.
+pub const KEYS_DIR: &str = "/etc/smith/keys";
+/// One file per `SecretDelivery::File` secret, named after the secret.
+pub const SECRETS_DIR: &str = "/etc/smith/secrets";
+/// `NAME=value` lines for every `SecretDelivery::Env` secret, suitable for a
+/// systemd `EnvironmentFile=`.
+pub const SECRETS_ENV_FILE: &str = "/etc/smith/secrets.env";
+
+const SECRET_KEY_FILE: &str = "secrets.x25519";
+
+/// Loads the device's X25519 key from `dir`, generating and persisting one on
+/// first use. The key never leaves the device; only its public half is reported.
+pub fn load_or_create_key(dir: &Path) -> Result<SecretKey> {
+    load_or_create_key_file(dir, SECRET_KEY_FILE)
+}
+
+/// Like [`load_or_create_key`], for a key kept apart from the secrets one.
+pub(crate) fn load_or_create_key_file(dir: &Path, file: &str) -> Result<SecretKey> {
+    let path = dir.join(file);
+
+    match std::fs::read(&path) {
+        Ok(bytes) => {
+            let bytes: [u8; 32] = bytes
+                .try_into()
+                .map_err(|_| anyhow!("{} is not a 32 byte key", path.display()))?;
+            return Ok(SecretKey::from_bytes(bytes));
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err).with_context(|| format!("reading {}", path.display())),
+    }
+
+    std::fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+    std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))
+        .with_context(|| format!("securing {}", dir.display()))?;
+
+    let key = SecretKey::generate(&mut OsRng);
+    write_private(&path, &key.to_bytes())?;
+    info!("Generated device key at {}", path.display());
+
+    Ok(key)
+}
+
+pub fn public_key_base64(key: &SecretKey) -> String {
+    BASE64.encode(key.public_key().as_bytes())
+}
+
+/// Seals `plaintext` to a device's base64 public key. Used by the api.
+pub fn seal(public_key: &str, plaintext: &[u8]) -> Result<String> {
+    let bytes: [u8; 32] = BASE64
+        .decode(public_key.trim())
+        .context("public key is not valid base64")?
+        .try_into()
+        .map_err(|_| anyhow!("public key is not 32 bytes"))?;
+
+    let sealed = PublicKey::from(bytes)
+        .seal(&mut OsRng, plaintext)
+        .map_err(|_| anyhow!("failed to seal secret"))?;
+
+    Ok(BASE64.encode(sealed))
+}
+
+pub fn open(key: &SecretKey, ciphertext: &str) -> Result<Vec<u8>> {
+    let sealed = BASE64
+        .decode(ciphertext.trim())
+        .context("ciphertext is not valid base64")?;
+    key.unseal(&sealed)
+        .map_err(|_| anyhow!("ciphertext was not sealed to this device's key"))
+}
+
+/// Secret names become file names and environment variable names, so only
+/// `[A-Za-z0-9_]` is accepted and the name may not start with a digit.
+pub fn validate_name(name: &str) -> Result<()> {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return Err(anyhow!("secret name must start with a letter or '_'")),
+    }
+    if !chars.all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(anyhow!("secret name may only contain [A-Za-z0-9_]"));
+    }
+    Ok(())
+}
+
+/// Replaces the device's secrets with `secrets`: opens each one, writes it to
+/// `dir` or `env_file` according to its delivery, and removes any secret no
+/// longer in the set. Returns the names that could not be applied; the others are
+/// still written so one bad secret does not hold back the rest, and a secret that
+/// fails keeps whatever value it had, so a bad ciphertext can't wipe a working
+/// credential.
+pub fn apply(
+    key: &SecretKey,
+    secrets: &[SealedSecret],
+    dir: &Path,
+    env_file: &Path,
+) -> Vec<String> {
+    let mut failed = Vec::new();
+    let mut files = BTreeMap::new();
+    let mut env = BTreeMap::new();
+
+    for secret in secrets {
+        let value = validate_name(&secret.name).and_then(|_| open(key, &secret.ciphertext));
+        match (value, secret.delivery) {
+            (Ok(value), SecretDelivery::File) => {
+                files.insert(secret.name.as_str(), value);
+            }
+            (Ok(value), SecretDelivery::Env) => match String::from_utf8(value) {
+                Ok(value) if !value.contains('\n') => {
+                    env.insert(secret.name.as_str(), value);
+                }
+                _ => {
+                    warn!(
+                        name = secret.name,
+                        "Env secret is not a single line of UTF-8"
+                    );
+                    failed.push(secret.name.clone());
+                }
+            },
+            (Err(err), _) => {
+                warn!(name = secret.name, "Failed to open secret: {err:#}");
+                failed.push(secret.name.clone());
+            }
+        }
+    }
+
+    let kept: BTreeSet<String> = failed.iter().cloned().collect();
+
+    if let Err(err) = write_files(dir, &files, &kept) {
+        error!("Failed to write secrets to {}: {err:#}", dir.display());
+        failed.extend(files.keys().map(|name| name.to_string()));
+    }
+
+    let mut lines: BTreeMap<String, String> = previous_env_lines(env_file)
+        .into_iter()
+        .filter(|(name, _)| kept.contains(name.as_str()))
+        .collect();
+    lines.extend(
+        env.iter()
+            .map(|(name, value)| (name.to_string(), format!("{name}={}", quote_env(value)))),
+    );
+    let contents: String = lines.values().map(|line| format!("{line}\n")).collect();
+    if let Err(err) = write_private(env_file, contents.as_bytes()) {
+        error!("Failed to write {}: {err:#}", env_file.display());
+        failed.extend(env.keys().map(|name| name.to_string()));
+    }
+
+    failed
+}
+
+/// The lines of the current env file by name, as written.
+fn previous_env_lines(env_file: &Path) -> BTreeMap<String, String> {
+    std::fs::read_to_string(env_file)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let (name, _) = line.split_once('=')?;
+            Some((name.to_string(), line.to_string()))
+        })
+        .collect()
+}
+
+/// Writes `files` and removes every other file but those in `kept`.
+fn write_files(dir: &Path, files: &BTreeMap<&str, Vec<u8>>, kept: &BTreeSet<String>) -> Result<()> {
+    std::fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+    std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))
+        .with_context(|| format!("securing {}", dir.display()))?;
+
+    for (name, value) in files {
+        write_private(&dir.join(name), value)?;
+    }
+
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let stale = name.to_str().is_none_or(|name| {
+            !name.starts_with('.') && !files.contains_key(name) && !kept.contains(name)
+        });
+        if stale {
+            std::fs::remove_file(entry.path())
+                .with_context(|| format!("removing {}", entry.path().display()))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes `contents` as a 0600 file via a temp file and rename, so a reader
+/// never sees a half-written secret and the mode is right from the first byte.
+pub(crate) fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    let tmp = path.with_file_name(format!(
+        ".{}.tmp",
+        path.file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("secret")
+    ));
+
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp)
+        .with_context(|| format!("creating {}", tmp.display()))?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+
+    std::fs::rename(&tmp, path).with_context(|| format!("replacing {}", path.display()))
+}
+
+/// systemd's `EnvironmentFile=` strips unquoted whitespace and interprets
+/// backslashes, so every value is double-quoted with `"` and `\` escaped.
+fn quote_env(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sealed_secret_opens_only_with_the_device_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = load_or_create_key(dir.path()).unwrap();
+        let other = SecretKey::generate(&mut OsRng);
+
+        let sealed = seal(&public_key_base64(&key), b"hunter2").unwrap();
+
+        assert_eq!(open(&key, &sealed).unwrap(), b"hunter2");
+        assert!(open(&other, &sealed).is_err());
+    }
+
+    #[test]
+    fn key_is_persisted_across_loads() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = load_or_create_key(dir.path()).unwrap();
+        let second = load_or_create_key(dir.path()).unwrap();
+
+        assert_eq!(public_key_base64(&first), public_key_base64(&second));
+
+        let mode = std::fs::metadata(dir.path().join(SECRET_KEY_FILE))
+            .unwrap()
+            .permissions()
+            .mode()
+            & 0o777;
+        assert_eq!(mode, 0o600);
+    }
+
+    #[test]
+    fn names_that_could_escape_the_secrets_dir_are_rejected() {
+        assert!(validate_name("DB_PASSWORD").is_ok());
+        assert!(validate_name("_token2").is_ok());
+        assert!(validate_name("../etc/shadow").is_err());
+        assert!(validate_name("1PASSWORD").is_err());
+        assert!(validate_name("").is_err());
+    }
+
+    #[test]
+    fn apply_replaces_the_whole_set() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = load_or_create_key(&dir.path().join("keys")).unwrap();
+        let public = public_key_base64(&key);
+        let secrets_dir = dir.path().join("secrets");
+        let env_file = dir.path().join("secrets.env");
+
+        let sealed = |name: &str, value: &str, delivery| SealedSecret {
+            name: name.to_string(),
+            ciphertext: seal(&public, value.as_bytes()).unwrap(),
+            delivery,
+        };
+
+        let failed = apply(
+            &key,
+            &[
+                sealed("OLD", "stale", SecretDelivery::File),
+                sealed("API_KEY", "abc\"123", SecretDelivery::Env),
+            ],
+            &secrets_dir,
+            &env_file,
+        );
+        assert!(failed.is_empty());
+        assert_eq!(
+            std::fs::read_to_string(&env_file).unwrap(),
+            "API_KEY=\"abc\\\"123\"\n"
+        );
+
+        let failed = apply(
+            &key,
+            &[
+                sealed("CERT", "-----BEGIN-----\n", SecretDelivery::File),
+                SealedSecret {
+                    name: "BROKEN".to_string(),
+                    ciphertext: "not-a-box".to_string(),
+                    delivery: SecretDelivery::File,
+                },
+            ],
+            &secrets_dir,
+            &env_file,
+        );
+
+        assert_eq!(failed, vec!["BROKEN".to_string()]);
+        assert_eq!(
+            std::fs::read_to_string(secrets_dir.join("CERT")).unwrap(),
+            "-----BEGIN-----\n"
+        );
+        assert!(!secrets_dir.join("OLD").exists());
+        assert_eq!(std::fs::read_to_string(&env_file).unwrap(), "");
+    }
+
+    #[test]
+    fn a_secret_that_fails_to_open_keeps_its_value() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = load_or_create_key(&dir.path().join("keys")).unwrap();
+        let public = public_key_base64(&key);
+        let secrets_dir = dir.path().join("secrets");
+        let env_file = dir.path().join("secrets.env");
+
+        let sealed = |name: &str, value: &str, delivery| SealedSecret {
+            name: name.to_string(),
+            ciphertext: seal(&public, value.as_bytes()).unwrap(),
+            delivery,
+        };
+        let broken = |name: &str, delivery| SealedSecret {
+            name: name.to_string(),
+            ciphertext: "not-a-box".to_string(),
+            delivery,
+        };
+
+        let failed = apply(
+            &key,
+            &[
+                sealed("CERT", "v1", SecretDelivery::File),
+                sealed("TOKEN", "t1", SecretDelivery::Env),
+                sealed("GONE", "g1", SecretDelivery::Env),
+            ],
+            &secrets_dir,
+            &env_file,
+        );
+        assert!(failed.is_empty());
+
+        let failed = apply(
+            &key,
+            &[
+                broken("CERT", SecretDelivery::File),
+                broken("TOKEN", SecretDelivery::Env),
+                sealed("NEW", "n1", SecretDelivery::Env),
+            ],
+            &secrets_dir,
+            &env_file,
+        );
+        assert_eq!(failed, vec!["CERT".to_string(), "TOKEN".to_string()]);
+        assert_eq!(
+            std::fs::read_to_string(secrets_dir.join("CERT")).unwrap(),
+            "v1"
+        );
+        assert_eq!(
+            std::fs::read_to_string(&env_file).unwrap(),
+            "NEW=\"n1\"\nTOKEN=\"t1\"\n"
+        );
+    }
+}
@@ -4,26 +4,37 @@
 //! path, smith api verifies locally) or the long-lived opaque token from
 //! magic.toml as fallback when no JWT is available yet.
 //!
-//! The JWT is never persisted — on restart the daemon re-mints it from the
-//! opaque token. This means a smith-api outage longer than the JWT lifetime
-//! degrades us back to opaque-token traffic, which still works.
+//! Refresh signs a single-use challenge with the device identity key
+//! (`crate::identity`) rather than sending the opaque token, falling back to the
+//! token only against an api that predates identity keys.
+//!
+//! The JWT is never persisted — on restart the daemon re-mints it. This means a
+//! smith-api outage longer than the JWT lifetime degrades us back to
+//! opaque-token traffic, which still works.
 
+use crate::identity::{self, Purpose};
 use crate::magic::MagicHandle;
+use crate::secrets;
 use crate::shutdown::ShutdownSignals;
+use crate::utils::schema::{Challenge, IdentityProof, SessionProof};
+use crate::utils::system::get_serial_number;
 use anyhow::{Context, anyhow};
+use ed25519_dalek::SigningKey;
 use reqwest::StatusCode;
 use serde::Deserialize;
+use std::path::Path;
 use std::time::Duration;
 use tokio::sync::{mpsc, oneshot};
 use tracing::{debug, error, info, warn};
 
-/// Outcome of an attempt to mint a new JWT from the opaque token.
+/// Outcome of an attempt to mint a new JWT.
 /// Lets callers distinguish "refresh token is dead, give up" from
 /// "transient blip, try again later".
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RefreshOutcome {
     Refreshed,
-    /// `/auth/session` returned 401 — the opaque token is revoked.
+    /// `/auth/session` returned 401 — the device is revoked or its identity
+    /// key was rejected.
     Unauthorized,
     /// Network error, 5xx, parse failure, or no opaque token yet.
     Transient,
@@ -38,7 +49,7 @@ enum RefreshError {
 impl std::fmt::Display for RefreshError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            RefreshError::Unauthorized => write!(f, "opaque token rejected (401)"),
+            RefreshError::Unauthorized => write!(f, "session refresh rejected (401)"),
             RefreshError::Other(err) => write!(f, "{err:#}"),
         }
     }
@@ -74,6 +85,7 @@ struct Session {
     jwt: Option<String>,
     /// Unix seconds when the current JWT expires.
     expires_at: u64,
+    identity: Option<SigningKey>,
 }
 
 impl Session {
@@ -98,6 +110,7 @@ impl Session {
             http,
             jwt: None,
             expires_at: 0,
+            identity: None,
         }
     }
 
@@ -118,21 +131,13 @@ impl Session {
     }
 
     async fn refresh(&mut self) -> std::result::Result<(), RefreshError> {
-        let opaque = self.magic.get_token().await.ok_or_else(|| {
-            RefreshError::Other(anyhow!("no opaque device token available; cannot mint JWT"))
-        })?;
         let server = self.magic.get_server().await;
-
         let url = build_session_url(&server).map_err(RefreshError::Other)?;
 
-        let response = self
-            .http
-            .get(url)
-            .header("Authorization", format!("Bearer {}", opaque))
-            .send()
-            .await
-            .context("smith api /auth/session request failed")
-            .map_err(RefreshError::Other)?;
+        let response = match self.session_with_identity(&server, &url).await? {
+            Some(response) => response,
+            None => self.session_with_token(&url).await?,
+        };
 
         let status = response.status();
         if status == StatusCode::UNAUTHORIZED {
@@ -162,6 +167,147 @@ impl Session {
         Ok(())
     }
 
+    /// Proves possession of the identity key instead of sending the opaque
+    /// token. Returns `None` when the api can't take a proof (it predates
+    /// identity keys, or the key isn't pinned and pinning it failed), so the
+    /// caller falls back to the opaque token.
+    async fn session_with_identity(
+        &mut self,
+        server: &str,
+        url: &str,
+    ) -> std::result::Result<Option<reqwest::Response>, RefreshError> {
+        let Some(response) = self.post_session_proof(server, url).await? else {
+            return Ok(None);
+        };
+
+        if response.status() != StatusCode::CONFLICT {
+            return Ok(Some(response));
+        }
+
+        // Registered before identity keys existed: pin ours using the token we
+        // already hold, then prove it.
+        if let Err(err) = self.enroll_identity(server).await {
+            warn!("Failed to pin device identity key, using opaque token: {err:#}");
+            return Ok(None);
+        }
+        self.post_session_proof(server, url).await
+    }
+
+    async fn post_session_proof(
+        &mut self,
+        server: &str,
+        url: &str,
+    ) -> std::result::Result<Option<reqwest::Response>, RefreshError> {
+        let Some(challenge) = self.challenge(server).await? else {
+            return Ok(None);
+        };
+        let key = self.identity_key().await.map_err(RefreshError::Other)?;
+        let serial_number = get_serial_number();
+
+        let proof = SessionProof {
+            signature: identity::sign(&key, Purpose::Session, &serial_number, &challenge),
+            serial_number,
+            challenge,
+        };
+
+        let response = self
+            .http
+            .post(url)
+            .json(&proof)
+            .send()
+            .await
+            .context("smith api /auth/session request failed")
+            .map_err(RefreshError::Other)?;
+
+        Ok(Some(response))
+    }
+
+    async fn session_with_token(
+        &self,
+        url: &str,
+    ) -> std::result::Result<reqwest::Response, RefreshError> {
+        let opaque = self.magic.get_token().await.ok_or_else(|| {
+            RefreshError::Other(anyhow!("no opaque device token available; cannot mint JWT"))
+        })?;
+
+        self.http
+            .get(url)
+            .header("Authorization", format!("Bearer {}", opaque))
+            .send()
+            .await
+            .context("smith api /auth/session request failed")
+            .map_err(RefreshError::Other)
+    }
+
+    async fn enroll_identity(&mut self, server: &str) -> anyhow::Result<()> {
+        let opaque = self
+            .magic
+            .get_token()
+            .await
+            .ok_or_else(|| anyhow!("no opaque device token available"))?;
+        let challenge = self
+            .challenge(server)
+            .await
+            .map_err(|err| anyhow!("{err}"))?
+            .ok_or_else(|| anyhow!("api does not issue challenges"))?;
+        let key = self.identity_key().await?;
+
+        let proof = IdentityProof {
+            public_key: identity::public_key_base64(&key),
+            signature: identity::sign(&key, Purpose::Register, &get_serial_number(), &challenge),
+            challenge,
+        };
+
+        self.http
+            .post(format!("{}/identity", server.trim_end_matches('/')))
+            .header("Authorization", format!("Bearer {}", opaque))
+            .json(&proof)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        info!("Pinned device identity key with smith api");
+        Ok(())
+    }
+
+    /// `None` if the api predates identity keys.
+    async fn challenge(&self, server: &str) -> std::result::Result<Option<String>, RefreshError> {
+        let response = self
+            .http
+            .post(format!("{}/challenge", server.trim_end_matches('/')))
+            .send()
+            .await
+            .context("smith api /challenge request failed")
+            .map_err(RefreshError::Other)?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let challenge: Challenge = response
+            .error_for_status()
+            .context("smith api /challenge failed")
+            .map_err(RefreshError::Other)?
+            .json()
+            .await
+            .context("failed to parse /challenge response")
+            .map_err(RefreshError::Other)?;
+
+        Ok(Some(challenge.challenge))
+    }
+
+    async fn identity_key(&mut self) -> anyhow::Result<SigningKey> {
+        if let Some(key) = &self.identity {
+            return Ok(key.clone());
+        }
+
+        let key_dir = Path::new(secrets::KEYS_DIR);
+        let key =
+            tokio::task::spawn_blocking(move || identity::load_or_create_key(key_dir)).await??;
+        self.identity = Some(key.clone());
+        Ok(key)
+    }
+
     async fn handle_message(&mut self, msg: SessionMessage) {
         match msg {
             SessionMessage::GetBearer { rpc } => {
@@ -178,7 +324,7 @@ impl Session {
                 let outcome = match self.refresh().await {
                     Ok(()) => RefreshOutcome::Refreshed,
                     Err(RefreshError::Unauthorized) => {
-                        warn!("Forced JWT refresh: opaque token rejected (401)");
+                        warn!("Forced JWT refresh rejected (401)");
                         RefreshOutcome::Unauthorized
                     }
                     Err(RefreshError::Other(err)) => {
@@ -269,9 +415,8 @@ impl SessionHandle {
         fut.await.ok().flatten()
     }
 
-    /// Try to mint a fresh JWT from the opaque token. Call this when the API
-    /// returns 401 so callers can distinguish a recoverable JWT expiry from a
-    /// terminal opaque-token revocation.
+    /// Try to mint a fresh JWT. Call this when the API returns 401 so callers
+    /// can distinguish a recoverable JWT expiry from a terminal revocation.
     pub async fn force_refresh(&self) -> RefreshOutcome {
         let (rpc, fut) = oneshot::channel();
         if let Err(err) = self.sender.send(SessionMessage::ForceRefresh { rpc }).await {
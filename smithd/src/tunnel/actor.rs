@@ -1,3 +1,4 @@
+use crate::events::{DaemonEvent, EventBus};
 use crate::magic::MagicHandle;
 use crate::shutdown::ShutdownSignals;
 use crate::utils::files::{add_key, ensure_ssh_dir, remove_key};
@@ -22,8 +23,12 @@ struct ForwardConnection {
 }
 
 impl ForwardConnection {
-    async fn remove(&self) {
+    async fn remove(&self, local: u16, events: &EventBus) {
         self.task.abort();
+        events.publish(DaemonEvent::TunnelClosed {
+            local_port: local,
+            remote_port: self.remote,
+        });
         if let Some(remote_login) = &self.remote_login {
             info!("Removing remote login info");
             let res = remove_key(&remote_login.user, &self.tag).await;
@@ -50,6 +55,7 @@ pub struct Actor {
     shutdown: ShutdownSignals,
     receiver: mpsc::Receiver<ActorMessage>,
     magic: MagicHandle,
+    events: EventBus,
     ports: HashMap<u16, ForwardConnection>,
 }
 
@@ -58,11 +64,13 @@ impl Actor {
         shutdown: ShutdownSignals,
         receiver: mpsc::Receiver<ActorMessage>,
         magic: MagicHandle,
+        events: EventBus,
     ) -> Self {
         Self {
             shutdown,
             receiver,
             magic,
+            events,
             ports: HashMap::new(),
         }
     }
@@ -147,6 +155,11 @@ impl Actor {
 
                 _ = remote.send(port);
 
+                self.events.publish(DaemonEvent::TunnelOpened {
+                    local_port: local,
+                    remote_port: port,
+                });
+
                 self.ports.insert(
                     local,
                     ForwardConnection {
@@ -160,7 +173,7 @@ impl Actor {
             }
             ActorMessage::ClosePort { local } => {
                 if let Some(conn) = self.ports.remove(&local) {
-                    conn.remove().await;
+                    conn.remove(local, &self.events).await;
                 }
             }
         }
@@ -180,7 +193,7 @@ impl Actor {
         for port in to_remove {
             info!("Closing port {} due to timeout", port);
             if let Some(conn) = self.ports.remove(&port) {
-                conn.remove().await;
+                conn.remove(port, &self.events).await;
             }
         }
     }
@@ -1,4 +1,5 @@
 use super::actor::{Actor, ActorMessage, RemoteLogin};
+use crate::events::EventBus;
 use crate::magic::MagicHandle;
 use crate::shutdown::ShutdownSignals;
 use tokio::sync::{mpsc, oneshot};
@@ -9,9 +10,9 @@ pub struct Handler {
 }
 
 impl Handler {
-    pub fn new(shutdown: ShutdownSignals, magic: MagicHandle) -> Self {
+    pub fn new(shutdown: ShutdownSignals, magic: MagicHandle, events: EventBus) -> Self {
         let (sender, receiver) = mpsc::channel(8);
-        let mut actor = Actor::new(shutdown, receiver, magic);
+        let mut actor = Actor::new(shutdown, receiver, magic, events);
         tokio::spawn(async move { actor.run().await });
 
         Self { sender }
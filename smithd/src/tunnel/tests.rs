@@ -1,5 +1,6 @@
 #[tokio::test]
 async fn secret_from_magic_toml() {
+    use crate::events::EventBus;
     use crate::magic::MagicHandle;
     use crate::shutdown::ShutdownHandler;
     use bore_cli::server::Server;
@@ -58,7 +59,7 @@ secret = "{random_secret}"
     let shutdown = ShutdownHandler::new();
     let configuration = MagicHandle::new(shutdown.signals());
     configuration.load(Some(path)).await;
-    let tunnel = super::TunnelHandle::new(shutdown.signals(), configuration);
+    let tunnel = super::TunnelHandle::new(shutdown.signals(), configuration, EventBus::new());
 
     let resp = tunnel.start_tunnel(Some(local_port), None, None).await;
 
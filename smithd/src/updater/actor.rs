@@ -1,9 +1,19 @@
-use crate::downloader::DownloaderHandle;
+use super::appimage::AppImages;
+use super::cache::BlobCache;
+use super::containers;
+use super::hooks;
+use super::plan;
+use crate::downloader::{DownloadJob, DownloaderHandle};
+use crate::events::{DaemonEvent, EventBus};
 use crate::magic::MagicHandle;
 use crate::magic::structure::ConfigPackage;
 use crate::session::SessionHandle;
 use crate::shutdown::ShutdownSignals;
 use crate::utils::network::NetworkClient;
+use crate::utils::schema::{
+    ContainerSpec, DownloadPriority, HookOutput, HookStage, ReleaseHooks, UpgradePlan,
+    UpgradeReport,
+};
 use anyhow::Context;
 use anyhow::Result;
 use std::collections::HashMap;
@@ -13,6 +23,7 @@ use std::path::PathBuf;
 use tokio::process::Command;
 use tokio::sync::mpsc;
 use tokio::sync::oneshot;
+use tokio::sync::watch;
 use tokio::time::{self, Duration};
 use tracing::{error, info, warn};
 
@@ -89,7 +100,13 @@ pub enum ActorMessage {
     Update,
     Upgrade,
     Check,
-    StatusReport { rpc: oneshot::Sender<String> },
+    StatusReport {
+        rpc: oneshot::Sender<String>,
+    },
+    Plan {
+        release_id: Option<i32>,
+        rpc: oneshot::Sender<Result<UpgradePlan>>,
+    },
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -120,8 +137,14 @@ pub struct Actor {
     last_update: Option<Result<time::Instant>>,
     last_upgrade: Option<Result<time::Instant>>,
     downloader: DownloaderHandle,
+    events: EventBus,
     install_failures: HashMap<String, PackageFailure>,
     packages_dir: PathBuf,
+    cache: BlobCache,
+    appimages: AppImages,
+    /// Output of the release hooks the running upgrade has run so far.
+    hook_outputs: Vec<HookOutput>,
+    report: watch::Sender<Option<UpgradeReport>>,
 }
 
 impl Actor {
@@ -131,6 +154,8 @@ impl Actor {
         magic: MagicHandle,
         downloader: DownloaderHandle,
         session: SessionHandle,
+        events: EventBus,
+        report: watch::Sender<Option<UpgradeReport>>,
     ) -> Self {
         let network = NetworkClient::new();
 
@@ -148,8 +173,13 @@ impl Actor {
             last_update: None,
             last_upgrade: None,
             downloader,
+            events,
             install_failures: HashMap::new(),
+            cache: BlobCache::new(&packages_dir),
             packages_dir,
+            appimages: AppImages::default(),
+            hook_outputs: Vec::new(),
+            report,
         }
     }
 
@@ -233,6 +263,9 @@ impl Actor {
                     self.upgrade().await;
                 }
             }
+            ActorMessage::Plan { release_id, rpc } => {
+                let _rpc = rpc.send(self.plan_upgrade(release_id).await);
+            }
             ActorMessage::StatusReport { rpc } => {
                 let interval = |time: time::Instant| {
                     let duration = time.elapsed();
@@ -287,12 +320,59 @@ impl Actor {
     async fn upgrade(&mut self) {
         info!("Upgrading device");
         self.status = Status::Upgrading;
+        self.events.publish(DaemonEvent::UpgradeStarted);
+        self.hook_outputs.clear();
         let res = self.upgrade_device().await.map(|_| time::Instant::now());
         info!("Upgrading result: {:?}", res);
+        let report = UpgradeReport {
+            release_id: self.magic.get_target_release_id().await.ok(),
+            success: res.is_ok(),
+            error: res.as_ref().err().map(|e| format!("{e:#}")),
+            hooks: std::mem::take(&mut self.hook_outputs),
+        };
+        self.events.publish(DaemonEvent::UpgradeFinished {
+            success: report.success,
+            error: report.error.clone(),
+            hooks: report.hooks.clone(),
+        });
+        self.report.send_replace(Some(report));
         self.last_upgrade = Some(res);
         self.status = Status::Idle;
     }
 
+    /// What upgrading to `release_id`, or to the target release, would do.
+    async fn plan_upgrade(&self, release_id: Option<i32>) -> Result<UpgradePlan> {
+        let release_id = match release_id {
+            Some(release_id) => release_id,
+            None => self
+                .magic
+                .get_target_release_id()
+                .await
+                .with_context(|| "Failed to get Target Release ID")?,
+        };
+        info!(release_id, "planning upgrade");
+
+        let token = self.session.bearer_token().await.unwrap_or_default();
+        let packages = match self.network.get_release_packages(release_id, &token).await {
+            Ok(packages) => packages,
+            // Offline, a release that is already cached can still be planned.
+            Err(err) => {
+                let release_cache = self
+                    .packages_dir
+                    .join("versions")
+                    .join(release_id.to_string());
+                let content = tokio::fs::read_to_string(&release_cache)
+                    .await
+                    .map_err(|_| err)
+                    .with_context(|| "failed to fetch release packages manifest")?;
+                ConfigPackage::parse_manifest(&content)?
+            }
+        };
+
+        let blobs = self.packages_dir.join("blobs");
+        Ok(plan::plan(release_id, &packages, &blobs, &self.network, &token).await)
+    }
+
     async fn write_manifest(&self, path: &Path, contents: &str) -> Result<()> {
         if let Some(parent) = path.parent() {
             tokio::fs::create_dir_all(parent).await?;
@@ -323,8 +403,11 @@ impl Actor {
 
         info!(?remote, "downloading");
         self.downloader
-            // 2 MB/s keeps us friendly on constrained networks.
-            .download_blocking(&remote, download_to, 2.0)
+            // 2 MB/s keeps us friendly on constrained networks. Packages are
+            // small and an upgrade waits on them, so they jump bulk transfers.
+            .download_blocking(
+                DownloadJob::new(&remote, download_to, 2.0).priority(DownloadPriority::High),
+            )
             .await?;
 
         Ok(())
@@ -370,21 +453,26 @@ impl Actor {
             .await
             .with_context(|| "failed to fetch release packages manifest")?;
 
+        // Make room before downloading the release's blobs.
+        self.collect_cache(&release_packages).await;
+
         let blobs = self.packages_dir.join("blobs");
         let mut manifest = String::new();
         let mut all_cached = true;
 
         for package in &release_packages {
             info!("Processing package: {}", package.file);
+            // Pulled by the container runtime when the release is deployed.
+            if package.is_container_image() {
+                writeln!(manifest, "{}", package.manifest_line())?;
+                continue;
+            }
             let blob_path = blobs.join(&package.file);
 
             if self.blob_is_valid(&blob_path).await? {
                 info!("blob present in cache");
-                writeln!(
-                    manifest,
-                    "{} {} {}",
-                    package.name, package.version, package.file
-                )?;
+                self.cache.touch(&package.file).await;
+                writeln!(manifest, "{}", package.manifest_line())?;
                 continue;
             }
 
@@ -395,6 +483,27 @@ impl Actor {
         }
 
         if all_cached {
+            // Written before the manifest, so a cached release always has them.
+            let release_hooks = self
+                .network
+                .get_release_hooks(release_id, &token)
+                .await
+                .with_context(|| "failed to fetch release hooks")?;
+            self.write_manifest(
+                &hooks::hooks_path(&release_cache),
+                &serde_json::to_string(&release_hooks)?,
+            )
+            .await?;
+            let containers = self
+                .network
+                .get_release_containers(release_id, &token)
+                .await
+                .with_context(|| "failed to fetch release containers")?;
+            self.write_manifest(
+                &containers_path(&release_cache),
+                &serde_json::to_string(&containers)?,
+            )
+            .await?;
             self.write_manifest(&release_cache, &manifest).await?;
             info!(release_id, "release cache ready");
         } else {
@@ -491,30 +600,11 @@ impl Actor {
         let content = tokio::fs::read(&release_cache).await?;
         let content = std::str::from_utf8(&content)?;
 
-        let packages: Vec<ConfigPackage> = content
-            .lines()
-            .filter(|line| !line.trim().is_empty())
-            .map(|line| {
-                let mut parts = line.splitn(3, ' ');
-                Ok::<_, anyhow::Error>(ConfigPackage {
-                    name: parts
-                        .next()
-                        .ok_or_else(|| anyhow::anyhow!("missing name"))?
-                        .to_string(),
-                    version: parts
-                        .next()
-                        .ok_or_else(|| anyhow::anyhow!("missing version"))?
-                        .to_string(),
-                    file: parts
-                        .next()
-                        .ok_or_else(|| anyhow::anyhow!("missing file"))?
-                        .to_string(),
-                })
-            })
-            .collect::<Result<_, _>>()?;
+        let packages = ConfigPackage::parse_manifest(content)?;
+        let release_hooks = hooks::load(&release_cache).await?;
 
         // check if all packages are available locally
-        for package in &packages {
+        for package in packages.iter().filter(|p| !p.is_container_image()) {
             info!("Checking package: {}", package.name);
             let package_name = &package.name;
             let package_file = &package.file;
@@ -524,6 +614,7 @@ impl Actor {
 
             if package_file.exists() {
                 info!("Package {} exists locally", package_name);
+                self.cache.touch(&package.file).await;
                 continue;
             } else {
                 info!("Package {} does not exist locally", package_name);
@@ -537,8 +628,9 @@ impl Actor {
         // now install packages
         let mut update_smith = false;
         let mut to_install: Vec<(String, PathBuf)> = Vec::new();
+        let mut appimages: Vec<ConfigPackage> = Vec::new();
         for package in packages {
-            if self.should_skip_install(&package.name) {
+            if package.is_container_image() || self.should_skip_install(&package.name) {
                 continue;
             }
 
@@ -559,11 +651,24 @@ impl Actor {
                     update_smith = true;
                     continue;
                 }
+                if package.is_appimage() {
+                    appimages.push(package);
+                    continue;
+                }
                 let blob_path = blobs.join(&package.file);
                 to_install.push((package.name, blob_path));
             }
         }
 
+        // Nothing is installed yet, so a failure here keeps the current release.
+        self.run_hook(
+            &release_hooks,
+            HookStage::PreInstall,
+            &release_cache,
+            target_release_id,
+        )
+        .await?;
+
         // One apt transaction: everything is unpacked before any postinst runs.
         if !to_install.is_empty() {
             match self.batch_install(&to_install).await {
@@ -589,6 +694,40 @@ impl Actor {
             }
         }
 
+        // AppImages may depend on what the debs just installed.
+        let mut started = Vec::new();
+        for package in &appimages {
+            let blob_path = blobs.join(&package.file);
+            match self.appimages.install(package, &blob_path).await {
+                Ok(app) => started.push(app),
+                Err(err) => {
+                    error!("Failed to install AppImage {}: {err:#}", package.name);
+                    self.handle_install_failure(&package.name, InstallFailureKind::SystemError);
+                }
+            }
+        }
+        for (name, result) in self.appimages.confirm(started).await {
+            match result {
+                Ok(()) => {
+                    self.install_failures.remove(&name);
+                }
+                Err(err) => {
+                    error!("Failed to install AppImage {name}: {err:#}");
+                    self.handle_install_failure(&name, InstallFailureKind::SystemError);
+                }
+            }
+        }
+
+        self.deploy_containers(&release_cache).await?;
+
+        self.run_hook(
+            &release_hooks,
+            HookStage::PostInstall,
+            &release_cache,
+            target_release_id,
+        )
+        .await?;
+
         if update_smith {
             let status = Command::new("sh")
                 .arg("-c")
@@ -604,11 +743,68 @@ impl Actor {
 
         self.are_packages_up_to_date().await?;
 
+        self.run_hook(
+            &release_hooks,
+            HookStage::Verify,
+            &release_cache,
+            target_release_id,
+        )
+        .await?;
+
         self.magic.set_release_id(target_release_id).await;
 
         self.clean_up_old_packages().await
     }
 
+    /// Runs the release's hook for `stage`, if it has one, and fails the
+    /// upgrade if the hook fails.
+    async fn run_hook(
+        &mut self,
+        release_hooks: &ReleaseHooks,
+        stage: HookStage,
+        release_cache: &Path,
+        release_id: i32,
+    ) -> Result<()> {
+        let Some(hook) = release_hooks.get(stage) else {
+            return Ok(());
+        };
+        let dir = release_cache.with_extension("hooks");
+        let output = hooks::run(stage, hook, &dir, release_id).await;
+        let result = match (output.success(), output.timed_out) {
+            (true, _) => Ok(()),
+            (false, true) => Err(anyhow::anyhow!(
+                "{stage} hook timed out after {} seconds",
+                hook.timeout_sec
+            )),
+            (false, false) => Err(anyhow::anyhow!(
+                "{stage} hook failed with exit code {:?}",
+                output.exit_code
+            )),
+        };
+        self.hook_outputs.push(output);
+        result
+    }
+
+    /// Runs the containers the release cached next to `release_cache`. Caches
+    /// from before container support have none, and leave containers alone.
+    async fn deploy_containers(&self, release_cache: &Path) -> Result<()> {
+        let path = containers_path(release_cache);
+        let spec: ContainerSpec = match tokio::fs::read_to_string(&path).await {
+            Ok(contents) => serde_json::from_str(&contents)
+                .with_context(|| format!("parsing {}", path.display()))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err).with_context(|| format!("reading {}", path.display())),
+        };
+
+        match containers::Runtime::detect() {
+            Some(runtime) => runtime.deploy(&spec).await,
+            None if spec.services.is_empty() => Ok(()),
+            None => Err(anyhow::anyhow!(
+                "the release runs containers but no container runtime socket was found"
+            )),
+        }
+    }
+
     async fn batch_install(
         &self,
         to_install: &[(String, PathBuf)],
@@ -706,8 +902,7 @@ impl Actor {
     }
 
     async fn clean_up_old_packages(&self) -> Result<()> {
-        // for now we are gonna delete packages in /packages
-        // TODO: lets improve on the caching mechanism later
+        // Debs from before the blob cache sit in /packages itself.
         let mut entries = tokio::fs::read_dir(&self.packages_dir).await?;
         let mut bytes_freed: u64 = 0;
         while let Some(entry) = entries.next_entry().await? {
@@ -726,9 +921,36 @@ impl Actor {
             "Cleaned up old packages, freed {} MB",
             bytes_freed / 1024 / 1024
         );
+
+        self.collect_cache(&[]).await;
         Ok(())
     }
 
+    /// Drops the cached releases other than the current, target and previous
+    /// one, and evicts blobs over the cache quota that neither they nor
+    /// `in_flight` use. Failing to only costs disk space, so it is logged.
+    async fn collect_cache(&self, in_flight: &[ConfigPackage]) {
+        let keep: Vec<i32> = [
+            self.magic.get_release_id().await.ok(),
+            self.magic.get_target_release_id().await.ok(),
+            self.magic.get_previous_release_id().await,
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        let quota = self.magic.get_cache().await.quota_bytes();
+
+        match self.cache.collect(&keep, in_flight, quota).await {
+            Ok(collected) => info!(
+                "Collected package cache, kept releases {keep:?}, dropped {} release(s), evicted {} blob(s), freed {} MB",
+                collected.releases_dropped,
+                collected.blobs_evicted,
+                collected.bytes_freed / 1024 / 1024
+            ),
+            Err(err) => warn!("Failed to collect the package cache: {err:#}"),
+        }
+    }
+
     /// Checks whether packages are up to date.
     ///
     /// Returns `Ok` if all packages are, `Err` otherwise.
@@ -748,27 +970,7 @@ impl Actor {
         let content = tokio::fs::read(&release_cache).await?;
         let content = std::str::from_utf8(&content)?;
 
-        let packages: Vec<ConfigPackage> = content
-            .lines()
-            .filter(|line| !line.trim().is_empty())
-            .map(|line| {
-                let mut parts = line.splitn(3, ' ');
-                Ok::<_, anyhow::Error>(ConfigPackage {
-                    name: parts
-                        .next()
-                        .ok_or_else(|| anyhow::anyhow!("missing name"))?
-                        .to_string(),
-                    version: parts
-                        .next()
-                        .ok_or_else(|| anyhow::anyhow!("missing version"))?
-                        .to_string(),
-                    file: parts
-                        .next()
-                        .ok_or_else(|| anyhow::anyhow!("missing file"))?
-                        .to_string(),
-                })
-            })
-            .collect::<Result<_, _>>()?;
+        let packages = ConfigPackage::parse_manifest(content)?;
 
         // check the system version of the packages in the magic file
         for package in packages {
@@ -820,3 +1022,8 @@ impl Actor {
         info!("Updater shutting down");
     }
 }
+
+/// Where a release's container spec is cached, next to its manifest.
+fn containers_path(release_cache: &Path) -> PathBuf {
+    release_cache.with_extension("containers.json")
+}
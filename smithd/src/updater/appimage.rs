@@ -0,0 +1,444 @@
+//! AppImage packages.
+//!
+//! Each version is kept in its own directory under [`APPS_DIR`], and a
+//! `current` symlink points at the one that runs. Switching versions replaces
+//! that symlink with a rename, so the app's systemd unit never sees a half
+//! installed version, and the previous version stays around to roll back to.
+//!
+//! ```text
+//! /opt/smith/apps/teton-craft-table/
+//!     0.1.0/teton-craft-table.AppImage
+//!     0.2.0/teton-craft-table.AppImage
+//!     current -> 0.2.0
+//! ```
+use crate::magic::structure::ConfigPackage;
+use crate::utils::files::write_file_atomic;
+use anyhow::{Context, Result, anyhow};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+use tokio::process::Command;
+use tokio::time::{self, Duration};
+use tracing::{error, info, warn};
+
+pub const APPS_DIR: &str = "/opt/smith/apps";
+const UNIT_DIR: &str = "/etc/systemd/system";
+const CURRENT: &str = "current";
+
+/// How long a restarted app must stay up, without systemd restarting it, to
+/// count as started. Longer than the unit's `RestartSec`.
+const STARTUP_GRACE: Duration = Duration::from_secs(15);
+
+pub fn is_appimage(file: &str) -> bool {
+    file.ends_with(".AppImage")
+}
+
+/// A name usable in paths and unit names, e.g. `Teton Craft Table` ->
+/// `teton-craft-table`.
+fn slug(name: &str) -> String {
+    let mut slug = String::with_capacity(name.len());
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_matches('-').to_owned()
+}
+
+pub fn unit_name(name: &str) -> String {
+    format!("smith-app-{}.service", slug(name))
+}
+
+/// Whether `version` names a single directory in the app's, other than the
+/// `current` link. It comes from the api and is joined into paths.
+fn is_plain_version(version: &str) -> bool {
+    let mut components = Path::new(version).components();
+    matches!(components.next(), Some(Component::Normal(first)) if first == version)
+        && components.next().is_none()
+        && version != CURRENT
+}
+
+/// An AppImage restarted on a new version, waiting for [`AppImages::confirm`].
+pub struct Started {
+    name: String,
+    version: String,
+    unit: String,
+    previous: Option<String>,
+    /// `NRestarts` of the unit right after restarting it.
+    restarts: u32,
+}
+
+pub struct AppImages {
+    root: PathBuf,
+    unit_dir: PathBuf,
+}
+
+impl Default for AppImages {
+    fn default() -> Self {
+        Self {
+            root: PathBuf::from(APPS_DIR),
+            unit_dir: PathBuf::from(UNIT_DIR),
+        }
+    }
+}
+
+impl AppImages {
+    fn app_dir(&self, name: &str) -> PathBuf {
+        self.root.join(slug(name))
+    }
+
+    fn executable(&self, name: &str, version_dir: &Path) -> PathBuf {
+        version_dir.join(format!("{}.AppImage", slug(name)))
+    }
+
+    /// The version `current` points at, if its AppImage is in place.
+    pub async fn installed_version(&self, name: &str) -> Result<String> {
+        let app_dir = self.app_dir(name);
+        let target = tokio::fs::read_link(app_dir.join(CURRENT))
+            .await
+            .with_context(|| format!("{name} is not installed"))?;
+        let version = target
+            .file_name()
+            .and_then(|v| v.to_str())
+            .ok_or_else(|| anyhow!("{name} points at {}", target.display()))?
+            .to_owned();
+
+        let executable = self.executable(name, &app_dir.join(&version));
+        let metadata = tokio::fs::metadata(&executable)
+            .await
+            .with_context(|| format!("{} is missing", executable.display()))?;
+        if metadata.permissions().mode() & 0o111 == 0 {
+            return Err(anyhow!("{} is not executable", executable.display()));
+        }
+        Ok(version)
+    }
+
+    /// Installs `blob` as the package's version and restarts its unit on it.
+    /// Whether it stays up is left to [`AppImages::confirm`], so that every
+    /// AppImage of a release shares one grace period.
+    pub async fn install(&self, package: &ConfigPackage, blob: &Path) -> Result<Started> {
+        if !is_plain_version(&package.version) {
+            return Err(anyhow!(
+                "{} has an unusable version {:?}",
+                package.name,
+                package.version
+            ));
+        }
+        let mut started = Started {
+            name: package.name.clone(),
+            version: package.version.clone(),
+            unit: unit_name(&package.name),
+            previous: self.stage(package, blob).await?,
+            restarts: 0,
+        };
+
+        let restarted = self.restart(&started.unit, &package.name).await;
+        match restarted {
+            Ok(restarts) => {
+                started.restarts = restarts;
+                Ok(started)
+            }
+            Err(err) => {
+                self.roll_back(&started).await;
+                Err(err)
+            }
+        }
+    }
+
+    /// Writes and enables the unit, restarts it and returns its `NRestarts`.
+    async fn restart(&self, unit: &str, name: &str) -> Result<u32> {
+        if self.write_unit(name).await? {
+            systemctl(&["daemon-reload"]).await?;
+        }
+        systemctl(&["enable", unit]).await?;
+        systemctl(&["restart", unit]).await?;
+        Ok(unit_status(unit).await?.1)
+    }
+
+    /// Waits [`STARTUP_GRACE`] once, then keeps every app in `started` whose
+    /// unit is still active without systemd having restarted it, and rolls the
+    /// others back to their previous version. A new version that crashes on
+    /// startup would otherwise just be restarted over and over.
+    pub async fn confirm(&self, started: Vec<Started>) -> Vec<(String, Result<()>)> {
+        if started.is_empty() {
+            return Vec::new();
+        }
+        time::sleep(STARTUP_GRACE).await;
+
+        let mut results = Vec::with_capacity(started.len());
+        for app in started {
+            let result = match unit_status(&app.unit).await {
+                Ok((state, restarts)) if state == "active" && restarts == app.restarts => Ok(()),
+                Ok((state, restarts)) => Err(anyhow!(
+                    "{} did not stay up: {state}, restarted {} times",
+                    app.unit,
+                    restarts.saturating_sub(app.restarts)
+                )),
+                Err(err) => Err(err),
+            };
+            match &result {
+                Ok(()) => {
+                    self.prune(&app.name, app.previous.as_deref()).await;
+                    info!("Installed {} {}", app.name, app.version);
+                }
+                Err(_) => self.roll_back(&app).await,
+            }
+            results.push((app.name, result));
+        }
+        results
+    }
+
+    /// Points the app back at the version it ran before, if it had one.
+    async fn roll_back(&self, app: &Started) {
+        let Some(previous) = &app.previous else {
+            return;
+        };
+        warn!(
+            "{} {} failed to start, rolling back to {previous}",
+            app.name, app.version
+        );
+        if let Err(err) = self.switch(&self.app_dir(&app.name), previous).await {
+            error!("Failed to roll {} back to {previous}: {err:#}", app.name);
+            return;
+        }
+        _ = systemctl(&["restart", &app.unit]).await;
+    }
+
+    /// Copies the AppImage into its version directory and points `current`
+    /// at it. Returns the version that was current before.
+    async fn stage(&self, package: &ConfigPackage, blob: &Path) -> Result<Option<String>> {
+        let app_dir = self.app_dir(&package.name);
+        let version_dir = app_dir.join(&package.version);
+        tokio::fs::create_dir_all(&version_dir)
+            .await
+            .with_context(|| format!("creating {}", version_dir.display()))?;
+
+        let executable = self.executable(&package.name, &version_dir);
+        let partial = executable.with_extension("AppImage.part");
+        tokio::fs::copy(blob, &partial)
+            .await
+            .with_context(|| format!("copying {} to {}", blob.display(), partial.display()))?;
+        tokio::fs::set_permissions(&partial, std::fs::Permissions::from_mode(0o755)).await?;
+        tokio::fs::rename(&partial, &executable)
+            .await
+            .with_context(|| format!("moving {} into place", executable.display()))?;
+
+        let previous = self.installed_version(&package.name).await.ok();
+        self.switch(&app_dir, &package.version).await?;
+        Ok(previous.filter(|previous| *previous != package.version))
+    }
+
+    /// Points `current` at `version` by renaming a new symlink over it.
+    async fn switch(&self, app_dir: &Path, version: &str) -> Result<()> {
+        let link = app_dir.join(CURRENT);
+        let staging = app_dir.join(format!(".{CURRENT}.new"));
+        _ = tokio::fs::remove_file(&staging).await;
+        tokio::fs::symlink(version, &staging)
+            .await
+            .with_context(|| format!("linking {}", staging.display()))?;
+        tokio::fs::rename(&staging, &link)
+            .await
+            .with_context(|| format!("switching {} to {version}", link.display()))
+    }
+
+    fn unit(&self, name: &str) -> String {
+        let executable = self.executable(name, &self.app_dir(name).join(CURRENT));
+        format!(
+            "[Unit]\n\
+             Description={name} (AppImage managed by smith)\n\
+             After=network-online.target\n\
+             \n\
+             [Service]\n\
+             # Restarting fails if the AppImage can't be executed at all.\n\
+             Type=exec\n\
+             ExecStart={}\n\
+             # Devices rarely have FUSE 2, which AppImages mount themselves with.\n\
+             Environment=APPIMAGE_EXTRACT_AND_RUN=1\n\
+             Restart=on-failure\n\
+             RestartSec=5\n\
+             \n\
+             [Install]\n\
+             WantedBy=multi-user.target\n",
+            executable.display()
+        )
+    }
+
+    /// Writes the app's unit file, returning whether it changed.
+    async fn write_unit(&self, name: &str) -> Result<bool> {
+        let path = self.unit_dir.join(unit_name(name));
+        let unit = self.unit(name);
+        if tokio::fs::read_to_string(&path).await.ok().as_deref() == Some(unit.as_str()) {
+            return Ok(false);
+        }
+        write_file_atomic(&path, &unit, 0o644).await?;
+        Ok(true)
+    }
+
+    /// Removes every version but the current one and `keep`.
+    async fn prune(&self, name: &str, keep: Option<&str>) {
+        let app_dir = self.app_dir(name);
+        let Ok(current) = self.installed_version(name).await else {
+            return;
+        };
+        let Ok(mut entries) = tokio::fs::read_dir(&app_dir).await else {
+            return;
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let file_name = entry.file_name();
+            let Some(version) = file_name.to_str() else {
+                continue;
+            };
+            let is_dir = entry.file_type().await.is_ok_and(|t| t.is_dir());
+            if !is_dir || version == current || Some(version) == keep {
+                continue;
+            }
+            if let Err(err) = tokio::fs::remove_dir_all(entry.path()).await {
+                warn!("Failed to remove {}: {err}", entry.path().display());
+            }
+        }
+    }
+}
+
+/// `ActiveState` and `NRestarts` of `unit`.
+async fn unit_status(unit: &str) -> Result<(String, u32)> {
+    let output = systemctl(&["show", "-p", "ActiveState", "-p", "NRestarts", unit]).await?;
+    parse_unit_status(&output).ok_or_else(|| anyhow!("unreadable status of {unit}: {output}"))
+}
+
+fn parse_unit_status(output: &str) -> Option<(String, u32)> {
+    let value = |key: &str| {
+        output
+            .lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+    };
+    Some((
+        value("ActiveState")?.to_owned(),
+        value("NRestarts")?.parse().ok()?,
+    ))
+}
+
+async fn systemctl(args: &[&str]) -> Result<String> {
+    let output = Command::new("systemctl")
+        .args(args)
+        .output()
+        .await
+        .context("running systemctl")?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "systemctl {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apps(dir: &Path) -> AppImages {
+        AppImages {
+            root: dir.join("apps"),
+            unit_dir: dir.join("units"),
+        }
+    }
+
+    fn package(version: &str) -> ConfigPackage {
+        ConfigPackage {
+            name: "Teton Craft Table".into(),
+            version: version.into(),
+            file: format!("Teton Craft Table_{version}_amd64.AppImage"),
+        }
+    }
+
+    #[test]
+    fn names_are_slugged() {
+        assert_eq!(slug("Teton Craft Table"), "teton-craft-table");
+        assert_eq!(slug("  my_app--2 "), "my-app-2");
+        assert_eq!(
+            unit_name("Teton Craft Table"),
+            "smith-app-teton-craft-table.service"
+        );
+    }
+
+    #[test]
+    fn versions_are_single_path_components() {
+        assert!(is_plain_version("0.2.0"));
+        assert!(is_plain_version("2026.10.1-rc1"));
+        for version in [
+            "", ".", "..", "../etc", "0.2.0/..", "/opt", "a/b", "current",
+        ] {
+            assert!(!is_plain_version(version), "{version:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn staging_switches_current_and_keeps_the_previous_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let apps = apps(dir.path());
+        let blob = dir.path().join("blob");
+        std::fs::write(&blob, b"v1").unwrap();
+
+        assert!(apps.installed_version("Teton Craft Table").await.is_err());
+        assert_eq!(apps.stage(&package("0.1.0"), &blob).await.unwrap(), None);
+        assert_eq!(
+            apps.installed_version("Teton Craft Table").await.unwrap(),
+            "0.1.0"
+        );
+
+        std::fs::write(&blob, b"v2").unwrap();
+        assert_eq!(
+            apps.stage(&package("0.2.0"), &blob)
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("0.1.0")
+        );
+        let app_dir = dir.path().join("apps/teton-craft-table");
+        assert_eq!(
+            std::fs::read(app_dir.join("current/teton-craft-table.AppImage")).unwrap(),
+            b"v2"
+        );
+
+        // Reinstalling the current version has nothing to roll back to.
+        assert_eq!(apps.stage(&package("0.2.0"), &blob).await.unwrap(), None);
+
+        std::fs::write(&blob, b"v3").unwrap();
+        apps.stage(&package("0.3.0"), &blob).await.unwrap();
+        apps.prune("Teton Craft Table", Some("0.2.0")).await;
+        assert!(!app_dir.join("0.1.0").exists());
+        assert!(app_dir.join("0.2.0").exists());
+        assert!(app_dir.join("0.3.0").exists());
+    }
+
+    #[tokio::test]
+    async fn unit_runs_the_current_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let apps = apps(dir.path());
+        std::fs::create_dir_all(dir.path().join("units")).unwrap();
+
+        assert!(apps.write_unit("Teton Craft Table").await.unwrap());
+        assert!(!apps.write_unit("Teton Craft Table").await.unwrap());
+
+        let unit =
+            std::fs::read_to_string(dir.path().join("units/smith-app-teton-craft-table.service"))
+                .unwrap();
+        let exec = format!(
+            "ExecStart={}/apps/teton-craft-table/current/teton-craft-table.AppImage\n",
+            dir.path().display()
+        );
+        assert!(unit.contains(&exec), "{unit}");
+        assert!(unit.contains("Type=exec\n"), "{unit}");
+    }
+
+    #[test]
+    fn unit_status_is_read_from_systemctl_show() {
+        assert_eq!(
+            parse_unit_status("NRestarts=3\nActiveState=activating\n"),
+            Some(("activating".to_owned(), 3))
+        );
+        assert_eq!(parse_unit_status("ActiveState=active\n"), None);
+    }
+}
@@ -0,0 +1,302 @@
+//! The package cache.
+//!
+//! Blobs live in `packages/blobs` and the releases that use them in
+//! `packages/versions`. Collecting keeps the releases it is told to and the
+//! blobs their manifests name, drops every other release, and evicts the
+//! least recently used of the remaining blobs until the cache fits its quota.
+//! Blobs are marked used by bumping their modification time.
+use crate::magic::structure::ConfigPackage;
+use crate::utils::system::CacheUsage;
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tracing::{info, warn};
+
+/// What a collection removed.
+#[derive(Debug, Default, PartialEq)]
+pub struct Collected {
+    pub bytes_freed: u64,
+    pub blobs_evicted: usize,
+    pub releases_dropped: usize,
+}
+
+pub struct BlobCache {
+    packages_dir: PathBuf,
+}
+
+impl BlobCache {
+    pub fn new(packages_dir: &Path) -> Self {
+        Self {
+            packages_dir: packages_dir.to_owned(),
+        }
+    }
+
+    fn blobs(&self) -> PathBuf {
+        self.packages_dir.join("blobs")
+    }
+
+    fn versions(&self) -> PathBuf {
+        self.packages_dir.join("versions")
+    }
+
+    /// Marks a blob as just used, so it is evicted last.
+    pub async fn touch(&self, file: &str) {
+        let path = self.blobs().join(file);
+        let touched = async {
+            let file = tokio::fs::File::open(&path).await?.into_std().await;
+            tokio::task::spawn_blocking(move || file.set_modified(SystemTime::now())).await?
+        };
+        if let Err(err) = touched.await {
+            warn!("Could not mark {} as used: {err:#}", path.display());
+        }
+    }
+
+    /// Drops the releases not in `keep_releases`, then evicts blobs that none
+    /// of the kept releases nor `in_flight` use, oldest first, until the
+    /// blobs take no more than `quota_bytes`.
+    pub async fn collect(
+        &self,
+        keep_releases: &[i32],
+        in_flight: &[ConfigPackage],
+        quota_bytes: u64,
+    ) -> Result<Collected> {
+        let mut collected = Collected::default();
+        let mut protected: HashSet<String> = in_flight
+            .iter()
+            .map(|package| package.file.clone())
+            .collect();
+
+        for (path, release_id) in list(&self.versions()).await? {
+            if release_id.is_some_and(|id| keep_releases.contains(&id)) {
+                // The manifest itself, not its hooks or containers.
+                if path.extension().is_none()
+                    && let Ok(content) = tokio::fs::read_to_string(&path).await
+                {
+                    match ConfigPackage::parse_manifest(&content) {
+                        Ok(packages) => protected.extend(packages.into_iter().map(|p| p.file)),
+                        Err(err) => warn!("Could not parse {}: {err:#}", path.display()),
+                    }
+                }
+                continue;
+            }
+            let removed = if path.is_dir() {
+                tokio::fs::remove_dir_all(&path).await
+            } else {
+                tokio::fs::remove_file(&path).await
+            };
+            match removed {
+                Ok(()) if path.extension().is_none() => collected.releases_dropped += 1,
+                Ok(()) => {}
+                Err(err) => warn!("Could not remove {}: {err:#}", path.display()),
+            }
+        }
+
+        let mut blobs = Vec::new();
+        let mut total = 0;
+        for (path, _) in list(&self.blobs()).await? {
+            let Ok(metadata) = tokio::fs::metadata(&path).await else {
+                continue;
+            };
+            total += metadata.len();
+            let name = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or_default();
+            // Downloads in progress are resumed, not evicted.
+            if metadata.is_file() && !name.ends_with(".part") && !protected.contains(name) {
+                let used = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                blobs.push((used, metadata.len(), path));
+            }
+        }
+
+        blobs.sort();
+        for (_, size, path) in blobs {
+            if total <= quota_bytes {
+                break;
+            }
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => {
+                    info!("Evicted {} from the package cache", path.display());
+                    total -= size;
+                    collected.bytes_freed += size;
+                    collected.blobs_evicted += 1;
+                }
+                Err(err) => warn!("Could not evict {}: {err:#}", path.display()),
+            }
+        }
+        if total > quota_bytes {
+            warn!(
+                "Package cache holds {} MB, over its {} MB quota, in blobs releases still use",
+                total / 1024 / 1024,
+                quota_bytes / 1024 / 1024
+            );
+        }
+
+        Ok(collected)
+    }
+
+    /// How much the cache holds.
+    pub async fn usage(&self) -> CacheUsage {
+        let mut usage = CacheUsage::default();
+        for (path, _) in list(&self.blobs()).await.unwrap_or_default() {
+            if let Ok(metadata) = tokio::fs::metadata(&path).await {
+                usage.bytes += metadata.len();
+                usage.blobs += 1;
+            }
+        }
+        for (path, _) in list(&self.versions()).await.unwrap_or_default() {
+            if path.extension().is_none() && path.is_file() {
+                usage.releases += 1;
+            }
+        }
+        usage
+    }
+}
+
+/// The entries of `dir` with the release id their name starts with, e.g. 12
+/// for `12.hooks.json`. A missing directory is empty.
+async fn list(dir: &Path) -> Result<Vec<(PathBuf, Option<i32>)>> {
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).with_context(|| format!("reading {}", dir.display())),
+    };
+    let mut listed = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        let release_id = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.split('.').next())
+            .and_then(|id| id.parse().ok());
+        listed.push((entry.path(), release_id));
+    }
+    Ok(listed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn package(file: &str) -> ConfigPackage {
+        ConfigPackage {
+            name: file.split('_').next().unwrap().into(),
+            version: "1.0.0".into(),
+            file: file.into(),
+        }
+    }
+
+    fn release(dir: &Path, release_id: i32, files: &[&str]) {
+        let manifest: String = files
+            .iter()
+            .map(|file| package(file).manifest_line() + "\n")
+            .collect();
+        let versions = dir.join("versions");
+        std::fs::create_dir_all(versions.join(format!("{release_id}.hooks"))).unwrap();
+        std::fs::write(versions.join(release_id.to_string()), manifest).unwrap();
+        std::fs::write(versions.join(format!("{release_id}.hooks.json")), "{}").unwrap();
+    }
+
+    fn blob(dir: &Path, file: &str, size: usize, age_secs: u64) {
+        let path = dir.join("blobs").join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, vec![0u8; size]).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(age_secs))
+            .unwrap();
+    }
+
+    fn exists(dir: &Path, file: &str) -> bool {
+        dir.join("blobs").join(file).exists()
+    }
+
+    #[tokio::test]
+    async fn collection_keeps_releases_in_use_and_evicts_the_oldest_rest() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        release(dir, 1, &["app_1.deb"]);
+        release(dir, 2, &["app_2.deb", "lib_1.deb"]);
+        release(dir, 3, &["app_3.deb", "lib_1.deb"]);
+        blob(dir, "app_1.deb", 100, 300);
+        blob(dir, "app_2.deb", 100, 10);
+        blob(dir, "app_3.deb", 100, 200);
+        blob(dir, "lib_1.deb", 100, 400);
+        blob(dir, "stray_1.deb", 100, 100);
+        blob(dir, "next_1.deb", 100, 500);
+        blob(dir, "big_1.deb.part", 100, 600);
+
+        let cache = BlobCache::new(dir);
+        cache.touch("app_1.deb").await;
+        let collected = cache
+            .collect(&[2, 3], &[package("next_1.deb")], 600)
+            .await
+            .unwrap();
+
+        // Release 1 is dropped, and of its blob and the stray one, the blob
+        // was used last.
+        assert_eq!(
+            collected,
+            Collected {
+                bytes_freed: 100,
+                blobs_evicted: 1,
+                releases_dropped: 1,
+            }
+        );
+        assert!(!dir.join("versions/1").exists());
+        assert!(!dir.join("versions/1.hooks.json").exists());
+        assert!(!dir.join("versions/1.hooks").exists());
+        assert!(dir.join("versions/2.hooks.json").exists());
+        assert!(!exists(dir, "stray_1.deb"));
+        for kept in [
+            "app_1.deb",
+            "app_2.deb",
+            "app_3.deb",
+            "lib_1.deb",
+            "next_1.deb",
+        ] {
+            assert!(exists(dir, kept), "{kept}");
+        }
+        assert!(exists(dir, "big_1.deb.part"));
+
+        assert_eq!(
+            cache.usage().await,
+            CacheUsage {
+                bytes: 600,
+                blobs: 6,
+                releases: 2,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn blobs_are_kept_while_under_quota() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        blob(dir, "app_1.deb", 100, 300);
+        blob(dir, "app_2.deb", 100, 100);
+
+        let cache = BlobCache::new(dir);
+        let collected = cache.collect(&[], &[], 150).await.unwrap();
+        assert_eq!(collected.blobs_evicted, 1);
+        assert!(!exists(dir, "app_1.deb"));
+        assert!(exists(dir, "app_2.deb"));
+
+        let collected = cache.collect(&[], &[], 150).await.unwrap();
+        assert_eq!(collected, Collected::default());
+    }
+
+    #[tokio::test]
+    async fn a_missing_cache_is_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = BlobCache::new(&dir.path().join("packages"));
+        assert_eq!(
+            cache.collect(&[1], &[], 0).await.unwrap(),
+            Collected::default()
+        );
+        assert_eq!(cache.usage().await, CacheUsage::default());
+    }
+}
@@ -0,0 +1,553 @@
+//! Container images, run through the local Docker or Podman socket.
+//!
+//! Each service of a release's [`ContainerSpec`] runs as a container named
+//! `smith-<service>`, labelled with a hash of its spec so an unchanged service
+//! is left alone. Images are all pulled before anything is touched. A changed
+//! service keeps its old container, stopped and renamed, until the new one is
+//! up; if any service fails to come up, every service swapped so far goes back
+//! to its old container.
+use crate::utils::schema::{ContainerPort, ContainerService, ContainerSpec};
+use anyhow::{Context, Result, anyhow};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use tokio::time::{self, Duration, Instant};
+use tracing::{info, warn};
+
+/// Docker's socket first; Podman serves the same API on its own.
+const SOCKETS: [&str; 2] = ["/var/run/docker.sock", "/run/podman/podman.sock"];
+const API: &str = "http://localhost/v1.41";
+
+const SERVICE_LABEL: &str = "smith.service";
+const SPEC_LABEL: &str = "smith.spec";
+
+/// How long a container without a healthcheck must stay up to count as started.
+const STARTUP_GRACE: Duration = Duration::from_secs(10);
+const STOP_TIMEOUT_SEC: u32 = 10;
+
+fn container_name(service: &str) -> String {
+    format!("smith-{service}")
+}
+
+fn previous_name(service: &str) -> String {
+    format!("smith-{service}-previous")
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct Inspect {
+    id: String,
+    state: InspectState,
+    #[serde(default)]
+    restart_count: u32,
+    #[serde(default)]
+    config: InspectConfig,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "PascalCase")]
+struct InspectState {
+    status: String,
+    #[serde(default)]
+    running: bool,
+    #[serde(default)]
+    restarting: bool,
+    #[serde(default)]
+    health: Option<InspectHealth>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct InspectHealth {
+    status: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "PascalCase")]
+struct InspectConfig {
+    #[serde(default)]
+    labels: Option<HashMap<String, String>>,
+}
+
+impl Inspect {
+    fn label(&self, key: &str) -> Option<&str> {
+        self.config.labels.as_ref()?.get(key).map(String::as_str)
+    }
+
+    /// The container's state in systemd's terms, as release services report it.
+    fn active_state(&self) -> &'static str {
+        let health = self.state.health.as_ref().map(|h| h.status.as_str());
+        match (self.state.status.as_str(), health) {
+            ("running", Some("unhealthy")) => "failed",
+            ("running", Some("starting")) | ("restarting", _) => "activating",
+            ("running", _) => "active",
+            ("created" | "paused", _) => "inactive",
+            ("removing", _) => "deactivating",
+            _ => "failed",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct Listed {
+    id: String,
+    #[serde(default)]
+    labels: HashMap<String, String>,
+}
+
+/// A service whose container was replaced in this deploy.
+struct Swap {
+    service: String,
+    previous: Option<String>,
+}
+
+pub struct Runtime {
+    client: reqwest::Client,
+    socket: PathBuf,
+}
+
+impl Runtime {
+    /// The first container runtime socket that exists.
+    pub fn detect() -> Option<Self> {
+        let socket = SOCKETS.iter().map(Path::new).find(|s| s.exists())?;
+        Self::new(socket).ok()
+    }
+
+    fn new(socket: &Path) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .unix_socket(socket)
+            .build()
+            .context("building the container runtime client")?;
+        Ok(Self {
+            client,
+            socket: socket.to_owned(),
+        })
+    }
+
+    async fn check(response: reqwest::Response, what: &str) -> Result<reqwest::Response> {
+        let status = response.status();
+        if status.is_success() || status == StatusCode::NOT_MODIFIED {
+            return Ok(response);
+        }
+        let body = response.text().await.unwrap_or_default();
+        let message = serde_json::from_str::<Value>(&body)
+            .ok()
+            .and_then(|v| v.get("message")?.as_str().map(str::to_owned))
+            .unwrap_or(body);
+        Err(anyhow!("{what} failed with {status}: {}", message.trim()))
+    }
+
+    async fn inspect(&self, container: &str) -> Result<Option<Inspect>> {
+        let response = self
+            .client
+            .get(format!("{API}/containers/{container}/json"))
+            .send()
+            .await
+            .with_context(|| format!("reaching {}", self.socket.display()))?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response = Self::check(response, &format!("inspecting {container}")).await?;
+        Ok(Some(response.json().await?))
+    }
+
+    pub async fn has_image(&self, reference: &str) -> Result<bool> {
+        let response = self
+            .client
+            .get(format!("{API}/images/{reference}/json"))
+            .send()
+            .await
+            .with_context(|| format!("reaching {}", self.socket.display()))?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        Self::check(response, &format!("inspecting {reference}")).await?;
+        Ok(true)
+    }
+
+    async fn pull(&self, reference: &str) -> Result<()> {
+        if self.has_image(reference).await? {
+            return Ok(());
+        }
+        info!("Pulling {reference}");
+        let response = self
+            .client
+            .post(format!("{API}/images/create"))
+            .query(&[("fromImage", reference)])
+            .send()
+            .await?;
+        let response = Self::check(response, &format!("pulling {reference}")).await?;
+
+        // Progress streams as JSON lines, and a failed pull still answers 200.
+        let body = response.text().await?;
+        for line in body.lines() {
+            if let Ok(progress) = serde_json::from_str::<Value>(line)
+                && let Some(error) = progress.get("error").and_then(Value::as_str)
+            {
+                return Err(anyhow!("pulling {reference}: {error}"));
+            }
+        }
+        Ok(())
+    }
+
+    async fn post(&self, path: &str, what: &str) -> Result<()> {
+        let response = self.client.post(format!("{API}{path}")).send().await?;
+        Self::check(response, what).await?;
+        Ok(())
+    }
+
+    async fn start(&self, container: &str) -> Result<()> {
+        self.post(
+            &format!("/containers/{container}/start"),
+            &format!("starting {container}"),
+        )
+        .await
+    }
+
+    async fn stop(&self, container: &str) -> Result<()> {
+        self.post(
+            &format!("/containers/{container}/stop?t={STOP_TIMEOUT_SEC}"),
+            &format!("stopping {container}"),
+        )
+        .await
+    }
+
+    async fn rename(&self, container: &str, name: &str) -> Result<()> {
+        self.post(
+            &format!("/containers/{container}/rename?name={name}"),
+            &format!("renaming {container} to {name}"),
+        )
+        .await
+    }
+
+    /// Removes `container`, stopping it first. A missing container is fine.
+    async fn remove(&self, container: &str) -> Result<()> {
+        let response = self
+            .client
+            .delete(format!("{API}/containers/{container}"))
+            .query(&[("force", "true")])
+            .send()
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(());
+        }
+        Self::check(response, &format!("removing {container}")).await?;
+        Ok(())
+    }
+
+    async fn create(&self, service: &str, spec: &ContainerService) -> Result<String> {
+        let name = container_name(service);
+        let response = self
+            .client
+            .post(format!("{API}/containers/create"))
+            .query(&[("name", &name)])
+            .json(&create_body(service, spec))
+            .send()
+            .await?;
+        let response = Self::check(response, &format!("creating {name}")).await?;
+        let created: Value = response.json().await?;
+        created
+            .get("Id")
+            .and_then(Value::as_str)
+            .map(str::to_owned)
+            .ok_or_else(|| anyhow!("creating {name} returned no id"))
+    }
+
+    /// Containers smith manages, by service.
+    async fn managed(&self) -> Result<Vec<(String, String)>> {
+        let filters = json!({ "label": [SERVICE_LABEL] }).to_string();
+        let response = self
+            .client
+            .get(format!("{API}/containers/json"))
+            .query(&[("all", "true"), ("filters", filters.as_str())])
+            .send()
+            .await?;
+        let response = Self::check(response, "listing containers").await?;
+        let listed: Vec<Listed> = response.json().await?;
+        Ok(listed
+            .into_iter()
+            .filter_map(|c| Some((c.labels.get(SERVICE_LABEL)?.clone(), c.id)))
+            .collect())
+    }
+
+    /// Waits for `container` to come up: healthy if it has a healthcheck,
+    /// otherwise still running once [`STARTUP_GRACE`] has passed.
+    async fn wait_started(&self, container: &str, spec: &ContainerService) -> Result<()> {
+        let deadline = Instant::now()
+            + match &spec.healthcheck {
+                Some(check) => {
+                    Duration::from_secs(
+                        u64::from(check.interval_sec) * u64::from(check.retries + 1),
+                    ) + STARTUP_GRACE
+                }
+                None => STARTUP_GRACE,
+            };
+
+        loop {
+            time::sleep(Duration::from_secs(2)).await;
+            let inspect = self
+                .inspect(container)
+                .await?
+                .ok_or_else(|| anyhow!("{container} disappeared"))?;
+            let health = inspect.state.health.as_ref().map(|h| h.status.as_str());
+
+            if !inspect.state.running && !inspect.state.restarting {
+                return Err(anyhow!("{container} is {}", inspect.state.status));
+            }
+            match health {
+                Some("healthy") => return Ok(()),
+                Some("unhealthy") => return Err(anyhow!("{container} is unhealthy")),
+                _ => {}
+            }
+            if Instant::now() >= deadline {
+                return match spec.healthcheck {
+                    Some(_) => Err(anyhow!("{container} did not become healthy")),
+                    None if inspect.state.restarting || inspect.restart_count > 0 => {
+                        Err(anyhow!("{container} keeps restarting"))
+                    }
+                    None => Ok(()),
+                };
+            }
+        }
+    }
+
+    /// Replaces `service`'s container if its spec changed. Returns what was
+    /// swapped, or `None` if the running container already matches.
+    async fn swap(&self, service: &str, spec: &ContainerService) -> Result<Option<Swap>> {
+        let name = container_name(service);
+        let hash = spec_hash(spec);
+        let existing = self.inspect(&name).await?;
+
+        if let Some(existing) = &existing
+            && existing.label(SPEC_LABEL) == Some(hash.as_str())
+        {
+            self.start(&name).await?;
+            return Ok(None);
+        }
+
+        let previous = match existing {
+            Some(existing) => {
+                self.remove(&previous_name(service)).await?;
+                self.stop(&existing.id).await?;
+                self.rename(&existing.id, &previous_name(service)).await?;
+                Some(existing.id)
+            }
+            None => None,
+        };
+        let swap = Swap {
+            service: service.to_owned(),
+            previous,
+        };
+
+        info!("Starting {name} on {}", spec.image);
+        let started = async {
+            let id = self.create(service, spec).await?;
+            self.start(&id).await?;
+            self.wait_started(&name, spec).await
+        }
+        .await;
+        if let Err(err) = started {
+            self.roll_back(&swap).await;
+            return Err(err);
+        }
+        Ok(Some(swap))
+    }
+
+    async fn roll_back(&self, swap: &Swap) {
+        let name = container_name(&swap.service);
+        if let Err(err) = self.remove(&name).await {
+            warn!("Failed to remove {name} while rolling back: {err:#}");
+        }
+        let Some(previous) = &swap.previous else {
+            return;
+        };
+        let restored = async {
+            self.rename(previous, &name).await?;
+            self.start(&name).await
+        }
+        .await;
+        match restored {
+            Ok(()) => info!("Rolled {name} back"),
+            Err(err) => warn!("Failed to roll {name} back: {err:#}"),
+        }
+    }
+
+    /// Makes the running containers match `spec`.
+    pub async fn deploy(&self, spec: &ContainerSpec) -> Result<()> {
+        for service in spec.services.values() {
+            self.pull(&service.image).await?;
+        }
+
+        let mut swapped = Vec::new();
+        for (service, container) in &spec.services {
+            match self.swap(service, container).await {
+                Ok(Some(swap)) => swapped.push(swap),
+                Ok(None) => {}
+                Err(err) => {
+                    warn!("{service} failed to start, rolling back the release's containers");
+                    for swap in swapped.iter().rev() {
+                        self.roll_back(swap).await;
+                    }
+                    return Err(err.context(format!("starting container {service}")));
+                }
+            }
+        }
+
+        for swap in &swapped {
+            if swap.previous.is_some() {
+                self.remove(&previous_name(&swap.service)).await?;
+            }
+        }
+        for (service, id) in self.managed().await? {
+            if !spec.services.contains_key(&service) {
+                info!("Removing container {service}, which the release no longer has");
+                self.remove(&id).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The state and restart count of `service`'s container, for service checks.
+    pub async fn service_status(&self, service: &str) -> Result<(String, u32)> {
+        Ok(match self.inspect(&container_name(service)).await? {
+            Some(inspect) => (inspect.active_state().to_owned(), inspect.restart_count),
+            None => ("inactive".to_owned(), 0),
+        })
+    }
+}
+
+fn spec_hash(spec: &ContainerService) -> String {
+    let json = serde_json::to_vec(spec).unwrap_or_default();
+    let mut hex = String::with_capacity(64);
+    for byte in Sha256::digest(json) {
+        _ = write!(hex, "{byte:02x}");
+    }
+    hex
+}
+
+/// The Engine API body creating `service`'s container.
+fn create_body(service: &str, spec: &ContainerService) -> Value {
+    let mut exposed = serde_json::Map::new();
+    let mut bindings = serde_json::Map::new();
+    for port in spec.ports.iter().filter_map(|p| ContainerPort::parse(p)) {
+        let key = format!("{}/{}", port.container, port.protocol);
+        exposed.insert(key.clone(), json!({}));
+        bindings.insert(key, json!([{ "HostPort": port.host.to_string() }]));
+    }
+
+    let mut body = json!({
+        "Image": spec.image,
+        "Env": spec
+            .environment
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect::<Vec<_>>(),
+        "ExposedPorts": exposed,
+        "Labels": {
+            SERVICE_LABEL: service,
+            SPEC_LABEL: spec_hash(spec),
+        },
+        "HostConfig": {
+            "Binds": spec.volumes,
+            "PortBindings": bindings,
+            "RestartPolicy": { "Name": spec.restart.as_deref().unwrap_or("unless-stopped") },
+            "Privileged": spec.privileged,
+        },
+    });
+    if !spec.command.is_empty() {
+        body["Cmd"] = json!(spec.command);
+    }
+    if let Some(mode) = &spec.network_mode {
+        body["HostConfig"]["NetworkMode"] = json!(mode);
+    }
+    if let Some(check) = &spec.healthcheck {
+        let nanos = u64::from(check.interval_sec) * 1_000_000_000;
+        body["Healthcheck"] = json!({
+            "Test": check.test,
+            "Interval": nanos,
+            "Retries": check.retries,
+        });
+    }
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::schema::ContainerHealthcheck;
+
+    #[test]
+    fn create_body_maps_the_service_spec() {
+        let spec = ContainerService {
+            image: "ghcr.io/teton/vision@sha256:abc".into(),
+            ports: vec!["8080:80".into(), "5353:53/udp".into()],
+            volumes: vec!["/data:/data:ro".into()],
+            environment: [("MODE".to_string(), "edge".to_string())].into(),
+            healthcheck: Some(ContainerHealthcheck {
+                test: vec!["CMD".into(), "true".into()],
+                interval_sec: 5,
+                retries: 2,
+            }),
+            ..Default::default()
+        };
+        let body = create_body("vision", &spec);
+
+        assert_eq!(body["Image"], "ghcr.io/teton/vision@sha256:abc");
+        assert_eq!(body["Env"], json!(["MODE=edge"]));
+        assert_eq!(
+            body["HostConfig"]["PortBindings"]["53/udp"],
+            json!([{ "HostPort": "5353" }])
+        );
+        assert_eq!(body["HostConfig"]["Binds"], json!(["/data:/data:ro"]));
+        assert_eq!(
+            body["HostConfig"]["RestartPolicy"]["Name"],
+            "unless-stopped"
+        );
+        assert_eq!(body["Healthcheck"]["Interval"], 5_000_000_000u64);
+        assert_eq!(body["Labels"][SERVICE_LABEL], "vision");
+        assert!(body.get("Cmd").is_none());
+
+        // Any change to the spec must replace the container.
+        let changed = ContainerService {
+            privileged: true,
+            ..spec.clone()
+        };
+        assert_ne!(spec_hash(&spec), spec_hash(&changed));
+    }
+
+    #[test]
+    fn container_states_map_to_systemd_states() {
+        let inspect = |status: &str, health: Option<&str>| Inspect {
+            id: "1".into(),
+            state: InspectState {
+                status: status.into(),
+                running: status == "running",
+                restarting: status == "restarting",
+                health: health.map(|status| InspectHealth {
+                    status: status.into(),
+                }),
+            },
+            restart_count: 0,
+            config: InspectConfig::default(),
+        };
+
+        assert_eq!(inspect("running", None).active_state(), "active");
+        assert_eq!(inspect("running", Some("healthy")).active_state(), "active");
+        assert_eq!(
+            inspect("running", Some("starting")).active_state(),
+            "activating"
+        );
+        assert_eq!(
+            inspect("running", Some("unhealthy")).active_state(),
+            "failed"
+        );
+        assert_eq!(inspect("restarting", None).active_state(), "activating");
+        assert_eq!(inspect("exited", None).active_state(), "failed");
+        assert_eq!(inspect("created", None).active_state(), "inactive");
+    }
+}
@@ -1,16 +1,23 @@
 use super::actor::Actor;
 use super::actor::ActorMessage;
 use crate::downloader::DownloaderHandle;
+use crate::events::EventBus;
 use crate::magic::MagicHandle;
 use crate::session::SessionHandle;
 use crate::shutdown::ShutdownSignals;
-use tokio::sync::{mpsc, oneshot};
+use crate::utils::schema::{UpgradePlan, UpgradeReport};
+use tokio::sync::{mpsc, oneshot, watch};
 use tokio::time::{Duration, timeout};
 use tracing::warn;
 
+/// Planning queues behind any update or upgrade in progress, and holds up the
+/// commands after it meanwhile, so it gives up rather than wait out a long one.
+const PLAN_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
 #[derive(Clone)]
 pub struct Handler {
     sender: mpsc::Sender<ActorMessage>,
+    last_upgrade: watch::Receiver<Option<UpgradeReport>>,
 }
 
 impl Handler {
@@ -19,12 +26,19 @@ impl Handler {
         magic: MagicHandle,
         downloader: DownloaderHandle,
         session: SessionHandle,
+        events: EventBus,
     ) -> Self {
         let (sender, receiver) = mpsc::channel(8);
-        let mut actor = Actor::new(shutdown, receiver, magic, downloader, session);
+        let (report, last_upgrade) = watch::channel(None);
+        let mut actor = Actor::new(
+            shutdown, receiver, magic, downloader, session, events, report,
+        );
         tokio::spawn(async move { actor.run().await });
 
-        Self { sender }
+        Self {
+            sender,
+            last_upgrade,
+        }
     }
 
     pub async fn check_for_updates(&self) -> bool {
@@ -38,6 +52,26 @@ impl Handler {
         self.sender.send(ActorMessage::Upgrade).await.unwrap();
     }
 
+    /// What upgrading to `release_id`, or to the target release when `None`,
+    /// would do. Waits for any update or upgrade in progress to finish first.
+    pub async fn plan_upgrade(&self, release_id: Option<i32>) -> anyhow::Result<UpgradePlan> {
+        let (rpc, receiver) = oneshot::channel();
+        self.sender
+            .send(ActorMessage::Plan { release_id, rpc })
+            .await
+            .map_err(|_| anyhow::anyhow!("updater is not running"))?;
+
+        timeout(PLAN_TIMEOUT, receiver)
+            .await
+            .map_err(|_| anyhow::anyhow!("updater busy; no plan within {PLAN_TIMEOUT:?}"))?
+            .map_err(|_| anyhow::anyhow!("updater dropped the plan request"))?
+    }
+
+    /// The outcome of the last upgrade this run of the daemon, if any.
+    pub fn last_upgrade(&self) -> Option<UpgradeReport> {
+        self.last_upgrade.borrow().clone()
+    }
+
     pub async fn status(&self) -> String {
         let (rpc, receiver) = oneshot::channel();
 
@@ -0,0 +1,238 @@
+//! Release hooks.
+//!
+//! A release may carry pre-install, post-install and verify scripts, see
+//! [`ReleaseHooks`]. They are cached next to the release manifest and run by
+//! the updater around installing the release's packages. Each script runs in
+//! a process group of its own, so a timeout kills whatever it started too.
+use crate::utils::schema::{HookOutput, HookStage, ReleaseHook, ReleaseHooks};
+use anyhow::{Context, Result};
+use nix::sys::signal::{Signal, killpg};
+use nix::unistd::Pid;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::Command;
+use tokio::time::{self, Duration};
+use tracing::{info, warn};
+
+/// Output kept of each stream, from its end, where the error usually is.
+const OUTPUT_LIMIT: usize = 16 * 1024;
+
+/// How long output may keep trickling in after a hook was killed, from
+/// anything that escaped its process group.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where a release's hooks are cached, next to its manifest.
+pub fn hooks_path(release_cache: &Path) -> PathBuf {
+    release_cache.with_extension("hooks.json")
+}
+
+/// The hooks cached for a release. Caches from before hook support have none.
+pub async fn load(release_cache: &Path) -> Result<ReleaseHooks> {
+    let path = hooks_path(release_cache);
+    match tokio::fs::read_to_string(&path).await {
+        Ok(contents) => {
+            serde_json::from_str(&contents).with_context(|| format!("parsing {}", path.display()))
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(ReleaseHooks::default()),
+        Err(err) => Err(err).with_context(|| format!("reading {}", path.display())),
+    }
+}
+
+/// Runs a hook of release `release_id` from a script written into `dir`. A
+/// hook that cannot be started is reported like one that failed.
+pub async fn run(stage: HookStage, hook: &ReleaseHook, dir: &Path, release_id: i32) -> HookOutput {
+    info!("Running {stage} hook of release {release_id}");
+    let output = match spawn(stage, hook, dir, release_id).await {
+        Ok(output) => output,
+        Err(err) => HookOutput {
+            stage,
+            exit_code: None,
+            timed_out: false,
+            stdout: String::new(),
+            stderr: format!("{err:#}"),
+        },
+    };
+    if output.success() {
+        info!("{stage} hook of release {release_id} succeeded");
+    } else {
+        warn!(
+            "{stage} hook of release {release_id} failed (exit code {:?}, timed out: {}): {}",
+            output.exit_code, output.timed_out, output.stderr
+        );
+    }
+    output
+}
+
+async fn spawn(
+    stage: HookStage,
+    hook: &ReleaseHook,
+    dir: &Path,
+    release_id: i32,
+) -> Result<HookOutput> {
+    tokio::fs::create_dir_all(dir)
+        .await
+        .with_context(|| format!("creating {}", dir.display()))?;
+    let script = dir.join(stage.to_string());
+    tokio::fs::write(&script, &hook.script)
+        .await
+        .with_context(|| format!("writing {}", script.display()))?;
+    tokio::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o700)).await?;
+
+    // A script without a #! line is a shell script.
+    let mut command = if hook.script.starts_with("#!") {
+        Command::new(&script)
+    } else {
+        let mut command = Command::new("sh");
+        command.arg(&script);
+        command
+    };
+    let mut child = command
+        .current_dir(dir)
+        .env("SMITH_RELEASE_ID", release_id.to_string())
+        .env("SMITH_HOOK_STAGE", stage.to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("starting {}", script.display()))?;
+
+    let stdout = tokio::spawn(read_tail(child.stdout.take()));
+    let stderr = tokio::spawn(read_tail(child.stderr.take()));
+
+    let timeout = Duration::from_secs(hook.timeout_sec.into());
+    let (exit_code, timed_out) = match time::timeout(timeout, child.wait()).await {
+        Ok(status) => (status?.code(), false),
+        Err(_) => {
+            if let Some(pid) = child.id() {
+                let _ = killpg(Pid::from_raw(pid as i32), Signal::SIGKILL);
+            }
+            let _ = child.kill().await;
+            (None, true)
+        }
+    };
+
+    let drain = |task: tokio::task::JoinHandle<String>| async move {
+        time::timeout(DRAIN_TIMEOUT, task)
+            .await
+            .ok()
+            .and_then(|output| output.ok())
+            .unwrap_or_default()
+    };
+    let mut stderr = drain(stderr).await;
+    if timed_out {
+        if !stderr.is_empty() && !stderr.ends_with('\n') {
+            stderr.push('\n');
+        }
+        stderr.push_str(&format!("timed out after {} seconds", hook.timeout_sec));
+    }
+
+    Ok(HookOutput {
+        stage,
+        exit_code,
+        timed_out,
+        stdout: drain(stdout).await,
+        stderr,
+    })
+}
+
+async fn read_tail(stream: Option<impl AsyncRead + Unpin>) -> String {
+    let Some(mut stream) = stream else {
+        return String::new();
+    };
+    let mut tail = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        match stream.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => {
+                tail.extend_from_slice(&buf[..n]);
+                if tail.len() > OUTPUT_LIMIT {
+                    tail.drain(..tail.len() - OUTPUT_LIMIT);
+                }
+            }
+        }
+    }
+    // Cutting may have split a character; lossy conversion just drops it.
+    String::from_utf8_lossy(&tail).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hook(script: &str, timeout_sec: u32) -> ReleaseHook {
+        ReleaseHook {
+            script: script.into(),
+            timeout_sec,
+        }
+    }
+
+    #[tokio::test]
+    async fn hooks_report_their_output_and_exit_code() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let output = run(
+            HookStage::PreInstall,
+            &hook(
+                "echo \"$SMITH_HOOK_STAGE $SMITH_RELEASE_ID\"\necho oops >&2\nexit 3",
+                5,
+            ),
+            dir.path(),
+            42,
+        )
+        .await;
+        assert_eq!(output.exit_code, Some(3));
+        assert!(!output.success());
+        assert_eq!(output.stdout, "pre_install 42\n");
+        assert_eq!(output.stderr, "oops\n");
+
+        let output = run(
+            HookStage::Verify,
+            &hook("#!/bin/sh\nprintf ok", 5),
+            dir.path(),
+            42,
+        )
+        .await;
+        assert!(output.success());
+        assert_eq!(output.stdout, "ok");
+    }
+
+    #[tokio::test]
+    async fn hooks_that_run_too_long_are_killed() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let started = std::time::Instant::now();
+        let output = run(
+            HookStage::PostInstall,
+            &hook("echo started\nsleep 30 &\nsleep 30", 1),
+            dir.path(),
+            42,
+        )
+        .await;
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(output.timed_out);
+        assert_eq!(output.exit_code, None);
+        assert_eq!(output.stdout, "started\n");
+        assert_eq!(output.stderr, "timed out after 1 seconds");
+    }
+
+    #[tokio::test]
+    async fn output_keeps_its_tail() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let output = run(
+            HookStage::PostInstall,
+            &hook("head -c 40000 /dev/zero | tr '\\0' a\necho end", 5),
+            dir.path(),
+            42,
+        )
+        .await;
+        assert!(output.success());
+        assert_eq!(output.stdout.len(), OUTPUT_LIMIT);
+        assert!(output.stdout.ends_with("aend\n"));
+    }
+}
@@ -1,4 +1,9 @@
 mod actor;
+pub(crate) mod appimage;
+pub(crate) mod cache;
+pub(crate) mod containers;
 mod handler;
+pub(crate) mod hooks;
+mod plan;
 
 pub use handler::Handler as UpdaterHandle;
@@ -0,0 +1,197 @@
+//! Upgrade plans.
+//!
+//! Works out what upgrading to a release would do to each of its packages,
+//! what is left to download and whether apt would accept the debs, all without
+//! installing or downloading anything.
+use crate::magic::structure::ConfigPackage;
+use crate::utils::network::NetworkClient;
+use crate::utils::schema::{PlanAction, PlannedPackage, UpgradePlan};
+use nix::sys::statvfs::statvfs;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+use tokio::time::{self, Duration};
+use tracing::warn;
+
+const SIMULATE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Resolver output kept, from its end, where the verdict is.
+const RESOLVER_OUTPUT_LIMIT: usize = 8 * 1024;
+
+pub async fn plan(
+    release_id: i32,
+    packages: &[ConfigPackage],
+    blobs: &Path,
+    network: &NetworkClient,
+    token: &str,
+) -> UpgradePlan {
+    let mut planned = Vec::with_capacity(packages.len());
+    let mut debs = Vec::new();
+    for package in packages {
+        let state = package.get_system_state().await.ok();
+        let action = match &state {
+            Some((status, installed)) if is_installed(status) => {
+                if *installed != package.version {
+                    if is_older(&package.version, installed).await {
+                        PlanAction::Downgrade
+                    } else {
+                        PlanAction::Upgrade
+                    }
+                } else if status == "ii" {
+                    PlanAction::Unchanged
+                } else {
+                    PlanAction::Reinstall
+                }
+            }
+            _ => PlanAction::Install,
+        };
+
+        let blob = blobs.join(&package.file);
+        let (cached, download_bytes) = if package.is_container_image() {
+            // An image the runtime has is reported installed.
+            let pulled = state.is_some();
+            (pulled, pulled.then_some(0))
+        } else if tokio::fs::metadata(&blob).await.is_ok_and(|m| m.len() > 0) {
+            (true, Some(0))
+        } else {
+            let size = network
+                .get_file_size(&format!("packages/{}", package.file), token)
+                .await
+                .inspect_err(|err| warn!("Could not get the size of {}: {err:#}", package.file))
+                .ok();
+            (false, size)
+        };
+
+        if action != PlanAction::Unchanged
+            && !package.is_container_image()
+            && !package.is_appimage()
+        {
+            debs.push((package.name.clone(), cached.then_some(blob)));
+        }
+
+        planned.push(PlannedPackage {
+            name: package.name.clone(),
+            version: package.version.clone(),
+            installed_version: state
+                .filter(|(status, _)| is_installed(status))
+                .map(|(_, version)| version),
+            action,
+            cached,
+            download_bytes,
+        });
+    }
+
+    let (resolvable, resolver_output) = simulate(&debs).await;
+    UpgradePlan {
+        release_id,
+        download_bytes: planned.iter().filter_map(|p| p.download_bytes).sum(),
+        packages: planned,
+        free_bytes: statvfs(blobs)
+            .ok()
+            .map(|stat| stat.blocks_available() * stat.fragment_size()),
+        resolvable,
+        resolver_output,
+    }
+}
+
+/// Whether a dpkg status, e.g. `ii` or `rc`, has the package on the system.
+/// The second letter is the current state; `n` and `c` mean it is not, or
+/// only its config files are.
+fn is_installed(status: &str) -> bool {
+    !matches!(status.chars().nth(1), None | Some('n') | Some('c'))
+}
+
+/// Whether version `a` is older than `b` by dpkg's rules.
+async fn is_older(a: &str, b: &str) -> bool {
+    Command::new("dpkg")
+        .args(["--compare-versions", a, "lt", b])
+        .status()
+        .await
+        .is_ok_and(|status| status.success())
+}
+
+/// Asks `apt-get -s` whether the debs could be installed together. Each deb is
+/// its name and, if it is cached, its blob.
+async fn simulate(debs: &[(String, Option<PathBuf>)]) -> (Option<bool>, String) {
+    if debs.is_empty() {
+        return (Some(true), "no debs to install".to_owned());
+    }
+    let missing: Vec<&str> = debs
+        .iter()
+        .filter(|(_, blob)| blob.is_none())
+        .map(|(name, _)| name.as_str())
+        .collect();
+    if !missing.is_empty() {
+        return (None, format!("not downloaded yet: {}", missing.join(", ")));
+    }
+
+    let simulation = Command::new("apt-get")
+        .args(["install", "-s", "-y", "--allow-downgrades"])
+        .args(debs.iter().filter_map(|(_, blob)| blob.as_deref()))
+        .kill_on_drop(true)
+        .output();
+    match time::timeout(SIMULATE_TIMEOUT, simulation).await {
+        Ok(Ok(output)) => {
+            let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+            text.push_str(&String::from_utf8_lossy(&output.stderr));
+            (Some(output.status.success()), tail(&text).to_owned())
+        }
+        Ok(Err(err)) => (None, format!("failed to run apt-get: {err}")),
+        Err(_) => (
+            None,
+            format!(
+                "apt-get -s timed out after {} seconds",
+                SIMULATE_TIMEOUT.as_secs()
+            ),
+        ),
+    }
+}
+
+fn tail(text: &str) -> &str {
+    let mut start = text.len().saturating_sub(RESOLVER_OUTPUT_LIMIT);
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    &text[start..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dpkg_statuses_tell_whether_a_package_is_installed() {
+        assert!(is_installed("ii"));
+        assert!(is_installed("iF"));
+        assert!(is_installed("iU"));
+        assert!(!is_installed("rc"));
+        assert!(!is_installed("un"));
+        assert!(!is_installed(""));
+    }
+
+    #[tokio::test]
+    async fn debs_that_are_not_downloaded_are_not_simulated() {
+        assert_eq!(
+            simulate(&[]).await,
+            (Some(true), "no debs to install".to_owned())
+        );
+        assert_eq!(
+            simulate(&[
+                ("camera".into(), Some(PathBuf::from("/blobs/camera.deb"))),
+                ("vision".into(), None),
+            ])
+            .await,
+            (None, "not downloaded yet: vision".to_owned())
+        );
+    }
+
+    #[test]
+    fn resolver_output_keeps_its_tail() {
+        let text = format!(
+            "{}é{}",
+            "a".repeat(10),
+            "b".repeat(RESOLVER_OUTPUT_LIMIT - 1)
+        );
+        assert_eq!(tail(&text), "b".repeat(RESOLVER_OUTPUT_LIMIT - 1));
+        assert_eq!(tail("short"), "short");
+    }
+}
@@ -349,7 +349,7 @@ async fn reload_sshd() -> Result<()> {
 
 /// Atomically replace `path` with `contents` at the given mode, via a temp file
 /// in the same directory (same pattern as the authorized_keys writers above).
-async fn write_file_atomic(path: &Path, contents: &str, mode: u32) -> Result<()> {
+pub(crate) async fn write_file_atomic(path: &Path, contents: &str, mode: u32) -> Result<()> {
     let path = path.to_owned();
     let contents = contents.to_owned();
     task::spawn_blocking(move || -> Result<()> {
@@ -0,0 +1,365 @@
+//! The hardware inventory: CPU, memory, disks, USB and PCI devices and display
+//! outputs, read from procfs and sysfs so no extra tools are needed.
+use crate::utils::schema::{
+    BlockDevice, BlockDeviceKind, DisplayOutput, HardwareInventory, PciDevice, UsbDevice,
+};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Virtual and partition-like block devices that aren't hardware of their own.
+const IGNORED_BLOCK_PREFIXES: &[&str] = &["loop", "ram", "zram", "dm-", "md", "nbd", "sr", "fd"];
+
+/// The Linux Foundation's vendor id, which the root hubs carry.
+const ROOT_HUB_VENDOR: &str = "1d6b";
+
+pub async fn collect() -> HardwareInventory {
+    tokio::task::spawn_blocking(|| collect_from(Path::new("/")))
+        .await
+        .unwrap_or_default()
+}
+
+fn collect_from(root: &Path) -> HardwareInventory {
+    let (cpu_model, cpu_cores) =
+        parse_cpuinfo(&read(root.join("proc/cpuinfo")).unwrap_or_default());
+    let cpu_cores = if cpu_cores == 0 {
+        std::thread::available_parallelism().map_or(0, |n| n.get() as u32)
+    } else {
+        cpu_cores
+    };
+    HardwareInventory {
+        cpu_model,
+        cpu_cores,
+        memory_bytes: parse_mem_total(&read(root.join("proc/meminfo")).unwrap_or_default()),
+        block_devices: block_devices(&root.join("sys/block")),
+        usb_devices: usb_devices(&root.join("sys/bus/usb/devices")),
+        pci_devices: pci_devices(&root.join("sys/bus/pci/devices")),
+        displays: displays(&root.join("sys/class/drm")),
+    }
+}
+
+fn read(path: impl AsRef<Path>) -> Option<String> {
+    let value = fs::read_to_string(path).ok()?;
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// Entries of a sysfs directory by name, so that the order, and so whether
+/// the inventory changed, doesn't depend on the directory.
+fn entries(dir: &Path) -> Vec<(String, PathBuf)> {
+    let mut entries: Vec<_> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| {
+            (
+                entry.file_name().to_string_lossy().into_owned(),
+                entry.path(),
+            )
+        })
+        .collect();
+    entries.sort();
+    entries
+}
+
+/// The model and the number of logical cores. x86 names the model on every
+/// core; ARM kernels may only name the SoC, if anything.
+fn parse_cpuinfo(cpuinfo: &str) -> (Option<String>, u32) {
+    let mut model = None;
+    let mut hardware = None;
+    let mut cores = 0;
+    for line in cpuinfo.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match key.trim() {
+            "processor" => cores += 1,
+            "model name" | "Processor" | "cpu model" if model.is_none() && !value.is_empty() => {
+                model = Some(value.to_string());
+            }
+            "Hardware" if !value.is_empty() => hardware = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    (model.or(hardware), cores)
+}
+
+fn parse_mem_total(meminfo: &str) -> u64 {
+    meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))
+        .and_then(|kib| kib.trim().trim_end_matches("kB").trim().parse::<u64>().ok())
+        .map_or(0, |kib| kib * 1024)
+}
+
+fn block_devices(dir: &Path) -> Vec<BlockDevice> {
+    entries(dir)
+        .into_iter()
+        .filter(|(name, _)| is_disk(name))
+        .filter_map(|(name, path)| {
+            let sectors: u64 = read(path.join("size"))?.parse().ok()?;
+            // An empty card reader.
+            if sectors == 0 {
+                return None;
+            }
+            let kind = block_device_kind(&name, &path);
+            let model = match kind {
+                BlockDeviceKind::Emmc | BlockDeviceKind::Sd => read(path.join("device/name")),
+                _ => read(path.join("device/model")),
+            };
+            Some(BlockDevice {
+                kind,
+                model,
+                size_bytes: sectors * 512,
+                rotational: read(path.join("queue/rotational")).as_deref() == Some("1"),
+                removable: read(path.join("removable")).as_deref() == Some("1"),
+                name,
+            })
+        })
+        .collect()
+}
+
+fn is_disk(name: &str) -> bool {
+    if IGNORED_BLOCK_PREFIXES
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+    {
+        return false;
+    }
+    // The eMMC's boot and RPMB partitions.
+    !(name.starts_with("mmcblk") && (name.contains("boot") || name.contains("rpmb")))
+}
+
+fn block_device_kind(name: &str, path: &Path) -> BlockDeviceKind {
+    if name.starts_with("nvme") {
+        return BlockDeviceKind::Nvme;
+    }
+    if name.starts_with("mmcblk") {
+        return match read(path.join("device/type")).as_deref() {
+            Some("MMC") => BlockDeviceKind::Emmc,
+            Some("SD") => BlockDeviceKind::Sd,
+            _ => BlockDeviceKind::Other,
+        };
+    }
+    // /sys/block/<name> links to the device's place on its bus.
+    let on_usb = fs::read_link(path).is_ok_and(|target| {
+        target
+            .components()
+            .any(|part| part.as_os_str().to_string_lossy().starts_with("usb"))
+    });
+    if on_usb {
+        BlockDeviceKind::Usb
+    } else if name.starts_with("sd") {
+        BlockDeviceKind::Sata
+    } else {
+        BlockDeviceKind::Other
+    }
+}
+
+fn usb_devices(dir: &Path) -> Vec<UsbDevice> {
+    entries(dir)
+        .into_iter()
+        // Interfaces, e.g. `1-1:1.0`, have no ids of their own.
+        .filter_map(|(_, path)| {
+            let vendor_id = read(path.join("idVendor"))?;
+            if vendor_id == ROOT_HUB_VENDOR {
+                return None;
+            }
+            Some(UsbDevice {
+                vendor_id,
+                product_id: read(path.join("idProduct"))?,
+                manufacturer: read(path.join("manufacturer")),
+                product: read(path.join("product")),
+            })
+        })
+        .collect()
+}
+
+fn pci_devices(dir: &Path) -> Vec<PciDevice> {
+    let hex = |path: PathBuf| read(path).map(|id| id.trim_start_matches("0x").to_string());
+    entries(dir)
+        .into_iter()
+        .filter_map(|(address, path)| {
+            Some(PciDevice {
+                vendor_id: hex(path.join("vendor"))?,
+                device_id: hex(path.join("device"))?,
+                class: hex(path.join("class"))?,
+                address,
+            })
+        })
+        .collect()
+}
+
+fn displays(dir: &Path) -> Vec<DisplayOutput> {
+    entries(dir)
+        .into_iter()
+        // Connectors are `card<N>-<name>`; `card<N>` itself is the GPU.
+        .filter_map(|(entry, path)| {
+            let (card, name) = entry.split_once('-')?;
+            if !card.starts_with("card") {
+                return None;
+            }
+            let connected = read(path.join("status")).as_deref() == Some("connected");
+            let mode = read(path.join("modes"))
+                .and_then(|modes| modes.lines().next().map(str::to_string))
+                .filter(|_| connected);
+            Some(DisplayOutput {
+                name: name.to_string(),
+                connected,
+                mode,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    fn write(root: &Path, path: &str, contents: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn reads_the_cpu_model_of_x86_and_arm() {
+        let x86 = "processor\t: 0\nmodel name\t: Intel(R) Celeron(R) N5105 @ 2.00GHz\n\n\
+                   processor\t: 1\nmodel name\t: Intel(R) Celeron(R) N5105 @ 2.00GHz\n";
+        assert_eq!(
+            parse_cpuinfo(x86),
+            (Some("Intel(R) Celeron(R) N5105 @ 2.00GHz".into()), 2)
+        );
+
+        let arm = "processor\t: 0\nBogoMIPS\t: 108.00\n\nprocessor\t: 1\nBogoMIPS\t: 108.00\n\n\
+                   Hardware\t: BCM2835\nModel\t\t: Raspberry Pi 4 Model B Rev 1.4\n";
+        assert_eq!(parse_cpuinfo(arm), (Some("BCM2835".into()), 2));
+        assert_eq!(parse_cpuinfo(""), (None, 0));
+
+        assert_eq!(
+            parse_mem_total("MemTotal:        3884164 kB\nMemFree:  1 kB\n"),
+            3_884_164 * 1024
+        );
+    }
+
+    #[test]
+    fn collects_the_inventory_from_sysfs() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        write(
+            root,
+            "proc/cpuinfo",
+            "processor\t: 0\nmodel name\t: Cortex-A72\n",
+        );
+        write(root, "proc/meminfo", "MemTotal:        4096 kB\n");
+
+        write(root, "sys/block/mmcblk0/size", "61071360\n");
+        write(root, "sys/block/mmcblk0/device/type", "MMC\n");
+        write(root, "sys/block/mmcblk0/device/name", "DG4064\n");
+        write(root, "sys/block/mmcblk0/queue/rotational", "0\n");
+        write(root, "sys/block/mmcblk0/removable", "0\n");
+        write(root, "sys/block/mmcblk0boot0/size", "8192\n");
+        write(root, "sys/block/loop0/size", "100\n");
+        write(root, "sys/block/mmcblk1/size", "0\n");
+        write(
+            root,
+            "sys/devices/pci0000:00/0000:00:14.0/usb2/2-1/2-1:1.0/host0/block/sda/size",
+            "1953525168\n",
+        );
+        write(
+            root,
+            "sys/devices/pci0000:00/0000:00:14.0/usb2/2-1/2-1:1.0/host0/block/sda/device/model",
+            "Extreme SSD     \n",
+        );
+        symlink(
+            "../devices/pci0000:00/0000:00:14.0/usb2/2-1/2-1:1.0/host0/block/sda",
+            root.join("sys/block/sda"),
+        )
+        .unwrap();
+
+        write(root, "sys/bus/usb/devices/usb1/idVendor", "1d6b\n");
+        write(root, "sys/bus/usb/devices/usb1/idProduct", "0002\n");
+        write(root, "sys/bus/usb/devices/1-1/idVendor", "046d\n");
+        write(root, "sys/bus/usb/devices/1-1/idProduct", "0825\n");
+        write(root, "sys/bus/usb/devices/1-1/product", "Webcam C270\n");
+        write(root, "sys/bus/usb/devices/1-1:1.0/bInterfaceClass", "0e\n");
+
+        write(root, "sys/bus/pci/devices/0000:01:00.0/vendor", "0x10ec\n");
+        write(root, "sys/bus/pci/devices/0000:01:00.0/device", "0x8168\n");
+        write(root, "sys/bus/pci/devices/0000:01:00.0/class", "0x020000\n");
+
+        write(root, "sys/class/drm/card0-HDMI-A-1/status", "connected\n");
+        write(
+            root,
+            "sys/class/drm/card0-HDMI-A-1/modes",
+            "1920x1080\n1280x720\n",
+        );
+        write(
+            root,
+            "sys/class/drm/card0-HDMI-A-2/status",
+            "disconnected\n",
+        );
+        write(root, "sys/class/drm/card0/dev", "226:0\n");
+        write(root, "sys/class/drm/renderD128/dev", "226:128\n");
+
+        let inventory = collect_from(root);
+        assert_eq!(inventory.cpu_model.as_deref(), Some("Cortex-A72"));
+        assert_eq!(inventory.cpu_cores, 1);
+        assert_eq!(inventory.memory_bytes, 4096 * 1024);
+        assert_eq!(
+            inventory.block_devices,
+            vec![
+                BlockDevice {
+                    name: "mmcblk0".into(),
+                    kind: BlockDeviceKind::Emmc,
+                    model: Some("DG4064".into()),
+                    size_bytes: 61_071_360 * 512,
+                    rotational: false,
+                    removable: false,
+                },
+                BlockDevice {
+                    name: "sda".into(),
+                    kind: BlockDeviceKind::Usb,
+                    model: Some("Extreme SSD".into()),
+                    size_bytes: 1_953_525_168 * 512,
+                    rotational: false,
+                    removable: false,
+                },
+            ]
+        );
+        assert_eq!(
+            inventory.usb_devices,
+            vec![UsbDevice {
+                vendor_id: "046d".into(),
+                product_id: "0825".into(),
+                manufacturer: None,
+                product: Some("Webcam C270".into()),
+            }]
+        );
+        assert_eq!(
+            inventory.pci_devices,
+            vec![PciDevice {
+                address: "0000:01:00.0".into(),
+                vendor_id: "10ec".into(),
+                device_id: "8168".into(),
+                class: "020000".into(),
+            }]
+        );
+        assert_eq!(
+            inventory.displays,
+            vec![
+                DisplayOutput {
+                    name: "HDMI-A-1".into(),
+                    connected: true,
+                    mode: Some("1920x1080".into()),
+                },
+                DisplayOutput {
+                    name: "HDMI-A-2".into(),
+                    connected: false,
+                    mode: None,
+                },
+            ]
+        );
+    }
+}
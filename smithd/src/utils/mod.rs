@@ -1,4 +1,5 @@
 pub mod files;
+pub mod hardware;
 pub mod network;
 pub mod schema;
 pub mod system;
@@ -1,4 +1,6 @@
-use crate::{downloader::DownloaderHandle, magic::structure::ConfigPackage};
+use crate::downloader::{DownloadJob, DownloaderHandle};
+use crate::magic::structure::ConfigPackage;
+use crate::utils::schema::{Challenge, ContainerSpec, ReleaseHooks};
 use anyhow::{Context, Result};
 use flate2::{Compression, write::GzEncoder};
 use reqwest::{Response, StatusCode};
@@ -81,6 +83,26 @@ impl NetworkClient {
         Ok((status_code, request))
     }
 
+    /// Fetches a single-use identity challenge. `None` means the api predates
+    /// device identity keys (404), in which case callers fall back to the legacy
+    /// flow.
+    pub async fn get_challenge(&self) -> Result<Option<String>> {
+        let url = format!("{}/challenge", self.hostname);
+        let response = self.client.post(url).send().await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let challenge: Challenge = response
+            .error_for_status()?
+            .json()
+            .await
+            .with_context(|| "Failed to parse challenge")?;
+
+        Ok(Some(challenge.challenge))
+    }
+
     pub async fn get_release_packages(
         &self,
         release_id: i32,
@@ -100,6 +122,72 @@ impl NetworkClient {
             .with_context(|| "Failed to Parse JSON respone")
     }
 
+    /// The containers a release runs. An api without container support has
+    /// none to give.
+    pub async fn get_release_containers(
+        &self,
+        release_id: i32,
+        token: &str,
+    ) -> Result<ContainerSpec> {
+        let url = format!("{}/releases/{}/containers", self.hostname, release_id);
+        let response = self
+            .client
+            .get(url)
+            .header("Authorization", format!("Bearer {}", token))
+            .send()
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(ContainerSpec::default());
+        }
+
+        response
+            .error_for_status()?
+            .json()
+            .await
+            .with_context(|| "Failed to Parse JSON respone")
+    }
+
+    /// The scripts a release runs around its install. An api without hook
+    /// support has none to give.
+    pub async fn get_release_hooks(&self, release_id: i32, token: &str) -> Result<ReleaseHooks> {
+        let url = format!("{}/releases/{}/hooks", self.hostname, release_id);
+        let response = self
+            .client
+            .get(url)
+            .header("Authorization", format!("Bearer {}", token))
+            .send()
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(ReleaseHooks::default());
+        }
+
+        response
+            .error_for_status()?
+            .json()
+            .await
+            .with_context(|| "Failed to Parse JSON respone")
+    }
+
+    /// The size of a file the api serves, without downloading it: the api
+    /// answers `/download` with a signed link and the file's size.
+    pub async fn get_file_size(&self, remote_path: &str, token: &str) -> Result<u64> {
+        let response = self
+            .client
+            .get(format!("{}/download", self.hostname))
+            .query(&[("path", remote_path)])
+            .header("Authorization", format!("Bearer {}", token))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        response
+            .headers()
+            .get("x-file-size")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+            .with_context(|| "x-file-size header missing or invalid")
+    }
+
     async fn validate_package_file(path: &std::path::Path) -> Result<bool> {
         // Check if file exists and has content
         let metadata = match tokio::fs::metadata(path).await {
@@ -151,7 +239,11 @@ impl NetworkClient {
         let remote_file = format!("packages/{}", package_name);
         downloader
             // steady download at 2MB/s lets play nice in these networks
-            .download_blocking(&remote_file, local_package_path.to_str().unwrap_or(""), 2.0)
+            .download_blocking(DownloadJob::new(
+                &remote_file,
+                local_package_path.to_str().unwrap_or(""),
+                2.0,
+            ))
             .await?;
 
         Ok(())
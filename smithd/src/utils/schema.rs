@@ -28,6 +28,12 @@ pub struct HomePost {
     pub release_id: Option<i32>,
     #[serde(default)]
     pub service_statuses: Vec<ServiceStatus>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connectivity: Option<ConnectivityReport>,
+    /// The WireGuard tunnel, while the device has one. Sampled every few
+    /// minutes, so most posts leave it out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vpn: Option<VpnStatus>,
 }
 
 impl HomePost {
@@ -35,6 +41,8 @@ impl HomePost {
         responses: Vec<SafeCommandResponse>,
         release_id: Option<i32>,
         service_statuses: Vec<ServiceStatus>,
+        connectivity: Option<ConnectivityReport>,
+        vpn: Option<VpnStatus>,
     ) -> Self {
         let timestamp = time::Instant::now().elapsed();
         Self {
@@ -42,10 +50,46 @@ impl HomePost {
             responses,
             release_id,
             service_statuses,
+            connectivity,
+            vpn,
         }
     }
 }
 
+/// How far the device gets out, classified the way NetworkManager does.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectivityState {
+    /// Not probed yet.
+    #[default]
+    Unknown,
+    /// No default route at all.
+    None,
+    /// A route, but the check host doesn't resolve or answer.
+    Limited,
+    /// The check is answered by something else, typically a captive portal.
+    Portal,
+    Full,
+}
+
+/// A change of connectivity state, at unix seconds.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectivityTransition {
+    pub at: u64,
+    pub state: ConnectivityState,
+}
+
+/// The current connectivity state, with the transitions the API hasn't
+/// acknowledged yet, oldest first.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq, Eq)]
+pub struct ConnectivityReport {
+    pub state: ConnectivityState,
+    /// Unix seconds since the device has been in `state`.
+    pub since: u64,
+    #[serde(default)]
+    pub transitions: Vec<ConnectivityTransition>,
+}
+
 #[derive(Serialize, Deserialize, Default, Debug)]
 pub struct CreateSession {
     pub token: String,
@@ -118,6 +162,257 @@ pub struct NetworkCredentials {
     pub key_mgmt: String,
     #[serde(default)]
     pub psk: Option<String>,
+    /// Set when `key_mgmt` is `wpa-eap`.
+    #[serde(default)]
+    pub eap: Option<EapCredentials>,
+}
+
+/// 802.1X settings of an enterprise network. The certificates and the private
+/// key arrive sealed to the device's secrets key, like a [`SealedSecret`].
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct EapCredentials {
+    /// `peap`, `ttls` or `tls`.
+    pub method: String,
+    pub identity: String,
+    #[serde(default)]
+    pub anonymous_identity: Option<String>,
+    /// Inner authentication of PEAP and TTLS, e.g. `mschapv2`.
+    #[serde(default)]
+    pub phase2_auth: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// PEM, sealed.
+    #[serde(default)]
+    pub ca_cert: Option<String>,
+    /// PEM, sealed. TLS only.
+    #[serde(default)]
+    pub client_cert: Option<String>,
+    /// PEM, sealed. TLS only.
+    #[serde(default)]
+    pub private_key: Option<String>,
+    #[serde(default)]
+    pub private_key_password: Option<String>,
+    /// Requires management frame protection, which makes it WPA3-Enterprise.
+    #[serde(default)]
+    pub pmf_required: bool,
+}
+
+impl EapCredentials {
+    /// Checks that the method has what it authenticates with.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.identity.is_empty() {
+            return Err("EAP identity is empty".into());
+        }
+        match self.method.as_str() {
+            "peap" | "ttls" => {
+                if self.password.is_none() {
+                    return Err(format!("{} needs a password", self.method));
+                }
+            }
+            "tls" => {
+                if self.client_cert.is_none() || self.private_key.is_none() {
+                    return Err("tls needs a client certificate and private key".into());
+                }
+            }
+            other => return Err(format!("unsupported EAP method: {other}")),
+        }
+        Ok(())
+    }
+}
+
+/// How NetworkManager configures one address family of a wired profile.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum IpMethod {
+    /// DHCP for IPv4, SLAAC or DHCPv6 for IPv6.
+    #[default]
+    Auto,
+    Manual,
+    Disabled,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct IpConfig {
+    #[serde(default)]
+    pub method: IpMethod,
+    /// Addresses in CIDR notation, only used with `Manual`.
+    #[serde(default)]
+    pub addresses: Vec<String>,
+    #[serde(default)]
+    pub gateway: Option<String>,
+}
+
+/// A wired profile of the network intent: plain Ethernet, or a VLAN on top of
+/// `interface` when `vlan_id` is set.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct IntentEthernet {
+    pub profile_name: String,
+    /// The NIC the profile is bound to, any wired NIC when unset.
+    #[serde(default)]
+    pub interface: Option<String>,
+    #[serde(default)]
+    pub vlan_id: Option<u16>,
+    #[serde(default)]
+    pub ipv4: IpConfig,
+    #[serde(default)]
+    pub ipv6: IpConfig,
+    /// DNS servers of either family, replacing the ones DHCP hands out.
+    #[serde(default)]
+    pub dns: Vec<String>,
+    pub priority: i32,
+}
+
+impl IntentEthernet {
+    /// Checks what NetworkManager would otherwise reject halfway through an
+    /// apply, so the api can refuse it up front.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.profile_name.trim().is_empty() {
+            return Err("profile_name must not be empty".into());
+        }
+        if let Some(vlan_id) = self.vlan_id {
+            if !(1..=4094).contains(&vlan_id) {
+                return Err(format!("vlan_id {vlan_id} is outside 1-4094"));
+            }
+            if self.interface.is_none() {
+                return Err("a VLAN needs the interface it sits on".into());
+            }
+        }
+        for (family, config, v6) in [("ipv4", &self.ipv4, false), ("ipv6", &self.ipv6, true)] {
+            if config.method == IpMethod::Manual && config.addresses.is_empty() {
+                return Err(format!("{family} is manual but has no addresses"));
+            }
+            if config.method != IpMethod::Manual && !config.addresses.is_empty() {
+                return Err(format!("{family} addresses need the manual method"));
+            }
+            if config.method == IpMethod::Disabled && config.gateway.is_some() {
+                return Err(format!("{family} is disabled but has a gateway"));
+            }
+            for address in &config.addresses {
+                let (ip, prefix) = address
+                    .split_once('/')
+                    .ok_or_else(|| format!("{family} address {address} has no prefix length"))?;
+                let max = if v6 { 128 } else { 32 };
+                let valid = parse_ip(ip, v6).is_some()
+                    && prefix.parse::<u8>().is_ok_and(|prefix| prefix <= max);
+                if !valid {
+                    return Err(format!("{address} is not a valid {family} address"));
+                }
+            }
+            if let Some(gateway) = &config.gateway
+                && parse_ip(gateway, v6).is_none()
+            {
+                return Err(format!("{gateway} is not a valid {family} gateway"));
+            }
+        }
+        for server in &self.dns {
+            if server.parse::<std::net::IpAddr>().is_err() {
+                return Err(format!("{server} is not a valid DNS server"));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_ip(ip: &str, v6: bool) -> Option<std::net::IpAddr> {
+    ip.parse::<std::net::IpAddr>()
+        .ok()
+        .filter(|ip| ip.is_ipv6() == v6)
+}
+
+/// An address with a prefix length, of either family.
+fn is_cidr(cidr: &str) -> bool {
+    let Some((ip, prefix)) = cidr.split_once('/') else {
+        return false;
+    };
+    match ip.parse::<std::net::IpAddr>() {
+        Ok(ip) => {
+            let max = if ip.is_ipv6() { 128 } else { 32 };
+            prefix.parse::<u8>().is_ok_and(|prefix| prefix <= max)
+        }
+        Err(_) => false,
+    }
+}
+
+/// The device's WireGuard tunnel to a VPN concentrator. The private key never
+/// leaves the device, so it isn't part of this.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct VpnConfig {
+    /// The device's tunnel address, e.g. `10.90.0.7/32`.
+    pub address: String,
+    pub peer_public_key: String,
+    /// `host:port` of the concentrator.
+    pub endpoint: String,
+    /// What is routed through the tunnel.
+    pub allowed_ips: Vec<String>,
+    #[serde(default)]
+    pub dns: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub persistent_keepalive: Option<u16>,
+}
+
+impl VpnConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !is_cidr(&self.address) {
+            return Err(format!("{} is not a valid tunnel address", self.address));
+        }
+        if !is_wireguard_key(&self.peer_public_key) {
+            return Err("peer_public_key is not a WireGuard key".into());
+        }
+        match self.endpoint.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {}
+            _ => return Err(format!("endpoint {} is not host:port", self.endpoint)),
+        }
+        if self.allowed_ips.is_empty() {
+            return Err("allowed_ips must not be empty".into());
+        }
+        if let Some(ip) = self.allowed_ips.iter().find(|ip| !is_cidr(ip)) {
+            return Err(format!("{ip} is not a valid allowed IP range"));
+        }
+        for server in &self.dns {
+            if server.parse::<std::net::IpAddr>().is_err() {
+                return Err(format!("{server} is not a valid DNS server"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 32 bytes in base64, as `wg` prints keys.
+pub fn is_wireguard_key(key: &str) -> bool {
+    use base64::Engine;
+    base64::engine::general_purpose::STANDARD
+        .decode(key)
+        .is_ok_and(|bytes| bytes.len() == 32)
+}
+
+/// The tunnel as `wg` reports it.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct VpnStatus {
+    /// Unix seconds of the last handshake with the concentrator, missing if
+    /// there never was one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latest_handshake: Option<u64>,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+}
+
+/// Where smithd puts a decrypted secret: a file of its own in the secrets
+/// directory, or a `NAME=value` line in the shared environment file.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SecretDelivery {
+    #[default]
+    File,
+    Env,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SealedSecret {
+    pub name: String,
+    /// Base64 libsodium sealed box, see `crate::secrets::seal`.
+    pub ciphertext: String,
+    #[serde(default)]
+    pub delivery: SecretDelivery,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -152,6 +447,782 @@ pub struct WifiNetwork {
     pub channel: Option<i32>,
 }
 
+/// Queued downloads start highest priority first, oldest first within one.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DownloadPriority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownloadState {
+    Queued,
+    Downloading,
+    /// Held back by the bandwidth policy; resumes once it allows downloads again.
+    Paused,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+/// One download in the daemon's queue, as reported to the api and the control
+/// socket. Finished downloads are kept for a while so their outcome can be read.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DownloadInfo {
+    pub id: u64,
+    pub remote_file: String,
+    pub local_file: String,
+    pub priority: DownloadPriority,
+    /// Groups related downloads, e.g. `"ota"` for the tools and payload.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    pub state: DownloadState,
+    pub bytes_downloaded: u64,
+    /// `None` until the server has reported the file size.
+    pub total_bytes: Option<u64>,
+    pub bytes_per_second: u64,
+    pub eta_seconds: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The mirror the bytes are coming, or came, from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirror: Option<String>,
+}
+
+/// A place files can be downloaded from. Without a `url` it is the smith api
+/// itself, which redirects to a signed CDN link. Otherwise a file is fetched
+/// from `<url>/<path>`, keeping any query string `url` has, so an on-site cache
+/// or a CDN URL signed for a whole prefix can serve the same paths the api
+/// does. Bytes from a mirror are only kept if they match the file's sha256.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DownloadMirror {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+/// Release services named with this prefix are containers from the release's
+/// [`ContainerSpec`], checked through the container runtime instead of systemd.
+pub const CONTAINER_SERVICE_PREFIX: &str = "container:";
+
+/// Whether a release package is a container image. Their `file` is the image
+/// reference pinned to a digest, e.g. `ghcr.io/teton/vision@sha256:…`.
+pub fn is_container_image(file: &str) -> bool {
+    file.contains("@sha256:")
+}
+
+/// The containers a release runs, in the spirit of a compose file.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct ContainerSpec {
+    #[serde(default)]
+    pub services: std::collections::BTreeMap<String, ContainerService>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct ContainerService {
+    /// The name of a container image package in the release. The api resolves
+    /// it to that package's pinned reference before handing the spec to a
+    /// device.
+    pub image: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub command: Vec<String>,
+    #[serde(default, skip_serializing_if = "std::collections::BTreeMap::is_empty")]
+    pub environment: std::collections::BTreeMap<String, String>,
+    /// `host:container`, optionally with `/tcp` or `/udp`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ports: Vec<String>,
+    /// `host_path:container_path`, optionally with `:ro`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub volumes: Vec<String>,
+    /// A Docker restart policy; `unless-stopped` if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub restart: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network_mode: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub privileged: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub healthcheck: Option<ContainerHealthcheck>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ContainerHealthcheck {
+    /// As in a Dockerfile, e.g. `["CMD", "curl", "-f", "http://localhost/"]`.
+    pub test: Vec<String>,
+    #[serde(default = "ContainerHealthcheck::default_interval_sec")]
+    pub interval_sec: u32,
+    #[serde(default = "ContainerHealthcheck::default_retries")]
+    pub retries: u32,
+}
+
+impl ContainerHealthcheck {
+    fn default_interval_sec() -> u32 {
+        30
+    }
+
+    fn default_retries() -> u32 {
+        3
+    }
+}
+
+/// A port mapping from a [`ContainerService`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContainerPort {
+    pub host: u16,
+    pub container: u16,
+    pub protocol: &'static str,
+}
+
+impl ContainerPort {
+    pub fn parse(port: &str) -> Option<Self> {
+        let (ports, protocol) = match port.split_once('/') {
+            Some((ports, "tcp")) => (ports, "tcp"),
+            Some((ports, "udp")) => (ports, "udp"),
+            Some(_) => return None,
+            None => (port, "tcp"),
+        };
+        let (host, container) = ports.split_once(':')?;
+        Some(Self {
+            host: host.parse().ok()?,
+            container: container.parse().ok()?,
+            protocol,
+        })
+    }
+}
+
+impl ContainerSpec {
+    /// Every problem with the spec, so all of them can be fixed at once.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+        for (name, service) in &self.services {
+            // Names become container names, `smith-<name>`.
+            let valid_name = name
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+            if !valid_name {
+                errors.push(format!(
+                    "services.{name}: names may only use a-z, 0-9, - and _"
+                ));
+            }
+            if service.image.trim().is_empty() {
+                errors.push(format!("services.{name}.image is empty"));
+            }
+            for port in &service.ports {
+                if ContainerPort::parse(port).is_none() {
+                    errors.push(format!(
+                        "services.{name}.ports: {port:?} is not host:container[/tcp|/udp]"
+                    ));
+                }
+            }
+            for volume in &service.volumes {
+                let parts: Vec<&str> = volume.split(':').collect();
+                let valid = matches!(parts.as_slice(), [host, container] | [host, container, "ro" | "rw"]
+                    if host.starts_with('/') && container.starts_with('/'));
+                if !valid {
+                    errors.push(format!(
+                        "services.{name}.volumes: {volume:?} is not /host:/container[:ro]"
+                    ));
+                }
+            }
+            if let Some(healthcheck) = &service.healthcheck
+                && (healthcheck.test.is_empty() || healthcheck.interval_sec == 0)
+            {
+                errors.push(format!(
+                    "services.{name}.healthcheck needs a test and a non-zero interval"
+                ));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Scripts a release runs around installing its packages, for steps that do
+/// not belong in any one deb's maintainer scripts. A retried upgrade runs them
+/// again, so they should be safe to run twice.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct ReleaseHooks {
+    /// Runs before anything is installed. If it fails the upgrade stops and the
+    /// device stays on its current release.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_install: Option<ReleaseHook>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_install: Option<ReleaseHook>,
+    /// Runs last; the device only moves to the release once it passes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verify: Option<ReleaseHook>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReleaseHook {
+    /// Run with `sh`, or with the interpreter a `#!` line names.
+    pub script: String,
+    #[serde(default = "ReleaseHook::default_timeout_sec")]
+    pub timeout_sec: u32,
+}
+
+impl ReleaseHook {
+    pub const MAX_TIMEOUT_SEC: u32 = 60 * 60;
+
+    fn default_timeout_sec() -> u32 {
+        300
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HookStage {
+    PreInstall,
+    PostInstall,
+    Verify,
+}
+
+impl std::fmt::Display for HookStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HookStage::PreInstall => write!(f, "pre_install"),
+            HookStage::PostInstall => write!(f, "post_install"),
+            HookStage::Verify => write!(f, "verify"),
+        }
+    }
+}
+
+impl ReleaseHooks {
+    pub fn get(&self, stage: HookStage) -> Option<&ReleaseHook> {
+        match stage {
+            HookStage::PreInstall => self.pre_install.as_ref(),
+            HookStage::PostInstall => self.post_install.as_ref(),
+            HookStage::Verify => self.verify.as_ref(),
+        }
+    }
+
+    /// Every problem with the hooks, so all of them can be fixed at once.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+        for stage in [
+            HookStage::PreInstall,
+            HookStage::PostInstall,
+            HookStage::Verify,
+        ] {
+            let Some(hook) = self.get(stage) else {
+                continue;
+            };
+            if hook.script.trim().is_empty() {
+                errors.push(format!("{stage}.script is empty"));
+            }
+            if hook.timeout_sec == 0 || hook.timeout_sec > ReleaseHook::MAX_TIMEOUT_SEC {
+                errors.push(format!(
+                    "{stage}.timeout_sec must be between 1 and {}",
+                    ReleaseHook::MAX_TIMEOUT_SEC
+                ));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// What a release hook did. Output is cut to its last few KiB.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HookOutput {
+    pub stage: HookStage,
+    /// `None` if the hook could not be started, timed out or was killed.
+    pub exit_code: Option<i32>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub timed_out: bool,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl HookOutput {
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+}
+
+/// The outcome of an upgrade, with the output of the release hooks it ran.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct UpgradeReport {
+    pub release_id: Option<i32>,
+    pub success: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hooks: Vec<HookOutput>,
+}
+
+/// What upgrading would do to a package.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PlanAction {
+    Install,
+    Upgrade,
+    Downgrade,
+    /// At the release's version already, but not fully configured.
+    Reinstall,
+    Unchanged,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PlannedPackage {
+    pub name: String,
+    pub version: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub installed_version: Option<String>,
+    pub action: PlanAction,
+    /// Whether the blob is on the device already, or the image pulled.
+    pub cached: bool,
+    /// Still to download. `None` when the size is unknown, as it is for
+    /// container images.
+    pub download_bytes: Option<u64>,
+}
+
+/// What upgrading to a release would do, worked out without installing
+/// anything.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct UpgradePlan {
+    pub release_id: i32,
+    pub packages: Vec<PlannedPackage>,
+    /// The sum of the download sizes that are known.
+    pub download_bytes: u64,
+    /// Free space where blobs are cached.
+    pub free_bytes: Option<u64>,
+    /// Whether `apt-get -s` finds the debs to install satisfiable. `None` when
+    /// it could not be asked, e.g. because some are not downloaded yet.
+    pub resolvable: Option<bool>,
+    /// The end of what the resolver said, or why it was not asked.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub resolver_output: String,
+}
+
+/// Download caps in MB/s for each kind of link. A link without a cap downloads
+/// at whatever rate the caller asked for; a cap of 0 pauses downloads on it.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct LinkRates {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ethernet: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wifi: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lte: Option<f64>,
+}
+
+impl LinkRates {
+    pub fn for_link(&self, link: InterfaceType) -> Option<f64> {
+        match link {
+            InterfaceType::Ethernet => self.ethernet,
+            InterfaceType::Wifi => self.wifi,
+            InterfaceType::Lte => self.lte,
+            InterfaceType::Unknown => None,
+        }
+    }
+}
+
+/// Caps that replace the default ones between `start_hour` and `end_hour`
+/// device local time. The window wraps past midnight when `end_hour` is the
+/// smaller of the two, and covers the whole day when they are equal.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BandwidthSchedule {
+    pub start_hour: u8,
+    pub end_hour: u8,
+    pub rates: LinkRates,
+}
+
+impl BandwidthSchedule {
+    pub fn contains(&self, hour: u8) -> bool {
+        match self.start_hour.cmp(&self.end_hour) {
+            std::cmp::Ordering::Less => (self.start_hour..self.end_hour).contains(&hour),
+            std::cmp::Ordering::Greater => hour >= self.start_hour || hour < self.end_hour,
+            std::cmp::Ordering::Equal => true,
+        }
+    }
+}
+
+/// How fast the daemon may download, set by the api per device label.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct BandwidthPolicy {
+    #[serde(default)]
+    pub rates: LinkRates,
+    /// Checked in order; the first window containing the current hour wins.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub schedules: Vec<BandwidthSchedule>,
+    /// Bytes that may be downloaded over LTE per calendar month. Downloads on
+    /// LTE pause once it is spent and pick up again the next month.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lte_monthly_budget_bytes: Option<u64>,
+}
+
+impl BandwidthPolicy {
+    /// The cap for `link` at `hour`, `None` meaning uncapped.
+    pub fn cap(&self, link: InterfaceType, hour: u8) -> Option<f64> {
+        match self.schedules.iter().find(|s| s.contains(hour)) {
+            Some(schedule) => schedule.rates.for_link(link),
+            None => self.rates.for_link(link),
+        }
+    }
+}
+
+/// Bytes the daemon downloaded this month, per link, and the policy it is
+/// applying.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct BandwidthUsage {
+    /// `YYYY-MM`, device local time.
+    pub month: String,
+    pub ethernet_bytes: u64,
+    pub wifi_bytes: u64,
+    pub lte_bytes: u64,
+    /// Downloads over a link that could not be identified.
+    #[serde(default)]
+    pub other_bytes: u64,
+    /// The link downloads currently go over.
+    pub link: InterfaceType,
+    /// Why downloads are paused, if they are.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub paused: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy: Option<BandwidthPolicy>,
+}
+
+/// Where an OS update is. An update moves forward through these in
+/// order, and may end in `Failed` from any of them.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OtaPhase {
+    /// No update has been started.
+    #[default]
+    Idle,
+    Downloading,
+    /// Checking the payload against its sha256.
+    Verifying,
+    /// Downloaded and verified, waiting for `StartOTA`.
+    Staged,
+    /// The update backend is installing the payload.
+    Applying,
+    Rebooting,
+    /// Booted into the target version.
+    Verified,
+    Failed,
+}
+
+/// The state of the device's OTA update, as persisted by the daemon and
+/// reported to the api whenever it changes. Versions are L4T versions such as
+/// `35.4.1` on a Jetson, and the image's `IMAGE_VERSION` or `VERSION_ID` from
+/// `/etc/os-release` with the A/B image backend.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct OtaState {
+    pub phase: OtaPhase,
+    /// Which update backend the device uses, e.g. `jetson`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+    /// Version the update should boot into.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_version: Option<String>,
+    /// Version the device ran when the update was requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from_version: Option<String>,
+    /// Version the device runs now.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current_version: Option<String>,
+    /// Of the tools and payload together, 0-100.
+    #[serde(default)]
+    pub progress_percent: u8,
+    #[serde(default)]
+    pub downloaded_bytes: u64,
+    #[serde(default)]
+    pub total_bytes: u64,
+    /// The A/B slot the update was written to, for backends that have them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slot: Option<String>,
+    /// Digest of the downloaded payload, once it has been verified.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload_sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Unix seconds of the last phase change.
+    #[serde(default)]
+    pub updated_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditSeverity {
+    Info,
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+/// The outcome of one compliance check.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AuditCheckResult {
+    /// Stable name of the check, e.g. `firewall_active`.
+    pub id: String,
+    pub title: String,
+    pub severity: AuditSeverity,
+    /// `None` if the check could not be run, e.g. a tool is missing.
+    pub passed: Option<bool>,
+    /// What the check saw, e.g. the offending ports.
+    pub evidence: String,
+    /// How to make the check pass.
+    pub remediation: String,
+}
+
+/// What the integrity monitor records of a file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FileMetadata {
+    /// Hex SHA-256 of the contents, or of the target of a symlink.
+    pub sha256: String,
+    pub uid: u32,
+    pub gid: u32,
+    /// Permission bits, e.g. `0o440`.
+    pub mode: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IntegrityChangeKind {
+    Added,
+    Removed,
+    Changed,
+    /// The baseline itself went missing or unreadable after one was taken,
+    /// and was taken again; drift from before then went unseen. `path` is the
+    /// baseline's.
+    BaselineReset,
+}
+
+/// A monitored file that differs from the baseline.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IntegrityChange {
+    pub path: String,
+    pub kind: IntegrityChangeKind,
+    /// The file in the baseline, `None` if it was added.
+    pub old: Option<FileMetadata>,
+    /// The file on disk, `None` if it was removed.
+    pub new: Option<FileMetadata>,
+}
+
+/// The modem's state, as ModemManager tracks it.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ModemState {
+    Failed,
+    #[default]
+    Unknown,
+    Initializing,
+    /// The SIM needs a PIN.
+    Locked,
+    Disabled,
+    Disabling,
+    Enabling,
+    Enabled,
+    Searching,
+    Registered,
+    Disconnecting,
+    Connecting,
+    Connected,
+}
+
+/// Whether the modem is registered on a network, and whose.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ModemRegistration {
+    Idle,
+    Home,
+    Searching,
+    Denied,
+    #[default]
+    Unknown,
+    Roaming,
+    EmergencyOnly,
+}
+
+impl ModemRegistration {
+    pub fn is_registered(self) -> bool {
+        matches!(self, Self::Home | Self::Roaming)
+    }
+}
+
+/// One reading of the modem's signal. The LTE values are missing on other
+/// access technologies, or when the modem does not report them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ModemSignalSample {
+    /// Unix seconds.
+    pub at: u64,
+    /// 0-100, as ModemManager rates it.
+    pub quality: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access_technology: Option<String>,
+    /// dBm.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rssi: Option<f64>,
+    /// dBm.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rsrp: Option<f64>,
+    /// dB.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rsrq: Option<f64>,
+    /// dB.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snr: Option<f64>,
+}
+
+/// Round trips to one target over an interval, in milliseconds.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct LatencyStats {
+    pub sent: u32,
+    pub received: u32,
+    /// Missing when nothing came back.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rtt_p50_ms: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rtt_p90_ms: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rtt_p99_ms: Option<f64>,
+    /// Mean difference between consecutive round trips.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jitter_ms: Option<f64>,
+}
+
+/// The link quality over an interval, which ends early when the device
+/// roams, so that it is measured against a single access point.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct LinkQualityInterval {
+    /// Unix seconds.
+    pub start: u64,
+    pub end: u64,
+    /// The access point, missing when not on Wi-Fi.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bssid: Option<String>,
+    /// Mean Wi-Fi signal, 0-100.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api: Option<LatencyStats>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gateway: Option<LatencyStats>,
+}
+
+/// What the device is built from, read from procfs and sysfs.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct HardwareInventory {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_model: Option<String>,
+    pub cpu_cores: u32,
+    pub memory_bytes: u64,
+    #[serde(default)]
+    pub block_devices: Vec<BlockDevice>,
+    #[serde(default)]
+    pub usb_devices: Vec<UsbDevice>,
+    #[serde(default)]
+    pub pci_devices: Vec<PciDevice>,
+    #[serde(default)]
+    pub displays: Vec<DisplayOutput>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockDeviceKind {
+    Emmc,
+    Sd,
+    Nvme,
+    Usb,
+    /// SATA or SCSI.
+    Sata,
+    #[default]
+    Other,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct BlockDevice {
+    /// The kernel's name, e.g. `mmcblk0` or `nvme0n1`.
+    pub name: String,
+    pub kind: BlockDeviceKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    pub size_bytes: u64,
+    pub rotational: bool,
+    pub removable: bool,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct UsbDevice {
+    /// Four hex digits, e.g. `046d`.
+    pub vendor_id: String,
+    pub product_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manufacturer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub product: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct PciDevice {
+    /// e.g. `0000:01:00.0`.
+    pub address: String,
+    /// Four hex digits, e.g. `8086`.
+    pub vendor_id: String,
+    pub device_id: String,
+    /// Six hex digits of class, subclass and interface, e.g. `030000`.
+    pub class: String,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct DisplayOutput {
+    /// The DRM connector, e.g. `HDMI-A-1`.
+    pub name: String,
+    pub connected: bool,
+    /// The preferred mode of the attached display, e.g. `1920x1080`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+}
+
+/// The device's cellular modem, as read from ModemManager.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct ModemStatus {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manufacturer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub imei: Option<String>,
+    /// The SIM's ICCID, `None` without a SIM.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iccid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operator: Option<String>,
+    pub state: ModemState,
+    pub registration: ModemRegistration,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access_technology: Option<String>,
+    /// The APN of the connected data bearer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub apn: Option<String>,
+    /// Whether a data bearer is connected.
+    pub data_connected: bool,
+    /// Bytes through the data bearer since it connected.
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    /// Signal readings since the last report, oldest first.
+    #[serde(default)]
+    pub signal: Vec<ModemSignalSample>,
+    /// Power cycles the watchdog did since smithd started.
+    #[serde(default)]
+    pub watchdog_power_cycles: u32,
+}
+
 #[derive(Serialize, Deserialize, Default, Debug, Clone)]
 pub enum SafeCommandRx {
     #[default]
@@ -193,6 +1264,24 @@ pub enum SafeCommandRx {
     DownloadOTA,
     CheckOTAStatus {
         status: String,
+        /// The individual OTA downloads behind `status`.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        downloads: Vec<DownloadInfo>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ota: Option<OtaState>,
+    },
+    /// In reply to `DownloadOTA` and `StartOTA`, and sent whenever the update
+    /// moves on by itself.
+    OtaState {
+        state: OtaState,
+    },
+    Downloads {
+        downloads: Vec<DownloadInfo>,
+    },
+    /// `cancelled` is false when no queued or running download had that id.
+    DownloadCancelled {
+        id: u64,
+        cancelled: bool,
     },
     TestNetwork {
         bytes_downloaded: usize,
@@ -220,11 +1309,27 @@ pub enum SafeCommandRx {
     AuditReport {
         disk_encrypted: Option<bool>,
         password_access_disabled: Option<bool>,
+        /// Every check of the device's audit profile. Daemons from before
+        /// profiles only report the two flags above.
+        #[serde(default)]
+        checks: Vec<AuditCheckResult>,
+    },
+    /// How the monitored files differ from the baseline. An empty list means
+    /// they match it.
+    IntegrityReport {
+        files: usize,
+        changes: Vec<IntegrityChange>,
     },
     ApplyNetworksResult {
         applied_version: i32,
         conditions: Vec<NetworkCondition>,
     },
+    /// The public half of the device's WireGuard key, sent on startup.
+    WireGuardKey {
+        public_key: String,
+    },
+    /// In reply to `ApplyVpn`.
+    VpnApplied,
     FileSessionStarted {
         session_id: String,
     },
@@ -235,6 +1340,40 @@ pub enum SafeCommandRx {
         session_id: String,
         error: String,
     },
+    /// Sent on startup with the device's X25519 public key, so the api can seal
+    /// secrets to it and queue the device's current set.
+    GetSecrets {
+        public_key: String,
+    },
+    /// Names of the secrets that could not be opened or written. Empty on success.
+    UpdateSecrets {
+        failed: Vec<String>,
+    },
+    /// Sent periodically, and in reply to `UpdateBandwidthPolicy`.
+    BandwidthUsage {
+        usage: BandwidthUsage,
+    },
+    /// Sent after each upgrade with the output of the release hooks it ran.
+    UpgradeResult {
+        report: UpgradeReport,
+    },
+    UpgradePlan {
+        plan: UpgradePlan,
+    },
+    /// Sent periodically, when the registration changes, and in reply to the
+    /// modem commands. `None` when the device has no modem.
+    ModemStatus {
+        modem: Option<ModemStatus>,
+    },
+    /// Latency and loss to the API host and the default gateway, sent every
+    /// interval.
+    LinkQuality {
+        intervals: Vec<LinkQualityInterval>,
+    },
+    /// Sent on startup and whenever the hardware changes.
+    HardwareInventory {
+        inventory: HardwareInventory,
+    },
     /// Fallback for any report this build doesn't recognize; ignored by the api.
     Unknown,
 }
@@ -289,9 +1428,24 @@ pub enum SafeCommandTx {
         tools: String,
         payload: String,
         rate: f64,
+        /// Version the payload installs, checked after the reboot.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        version: Option<String>,
+        /// Expected sha256 of the payload, hex.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sha256: Option<String>,
     },
     CheckOTAStatus,
     StartOTA,
+    /// Reports the given downloads, or every download the daemon knows of
+    /// when `ids` is empty.
+    ListDownloads {
+        #[serde(default)]
+        ids: Vec<u64>,
+    },
+    CancelDownload {
+        id: u64,
+    },
     TestNetwork,
     ExtendedNetworkTest {
         duration_minutes: u32,
@@ -304,6 +1458,11 @@ pub enum SafeCommandTx {
         session_id: String,
     },
     RunAudit,
+    /// Takes these changes into the integrity baseline, each only if the file
+    /// still looks as it did in the change.
+    AcceptIntegrityBaseline {
+        changes: Vec<IntegrityChange>,
+    },
     GetLogs {
         unit: Option<String>,
         since: Option<String>,
@@ -313,6 +1472,8 @@ pub enum SafeCommandTx {
     ApplyNetworks {
         version: i32,
         networks: Vec<IntentNetwork>,
+        #[serde(default)]
+        ethernet: Vec<IntentEthernet>,
     },
     /// Dial back to the api and serve filesystem operations for the lifetime of
     /// the session. Carries no path: every operation is negotiated over the
@@ -323,6 +1484,36 @@ pub enum SafeCommandTx {
     CloseFileSession {
         session_id: String,
     },
+    /// The device's complete secret set, each sealed to its public key. Secrets
+    /// absent from the list are removed, like `UpdateVariables`.
+    UpdateSecrets {
+        secrets: Vec<SealedSecret>,
+    },
+    /// Replaces the device's bandwidth policy; `None` removes every cap.
+    UpdateBandwidthPolicy {
+        policy: Option<BandwidthPolicy>,
+    },
+    /// Works out what upgrading to `release_id`, or to the device's target
+    /// release when unset, would do, without installing anything.
+    UpgradePlan {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        release_id: Option<i32>,
+    },
+    /// Connects the modem's data bearer with this APN, which it also attaches
+    /// to the network with from now on. Stored in NetworkManager's gsm profile
+    /// where there is one, so it survives reconnects.
+    SetModemApn {
+        apn: String,
+    },
+    SetModemEnabled {
+        enabled: bool,
+    },
+    /// Turns the modem's radio off and on again.
+    PowerCycleModem,
+    /// Replaces the device's WireGuard tunnel; `None` removes it.
+    ApplyVpn {
+        vpn: Option<VpnConfig>,
+    },
     /// Fallback for any command this build doesn't recognize. Never issued by
     /// the api: it is produced locally by `deserialize_tx` and reported back
     /// with a failure status so the operator sees why nothing happened.
@@ -438,12 +1629,51 @@ pub struct HomePostResponse {
     pub target_release_id: Option<i32>,
     #[serde(default)]
     pub services: Vec<ServiceCheck>,
+    /// Download mirrors in order of preference. When set they replace the ones
+    /// in magic.toml.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<DownloadMirror>,
+    /// Whether the connectivity report in the post was stored. Until it is,
+    /// the device keeps its transitions and sends them again.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub connectivity_saved: bool,
 }
 
 #[derive(Serialize, Deserialize, Default, Debug)]
 pub struct DeviceRegistration {
     pub serial_number: String,
     pub wifi_mac: String,
+    /// Absent only from daemons that predate device identity keys, which the
+    /// api registers while `ALLOW_KEYLESS_REGISTRATION` is on. Once a device
+    /// has registered with a key the api refuses registrations without it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity: Option<IdentityProof>,
+}
+
+/// Proof of possession of the device identity key, see `crate::identity`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IdentityProof {
+    /// Base64 Ed25519 public key.
+    pub public_key: String,
+    /// Single-use challenge from `/smith/challenge`.
+    pub challenge: String,
+    /// Base64 signature over `identity::signed_message`.
+    pub signature: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Challenge {
+    pub challenge: String,
+    pub expires_in: u64,
+}
+
+/// Body of `POST /auth/session`: mint a JWT by signing a challenge with the
+/// identity key instead of presenting the long-lived opaque token.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SessionProof {
+    pub serial_number: String,
+    pub challenge: String,
+    pub signature: String,
 }
 
 #[derive(Serialize, Deserialize, Default, Debug)]
@@ -518,11 +1748,12 @@ pub struct SpeedSample {
     pub timed_out: bool,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum InterfaceType {
     Wifi,
     Ethernet,
     Lte,
+    #[default]
     Unknown,
 }
 
@@ -560,6 +1791,77 @@ pub struct NetworkInfo {
 mod tests {
     use super::*;
 
+    #[test]
+    fn container_specs_are_validated() {
+        let spec: ContainerSpec = serde_json::from_value(serde_json::json!({
+            "services": {
+                "vision": {
+                    "image": "vision",
+                    "ports": ["8080:80", "5353:53/udp"],
+                    "volumes": ["/data:/data:ro"],
+                    "healthcheck": { "test": ["CMD", "true"] }
+                }
+            }
+        }))
+        .unwrap();
+        assert_eq!(spec.validate(), Ok(()));
+        assert_eq!(
+            spec.services["vision"]
+                .healthcheck
+                .as_ref()
+                .unwrap()
+                .interval_sec,
+            30
+        );
+
+        let bad: ContainerSpec = serde_json::from_value(serde_json::json!({
+            "services": {
+                "Vision": {
+                    "image": "",
+                    "ports": ["80", "1:2/sctp"],
+                    "volumes": ["data:/data"]
+                }
+            }
+        }))
+        .unwrap();
+        assert_eq!(bad.validate().unwrap_err().len(), 5);
+
+        assert_eq!(
+            ContainerPort::parse("5353:53/udp"),
+            Some(ContainerPort {
+                host: 5353,
+                container: 53,
+                protocol: "udp"
+            })
+        );
+        assert!(is_container_image("ghcr.io/teton/vision@sha256:abc"));
+        assert!(!is_container_image("vision_1.0_arm64.deb"));
+    }
+
+    #[test]
+    fn release_hooks_are_validated() {
+        let hooks: ReleaseHooks = serde_json::from_value(serde_json::json!({
+            "pre_install": { "script": "systemctl stop camera" },
+            "verify": { "script": "curl -f http://localhost/health", "timeout_sec": 60 }
+        }))
+        .unwrap();
+        assert_eq!(hooks.validate(), Ok(()));
+        assert_eq!(hooks.get(HookStage::PreInstall).unwrap().timeout_sec, 300);
+        assert_eq!(hooks.get(HookStage::PostInstall), None);
+
+        let bad: ReleaseHooks = serde_json::from_value(serde_json::json!({
+            "post_install": { "script": " ", "timeout_sec": 0 }
+        }))
+        .unwrap();
+        assert_eq!(
+            bad.validate().unwrap_err(),
+            vec![
+                "post_install.script is empty",
+                "post_install.timeout_sec must be between 1 and 3600"
+            ]
+        );
+    }
+
     #[test]
     fn get_logs_protocol_round_trip() {
         // Deserialize the JSON shape the API stores in the cmd jsonb column.
@@ -592,6 +1894,128 @@ mod tests {
         assert_eq!(network.security_type, "");
     }
 
+    #[test]
+    fn apply_networks_without_ethernet_still_deserializes() {
+        let json = r#"{"ApplyNetworks":{"version":3,"networks":[]}}"#;
+        let cmd: SafeCommandTx = serde_json::from_str(json).unwrap();
+        assert!(matches!(
+            cmd,
+            SafeCommandTx::ApplyNetworks { version: 3, ethernet, .. } if ethernet.is_empty()
+        ));
+    }
+
+    #[test]
+    fn eap_credentials_validation() {
+        let eap = |method: &str| EapCredentials {
+            method: method.into(),
+            identity: "nurse-station-4".into(),
+            ..Default::default()
+        };
+        assert!(eap("peap").validate().is_err());
+        assert!(
+            EapCredentials {
+                password: Some("secret".into()),
+                ..eap("ttls")
+            }
+            .validate()
+            .is_ok()
+        );
+        assert!(
+            EapCredentials {
+                client_cert: Some("sealed".into()),
+                ..eap("tls")
+            }
+            .validate()
+            .is_err()
+        );
+        assert!(
+            EapCredentials {
+                client_cert: Some("sealed".into()),
+                private_key: Some("sealed".into()),
+                ..eap("tls")
+            }
+            .validate()
+            .is_ok()
+        );
+        assert!(eap("leap").validate().is_err());
+    }
+
+    #[test]
+    fn intent_ethernet_validation() {
+        let wired = |json: &str| {
+            serde_json::from_str::<IntentEthernet>(json)
+                .unwrap()
+                .validate()
+        };
+
+        assert!(wired(r#"{"profile_name":"lan","priority":0}"#).is_ok());
+        assert!(
+            wired(
+                r#"{"profile_name":"lan","interface":"eth0","vlan_id":20,"priority":0,
+                "ipv4":{"method":"manual","addresses":["10.0.20.5/24"],"gateway":"10.0.20.1"},
+                "ipv6":{"method":"disabled"},"dns":["10.0.0.53","2001:db8::53"]}"#
+            )
+            .is_ok()
+        );
+
+        for bad in [
+            r#"{"profile_name":"lan","vlan_id":20,"priority":0}"#,
+            r#"{"profile_name":"lan","interface":"eth0","vlan_id":4095,"priority":0}"#,
+            r#"{"profile_name":"lan","priority":0,"ipv4":{"method":"manual"}}"#,
+            r#"{"profile_name":"lan","priority":0,"ipv4":{"addresses":["10.0.0.5/24"]}}"#,
+            r#"{"profile_name":"lan","priority":0,"ipv4":{"method":"manual","addresses":["10.0.0.5"]}}"#,
+            r#"{"profile_name":"lan","priority":0,"ipv4":{"method":"manual","addresses":["2001:db8::5/64"]}}"#,
+            r#"{"profile_name":"lan","priority":0,"ipv6":{"method":"manual","addresses":["2001:db8::5/129"]}}"#,
+            r#"{"profile_name":"lan","priority":0,"ipv4":{"gateway":"fe80::1"}}"#,
+            r#"{"profile_name":"lan","priority":0,"dns":["dns.example"]}"#,
+            r#"{"profile_name":" ","priority":0}"#,
+        ] {
+            assert!(wired(bad).is_err(), "{bad} should be rejected");
+        }
+    }
+
+    #[test]
+    fn vpn_config_validation() {
+        let config = VpnConfig {
+            address: "10.90.0.7/32".into(),
+            peer_public_key: "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=".into(),
+            endpoint: "vpn.example.org:51820".into(),
+            allowed_ips: vec!["10.20.0.0/16".into(), "fd00::/8".into()],
+            dns: vec!["10.20.0.53".into()],
+            persistent_keepalive: Some(25),
+        };
+        assert!(config.validate().is_ok());
+
+        for bad in [
+            VpnConfig {
+                address: "10.90.0.7".into(),
+                ..config.clone()
+            },
+            VpnConfig {
+                peer_public_key: "c2hvcnQ=".into(),
+                ..config.clone()
+            },
+            VpnConfig {
+                endpoint: "vpn.example.org".into(),
+                ..config.clone()
+            },
+            VpnConfig {
+                allowed_ips: vec![],
+                ..config.clone()
+            },
+            VpnConfig {
+                allowed_ips: vec!["10.20.0.0/33".into()],
+                ..config.clone()
+            },
+            VpnConfig {
+                dns: vec!["dns.example".into()],
+                ..config.clone()
+            },
+        ] {
+            assert!(bad.validate().is_err(), "{bad:?} should be rejected");
+        }
+    }
+
     #[test]
     fn get_logs_omitted_fields_default_to_none() {
         // Fields absent from the JSON object must deserialize as None,
@@ -671,6 +2095,8 @@ mod tests {
                 active_state: "active".to_string(),
                 n_restarts: 2,
             }],
+            connectivity: None,
+            vpn: None,
         };
 
         let fixture: Value = serde_json::from_str(include_str!("fixtures/home_post.json")).unwrap();
@@ -725,6 +2151,8 @@ mod tests {
                 id: 1,
                 name: "smithd".to_string(),
             }],
+            mirrors: Vec::new(),
+            connectivity_saved: false,
         };
 
         let fixture: Value =
@@ -746,6 +2174,7 @@ mod tests {
         let registration = DeviceRegistration {
             serial_number: "smith-device-1".to_string(),
             wifi_mac: "aa:bb:cc:dd:ee:ff".to_string(),
+            identity: None,
         };
         let response = DeviceRegistrationResponse {
             token: "8b1a44a4-2a10-42da-9e59-6dc2b3f6e1b0".to_string(),
@@ -910,6 +2339,28 @@ mod tests {
         }
     }
 
+    #[test]
+    fn bandwidth_policy_schedules_override_default_caps() {
+        let json = r#"{"UpdateBandwidthPolicy":{"policy":{
+            "rates":{"wifi":5.0,"lte":0.5},
+            "schedules":[{"start_hour":22,"end_hour":6,"rates":{"wifi":20.0,"lte":2.0}}]
+        }}}"#;
+        let SafeCommandTx::UpdateBandwidthPolicy {
+            policy: Some(policy),
+        } = serde_json::from_str(json).unwrap()
+        else {
+            panic!("expected UpdateBandwidthPolicy with a policy");
+        };
+
+        assert_eq!(policy.lte_monthly_budget_bytes, None);
+        assert_eq!(policy.cap(InterfaceType::Wifi, 12), Some(5.0));
+        assert_eq!(policy.cap(InterfaceType::Wifi, 23), Some(20.0));
+        assert_eq!(policy.cap(InterfaceType::Lte, 3), Some(2.0));
+        assert_eq!(policy.cap(InterfaceType::Lte, 6), Some(0.5));
+        assert_eq!(policy.cap(InterfaceType::Ethernet, 12), None);
+        assert_eq!(policy.cap(InterfaceType::Unknown, 23), None);
+    }
+
     #[test]
     fn unknown_command_variant_round_trips() {
         // A daemon reporting a failed unknown command must not itself produce a
@@ -1,3 +1,4 @@
+use crate::updater::cache::BlobCache;
 use pnet::datalink;
 use pnet::datalink::NetworkInterface;
 use serde::{Deserialize, Serialize};
@@ -63,6 +64,14 @@ pub struct ConnectionStatus {
     pub device_name: String,
 }
 
+/// What the package cache holds.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Default, Clone)]
+pub struct CacheUsage {
+    pub bytes: u64,
+    pub blobs: usize,
+    pub releases: usize,
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub struct SystemInfo {
     pub smith: Smith,
@@ -72,6 +81,7 @@ pub struct SystemInfo {
     pub network: Network,
     pub device_tree: DeviceTree,
     pub connection_statuses: Vec<ConnectionStatus>,
+    pub cache: CacheUsage,
 }
 
 impl SystemInfo {
@@ -143,6 +153,7 @@ impl SystemInfo {
                     }),
             },
             connection_statuses: get_connection_statuses(),
+            cache: get_cache_usage().await,
         }
     }
     pub fn print(&self) {
@@ -156,6 +167,13 @@ impl SystemInfo {
     }
 }
 
+async fn get_cache_usage() -> CacheUsage {
+    match std::env::current_dir() {
+        Ok(smith_home) => BlobCache::new(&smith_home.join("packages")).usage().await,
+        Err(_) => CacheUsage::default(),
+    }
+}
+
 async fn get_last_boot_time() -> u64 {
     let content = tokio::fs::read_to_string("/proc/stat")
         .await
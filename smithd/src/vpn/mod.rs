@@ -0,0 +1,246 @@
+//! The device's WireGuard tunnel, applied through NetworkManager. The device
+//! generates its own key and only reports the public half; the api hands out
+//! the address and the concentrator to peer with.
+use crate::commander::network::execute_nmcli_command;
+use crate::secrets::{self, KEYS_DIR};
+use crate::utils::files::write_file_atomic;
+use crate::utils::schema::{VpnConfig, VpnStatus};
+use anyhow::{Context, Result, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use crypto_box::SecretKey;
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::path::Path;
+use tokio::process::Command;
+use tokio::time::{Duration, timeout};
+use tracing::warn;
+
+/// The interface, and the NetworkManager profile named after it.
+const INTERFACE: &str = "smith-wg";
+
+/// What a new profile is imported as before it replaces the old one.
+const STAGING: &str = "smith-wg-new";
+
+/// The sha256 of the config last applied, so applying it again is a no-op.
+const APPLIED_PATH: &str = "/etc/smith/vpn.sha256";
+
+const KEY_FILE: &str = "wireguard.x25519";
+
+/// The device's WireGuard key, generated on first use.
+pub async fn load_or_create_key() -> Result<SecretKey> {
+    tokio::task::spawn_blocking(|| secrets::load_or_create_key_file(Path::new(KEYS_DIR), KEY_FILE))
+        .await?
+}
+
+/// Replaces the tunnel with `config`, or removes it. The new profile is
+/// imported and brought up next to the old one, on an interface of its own,
+/// and the old one is only deleted once that worked. A config NetworkManager
+/// refuses, or that does not come up, leaves the running tunnel alone.
+/// Applying the config that is already up does nothing.
+pub async fn apply(config: Option<&VpnConfig>) -> Result<()> {
+    let Some(config) = config else {
+        nmcli(&["connection", "delete", INTERFACE], true).await?;
+        _ = tokio::fs::remove_file(APPLIED_PATH).await;
+        return Ok(());
+    };
+    if let Err(err) = config.validate() {
+        bail!("invalid VPN config: {err}");
+    }
+
+    let key = load_or_create_key().await?;
+    let conf = render(config, &BASE64.encode(key.to_bytes()));
+    let digest = digest(&conf);
+    let applied = tokio::fs::read_to_string(APPLIED_PATH)
+        .await
+        .unwrap_or_default();
+    if applied == digest
+        && nmcli(&["connection", "show", INTERFACE], false)
+            .await
+            .is_ok()
+    {
+        return Ok(());
+    }
+
+    // nmcli names the interface and the profile after the file.
+    let dir = tempfile::tempdir().context("creating a directory for the WireGuard config")?;
+    let path = dir.path().join(format!("{STAGING}.conf"));
+    secrets::write_private(&path, conf.as_bytes())?;
+
+    // Left over if an earlier apply was interrupted.
+    nmcli(&["connection", "delete", STAGING], true).await?;
+    if let Err(err) = stage(&path.to_string_lossy()).await {
+        _ = nmcli(&["connection", "delete", STAGING], true).await;
+        return Err(err);
+    }
+
+    // The staged profile works, so the old one can go. Renaming it moves the
+    // tunnel onto the interface everything else knows it by.
+    nmcli(&["connection", "delete", INTERFACE], true).await?;
+    nmcli(
+        &["connection", "modify", STAGING, "connection.id", INTERFACE],
+        false,
+    )
+    .await?;
+    nmcli(&["connection", "up", INTERFACE], false).await?;
+
+    if let Err(err) = write_file_atomic(Path::new(APPLIED_PATH), &digest, 0o600).await {
+        warn!("Failed to record the applied VPN config: {err:#}");
+    }
+    Ok(())
+}
+
+/// Imports the config at `path` as [`STAGING`] and brings it up, then sets
+/// what it takes over from the old profile for when it is activated next.
+async fn stage(path: &str) -> Result<()> {
+    nmcli(
+        &["connection", "import", "type", "wireguard", "file", path],
+        false,
+    )
+    .await?;
+    nmcli(&["connection", "up", STAGING], false).await?;
+    nmcli(
+        &[
+            "connection",
+            "modify",
+            STAGING,
+            "connection.interface-name",
+            INTERFACE,
+            "connection.autoconnect",
+            "yes",
+        ],
+        false,
+    )
+    .await
+}
+
+/// Runs an nmcli command, failing unless it succeeds or, with `missing_ok`,
+/// exits with 10: no such connection.
+async fn nmcli(args: &[&str], missing_ok: bool) -> Result<()> {
+    let mut cmd = Command::new("nmcli");
+    cmd.args(args);
+    let output = execute_nmcli_command(cmd).await?;
+    let missing = missing_ok && output.status.code() == Some(10);
+    if output.status.success() || missing {
+        return Ok(());
+    }
+    bail!(
+        "nmcli {} failed: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr).trim()
+    )
+}
+
+fn digest(conf: &str) -> String {
+    let mut hex = String::with_capacity(64);
+    for byte in Sha256::digest(conf.as_bytes()) {
+        _ = write!(hex, "{byte:02x}");
+    }
+    hex
+}
+
+/// A wg-quick file, which is what `nmcli connection import` reads.
+fn render(config: &VpnConfig, private_key: &str) -> String {
+    let mut conf = String::new();
+    _ = writeln!(conf, "[Interface]");
+    _ = writeln!(conf, "PrivateKey = {private_key}");
+    _ = writeln!(conf, "Address = {}", config.address);
+    if !config.dns.is_empty() {
+        _ = writeln!(conf, "DNS = {}", config.dns.join(", "));
+    }
+    _ = writeln!(conf);
+    _ = writeln!(conf, "[Peer]");
+    _ = writeln!(conf, "PublicKey = {}", config.peer_public_key);
+    _ = writeln!(conf, "Endpoint = {}", config.endpoint);
+    _ = writeln!(conf, "AllowedIPs = {}", config.allowed_ips.join(", "));
+    if let Some(keepalive) = config.persistent_keepalive {
+        _ = writeln!(conf, "PersistentKeepalive = {keepalive}");
+    }
+    conf
+}
+
+/// The tunnel's handshake and counters, `None` without a tunnel or `wg`.
+pub async fn status() -> Option<VpnStatus> {
+    if !Path::new("/sys/class/net").join(INTERFACE).exists() {
+        return None;
+    }
+    let mut cmd = Command::new("wg");
+    cmd.args(["show", INTERFACE, "dump"]).kill_on_drop(true);
+    let output = timeout(Duration::from_secs(5), cmd.output())
+        .await
+        .ok()?
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_dump(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// `wg show <interface> dump`: a line for the interface, then one per peer
+/// with its key, preshared key, endpoint, allowed IPs, latest handshake and
+/// the bytes received and sent, tab separated.
+fn parse_dump(dump: &str) -> Option<VpnStatus> {
+    let peer = dump.lines().nth(1)?;
+    let fields: Vec<&str> = peer.split('\t').collect();
+    if fields.len() < 7 {
+        return None;
+    }
+    let handshake: u64 = fields[4].parse().ok()?;
+    Some(VpnStatus {
+        latest_handshake: (handshake != 0).then_some(handshake),
+        rx_bytes: fields[5].parse().ok()?,
+        tx_bytes: fields[6].parse().ok()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_a_wg_quick_file() {
+        let config = VpnConfig {
+            address: "10.90.0.7/32".into(),
+            peer_public_key: "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=".into(),
+            endpoint: "vpn.example.org:51820".into(),
+            allowed_ips: vec!["10.20.0.0/16".into(), "10.90.0.1/32".into()],
+            dns: vec!["10.20.0.53".into()],
+            persistent_keepalive: Some(25),
+        };
+        assert_eq!(
+            render(&config, "PRIVATE"),
+            "[Interface]
+PrivateKey = PRIVATE
+Address = 10.90.0.7/32
+DNS = 10.20.0.53
+
+[Peer]
+PublicKey = xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=
+Endpoint = vpn.example.org:51820
+AllowedIPs = 10.20.0.0/16, 10.90.0.1/32
+PersistentKeepalive = 25
+"
+        );
+    }
+
+    #[test]
+    fn reads_the_peer_from_a_dump() {
+        let dump = "cPRIVATE=\tcPUBLIC=\t51820\toff
+xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=\t(none)\t203.0.113.4:51820\t10.20.0.0/16\t1760000000\t1024\t2048\t25
+";
+        assert_eq!(
+            parse_dump(dump),
+            Some(VpnStatus {
+                latest_handshake: Some(1_760_000_000),
+                rx_bytes: 1024,
+                tx_bytes: 2048,
+            })
+        );
+
+        let never = "cPRIVATE=\tcPUBLIC=\t51820\toff
+xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=\t(none)\t(none)\t10.20.0.0/16\t0\t0\t148\t25
+";
+        assert_eq!(parse_dump(never).unwrap().latest_handshake, None);
+        assert_eq!(parse_dump("cPRIVATE=\tcPUBLIC=\t51820\toff\n"), None);
+    }
+}